@@ -0,0 +1,64 @@
+//! Feeds arbitrary bytes to every decoder the driver runs on IOCTL input. Decoders must never
+//! panic, and whatever they accept must survive an encode/decode round trip.
+//!
+//! > cargo +nightly fuzz run decode_ioctl_input
+
+#![no_main]
+
+use common::{
+    evaluate::EvaluateRequest,
+    input::{self, check_input, MAX_INPUT_SIZE},
+    logging::LogLevel,
+    options::OptionsUpdate,
+    rule::RuleRecord,
+    volume::VolumePolicy,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if check_input(false, data.len()).is_err() {
+        assert!(data.len() > MAX_INPUT_SIZE);
+        return;
+    }
+
+    if let Ok(name) = input::exe_name_utf8(data) {
+        assert!(!name.is_empty() && !name.ends_with('\0'));
+    }
+    if let Ok(name) = input::exe_name_utf16(data) {
+        assert!(!name.is_empty() && data.len() % 2 == 0);
+    }
+    let _ = input::flags(data);
+    if let Ok(level) = LogLevel::decode(data) {
+        assert_eq!(data, (level as u32).to_le_bytes());
+    }
+
+    if let Ok((record, len)) = RuleRecord::decode(data) {
+        assert!(len <= data.len());
+        let mut buffer = vec![0u8; record.encoded_len()];
+        assert_eq!(record.encode(&mut buffer), Some(len));
+        let (again, _) = RuleRecord::decode(&buffer).expect("re-encoded rule must decode");
+        assert_eq!(again, record);
+    }
+
+    if let Ok(policy) = VolumePolicy::decode(data) {
+        let mut buffer = vec![0u8; policy.encoded_len()];
+        policy
+            .encode(&mut buffer)
+            .expect("decoded policy must encode");
+        assert_eq!(VolumePolicy::decode(&buffer), Ok(policy));
+    }
+
+    if let Ok(request) = EvaluateRequest::decode(data) {
+        let mut buffer = vec![0u8; request.encoded_len()];
+        request
+            .encode(&mut buffer)
+            .expect("decoded request must encode");
+        assert_eq!(EvaluateRequest::decode(&buffer), Ok(request));
+    }
+
+    if let Ok(update) = OptionsUpdate::decode(data) {
+        let mut buffer = [0u8; 8];
+        update.encode(&mut buffer);
+        assert_eq!(OptionsUpdate::decode(&buffer), Ok(update));
+    }
+});
@@ -0,0 +1,232 @@
+//! Challenge-response authorization of control device handles.
+//!
+//! A secret is configured once at install time with `IOCTL_DELPROTECT_SET_SECRET` and
+//! persisted in the `Secret` REG_BINARY of the service `Parameters` key. From then on the
+//! driver is locked: a handle may only change the configuration or allow an unload after it
+//! asked for a challenge (`IOCTL_DELPROTECT_GET_CHALLENGE`) and answered it with
+//! `HMAC-SHA256(secret, challenge)` (`IOCTL_DELPROTECT_AUTHENTICATE`). A challenge can be
+//! answered once, the authorization lasts until the handle is closed.
+//!
+//! Output of `IOCTL_DELPROTECT_GET_CHALLENGE`:
+//!
+//! ```text
+//! 0   u32  AUTH_FLAG_* flags
+//! 4   u32  reserved
+//! 8   ...  challenge (CHALLENGE_SIZE bytes)
+//! ```
+
+use crate::{input::DecodeError, wire::read_u32};
+
+pub const CHALLENGE_SIZE: usize = 32;
+pub const RESPONSE_SIZE: usize = 32;
+pub const CHALLENGE_MESSAGE_SIZE: usize = 8 + CHALLENGE_SIZE;
+pub const MIN_SECRET_SIZE: usize = 16;
+pub const MAX_SECRET_SIZE: usize = 64;
+
+/// A secret is configured, configuration changes need an authorized handle.
+pub const AUTH_FLAG_LOCKED: u32 = 0x1;
+/// The handle which asked for the challenge is already authorized.
+pub const AUTH_FLAG_AUTHORIZED: u32 = 0x2;
+/// An authorized handle allowed the next `fltmc unload`.
+pub const AUTH_FLAG_UNLOAD_ALLOWED: u32 = 0x4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChallengeMessage {
+    pub flags: u32,
+    pub challenge: [u8; CHALLENGE_SIZE],
+}
+
+impl ChallengeMessage {
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        if buffer.len() < CHALLENGE_MESSAGE_SIZE {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&self.flags.to_le_bytes());
+        buffer[4..8].copy_from_slice(&0u32.to_le_bytes());
+        buffer[8..CHALLENGE_MESSAGE_SIZE].copy_from_slice(&self.challenge);
+
+        Some(CHALLENGE_MESSAGE_SIZE)
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        if buffer.len() < CHALLENGE_MESSAGE_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let mut challenge = [0u8; CHALLENGE_SIZE];
+        challenge.copy_from_slice(&buffer[8..CHALLENGE_MESSAGE_SIZE]);
+        Ok(Self {
+            flags: read_u32(buffer, 0),
+            challenge,
+        })
+    }
+}
+
+pub fn is_valid_secret(secret: &[u8]) -> bool {
+    (MIN_SECRET_SIZE..=MAX_SECRET_SIZE).contains(&secret.len())
+}
+
+/// The answer to `challenge` expected from a holder of `secret`.
+pub fn response(secret: &[u8], challenge: &[u8; CHALLENGE_SIZE]) -> [u8; RESPONSE_SIZE] {
+    hmac_sha256(secret, challenge)
+}
+
+/// Compares without an early exit so the time taken does not reveal the matching prefix.
+pub fn verify_response(secret: &[u8], challenge: &[u8; CHALLENGE_SIZE], response: &[u8]) -> bool {
+    if response.len() != RESPONSE_SIZE {
+        return false;
+    }
+
+    let expected = self::response(secret, challenge);
+    expected
+        .iter()
+        .zip(response)
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+const BLOCK_SIZE: usize = 64;
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        let mut hasher = Sha256::new();
+        hasher.update(key);
+        block[..32].copy_from_slice(&hasher.finish());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut pad = [0u8; BLOCK_SIZE];
+    for (p, k) in pad.iter_mut().zip(block) {
+        *p = k ^ 0x36;
+    }
+    let mut inner = Sha256::new();
+    inner.update(&pad);
+    inner.update(message);
+    let inner = inner.finish();
+
+    for (p, k) in pad.iter_mut().zip(block) {
+        *p = k ^ 0x5c;
+    }
+    let mut outer = Sha256::new();
+    outer.update(&pad);
+    outer.update(&inner);
+    outer.finish()
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Minimal SHA-256, enough for HMAC over short messages without pulling in a crypto crate.
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; BLOCK_SIZE],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0u8; BLOCK_SIZE],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let take = (BLOCK_SIZE - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+
+            if self.block_len == BLOCK_SIZE {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len * 8;
+
+        self.block[self.block_len] = 0x80;
+        self.block[self.block_len + 1..].fill(0);
+        if self.block_len >= BLOCK_SIZE - 8 {
+            self.compress();
+            self.block.fill(0);
+        }
+        self.block[BLOCK_SIZE - 8..].copy_from_slice(&bit_len.to_be_bytes());
+        self.compress();
+
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
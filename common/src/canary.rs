@@ -0,0 +1,99 @@
+//! Canary files: files nobody has a reason to touch. A delete, rename or overwrite of one is
+//! denied and locks the process down, see `crate::detector`, with its descendants if the canary
+//! says so.
+//!
+//! Canaries are added with `IOCTL_DELPROTECT_ADD_CANARY` (a record, id and trips ignored),
+//! removed by id with `IOCTL_DELPROTECT_REMOVE_CANARY` and listed with
+//! `IOCTL_DELPROTECT_LIST_CANARIES`, a `ListHeader` followed by records. The path is the NT
+//! path of the file, e.g. `\Device\HarddiskVolume3\Users\Public\budget.xlsx`, compared without
+//! regard to case.
+//!
+//! ```text
+//! 0   u32  id (ignored on add)
+//! 4   u32  flags (CANARY_FLAG_*)
+//! 8   u32  trips (ignored on add)
+//! 12  u16  path length in bytes
+//! 14  u16  reserved
+//! 16  u64  time of the last trip, 0 if none (ignored on add)
+//! 24  ...  path, UTF-16LE
+//! ```
+
+use crate::{
+    input::DecodeError,
+    wire::{read_u16, read_u32, read_u64},
+};
+
+pub const CANARY_HEADER_SIZE: usize = 24;
+/// Longest path, the longest name the driver queries.
+pub const MAX_CANARY_PATH_BYTES: usize = 1024 * 2;
+
+/// The descendants the process creates after the trip are locked down as well.
+pub const CANARY_FLAG_DESCENDANTS: u32 = 0x1;
+pub const CANARY_FLAGS_ALL: u32 = CANARY_FLAG_DESCENDANTS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CanaryRecord<'a> {
+    pub id: u32,
+    pub flags: u32,
+    pub trips: u32,
+    pub last_trip: u64,
+    /// UTF-16LE bytes of the NT path.
+    pub path: &'a [u8],
+}
+
+impl<'a> CanaryRecord<'a> {
+    pub fn encoded_len(&self) -> usize {
+        CANARY_HEADER_SIZE + self.path.len()
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        if buffer.len() < len || self.path.len() > MAX_CANARY_PATH_BYTES {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&self.id.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.flags.to_le_bytes());
+        buffer[8..12].copy_from_slice(&self.trips.to_le_bytes());
+        buffer[12..14].copy_from_slice(&(self.path.len() as u16).to_le_bytes());
+        buffer[14..16].copy_from_slice(&0u16.to_le_bytes());
+        buffer[16..24].copy_from_slice(&self.last_trip.to_le_bytes());
+        buffer[CANARY_HEADER_SIZE..len].copy_from_slice(self.path);
+
+        Some(len)
+    }
+
+    /// Parses one record from the beginning of `buffer`, returning it together with the number
+    /// of bytes consumed. Unknown flags are rejected.
+    pub fn decode(buffer: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < CANARY_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let path_len = read_u16(buffer, 12) as usize;
+        let len = CANARY_HEADER_SIZE + path_len;
+        if path_len > MAX_CANARY_PATH_BYTES {
+            return Err(DecodeError::TooLong);
+        }
+        if !path_len.is_multiple_of(2) {
+            return Err(DecodeError::OddLength);
+        }
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let flags = read_u32(buffer, 4);
+        if flags & !CANARY_FLAGS_ALL != 0 {
+            return Err(DecodeError::InvalidValue);
+        }
+
+        let record = Self {
+            id: read_u32(buffer, 0),
+            flags,
+            trips: read_u32(buffer, 8),
+            last_trip: read_u64(buffer, 16),
+            path: &buffer[CANARY_HEADER_SIZE..len],
+        };
+        Ok((record, len))
+    }
+}
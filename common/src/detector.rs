@@ -0,0 +1,207 @@
+//! Mass-delete detector. The driver counts the deletes, renames and overwrites of every process
+//! over a sliding window, together with the directories they touch. A process going over the
+//! thresholds is locked down: its deletes, renames and overwrites are denied until it exits or
+//! is released with `IOCTL_DELPROTECT_RELEASE_LOCKDOWN`, whatever the rules say. Touching a
+//! canary, see `crate::canary`, locks a process down as well, and so does a rule demoting the
+//! process whose delete it denied, see `crate::rule::RuleAction::DenyAndDemote`.
+//!
+//! The settings are the input of `IOCTL_DELPROTECT_SET_DETECTOR`, the output of
+//! `IOCTL_DELPROTECT_GET_DETECTOR` and the `Detector` REG_BINARY of the `Parameters` key.
+//! `IOCTL_DELPROTECT_LIST_LOCKDOWNS` returns a `ListHeader` followed by lockdown records.
+//!
+//! ```text
+//! settings                                       lockdown record
+//! 0   u32  window in seconds, 0 disables         0   u32  record size in bytes
+//! 4   u32  operations in the window above        4   u32  process id
+//!          which a process is locked down        8   u64  time of the lockdown (FILETIME, UTC)
+//! 8   u32  distinct directories in the window    16  u32  operations in the window
+//!          above which, 0 to ignore them         20  u32  directories in the window
+//! 12  u32  reserved                              24  u16  process image name length in bytes
+//!                                                26  u16  cause (LockdownCause)
+//!                                                28  u32  id of the locked down parent whose
+//!                                                         lockdown was inherited, 0 if none
+//!                                                32  ...  process image name
+//! ```
+
+use crate::{
+    input::{exact, DecodeError},
+    wire::{read_u16, read_u32, read_u64},
+};
+
+pub const DETECTOR_SETTINGS_SIZE: usize = 16;
+pub const LOCKDOWN_HEADER_SIZE: usize = 32;
+
+/// Window set by `detector on` when none is given.
+pub const DEFAULT_WINDOW_SECONDS: u32 = 10;
+pub const DEFAULT_MAX_OPERATIONS: u32 = 200;
+pub const DEFAULT_MAX_DIRECTORIES: u32 = 10;
+/// Longest window, the counters of a process cover it in ten slots.
+pub const MAX_WINDOW_SECONDS: u32 = 3600;
+/// Directories remembered per process, `max_directories` has to stay below it.
+pub const MAX_TRACKED_DIRECTORIES: usize = 64;
+/// Longest image name kept in a lockdown record.
+pub const MAX_LOCKDOWN_NAME_BYTES: usize = 260 * 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DetectorSettings {
+    pub window_seconds: u32,
+    pub max_operations: u32,
+    pub max_directories: u32,
+}
+
+impl Default for DetectorSettings {
+    /// Disabled, with the default thresholds ready for `detector on`.
+    fn default() -> Self {
+        Self {
+            window_seconds: 0,
+            max_operations: DEFAULT_MAX_OPERATIONS,
+            max_directories: DEFAULT_MAX_DIRECTORIES,
+        }
+    }
+}
+
+impl DetectorSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.window_seconds != 0
+    }
+
+    /// True once a process did more than `max_operations` in the window and, unless
+    /// `max_directories` is 0, spread them over more than `max_directories` directories.
+    pub fn is_exceeded(&self, operations: u32, directories: u32) -> bool {
+        operations > self.max_operations
+            && (self.max_directories == 0 || directories > self.max_directories)
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        if buffer.len() < DETECTOR_SETTINGS_SIZE {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&self.window_seconds.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.max_operations.to_le_bytes());
+        buffer[8..12].copy_from_slice(&self.max_directories.to_le_bytes());
+        buffer[12..16].copy_from_slice(&0u32.to_le_bytes());
+
+        Some(DETECTOR_SETTINGS_SIZE)
+    }
+
+    /// Rejects windows above `MAX_WINDOW_SECONDS`, a threshold of 0 operations and directory
+    /// thresholds the driver cannot count up to.
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = exact(input, DETECTOR_SETTINGS_SIZE)?;
+        let settings = Self {
+            window_seconds: read_u32(bytes, 0),
+            max_operations: read_u32(bytes, 4),
+            max_directories: read_u32(bytes, 8),
+        };
+
+        if settings.window_seconds > MAX_WINDOW_SECONDS
+            || settings.max_operations == 0
+            || settings.max_directories as usize >= MAX_TRACKED_DIRECTORIES
+        {
+            return Err(DecodeError::InvalidValue);
+        }
+        Ok(settings)
+    }
+}
+
+/// Why a process was locked down.
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockdownCause {
+    MassDelete = 0,
+    Canary = 1,
+    /// A `deny-and-demote` rule denied one of its deletes.
+    Demoted = 2,
+}
+
+impl LockdownCause {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(Self::MassDelete),
+            1 => Some(Self::Canary),
+            2 => Some(Self::Demoted),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MassDelete => "mass-delete",
+            Self::Canary => "canary",
+            Self::Demoted => "demoted",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockdownRecord<'a> {
+    pub process_id: u32,
+    pub cause: LockdownCause,
+    pub parent_id: u32,
+    pub time: u64,
+    pub operations: u32,
+    pub directories: u32,
+    /// UTF-16LE bytes of the NT image path.
+    pub process: &'a [u8],
+}
+
+impl<'a> LockdownRecord<'a> {
+    pub fn encoded_len(&self) -> usize {
+        LOCKDOWN_HEADER_SIZE + self.process.len()
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        if buffer.len() < len || self.process.len() > MAX_LOCKDOWN_NAME_BYTES {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.process_id.to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.time.to_le_bytes());
+        buffer[16..20].copy_from_slice(&self.operations.to_le_bytes());
+        buffer[20..24].copy_from_slice(&self.directories.to_le_bytes());
+        buffer[24..26].copy_from_slice(&(self.process.len() as u16).to_le_bytes());
+        buffer[26..28].copy_from_slice(&(self.cause as u16).to_le_bytes());
+        buffer[28..32].copy_from_slice(&self.parent_id.to_le_bytes());
+        buffer[LOCKDOWN_HEADER_SIZE..len].copy_from_slice(self.process);
+
+        Some(len)
+    }
+
+    /// Parses one record from the beginning of `buffer`, returning it together with the number
+    /// of bytes consumed.
+    pub fn decode(buffer: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < LOCKDOWN_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let len = read_u32(buffer, 0) as usize;
+        let process_len = read_u16(buffer, 24) as usize;
+        if process_len > MAX_LOCKDOWN_NAME_BYTES {
+            return Err(DecodeError::TooLong);
+        }
+        if !process_len.is_multiple_of(2) {
+            return Err(DecodeError::OddLength);
+        }
+        if len < LOCKDOWN_HEADER_SIZE + process_len {
+            return Err(DecodeError::InvalidValue);
+        }
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let record = Self {
+            process_id: read_u32(buffer, 4),
+            cause: LockdownCause::from_u16(read_u16(buffer, 26))
+                .ok_or(DecodeError::InvalidValue)?,
+            parent_id: read_u32(buffer, 28),
+            time: read_u64(buffer, 8),
+            operations: read_u32(buffer, 16),
+            directories: read_u32(buffer, 20),
+            process: &buffer[LOCKDOWN_HEADER_SIZE..LOCKDOWN_HEADER_SIZE + process_len],
+        };
+        Ok((record, len))
+    }
+}
@@ -0,0 +1,260 @@
+//! Wire format of `IOCTL_DELPROTECT_EVALUATE`, which asks the driver how it would decide an
+//! operation without anything being done: the rules, the options deciding which operations
+//! they look at and the canaries (looked up, not tripped) decide it like the callbacks would.
+//! Lockdowns of the detector are per process and not asked for. Strings are UTF-16LE without
+//! terminating nulls, paths are NT paths like the driver sees them
+//! (`\Device\HarddiskVolume3\...`). The reply is followed by the trace of every rule (see
+//! `crate::explain`), as many records as the output holds.
+//!
+//! The ancestors are the image paths of the processes which created the process, nearest
+//! first, each followed by a null unit. `inherit` rules look at them like at the lineage the
+//! driver tracks for a real process.
+//!
+//! ```text
+//! request                                   reply
+//! 0   u16  operation (EvaluateOperation)    0   u16  decision (EvaluateDecision)
+//! 2   u16  process image path length        2   u16  number of rule traces following
+//! 4   u16  SID length, 0 if none            4   u32  id of the deciding rule or canary
+//! 6   u16  target path length               8   u64  time the rules were evaluated at
+//! 8   u64  time (FILETIME, UTC), 0 for now
+//! 16  u16  ancestors length, 0 if none
+//! 18  u16  reserved
+//! 20  ...  process image path, SID, target path, ancestors
+//! ```
+//!
+//! The rules do not look at users: the SID is accepted and ignored, a request decides the same
+//! with or without one.
+
+use crate::{
+    input::DecodeError,
+    wire::{read_u16, read_u32, read_u64},
+};
+
+pub const EVALUATE_HEADER_SIZE: usize = 20;
+/// Ancestors a request may name.
+pub const MAX_EVALUATE_ANCESTORS: usize = 16;
+pub const EVALUATE_REPLY_SIZE: usize = 16;
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvaluateOperation {
+    /// A delete on close open or a disposition change, the rules decide both the same way.
+    Delete = 1,
+    /// An open asking for DELETE access, only decided with `OPTION_DENY_DELETE_ACCESS`.
+    OpenForDelete = 2,
+    /// A size change cutting the file, only decided with `OPTION_GUARD_TRUNCATION`.
+    Truncate = 3,
+    /// A write, writable section or open for write access, decided by the immutable rules
+    /// covering the target.
+    Write = 4,
+    /// A change weakening the security descriptor or the attributes, only decided with
+    /// `OPTION_GUARD_SECURITY`.
+    SetSecurity = 5,
+    /// Only a canary denies it.
+    Rename = 6,
+    /// An open overwriting the file: decided like a write, then only a canary denies it.
+    Overwrite = 7,
+}
+
+impl EvaluateOperation {
+    pub const ALL: [Self; 7] = [
+        Self::Delete,
+        Self::OpenForDelete,
+        Self::Truncate,
+        Self::Write,
+        Self::SetSecurity,
+        Self::Rename,
+        Self::Overwrite,
+    ];
+
+    pub fn from_u16(value: u16) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|operation| **operation as u16 == value)
+            .copied()
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|operation| operation.as_str() == name)
+            .copied()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Delete => "delete",
+            Self::OpenForDelete => "open-for-delete",
+            Self::Truncate => "truncate",
+            Self::Write => "write",
+            Self::SetSecurity => "set-security",
+            Self::Rename => "rename",
+            Self::Overwrite => "overwrite",
+        }
+    }
+}
+
+/// How the rules decide the operation.
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvaluateDecision {
+    Allow = 0,
+    Deny = 1,
+    /// The delete goes on after the file was copied into the vault.
+    Preserve = 2,
+    /// The target is a canary: denied, and the process locked down. The reply carries the id
+    /// of the canary instead of a rule.
+    Canary = 3,
+}
+
+impl EvaluateDecision {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(Self::Allow),
+            1 => Some(Self::Deny),
+            2 => Some(Self::Preserve),
+            3 => Some(Self::Canary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvaluateRequest<'a> {
+    pub operation: EvaluateOperation,
+    pub time: u64,
+    pub process: &'a [u8],
+    /// String SID of the user, e.g. `S-1-5-21-...-1001`. Ignored, the rules do not look at users.
+    pub sid: &'a [u8],
+    pub target: &'a [u8],
+    /// UTF-16LE image paths, each terminated by a null unit.
+    pub ancestors: &'a [u8],
+}
+
+impl<'a> EvaluateRequest<'a> {
+    /// The ancestors, nearest first, as UTF-16LE bytes without their null unit. At most
+    /// `MAX_EVALUATE_ANCESTORS` are returned.
+    pub fn ancestors(&self) -> impl Iterator<Item = &'a [u8]> {
+        let ancestors = self.ancestors;
+        let mut start = 0;
+        (0..ancestors.len() / 2)
+            .filter(move |unit| ancestors[unit * 2..unit * 2 + 2] == [0, 0])
+            .map(move |unit| {
+                let name = &ancestors[start..unit * 2];
+                start = unit * 2 + 2;
+                name
+            })
+            .take(MAX_EVALUATE_ANCESTORS)
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        EVALUATE_HEADER_SIZE
+            + self.process.len()
+            + self.sid.len()
+            + self.target.len()
+            + self.ancestors.len()
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        let fields = [self.process, self.sid, self.target, self.ancestors];
+        if buffer.len() < len || fields.iter().any(|f| f.len() > u16::MAX as usize) {
+            return None;
+        }
+
+        buffer[0..2].copy_from_slice(&(self.operation as u16).to_le_bytes());
+        buffer[2..4].copy_from_slice(&(self.process.len() as u16).to_le_bytes());
+        buffer[4..6].copy_from_slice(&(self.sid.len() as u16).to_le_bytes());
+        buffer[6..8].copy_from_slice(&(self.target.len() as u16).to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.time.to_le_bytes());
+        buffer[16..18].copy_from_slice(&(self.ancestors.len() as u16).to_le_bytes());
+        buffer[18..20].copy_from_slice(&0u16.to_le_bytes());
+
+        let mut offset = EVALUATE_HEADER_SIZE;
+        for field in fields {
+            buffer[offset..offset + field.len()].copy_from_slice(field);
+            offset += field.len();
+        }
+
+        Some(len)
+    }
+
+    pub fn decode(buffer: &'a [u8]) -> Result<Self, DecodeError> {
+        if buffer.len() < EVALUATE_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let operation =
+            EvaluateOperation::from_u16(read_u16(buffer, 0)).ok_or(DecodeError::InvalidValue)?;
+        let lengths = [
+            read_u16(buffer, 2) as usize,
+            read_u16(buffer, 4) as usize,
+            read_u16(buffer, 6) as usize,
+            read_u16(buffer, 16) as usize,
+        ];
+        if lengths[0] == 0 {
+            return Err(DecodeError::Empty);
+        }
+        if lengths.iter().any(|len| !len.is_multiple_of(2)) {
+            return Err(DecodeError::OddLength);
+        }
+        let len = EVALUATE_HEADER_SIZE + lengths.iter().sum::<usize>();
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let process_end = EVALUATE_HEADER_SIZE + lengths[0];
+        let sid_end = process_end + lengths[1];
+        let target_end = sid_end + lengths[2];
+        let ancestors = &buffer[target_end..len];
+        if ancestors.len() >= 2 && ancestors[ancestors.len() - 2..] != [0, 0] {
+            return Err(DecodeError::InvalidValue);
+        }
+        Ok(Self {
+            operation,
+            time: read_u64(buffer, 8),
+            process: &buffer[EVALUATE_HEADER_SIZE..process_end],
+            sid: &buffer[process_end..sid_end],
+            target: &buffer[sid_end..target_end],
+            ancestors,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvaluateReply {
+    pub decision: EvaluateDecision,
+    pub trace_count: u16,
+    /// Id of the rule which decided, of the canary for `EvaluateDecision::Canary`, 0 if none.
+    pub rule_id: u32,
+    pub time: u64,
+}
+
+impl EvaluateReply {
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        if buffer.len() < EVALUATE_REPLY_SIZE {
+            return None;
+        }
+
+        buffer[0..2].copy_from_slice(&(self.decision as u16).to_le_bytes());
+        buffer[2..4].copy_from_slice(&self.trace_count.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.rule_id.to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.time.to_le_bytes());
+
+        Some(EVALUATE_REPLY_SIZE)
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        if buffer.len() < EVALUATE_REPLY_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        Ok(Self {
+            decision: EvaluateDecision::from_u16(read_u16(buffer, 0))
+                .ok_or(DecodeError::InvalidValue)?,
+            trace_count: read_u16(buffer, 2),
+            rule_id: read_u32(buffer, 4),
+            time: read_u64(buffer, 8),
+        })
+    }
+}
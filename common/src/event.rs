@@ -0,0 +1,217 @@
+//! Audit events returned by `IOCTL_DELPROTECT_GET_EVENTS`. Every record starts with its total
+//! size so readers can skip kinds they do not know. Strings are UTF-16LE, the decision trace
+//! of a denied operation follows them (see `crate::explain`).
+//!
+//! ```text
+//! 0   u32  record size in bytes
+//! 4   u16  kind (EventKind)
+//! 6   u16  severity (Severity)
+//! 8   u64  time (FILETIME, UTC)
+//! 16  u32  process id
+//! 20  u32  rule id, 0 if no rule is involved
+//! 24  i32  status returned to the caller
+//! 28  u16  process image name length in bytes
+//! 30  u16  target length in bytes (file path or volume name)
+//! 32  u16  detail length in bytes
+//! 34  u16  decision trace length in bytes, 0 if there is none
+//! 36  u16  response to the process (Response), 0 if none
+//! 38  u16  reserved
+//! 40  ...  process image name, target, detail, decision trace
+//! ```
+
+use crate::{
+    input::DecodeError,
+    rule::Response,
+    wire::{read_u16, read_u32, read_u64},
+};
+
+pub const EVENT_HEADER_SIZE: usize = 40;
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// Someone asked the filter manager to detach an instance (`fltmc detach`).
+    DetachAttempt = 1,
+    /// `fltmc unload` while the driver is locked and no authorized handle allowed it.
+    UnloadAttempt = 2,
+    /// A handle answered its challenge with a wrong response.
+    AuthFailure = 3,
+    /// A delete was blocked by a rule, the decision trace tells which one and why. The detail
+    /// is the lineage of the process if it has one, the response what the rule does to it.
+    DeleteDenied = 4,
+    /// A delete went on after the file was copied into the vault, the detail is the name of
+    /// the copy.
+    DeletePreserved = 5,
+    /// The detector locked a process down, the detail tells how many operations in how many
+    /// directories it saw.
+    MassDelete = 6,
+    /// A process touched a canary and was locked down, the detail names the operation and the
+    /// canary.
+    CanaryTripped = 7,
+    /// A write, a writable section or an open for write access was blocked by an immutable
+    /// rule, the detail names the operation and the lineage of the process.
+    WriteDenied = 8,
+    /// A security descriptor or attribute change weakening the protection of a file was
+    /// blocked by a rule, the detail names what it would have changed and the lineage of the
+    /// process.
+    SecurityChangeDenied = 9,
+    /// A lockdown found no room, at most `MAX_LOCKDOWNS` of the engine are kept: the process
+    /// named was not locked down, or its mass-delete lockdown was released to make room for a
+    /// canary or demotion one. The detail tells which.
+    LockdownDropped = 10,
+}
+
+impl EventKind {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            1 => Some(Self::DetachAttempt),
+            2 => Some(Self::UnloadAttempt),
+            3 => Some(Self::AuthFailure),
+            4 => Some(Self::DeleteDenied),
+            5 => Some(Self::DeletePreserved),
+            6 => Some(Self::MassDelete),
+            7 => Some(Self::CanaryTripped),
+            8 => Some(Self::WriteDenied),
+            9 => Some(Self::SecurityChangeDenied),
+            10 => Some(Self::LockdownDropped),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DetachAttempt => "detach-attempt",
+            Self::UnloadAttempt => "unload-attempt",
+            Self::AuthFailure => "auth-failure",
+            Self::DeleteDenied => "delete-denied",
+            Self::DeletePreserved => "delete-preserved",
+            Self::MassDelete => "mass-delete",
+            Self::CanaryTripped => "canary-tripped",
+            Self::WriteDenied => "write-denied",
+            Self::SecurityChangeDenied => "security-change-denied",
+            Self::LockdownDropped => "lockdown-dropped",
+        }
+    }
+}
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info = 0,
+    Warning = 1,
+    High = 2,
+}
+
+impl Severity {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(Self::Info),
+            1 => Some(Self::Warning),
+            2 => Some(Self::High),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::High => "high",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventRecord<'a> {
+    /// Raw kind, kept as a number so newer drivers do not break older clients.
+    pub kind: u16,
+    pub severity: Severity,
+    pub time: u64,
+    pub process_id: u32,
+    pub rule_id: u32,
+    pub status: i32,
+    pub response: Response,
+    pub process: &'a [u8],
+    pub target: &'a [u8],
+    pub detail: &'a [u8],
+    /// Encoded `RuleTrace` records, empty for events without a decision.
+    pub trace: &'a [u8],
+}
+
+impl<'a> EventRecord<'a> {
+    pub fn encoded_len(&self) -> usize {
+        EVENT_HEADER_SIZE
+            + self.process.len()
+            + self.target.len()
+            + self.detail.len()
+            + self.trace.len()
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        let strings = [self.process, self.target, self.detail, self.trace];
+        if buffer.len() < len || strings.iter().any(|s| s.len() > u16::MAX as usize) {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+        buffer[4..6].copy_from_slice(&self.kind.to_le_bytes());
+        buffer[6..8].copy_from_slice(&(self.severity as u16).to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.time.to_le_bytes());
+        buffer[16..20].copy_from_slice(&self.process_id.to_le_bytes());
+        buffer[20..24].copy_from_slice(&self.rule_id.to_le_bytes());
+        buffer[24..28].copy_from_slice(&self.status.to_le_bytes());
+        buffer[28..30].copy_from_slice(&(self.process.len() as u16).to_le_bytes());
+        buffer[30..32].copy_from_slice(&(self.target.len() as u16).to_le_bytes());
+        buffer[32..34].copy_from_slice(&(self.detail.len() as u16).to_le_bytes());
+        buffer[34..36].copy_from_slice(&(self.trace.len() as u16).to_le_bytes());
+        buffer[36..38].copy_from_slice(&(self.response as u16).to_le_bytes());
+        buffer[38..40].copy_from_slice(&0u16.to_le_bytes());
+
+        let mut offset = EVENT_HEADER_SIZE;
+        for s in strings {
+            buffer[offset..offset + s.len()].copy_from_slice(s);
+            offset += s.len();
+        }
+
+        Some(len)
+    }
+
+    pub fn decode(buffer: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < EVENT_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let len = read_u32(buffer, 0) as usize;
+        let process_len = read_u16(buffer, 28) as usize;
+        let target_len = read_u16(buffer, 30) as usize;
+        let detail_len = read_u16(buffer, 32) as usize;
+        let trace_len = read_u16(buffer, 34) as usize;
+        let strings_end = EVENT_HEADER_SIZE + process_len + target_len + detail_len;
+        let trace_end = strings_end + trace_len;
+        if len < trace_end {
+            return Err(DecodeError::InvalidValue);
+        }
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let process_end = EVENT_HEADER_SIZE + process_len;
+        let target_end = process_end + target_len;
+        let record = Self {
+            kind: read_u16(buffer, 4),
+            severity: Severity::from_u16(read_u16(buffer, 6)).ok_or(DecodeError::InvalidValue)?,
+            time: read_u64(buffer, 8),
+            process_id: read_u32(buffer, 16),
+            rule_id: read_u32(buffer, 20),
+            status: read_u32(buffer, 24) as i32,
+            response: Response::from_u16(read_u16(buffer, 36)).ok_or(DecodeError::InvalidValue)?,
+            process: &buffer[EVENT_HEADER_SIZE..process_end],
+            target: &buffer[process_end..target_end],
+            detail: &buffer[target_end..strings_end],
+            trace: &buffer[strings_end..trace_end],
+        };
+
+        Ok((record, len))
+    }
+}
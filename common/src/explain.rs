@@ -0,0 +1,189 @@
+//! Decision traces: how each rule was evaluated for one operation. They follow the reply of
+//! `IOCTL_DELPROTECT_EVALUATE` and the strings of a `delete-denied` event, one record per rule
+//! in evaluation order. The rule name is the process name of the rule, UTF-16LE.
+//!
+//! Rules are evaluated in the order they were added. The first rule whose predicates all hold
+//! decides the operation; later ones which hold as well are shadowed by it.
+//!
+//! An `inherit` rule (`crate::rule::RULE_FLAG_INHERIT`) which does not name the process may
+//! name one of the processes which created it, nearest first; the generation tells which one
+//! satisfied the process predicate.
+//!
+//! ```text
+//! 0   u32  rule id
+//! 4   u16  step, position of the rule in the evaluation order starting at 1
+//! 6   u8   predicates which held (PREDICATE_*)
+//! 7   u8   outcome (RuleOutcome)
+//! 8   u16  rule name length in bytes
+//! 10  u16  generation of the process the rule named: 0 itself, 1 its creator...
+//! 12  ...  rule name
+//! ```
+
+use crate::{
+    input::DecodeError,
+    schedule::TimeWindow,
+    wire::{read_u16, read_u32},
+};
+
+pub const RULE_TRACE_HEADER_SIZE: usize = 12;
+
+/// The NT image path of the process, or of an ancestor for an `inherit` rule, contains the
+/// rule's process name.
+pub const PREDICATE_PROCESS: u8 = 0x1;
+/// `not_before` is not set or has passed.
+pub const PREDICATE_NOT_BEFORE: u8 = 0x2;
+/// `not_after` is not set or has not passed yet.
+pub const PREDICATE_NOT_AFTER: u8 = 0x4;
+/// The weekly schedule covers the time, always true without a schedule.
+pub const PREDICATE_SCHEDULE: u8 = 0x8;
+/// Every predicate of a rule, the rule blocks the operation.
+pub const PREDICATE_ALL: u8 =
+    PREDICATE_PROCESS | PREDICATE_NOT_BEFORE | PREDICATE_NOT_AFTER | PREDICATE_SCHEDULE;
+
+/// Predicates in the order they are shown, with their names.
+pub const PREDICATES: [(u8, &str); 4] = [
+    (PREDICATE_PROCESS, "process"),
+    (PREDICATE_NOT_BEFORE, "not-before"),
+    (PREDICATE_NOT_AFTER, "not-after"),
+    (PREDICATE_SCHEDULE, "schedule"),
+];
+
+/// The time predicates of `window` which hold at `now`. They all hold exactly when
+/// `window.evaluate(now)` is `RuleState::Active`.
+pub fn window_predicates(window: &TimeWindow, now: u64) -> u8 {
+    let mut predicates = 0;
+    if window.not_before == 0 || now >= window.not_before {
+        predicates |= PREDICATE_NOT_BEFORE;
+    }
+    if window.not_after == 0 || now < window.not_after {
+        predicates |= PREDICATE_NOT_AFTER;
+    }
+    if window.schedule.contains(now) {
+        predicates |= PREDICATE_SCHEDULE;
+    }
+    predicates
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleOutcome {
+    /// At least one predicate did not hold.
+    NoMatch = 0,
+    /// The first rule whose predicates all held, its effect was applied.
+    Decided = 1,
+    /// Every predicate held but an earlier rule decided.
+    Shadowed = 2,
+}
+
+impl RuleOutcome {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::NoMatch),
+            1 => Some(Self::Decided),
+            2 => Some(Self::Shadowed),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoMatch => "no-match",
+            Self::Decided => "decided",
+            Self::Shadowed => "shadowed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuleTrace<'a> {
+    pub rule_id: u32,
+    pub step: u16,
+    pub predicates: u8,
+    pub outcome: RuleOutcome,
+    /// Ancestor whose image the rule named, 0 if it named the process itself.
+    pub generation: u16,
+    /// UTF-16LE bytes of the rule's process name.
+    pub name: &'a [u8],
+}
+
+impl<'a> RuleTrace<'a> {
+    pub fn encoded_len(&self) -> usize {
+        RULE_TRACE_HEADER_SIZE + self.name.len()
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        if buffer.len() < len || self.name.len() > u16::MAX as usize {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&self.rule_id.to_le_bytes());
+        buffer[4..6].copy_from_slice(&self.step.to_le_bytes());
+        buffer[6] = self.predicates;
+        buffer[7] = self.outcome as u8;
+        buffer[8..10].copy_from_slice(&(self.name.len() as u16).to_le_bytes());
+        buffer[10..12].copy_from_slice(&self.generation.to_le_bytes());
+        buffer[RULE_TRACE_HEADER_SIZE..len].copy_from_slice(self.name);
+
+        Some(len)
+    }
+
+    pub fn decode(buffer: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < RULE_TRACE_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let name_len = read_u16(buffer, 8) as usize;
+        if !name_len.is_multiple_of(2) {
+            return Err(DecodeError::OddLength);
+        }
+        let len = RULE_TRACE_HEADER_SIZE + name_len;
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let record = Self {
+            rule_id: read_u32(buffer, 0),
+            step: read_u16(buffer, 4),
+            predicates: buffer[6],
+            outcome: RuleOutcome::from_u8(buffer[7]).ok_or(DecodeError::InvalidValue)?,
+            generation: read_u16(buffer, 10),
+            name: &buffer[RULE_TRACE_HEADER_SIZE..len],
+        };
+
+        Ok((record, len))
+    }
+}
+
+/// The records of a trace. Iteration stops after the first malformed record.
+pub struct RuleTraces<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> RuleTraces<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for RuleTraces<'a> {
+    type Item = Result<RuleTrace<'a>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.buffer.len() {
+            return None;
+        }
+
+        match RuleTrace::decode(&self.buffer[self.offset..]) {
+            Ok((record, len)) => {
+                self.offset += len;
+                Some(Ok(record))
+            },
+            Err(e) => {
+                self.offset = self.buffer.len();
+                Some(Err(e))
+            },
+        }
+    }
+}
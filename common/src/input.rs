@@ -0,0 +1,139 @@
+//! Safe decoding of IOCTL buffers. The driver turns the system buffer into a slice once, after
+//! checking it against `MAX_INPUT_SIZE` and for NULL, and every handler decodes that slice with
+//! the functions of this module or the `decode` of a wire format. Errors are typed so the
+//! driver can map them to an NTSTATUS in one place.
+
+use alloc::string::String;
+
+use crate::{rule::MAX_PROCESS_NAME_BYTES, wire::read_u32};
+
+/// Largest input any IOCTL accepts. Bigger requests are refused before they are looked at.
+pub const MAX_INPUT_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A non-zero length was given without a buffer.
+    NullBuffer,
+    /// The request needs input but none was given.
+    Empty,
+    /// The input ends before the structure it has to hold.
+    Truncated,
+    /// The input is longer than `MAX_INPUT_SIZE` or than the field it holds allows.
+    TooLong,
+    /// UTF-16 input with an odd number of bytes.
+    OddLength,
+    /// Text which is not valid UTF-8 or UTF-16.
+    InvalidEncoding,
+    /// A field holds a value outside of its range.
+    InvalidValue,
+    /// The output buffer cannot hold the smallest valid answer.
+    OutputTooSmall,
+}
+
+impl DecodeError {
+    /// True if the caller should retry with a bigger output buffer
+    /// (`STATUS_BUFFER_TOO_SMALL`), false for malformed input (`STATUS_INVALID_PARAMETER`).
+    pub fn is_buffer_too_small(&self) -> bool {
+        matches!(self, Self::OutputTooSmall)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NullBuffer => "null buffer",
+            Self::Empty => "empty input",
+            Self::Truncated => "truncated input",
+            Self::TooLong => "input too long",
+            Self::OddLength => "odd UTF-16 length",
+            Self::InvalidEncoding => "invalid encoding",
+            Self::InvalidValue => "invalid value",
+            Self::OutputTooSmall => "output buffer too small",
+        }
+    }
+}
+
+/// Validates the raw length of an input buffer before a slice is built from it.
+pub fn check_input(is_null: bool, len: usize) -> Result<(), DecodeError> {
+    if len > MAX_INPUT_SIZE {
+        return Err(DecodeError::TooLong);
+    }
+    if is_null && len != 0 {
+        return Err(DecodeError::NullBuffer);
+    }
+    Ok(())
+}
+
+/// Validates the raw length of an output buffer against the smallest answer of a request.
+pub fn check_output(is_null: bool, len: usize, min_len: usize) -> Result<(), DecodeError> {
+    if is_null || len < min_len {
+        return Err(DecodeError::OutputTooSmall);
+    }
+    Ok(())
+}
+
+/// Decodes an executable name sent as UTF-8, with or without terminating nulls.
+pub fn exe_name_utf8(input: &[u8]) -> Result<&str, DecodeError> {
+    let name = trim_nulls(input, 1);
+    check_name_len(name.len())?;
+    core::str::from_utf8(name).map_err(|_| DecodeError::InvalidEncoding)
+}
+
+/// Decodes an executable name sent as UTF-16LE, with or without terminating nulls.
+pub fn exe_name_utf16(input: &[u8]) -> Result<String, DecodeError> {
+    if !input.len().is_multiple_of(2) {
+        return Err(DecodeError::OddLength);
+    }
+    let name = trim_nulls(input, 2);
+    check_name_len(name.len())?;
+
+    let units = name
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|_| DecodeError::InvalidEncoding)
+}
+
+/// Decodes an optional u32 of flags. No input means no flags.
+pub fn flags(input: &[u8]) -> Result<u32, DecodeError> {
+    match input.len() {
+        0 => Ok(0),
+        4 => Ok(read_u32(input, 0)),
+        1..=3 => Err(DecodeError::Truncated),
+        _ => Err(DecodeError::TooLong),
+    }
+}
+
+/// Checks that the input is exactly `len` bytes long, e.g. a fixed size response.
+pub fn exact(input: &[u8], len: usize) -> Result<&[u8], DecodeError> {
+    match input.len() {
+        0 => Err(DecodeError::Empty),
+        n if n < len => Err(DecodeError::Truncated),
+        n if n > len => Err(DecodeError::TooLong),
+        _ => Ok(input),
+    }
+}
+
+/// Checks that there is some input, for variable length payloads decoded elsewhere.
+pub fn non_empty(input: &[u8]) -> Result<&[u8], DecodeError> {
+    if input.is_empty() {
+        return Err(DecodeError::Empty);
+    }
+    Ok(input)
+}
+
+/// Strips terminating nulls, `unit` bytes at a time.
+fn trim_nulls(input: &[u8], unit: usize) -> &[u8] {
+    let mut end = input.len();
+    while end >= unit && input[end - unit..end].iter().all(|b| *b == 0) {
+        end -= unit;
+    }
+    &input[..end]
+}
+
+fn check_name_len(len: usize) -> Result<(), DecodeError> {
+    match len {
+        0 => Err(DecodeError::Empty),
+        n if n > MAX_PROCESS_NAME_BYTES => Err(DecodeError::TooLong),
+        _ => Ok(()),
+    }
+}
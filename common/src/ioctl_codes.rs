@@ -1,13 +1,205 @@
-use windows_sys::Win32::System::Ioctl::{FILE_ANY_ACCESS, METHOD_BUFFERED, METHOD_NEITHER};
-
-use kernel_macros::CTL_CODE;
-
-pub const IOCTL_DELPROTECT_ADD_EXE_UTF8: u32 =
-    CTL_CODE!(0x8000, 0x800, METHOD_BUFFERED, FILE_ANY_ACCESS);
-pub const IOCTL_DELPROTECT_ADD_EXE_UTF16: u32 =
-    CTL_CODE!(0x8000, 0x801, METHOD_BUFFERED, FILE_ANY_ACCESS);
-pub const IOCTL_DELPROTECT_REMOVE_EXE_UTF8: u32 =
-    CTL_CODE!(0x8000, 0x8012, METHOD_BUFFERED, FILE_ANY_ACCESS);
-pub const IOCTL_DELPROTECT_REMOVE_EXE_UTF16: u32 =
-    CTL_CODE!(0x8000, 0x8012, METHOD_BUFFERED, FILE_ANY_ACCESS);
-pub const IOCTL_DELPROTECT_CLEAR: u32 = CTL_CODE!(0x8000, 0x804, METHOD_NEITHER, FILE_ANY_ACCESS);
+//! Control codes of `\\.\DelProtect`. Codes which only read state use `FILE_READ_ACCESS`, codes
+//! which change it use `FILE_WRITE_ACCESS`, so the I/O manager checks the access the handle was
+//! opened with before the driver sees the request.
+
+// values of winioctl.h, kept here so the crate builds without Windows headers or bindings
+const FILE_DEVICE_DELPROTECT: u32 = 0x8000;
+const METHOD_BUFFERED: u32 = 0;
+const METHOD_NEITHER: u32 = 3;
+pub const FILE_READ_ACCESS: u32 = 0x1;
+pub const FILE_WRITE_ACCESS: u32 = 0x2;
+
+/// `CTL_CODE` of winioctl.h.
+pub const fn ctl_code(device_type: u32, function: u32, method: u32, access: u32) -> u32 {
+    (device_type << 16) | (access << 14) | (function << 2) | method
+}
+
+pub const IOCTL_DELPROTECT_ADD_EXE_UTF8: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x800,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_ADD_EXE_UTF16: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x801,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_REMOVE_EXE_UTF8: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x802,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_REMOVE_EXE_UTF16: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x803,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_CLEAR: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x804,
+    METHOD_NEITHER,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_ADD_RULE: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x805,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+/// Read-only unless `LIST_FLAG_PURGE_EXPIRED` is passed, which the driver checks itself.
+pub const IOCTL_DELPROTECT_LIST_RULES: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x806,
+    METHOD_BUFFERED,
+    FILE_READ_ACCESS,
+);
+pub const IOCTL_DELPROTECT_SET_VOLUME_POLICY: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x807,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_LIST_INSTANCES: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x808,
+    METHOD_BUFFERED,
+    FILE_READ_ACCESS,
+);
+/// Drains the events it returns, so it changes state like the codes which configure.
+pub const IOCTL_DELPROTECT_GET_EVENTS: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x809,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_GET_OPTIONS: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x80A,
+    METHOD_BUFFERED,
+    FILE_READ_ACCESS,
+);
+pub const IOCTL_DELPROTECT_SET_OPTIONS: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x80B,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+/// Replaces the challenge pending for the handle.
+pub const IOCTL_DELPROTECT_GET_CHALLENGE: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x80C,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_AUTHENTICATE: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x80D,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_SET_SECRET: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x80E,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_ALLOW_UNLOAD: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x80F,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+/// Answers how the rules would decide an operation, without side effects.
+pub const IOCTL_DELPROTECT_EVALUATE: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x810,
+    METHOD_BUFFERED,
+    FILE_READ_ACCESS,
+);
+/// Read-only unless `STATS_FLAG_RESET` is passed, which the driver checks itself.
+pub const IOCTL_DELPROTECT_GET_STATS: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x811,
+    METHOD_BUFFERED,
+    FILE_READ_ACCESS,
+);
+pub const IOCTL_DELPROTECT_SET_LOG_LEVEL: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x812,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_GET_LOG_LEVEL: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x813,
+    METHOD_BUFFERED,
+    FILE_READ_ACCESS,
+);
+pub const IOCTL_DELPROTECT_SET_DETECTOR: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x814,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_GET_DETECTOR: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x815,
+    METHOD_BUFFERED,
+    FILE_READ_ACCESS,
+);
+pub const IOCTL_DELPROTECT_LIST_LOCKDOWNS: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x816,
+    METHOD_BUFFERED,
+    FILE_READ_ACCESS,
+);
+/// Input is the u32 process id, 0 releases every locked down process.
+pub const IOCTL_DELPROTECT_RELEASE_LOCKDOWN: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x817,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+/// Input is a `common::canary::CanaryRecord`, output the u32 id of the new canary.
+pub const IOCTL_DELPROTECT_ADD_CANARY: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x818,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+pub const IOCTL_DELPROTECT_LIST_CANARIES: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x819,
+    METHOD_BUFFERED,
+    FILE_READ_ACCESS,
+);
+/// Input is the u32 id of the canary.
+pub const IOCTL_DELPROTECT_REMOVE_CANARY: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x81A,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+
+/// Lets the calling process change the vaults until the handle is closed, see
+/// `common::vault`.
+pub const IOCTL_DELPROTECT_OPEN_VAULT: u32 = ctl_code(
+    FILE_DEVICE_DELPROTECT,
+    0x81B,
+    METHOD_BUFFERED,
+    FILE_WRITE_ACCESS,
+);
+
+/// The `FILE_*_ACCESS` bits encoded in a control code.
+pub const fn required_access(code: u32) -> u32 {
+    (code >> 14) & 0x3
+}
+
+/// True for codes which change the driver state and need an elevated caller.
+pub const fn is_mutating(code: u32) -> bool {
+    required_access(code) & FILE_WRITE_ACCESS != 0
+}
@@ -1,2 +1,24 @@
 #![no_std]
-pub mod ioctl_codes;
\ No newline at end of file
+extern crate alloc;
+
+pub mod auth;
+pub mod canary;
+pub mod detector;
+pub mod evaluate;
+pub mod event;
+pub mod explain;
+pub mod input;
+pub mod ioctl_codes;
+pub mod logging;
+pub mod options;
+pub mod protection;
+pub mod rule;
+pub mod rule_args;
+pub mod schedule;
+pub mod stats;
+pub mod status;
+pub mod truncation;
+pub mod usn;
+pub mod vault;
+pub mod volume;
+pub mod wire;
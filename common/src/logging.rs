@@ -0,0 +1,66 @@
+//! Log level of the driver. `IOCTL_DELPROTECT_SET_LOG_LEVEL` takes it as a u32,
+//! `IOCTL_DELPROTECT_GET_LOG_LEVEL` returns it the same way, and the driver stores it in the
+//! `LogLevel` DWORD of its `Parameters` key. The values are those of `log::LevelFilter`.
+//!
+//! Messages carry one of the targets below, so a debugger filter can tell them apart.
+
+use crate::{
+    input::{exact, DecodeError},
+    wire::read_u32,
+};
+
+/// Delete decisions and the rules.
+pub const TARGET_POLICY: &str = "policy";
+/// Control device requests, including authorization.
+pub const TARGET_IOCTL: &str = "ioctl";
+/// Driver load and unload, instances and detach.
+pub const TARGET_LIFECYCLE: &str = "lifecycle";
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    /// What the driver logged before the level could be changed.
+    #[default]
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 6] = [
+        Self::Off,
+        Self::Error,
+        Self::Warn,
+        Self::Info,
+        Self::Debug,
+        Self::Trace,
+    ];
+
+    pub fn from_u32(value: u32) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|level| level.as_str() == name)
+    }
+
+    /// The u32 input of `IOCTL_DELPROTECT_SET_LOG_LEVEL`.
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = exact(input, 4)?;
+        Self::from_u32(read_u32(bytes, 0)).ok_or(DecodeError::InvalidValue)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Global driver options, changed with `IOCTL_DELPROTECT_SET_OPTIONS` and persisted in the
+//! `Options` REG_DWORD of the service `Parameters` key. A cleared bit is always the behavior
+//! the driver had before the option existed.
+
+use crate::{input::DecodeError, wire::read_u32};
+
+/// Refuse `fltmc detach` (and any other manual detach) of DelProtect instances.
+pub const OPTION_DENY_MANUAL_DETACH: u32 = 0x1;
+
+/// Decide opens asking for DELETE access like deletes, so a process a rule blocks never gets a
+/// handle it could delete or rename the file with. Off, such a process is only stopped when it
+/// sets the disposition, like one asking for MAXIMUM_ALLOWED.
+pub const OPTION_DENY_DELETE_ACCESS: u32 = 0x2;
+
+/// Decide size changes which cut a file, see `crate::truncation`, like deletes. Off, a
+/// process a rule blocks can still empty a file with SetEndOfFile.
+pub const OPTION_GUARD_TRUNCATION: u32 = 0x4;
+
+/// Decide security descriptor changes and the clearing of READONLY or SYSTEM, see
+/// `crate::protection`, like deletes. Off, a process a rule blocks can still take the file
+/// over, it only fails at the delete.
+pub const OPTION_GUARD_SECURITY: u32 = 0x8;
+
+pub const OPTION_NAMES: [(&str, u32); 4] = [
+    ("deny-detach", OPTION_DENY_MANUAL_DETACH),
+    ("deny-delete-access", OPTION_DENY_DELETE_ACCESS),
+    ("guard-truncation", OPTION_GUARD_TRUNCATION),
+    ("guard-security", OPTION_GUARD_SECURITY),
+];
+
+pub const OPTIONS_UPDATE_SIZE: usize = 8;
+
+/// Input of `IOCTL_DELPROTECT_SET_OPTIONS`: only the bits set in `mask` are changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OptionsUpdate {
+    pub mask: u32,
+    pub values: u32,
+}
+
+impl OptionsUpdate {
+    pub fn apply(&self, options: u32) -> u32 {
+        (options & !self.mask) | (self.values & self.mask)
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        if buffer.len() < OPTIONS_UPDATE_SIZE {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&self.mask.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.values.to_le_bytes());
+
+        Some(OPTIONS_UPDATE_SIZE)
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        if buffer.len() < OPTIONS_UPDATE_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        Ok(Self {
+            mask: read_u32(buffer, 0),
+            values: read_u32(buffer, 4),
+        })
+    }
+}
@@ -0,0 +1,43 @@
+//! Which security descriptor and attribute changes weaken the protection of a file before a
+//! delete, see `crate::options::OPTION_GUARD_SECURITY`. `takeown` and `icacls` give a process
+//! the owner and a DACL letting it delete the file, `attrib -r -s` clears what makes the
+//! shell and many tools refuse to.
+
+/// SECURITY_INFORMATION bits of winnt.h which change who may open the file and how:
+/// OWNER_SECURITY_INFORMATION, DACL_SECURITY_INFORMATION and LABEL_SECURITY_INFORMATION.
+/// Group, SACL and the other parts only matter for auditing and policies.
+pub const SECURITY_NAMES: [(u32, &str); 3] = [(0x01, "owner"), (0x04, "dacl"), (0x10, "label")];
+
+/// FILE_ATTRIBUTE_* bits of winnt.h whose clearing makes the file easier to delete.
+pub const ATTRIBUTE_NAMES: [(u32, &str); 2] = [(0x01, "readonly"), (0x04, "system")];
+
+/// The bits of `SECURITY_NAMES` an IRP_MJ_SET_SECURITY with `security_information` changes, 0
+/// if it weakens nothing.
+pub fn weakened_security(security_information: u32) -> u32 {
+    SECURITY_NAMES
+        .iter()
+        .fold(0, |bits, (bit, _)| bits | (security_information & bit))
+}
+
+/// The bits of `ATTRIBUTE_NAMES` a FileBasicInformation setting `new_attributes` clears on a
+/// file which has `attributes`. FileAttributes 0 leaves the attributes as they are, e.g. for
+/// SetFileTime.
+pub fn cleared_attributes(attributes: u32, new_attributes: u32) -> u32 {
+    if new_attributes == 0 {
+        return 0;
+    }
+    ATTRIBUTE_NAMES.iter().fold(0, |bits, (bit, _)| {
+        bits | (attributes & !new_attributes & bit)
+    })
+}
+
+/// The names of the bits of `names` set in `bits`, in the order of `names`.
+pub fn describe<'a>(
+    names: &'a [(u32, &'static str)],
+    bits: u32,
+) -> impl Iterator<Item = &'static str> + 'a {
+    names
+        .iter()
+        .filter(move |(bit, _)| bits & bit != 0)
+        .map(|(_, name)| *name)
+}
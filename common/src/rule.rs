@@ -0,0 +1,292 @@
+//! Wire format of rules exchanged through `IOCTL_DELPROTECT_ADD_RULE` and
+//! `IOCTL_DELPROTECT_LIST_RULES`. All integers are little endian, the process name and then the
+//! path follow the fixed header as UTF-16LE without terminating nulls.
+//!
+//! ```text
+//! 0   u32  id (ignored on add)
+//! 4   u16  state (RuleState, ignored on add)
+//! 6   u8   schedule days
+//! 7   u8   action (RuleAction)
+//! 8   u16  schedule start minute
+//! 10  u16  schedule end minute
+//! 12  u16  process name length in bytes
+//! 14  u16  flags (RULE_FLAG_*)
+//! 16  u64  not_before
+//! 24  u64  not_after
+//! 32  i32  failure status (one of FAILURE_STATUSES)
+//! 36  u16  path length in bytes (RULE_FLAG_IMMUTABLE only)
+//! 38  u16  reserved
+//! 40  ...  process name
+//! ..  ...  path
+//! ```
+
+use crate::{
+    input::DecodeError,
+    schedule::{RuleState, TimeWindow, WeeklySchedule},
+    status::{
+        NtStatus, STATUS_ACCESS_DENIED, STATUS_CANNOT_DELETE, STATUS_MEDIA_WRITE_PROTECTED,
+        STATUS_SHARING_VIOLATION,
+    },
+    wire::{read_u16, read_u32, read_u64},
+};
+
+pub const RULE_HEADER_SIZE: usize = 40;
+/// MAX_PATH characters, long enough for any image name the driver could compare against.
+pub const MAX_PROCESS_NAME_BYTES: usize = 260 * 2;
+/// Longest path of an immutable rule, the longest name the driver queries.
+pub const MAX_RULE_PATH_BYTES: usize = 1024 * 2;
+
+/// The rule also decides for the processes created by a process it names, and the ones they
+/// create in turn, see `crate::explain` for how the lineage shows in traces.
+pub const RULE_FLAG_INHERIT: u16 = 0x1;
+/// The processes the rule decides for cannot modify the files at its path either: their writes,
+/// writable section mappings and opens for write access fail with the failure status of the
+/// rule, whatever its action. Reads and changes of other files go on. The path is the NT path
+/// of a file or of a directory, which covers everything below it, compared without regard to
+/// case like the canaries. An immutable rule needs a path, the other rules cannot have one.
+pub const RULE_FLAG_IMMUTABLE: u16 = 0x2;
+pub const RULE_FLAGS_ALL: u16 = RULE_FLAG_INHERIT | RULE_FLAG_IMMUTABLE;
+
+/// The statuses a denying rule may fail the delete with, by name. Only failures an
+/// application can take for "cannot delete this file": a status which means something else
+/// (e.g. STATUS_PENDING or a success) would leave the I/O manager or the caller confused.
+pub const FAILURE_STATUSES: [(NtStatus, &str); 4] = [
+    (STATUS_ACCESS_DENIED, "access-denied"),
+    (STATUS_CANNOT_DELETE, "cannot-delete"),
+    (STATUS_SHARING_VIOLATION, "sharing-violation"),
+    (STATUS_MEDIA_WRITE_PROTECTED, "media-write-protected"),
+];
+
+pub fn is_failure_status(status: NtStatus) -> bool {
+    FAILURE_STATUSES
+        .iter()
+        .any(|(allowed, _)| *allowed == status)
+}
+
+/// `access-denied` or the other names of `FAILURE_STATUSES`, `None` for any other status.
+pub fn failure_status_name(status: NtStatus) -> Option<&'static str> {
+    FAILURE_STATUSES
+        .iter()
+        .find(|(allowed, _)| *allowed == status)
+        .map(|(_, name)| *name)
+}
+
+/// A name of `FAILURE_STATUSES` or its value in hexadecimal, e.g. `0xC0000121`.
+pub fn parse_failure_status(text: &str) -> Option<NtStatus> {
+    if let Some((status, _)) = FAILURE_STATUSES.iter().find(|(_, name)| *name == text) {
+        return Some(*status);
+    }
+    let hex = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))?;
+    let status = u32::from_str_radix(hex, 16).ok()? as NtStatus;
+    is_failure_status(status).then_some(status)
+}
+
+/// Input flag of `IOCTL_DELPROTECT_LIST_RULES`: drop expired rules after reporting them.
+pub const LIST_FLAG_PURGE_EXPIRED: u32 = 0x1;
+
+/// What a matching rule does with the delete.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RuleAction {
+    /// Fail it with the failure status of the rule.
+    #[default]
+    Deny = 0,
+    /// Copy the file into the vault of its volume and let the delete go on, see `crate::vault`.
+    Preserve = 1,
+    /// Deny, then terminate the process.
+    DenyAndTerminate = 2,
+    /// Deny, then suspend every thread of the process.
+    DenyAndSuspend = 3,
+    /// Deny, then deny every delete, rename and overwrite of the process until it exits, see
+    /// `crate::detector::LockdownCause::Demoted`.
+    DenyAndDemote = 4,
+}
+
+impl RuleAction {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Deny),
+            1 => Some(Self::Preserve),
+            2 => Some(Self::DenyAndTerminate),
+            3 => Some(Self::DenyAndSuspend),
+            4 => Some(Self::DenyAndDemote),
+            _ => None,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "deny" => Some(Self::Deny),
+            "preserve" => Some(Self::Preserve),
+            "deny-and-terminate" => Some(Self::DenyAndTerminate),
+            "deny-and-suspend" => Some(Self::DenyAndSuspend),
+            "deny-and-demote" => Some(Self::DenyAndDemote),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deny => "deny",
+            Self::Preserve => "preserve",
+            Self::DenyAndTerminate => "deny-and-terminate",
+            Self::DenyAndSuspend => "deny-and-suspend",
+            Self::DenyAndDemote => "deny-and-demote",
+        }
+    }
+
+    /// What is done to the process once its delete was denied, `Response::None` for the
+    /// actions which do not deny.
+    pub fn response(&self) -> Response {
+        match self {
+            Self::Deny | Self::Preserve => Response::None,
+            Self::DenyAndTerminate => Response::Terminate,
+            Self::DenyAndSuspend => Response::Suspend,
+            Self::DenyAndDemote => Response::Demote,
+        }
+    }
+}
+
+/// What a rule does to the process after denying its delete. Terminations and suspensions are
+/// carried out by a work item after the callback returned, a demotion at once. The response is
+/// recorded in the `delete-denied` event.
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Response {
+    #[default]
+    None = 0,
+    /// Terminated with STATUS_ACCESS_DENIED as exit status.
+    Terminate = 1,
+    Suspend = 2,
+    Demote = 3,
+}
+
+impl Response {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::Terminate),
+            2 => Some(Self::Suspend),
+            3 => Some(Self::Demote),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Terminate => "terminate",
+            Self::Suspend => "suspend",
+            Self::Demote => "demote",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuleRecord<'a> {
+    pub id: u32,
+    pub state: RuleState,
+    pub action: RuleAction,
+    /// RULE_FLAG_* bits.
+    pub flags: u16,
+    pub window: TimeWindow,
+    /// What a denied delete fails with, one of `FAILURE_STATUSES`.
+    pub failure_status: NtStatus,
+    /// UTF-16LE bytes of the process name.
+    pub process: &'a [u8],
+    /// UTF-16LE bytes of the NT path the writes are denied under, empty unless immutable.
+    pub path: &'a [u8],
+}
+
+impl<'a> RuleRecord<'a> {
+    pub fn encoded_len(&self) -> usize {
+        RULE_HEADER_SIZE + self.process.len() + self.path.len()
+    }
+
+    /// Writes the record at the beginning of `buffer` and returns the number of bytes used,
+    /// or `None` if it does not fit.
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        if buffer.len() < len
+            || self.process.len() > u16::MAX as usize
+            || self.path.len() > MAX_RULE_PATH_BYTES
+        {
+            return None;
+        }
+
+        let window = &self.window;
+        buffer[0..4].copy_from_slice(&self.id.to_le_bytes());
+        buffer[4..6].copy_from_slice(&(self.state as u16).to_le_bytes());
+        buffer[6] = window.schedule.days;
+        buffer[7] = self.action as u8;
+        buffer[8..10].copy_from_slice(&window.schedule.start_minute.to_le_bytes());
+        buffer[10..12].copy_from_slice(&window.schedule.end_minute.to_le_bytes());
+        buffer[12..14].copy_from_slice(&(self.process.len() as u16).to_le_bytes());
+        buffer[14..16].copy_from_slice(&self.flags.to_le_bytes());
+        buffer[16..24].copy_from_slice(&window.not_before.to_le_bytes());
+        buffer[24..32].copy_from_slice(&window.not_after.to_le_bytes());
+        buffer[32..36].copy_from_slice(&self.failure_status.to_le_bytes());
+        buffer[36..38].copy_from_slice(&(self.path.len() as u16).to_le_bytes());
+        buffer[38..40].copy_from_slice(&0u16.to_le_bytes());
+        let path = RULE_HEADER_SIZE + self.process.len();
+        buffer[RULE_HEADER_SIZE..path].copy_from_slice(self.process);
+        buffer[path..len].copy_from_slice(self.path);
+
+        Some(len)
+    }
+
+    /// Parses one record from the beginning of `buffer`, returning it together with the number
+    /// of bytes consumed. Unknown flags, failure statuses off the allow-list and immutable rules
+    /// without a path, or other rules with one, are rejected.
+    pub fn decode(buffer: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < RULE_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let process_len = read_u16(buffer, 12) as usize;
+        let path_len = read_u16(buffer, 36) as usize;
+        let path = RULE_HEADER_SIZE + process_len;
+        let len = path + path_len;
+        if process_len > MAX_PROCESS_NAME_BYTES || path_len > MAX_RULE_PATH_BYTES {
+            return Err(DecodeError::TooLong);
+        }
+        if !process_len.is_multiple_of(2) || !path_len.is_multiple_of(2) {
+            return Err(DecodeError::OddLength);
+        }
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let flags = read_u16(buffer, 14);
+        if flags & !RULE_FLAGS_ALL != 0 || (flags & RULE_FLAG_IMMUTABLE != 0) != (path_len != 0) {
+            return Err(DecodeError::InvalidValue);
+        }
+        let failure_status = read_u32(buffer, 32) as NtStatus;
+        if !is_failure_status(failure_status) {
+            return Err(DecodeError::InvalidValue);
+        }
+
+        let record = Self {
+            id: read_u32(buffer, 0),
+            state: RuleState::from_u16(read_u16(buffer, 4)).ok_or(DecodeError::InvalidValue)?,
+            action: RuleAction::from_u8(buffer[7]).ok_or(DecodeError::InvalidValue)?,
+            flags,
+            window: TimeWindow {
+                not_before: read_u64(buffer, 16),
+                not_after: read_u64(buffer, 24),
+                schedule: WeeklySchedule {
+                    days: buffer[6],
+                    start_minute: read_u16(buffer, 8),
+                    end_minute: read_u16(buffer, 10),
+                },
+            },
+            failure_status,
+            process: &buffer[RULE_HEADER_SIZE..path],
+            path: &buffer[path..len],
+        };
+
+        Ok((record, len))
+    }
+}
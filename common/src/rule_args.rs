@@ -0,0 +1,153 @@
+//! Text form of rule time windows, shared by the client command line and the policy files of
+//! the simulator: `--not-before`, `--not-after`, `--days`, `--hours`, all in UTC.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::schedule::{
+    filetime_from_utc, utc_from_filetime, TimeWindow, EVERY_DAY, FRIDAY, MONDAY, SATURDAY, SUNDAY,
+    THURSDAY, TUESDAY, WEDNESDAY,
+};
+
+const DAY_NAMES: [(&str, u8); 7] = [
+    ("sun", SUNDAY),
+    ("mon", MONDAY),
+    ("tue", TUESDAY),
+    ("wed", WEDNESDAY),
+    ("thu", THURSDAY),
+    ("fri", FRIDAY),
+    ("sat", SATURDAY),
+];
+
+/// Parses the options following `add <exename>` into a time window.
+pub fn parse_time_window(args: &[String]) -> Result<TimeWindow, String> {
+    let mut window = TimeWindow::default();
+    let mut hours = None;
+
+    let mut it = args.iter();
+    while let Some(option) = it.next() {
+        let value = it
+            .next()
+            .ok_or_else(|| format!("missing value for \"{option}\""))?;
+
+        match option.as_str() {
+            "--not-before" => window.not_before = parse_utc_time(value)?,
+            "--not-after" => window.not_after = parse_utc_time(value)?,
+            "--days" => window.schedule.days = parse_days(value)?,
+            "--hours" => hours = Some(parse_hours(value)?),
+            _ => return Err(format!("unknown option \"{option}\"")),
+        }
+    }
+
+    if let Some((start, end)) = hours {
+        if window.schedule.days == 0 {
+            window.schedule.days = EVERY_DAY;
+        }
+        window.schedule.start_minute = start;
+        window.schedule.end_minute = end;
+    }
+
+    if !window.is_valid() {
+        return Err("\"--not-before\" must be earlier than \"--not-after\"".to_string());
+    }
+
+    Ok(window)
+}
+
+/// Accepts `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM`, always in UTC.
+pub fn parse_utc_time(value: &str) -> Result<u64, String> {
+    let err = || format!("invalid time \"{value}\", expected YYYY-MM-DD[THH:MM] (UTC)");
+
+    let (date, time) = value.split_once('T').unwrap_or((value, "00:00"));
+    let date: Vec<&str> = date.split('-').collect();
+    if date.len() != 3 {
+        return Err(err());
+    }
+    let year = date[0].parse().map_err(|_| err())?;
+    let month = date[1].parse().map_err(|_| err())?;
+    let day = date[2].parse().map_err(|_| err())?;
+    let minute_of_day = parse_minute_of_day(time).ok_or_else(err)?;
+
+    filetime_from_utc(
+        year,
+        month,
+        day,
+        minute_of_day as u32 / 60,
+        minute_of_day as u32 % 60,
+    )
+    .ok_or_else(err)
+}
+
+/// Accepts a comma separated list like `mon,tue,fri`.
+fn parse_days(value: &str) -> Result<u8, String> {
+    let mut days = 0;
+    for name in value.split(',') {
+        let name = name.trim().to_ascii_lowercase();
+        match DAY_NAMES.iter().find(|(day, _)| name.starts_with(day)) {
+            Some((_, bit)) => days |= bit,
+            None => return Err(format!("invalid day \"{name}\"")),
+        }
+    }
+    Ok(days)
+}
+
+/// Accepts `HH:MM-HH:MM`.
+fn parse_hours(value: &str) -> Result<(u16, u16), String> {
+    let err = || format!("invalid hours \"{value}\", expected HH:MM-HH:MM (UTC)");
+
+    let (start, end) = value.split_once('-').ok_or_else(err)?;
+    let start = parse_minute_of_day(start).ok_or_else(err)?;
+    let end = parse_minute_of_day(end).ok_or_else(err)?;
+    Ok((start, end))
+}
+
+fn parse_minute_of_day(value: &str) -> Option<u16> {
+    let (hour, minute) = value.split_once(':')?;
+    let hour: u16 = hour.parse().ok()?;
+    let minute: u16 = minute.parse().ok()?;
+    if hour > 23 || minute > 59 {
+        return None;
+    }
+    Some(hour * 60 + minute)
+}
+
+pub fn format_utc_time(ticks: u64) -> String {
+    let (year, month, day, hour, minute) = utc_from_filetime(ticks);
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}")
+}
+
+pub fn format_time_window(window: &TimeWindow) -> String {
+    let mut parts = Vec::new();
+    if window.not_before != 0 {
+        parts.push(format!("not before {}", format_utc_time(window.not_before)));
+    }
+    if window.not_after != 0 {
+        parts.push(format!("not after {}", format_utc_time(window.not_after)));
+    }
+
+    let schedule = &window.schedule;
+    if schedule.days != 0 {
+        let days: Vec<&str> = DAY_NAMES
+            .iter()
+            .filter(|(_, bit)| schedule.days & bit != 0)
+            .map(|(day, _)| *day)
+            .collect();
+        parts.push(format!(
+            "{} {:02}:{:02}-{:02}:{:02}",
+            days.join(","),
+            schedule.start_minute / 60,
+            schedule.start_minute % 60,
+            schedule.end_minute / 60,
+            schedule.end_minute % 60
+        ));
+    }
+
+    if parts.is_empty() {
+        "always".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
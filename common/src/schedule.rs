@@ -0,0 +1,188 @@
+//! Time-window evaluation for rules. All timestamps are FILETIME values (100ns ticks since
+//! 1601-01-01 UTC), the same unit `KeQuerySystemTime` returns, so the driver can feed the
+//! system time in directly. Everything here is UTC, there is no timezone handling.
+
+pub const TICKS_PER_SECOND: u64 = 10_000_000;
+pub const TICKS_PER_MINUTE: u64 = 60 * TICKS_PER_SECOND;
+pub const TICKS_PER_DAY: u64 = 24 * 60 * TICKS_PER_MINUTE;
+pub const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// 1601-01-01 was a Monday, so day 0 of the FILETIME epoch has weekday 1.
+const EPOCH_WEEKDAY: u64 = 1;
+
+pub const SUNDAY: u8 = 1 << 0;
+pub const MONDAY: u8 = 1 << 1;
+pub const TUESDAY: u8 = 1 << 2;
+pub const WEDNESDAY: u8 = 1 << 3;
+pub const THURSDAY: u8 = 1 << 4;
+pub const FRIDAY: u8 = 1 << 5;
+pub const SATURDAY: u8 = 1 << 6;
+pub const EVERY_DAY: u8 = 0x7f;
+
+/// Weekly recurring window. `days` is a bitmask of the constants above, `start_minute` and
+/// `end_minute` are minutes since midnight. A window with `end_minute < start_minute` wraps
+/// past midnight into the next day, `start_minute == end_minute` covers the whole day.
+/// `days == 0` means there is no schedule at all and the rule is always on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WeeklySchedule {
+    pub days: u8,
+    pub start_minute: u16,
+    pub end_minute: u16,
+}
+
+impl WeeklySchedule {
+    pub fn is_valid(&self) -> bool {
+        self.days & !EVERY_DAY == 0
+            && self.start_minute < MINUTES_PER_DAY
+            && self.end_minute < MINUTES_PER_DAY
+    }
+
+    pub fn contains(&self, now: u64) -> bool {
+        if self.days == 0 {
+            return true;
+        }
+
+        let day = now / TICKS_PER_DAY;
+        let minute = ((now % TICKS_PER_DAY) / TICKS_PER_MINUTE) as u16;
+        let today = weekday_bit(day);
+        let yesterday = weekday_bit(day + 6);
+
+        if self.start_minute == self.end_minute {
+            self.days & today != 0
+        } else if self.start_minute < self.end_minute {
+            self.days & today != 0 && minute >= self.start_minute && minute < self.end_minute
+        } else {
+            (self.days & today != 0 && minute >= self.start_minute)
+                || (self.days & yesterday != 0 && minute < self.end_minute)
+        }
+    }
+}
+
+/// Absolute validity of a rule plus an optional weekly schedule. Zero in `not_before` or
+/// `not_after` means the bound is not set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub not_before: u64,
+    pub not_after: u64,
+    pub schedule: WeeklySchedule,
+}
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleState {
+    /// `not_before` is still in the future.
+    Pending = 0,
+    Active = 1,
+    /// Inside the validity period but outside the weekly schedule.
+    Idle = 2,
+    /// `not_after` has passed, the rule will never be active again.
+    Expired = 3,
+}
+
+impl RuleState {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(Self::Pending),
+            1 => Some(Self::Active),
+            2 => Some(Self::Idle),
+            3 => Some(Self::Expired),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Active => "active",
+            Self::Idle => "idle",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+impl TimeWindow {
+    pub fn is_valid(&self) -> bool {
+        self.schedule.is_valid()
+            && (self.not_before == 0 || self.not_after == 0 || self.not_before < self.not_after)
+    }
+
+    pub fn evaluate(&self, now: u64) -> RuleState {
+        if self.not_after != 0 && now >= self.not_after {
+            RuleState::Expired
+        } else if self.not_before != 0 && now < self.not_before {
+            RuleState::Pending
+        } else if self.schedule.contains(now) {
+            RuleState::Active
+        } else {
+            RuleState::Idle
+        }
+    }
+}
+
+fn weekday_bit(day: u64) -> u8 {
+    1 << ((day + EPOCH_WEEKDAY) % 7)
+}
+
+/// Converts a UTC calendar date and time into FILETIME ticks. Returns `None` for dates before
+/// the FILETIME epoch or for out of range fields.
+pub fn filetime_from_utc(year: u32, month: u32, day: u32, hour: u32, minute: u32) -> Option<u64> {
+    if year < 1601 || !(1..=12).contains(&month) || hour > 23 || minute > 59 {
+        return None;
+    }
+    if day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day) - days_from_civil(1601, 1, 1);
+    let minutes = days as u64 * MINUTES_PER_DAY as u64 + (hour * 60 + minute) as u64;
+    Some(minutes * TICKS_PER_MINUTE)
+}
+
+/// Splits FILETIME ticks into (year, month, day, hour, minute) in UTC.
+pub fn utc_from_filetime(ticks: u64) -> (u32, u32, u32, u32, u32) {
+    let days = (ticks / TICKS_PER_DAY) as i64 + days_from_civil(1601, 1, 1);
+    let minute_of_day = ((ticks % TICKS_PER_DAY) / TICKS_PER_MINUTE) as u32;
+    let (year, month, day) = civil_from_days(days);
+    (year, month, day, minute_of_day / 60, minute_of_day % 60)
+}
+
+fn is_leap_year(year: u32) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar (Howard Hinnant's algorithm).
+fn days_from_civil(year: u32, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 {
+        year as i64 - 1
+    } else {
+        year as i64
+    };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (u32, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as u32;
+    (year, month, day)
+}
@@ -0,0 +1,192 @@
+//! Wire format of `IOCTL_DELPROTECT_GET_STATS`: the driver counters followed by one record per
+//! rule. The input is an optional u32 of STATS_FLAG_* flags.
+//!
+//! ```text
+//! 0   u32  size of the header in bytes, newer drivers may append counters
+//! 4   u32  number of rule records following the header
+//! 8   u64  time the counters started (FILETIME, UTC), driver start or last reset
+//! 16  u64  counters in the order of `Counter`
+//! ..  ...  rule records
+//!
+//! rule record
+//! 0   u32  rule id
+//! 4   u32  reserved
+//! 8   u64  deletes the rule blocked or preserved
+//! 16  u64  time of the last one (FILETIME, UTC), 0 if none
+//! ```
+
+use crate::{
+    input::DecodeError,
+    wire::{read_u32, read_u64},
+};
+
+/// Input flag: zero the counters after reporting them.
+pub const STATS_FLAG_RESET: u32 = 0x1;
+
+pub const COUNTER_COUNT: usize = 14;
+pub const STATS_HEADER_SIZE: usize = 16 + COUNTER_COUNT * 8;
+pub const RULE_STATS_SIZE: usize = 24;
+
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    /// IRP_MJ_CREATE pre-operations.
+    CreatesInspected = 0,
+    /// Opens with FILE_DELETE_ON_CLOSE from user mode.
+    DeleteOnClose = 1,
+    /// FileDispositionInformation(Ex) setting the delete flag.
+    SetInformationDeletes = 2,
+    /// Deletes a rule denied.
+    Blocked = 3,
+    /// Deletes the rules let through.
+    Allowed = 4,
+    /// Process image or file name queries which failed.
+    NameQueryFailures = 5,
+    /// Name buffers which could not be allocated.
+    AllocationFailures = 6,
+    /// Deletes let through after the file was copied into the vault.
+    Preserved = 7,
+    /// Deletes, renames and overwrites of locked down processes, see `crate::detector`.
+    LockdownDenied = 8,
+    /// Terminations and suspensions of a process whose delete was denied which could not be
+    /// queued or carried out, see `crate::rule::Response`.
+    ResponseFailures = 9,
+    /// Opens with DELETE access from user mode decided at open time, see
+    /// `crate::options::OPTION_DENY_DELETE_ACCESS`.
+    DeleteAccessOpens = 10,
+    /// Size changes which would cut a file decided like deletes, see `crate::truncation`.
+    Truncations = 11,
+    /// Writes, writable sections and opens for write access decided for immutable rules.
+    Writes = 12,
+    /// Security descriptor and attribute changes decided like deletes, see
+    /// `crate::protection`.
+    SecurityChanges = 13,
+}
+
+impl Counter {
+    pub const ALL: [Counter; COUNTER_COUNT] = [
+        Self::CreatesInspected,
+        Self::DeleteOnClose,
+        Self::SetInformationDeletes,
+        Self::Blocked,
+        Self::Allowed,
+        Self::NameQueryFailures,
+        Self::AllocationFailures,
+        Self::Preserved,
+        Self::LockdownDenied,
+        Self::ResponseFailures,
+        Self::DeleteAccessOpens,
+        Self::Truncations,
+        Self::Writes,
+        Self::SecurityChanges,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CreatesInspected => "creates-inspected",
+            Self::DeleteOnClose => "delete-on-close",
+            Self::SetInformationDeletes => "set-information-deletes",
+            Self::Blocked => "blocked",
+            Self::Allowed => "allowed",
+            Self::NameQueryFailures => "name-query-failures",
+            Self::AllocationFailures => "allocation-failures",
+            Self::Preserved => "preserved",
+            Self::LockdownDenied => "lockdown-denied",
+            Self::ResponseFailures => "response-failures",
+            Self::DeleteAccessOpens => "delete-access-opens",
+            Self::Truncations => "truncations",
+            Self::Writes => "writes",
+            Self::SecurityChanges => "security-changes",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatsHeader {
+    pub rule_count: u32,
+    pub since: u64,
+    /// Indexed by `Counter`.
+    pub counters: [u64; COUNTER_COUNT],
+}
+
+impl StatsHeader {
+    pub fn counter(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize]
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        if buffer.len() < STATS_HEADER_SIZE {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&(STATS_HEADER_SIZE as u32).to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.rule_count.to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.since.to_le_bytes());
+        for (i, value) in self.counters.iter().enumerate() {
+            let offset = 16 + i * 8;
+            buffer[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        Some(STATS_HEADER_SIZE)
+    }
+
+    /// Returns the header and its size, the rule records start there.
+    pub fn decode(buffer: &[u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < STATS_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let len = read_u32(buffer, 0) as usize;
+        if len < STATS_HEADER_SIZE {
+            return Err(DecodeError::InvalidValue);
+        }
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let mut header = Self {
+            rule_count: read_u32(buffer, 4),
+            since: read_u64(buffer, 8),
+            counters: [0; COUNTER_COUNT],
+        };
+        for (i, value) in header.counters.iter_mut().enumerate() {
+            *value = read_u64(buffer, 16 + i * 8);
+        }
+
+        Ok((header, len))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RuleStats {
+    pub rule_id: u32,
+    pub hits: u64,
+    pub last_hit: u64,
+}
+
+impl RuleStats {
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        if buffer.len() < RULE_STATS_SIZE {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&self.rule_id.to_le_bytes());
+        buffer[4..8].copy_from_slice(&0u32.to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.hits.to_le_bytes());
+        buffer[16..24].copy_from_slice(&self.last_hit.to_le_bytes());
+
+        Some(RULE_STATS_SIZE)
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        if buffer.len() < RULE_STATS_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        Ok(Self {
+            rule_id: read_u32(buffer, 0),
+            hits: read_u64(buffer, 8),
+            last_hit: read_u64(buffer, 16),
+        })
+    }
+}
@@ -0,0 +1,25 @@
+//! NTSTATUS values shared by the driver, the core and the client. Same numbers as ntstatus.h,
+//! kept here so the crates which decide on them do not need Windows bindings.
+
+pub type NtStatus = i32;
+
+pub const STATUS_SUCCESS: NtStatus = 0;
+pub const STATUS_INVALID_PARAMETER: NtStatus = 0xC000_000Du32 as i32;
+pub const STATUS_INVALID_DEVICE_REQUEST: NtStatus = 0xC000_0010u32 as i32;
+pub const STATUS_ACCESS_DENIED: NtStatus = 0xC000_0022u32 as i32;
+pub const STATUS_BUFFER_TOO_SMALL: NtStatus = 0xC000_0023u32 as i32;
+pub const STATUS_OBJECT_NAME_NOT_FOUND: NtStatus = 0xC000_0034u32 as i32;
+pub const STATUS_OBJECT_NAME_COLLISION: NtStatus = 0xC000_0035u32 as i32;
+pub const STATUS_SHARING_VIOLATION: NtStatus = 0xC000_0043u32 as i32;
+pub const STATUS_DISK_FULL: NtStatus = 0xC000_007Fu32 as i32;
+pub const STATUS_INSUFFICIENT_RESOURCES: NtStatus = 0xC000_009Au32 as i32;
+pub const STATUS_NOT_SUPPORTED: NtStatus = 0xC000_00BBu32 as i32;
+pub const STATUS_MEDIA_WRITE_PROTECTED: NtStatus = 0xC000_00A2u32 as i32;
+pub const STATUS_CANNOT_DELETE: NtStatus = 0xC000_0121u32 as i32;
+pub const STATUS_NOT_FOUND: NtStatus = 0xC000_0225u32 as i32;
+pub const STATUS_FLT_DO_NOT_DETACH: NtStatus = 0xC01C_0010u32 as i32;
+
+/// `NT_SUCCESS` of ntdef.h: success and informational codes are non-negative.
+pub const fn nt_success(status: NtStatus) -> bool {
+    status >= 0
+}
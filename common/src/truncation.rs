@@ -0,0 +1,56 @@
+//! Which size changes of IRP_MJ_SET_INFORMATION destroy content like a delete does, see
+//! `crate::options::OPTION_GUARD_TRUNCATION`. All three classes carry the new size as the
+//! first LARGE_INTEGER of their buffer; the driver compares it with the end of file the
+//! file system reports before the change.
+
+/// FILE_INFORMATION_CLASS values of wdm.h which change a size of the file.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeInformation {
+    /// FileAllocationInformation, the space reserved on disk.
+    Allocation = 19,
+    /// FileEndOfFileInformation, what SetEndOfFile and an overwriting truncate set.
+    EndOfFile = 20,
+    /// FileValidDataLengthInformation, what SetFileValidData sets.
+    ValidDataLength = 39,
+}
+
+impl SizeInformation {
+    pub fn from_class(class: u32) -> Option<Self> {
+        match class {
+            19 => Some(Self::Allocation),
+            20 => Some(Self::EndOfFile),
+            39 => Some(Self::ValidDataLength),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allocation => "allocation",
+            Self::EndOfFile => "end-of-file",
+            Self::ValidDataLength => "valid-data-length",
+        }
+    }
+}
+
+/// True if setting `information` to `new_size` on a file `end_of_file` bytes long loses data:
+///
+/// - an end of file below the current one cuts the file;
+/// - an allocation below the end of file cuts it as well, trimming space reserved past the
+///   end of file does not;
+/// - a valid data length below the end of file would turn the bytes past it into zeros. The
+///   driver does not know the current valid data length, so extending it to somewhere short
+///   of the end of file counts as well; only processes a rule blocks are ever asked about.
+///
+/// A negative size, which the file system rejects, is never destructive.
+pub fn is_destructive(information: SizeInformation, new_size: i64, end_of_file: u64) -> bool {
+    let Ok(new_size) = u64::try_from(new_size) else {
+        return false;
+    };
+    match information {
+        SizeInformation::EndOfFile
+        | SizeInformation::Allocation
+        | SizeInformation::ValidDataLength => new_size < end_of_file,
+    }
+}
@@ -0,0 +1,189 @@
+//! NTFS change journal records, `USN_RECORD_V2` and `USN_RECORD_V3` as FSCTL_READ_USN_JOURNAL
+//! returns them and as they are stored in `$Extend\$UsnJrnl:$J`. Records are 8 byte aligned,
+//! their length includes the padding. The stream of a `$J` extract starts with a sparse, zero
+//! filled range, zeroes between records are skipped.
+//!
+//! ```text
+//!         V2                              V3
+//! 0   u32  record length            0   u32  record length
+//! 4   u16  major version (2)        4   u16  major version (3)
+//! 6   u16  minor version            6   u16  minor version
+//! 8   u64  file reference           8   u128 file reference
+//! 16  u64  parent file reference    24  u128 parent file reference
+//! 24  i64  usn                      40  i64  usn
+//! 32  u64  time stamp (FILETIME)    48  u64  time stamp (FILETIME)
+//! 40  u32  reason                   56  u32  reason
+//! 44  u32  source info              60  u32  source info
+//! 48  u32  security id              64  u32  security id
+//! 52  u32  file attributes          68  u32  file attributes
+//! 56  u16  file name length         72  u16  file name length
+//! 58  u16  file name offset         74  u16  file name offset
+//! 60  ...  file name (UTF-16LE)     76  ...  file name (UTF-16LE)
+//! ```
+
+use crate::{
+    input::DecodeError,
+    wire::{read_u16, read_u32, read_u64},
+};
+
+pub const USN_RECORD_V2_HEADER_SIZE: usize = 60;
+pub const USN_RECORD_V3_HEADER_SIZE: usize = 76;
+
+/// Records are aligned on this boundary.
+pub const USN_RECORD_ALIGNMENT: usize = 8;
+
+pub const USN_REASON_DATA_OVERWRITE: u32 = 0x0000_0001;
+pub const USN_REASON_DATA_EXTEND: u32 = 0x0000_0002;
+pub const USN_REASON_DATA_TRUNCATION: u32 = 0x0000_0004;
+pub const USN_REASON_FILE_CREATE: u32 = 0x0000_0100;
+pub const USN_REASON_FILE_DELETE: u32 = 0x0000_0200;
+pub const USN_REASON_RENAME_OLD_NAME: u32 = 0x0000_1000;
+pub const USN_REASON_RENAME_NEW_NAME: u32 = 0x0000_2000;
+pub const USN_REASON_CLOSE: u32 = 0x8000_0000;
+
+/// One record. File references are widened to 128 bits, V2 references fill the low half.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UsnRecord<'a> {
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub file_reference: u128,
+    pub parent_reference: u128,
+    pub usn: i64,
+    pub time_stamp: u64,
+    pub reason: u32,
+    pub source_info: u32,
+    pub security_id: u32,
+    pub file_attributes: u32,
+    /// UTF-16LE, without terminating null.
+    pub file_name: &'a [u8],
+}
+
+impl<'a> UsnRecord<'a> {
+    /// Decodes the record at the start of `buffer` and returns it with its length, padding
+    /// included.
+    pub fn decode(buffer: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < 8 {
+            return Err(DecodeError::Truncated);
+        }
+
+        let length = read_u32(buffer, 0) as usize;
+        let major_version = read_u16(buffer, 4);
+        let header_size = match major_version {
+            2 => USN_RECORD_V2_HEADER_SIZE,
+            3 => USN_RECORD_V3_HEADER_SIZE,
+            _ => return Err(DecodeError::InvalidValue),
+        };
+        if length < header_size || !length.is_multiple_of(USN_RECORD_ALIGNMENT) {
+            return Err(DecodeError::InvalidValue);
+        }
+        if buffer.len() < length {
+            return Err(DecodeError::Truncated);
+        }
+        let record = &buffer[..length];
+
+        let (file_reference, parent_reference, fields) = if major_version == 2 {
+            (
+                read_u64(record, 8) as u128,
+                read_u64(record, 16) as u128,
+                24,
+            )
+        } else {
+            (read_u128(record, 8), read_u128(record, 24), 40)
+        };
+
+        let name_length = read_u16(record, fields + 32) as usize;
+        let name_offset = read_u16(record, fields + 34) as usize;
+        if !name_length.is_multiple_of(2) {
+            return Err(DecodeError::OddLength);
+        }
+        if name_offset < header_size || name_offset + name_length > length {
+            return Err(DecodeError::InvalidValue);
+        }
+
+        Ok((
+            Self {
+                major_version,
+                minor_version: read_u16(record, 6),
+                file_reference,
+                parent_reference,
+                usn: read_u64(record, fields) as i64,
+                time_stamp: read_u64(record, fields + 8),
+                reason: read_u32(record, fields + 16),
+                source_info: read_u32(record, fields + 20),
+                security_id: read_u32(record, fields + 24),
+                file_attributes: read_u32(record, fields + 28),
+                file_name: &record[name_offset..name_offset + name_length],
+            },
+            length,
+        ))
+    }
+
+    pub fn file_name_units(&self) -> impl Iterator<Item = u16> + 'a {
+        self.file_name
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+    }
+
+    pub fn has_reason(&self, reason: u32) -> bool {
+        self.reason & reason != 0
+    }
+}
+
+/// The records of a journal stream in order. Zeroes between records are skipped, the first
+/// malformed record ends the iteration with its error.
+pub struct UsnRecords<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> UsnRecords<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self {
+            buffer,
+            offset: 0,
+            failed: false,
+        }
+    }
+
+    /// Offset of the next record in the stream.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for UsnRecords<'a> {
+    type Item = Result<UsnRecord<'a>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        loop {
+            let rest = &self.buffer[self.offset..];
+            if rest.len() < 4 {
+                return None;
+            }
+            if read_u32(rest, 0) != 0 {
+                break;
+            }
+            self.offset = (self.offset + USN_RECORD_ALIGNMENT).min(self.buffer.len());
+        }
+
+        match UsnRecord::decode(&self.buffer[self.offset..]) {
+            Ok((record, length)) => {
+                self.offset += length;
+                Some(Ok(record))
+            },
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+fn read_u128(buffer: &[u8], offset: usize) -> u128 {
+    read_u64(buffer, offset) as u128 | (read_u64(buffer, offset + 8) as u128) << 64
+}
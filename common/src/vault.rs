@@ -0,0 +1,311 @@
+//! Recovery vault of the `preserve` rule action. Before letting such a delete go on the driver
+//! copies the file into `\$DelProtectVault` at the root of its volume, names the copy after the
+//! id of its entry (16 hex digits) and appends the entry to `index.dat` in the same directory.
+//! The client lists, restores and purges entries by reading and rewriting the index. All
+//! integers are little endian, strings UTF-16LE without terminating nulls.
+//!
+//! ```text
+//! index header                                   entry, oldest first after the header
+//! 0   u32  magic "DPVI"                          0   u32  entry size in bytes
+//! 4   u16  version (VAULT_VERSION)               4   u32  process id
+//! 6   u16  header size                           8   u64  id
+//! 8   u32  retention in days, 0 to keep          16  u64  time of the delete (FILETIME, UTC)
+//! 12  u32  number of entries                     24  u64  size of the file in bytes
+//! 16  u64  size cap of the copies, 0 for none    32  u32  id of the preserving rule
+//! 24  u64  bytes used by the copies              36  u16  original path length in bytes
+//!                                                38  u16  process image path length in bytes
+//!                                                40  [32] SHA-256 of the content
+//!                                                72  ...  original NT path, process image path
+//! ```
+//!
+//! The driver refuses to preserve, and so denies the delete, once the copies would exceed the
+//! size cap. It only ever appends to the index: retention is manual, entries older than the
+//! retention are dropped by `vault purge` alone, which has to be run (e.g. scheduled) before the
+//! cap is reached.
+
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    input::DecodeError,
+    schedule::TICKS_PER_DAY,
+    wire::{read_u16, read_u32, read_u64},
+};
+
+/// Directory of the vault at the root of a volume.
+pub const VAULT_DIRECTORY: &str = "$DelProtectVault";
+pub const VAULT_INDEX_FILE: &str = "index.dat";
+
+pub const VAULT_MAGIC: u32 = u32::from_le_bytes(*b"DPVI");
+pub const VAULT_VERSION: u16 = 1;
+pub const VAULT_HEADER_SIZE: usize = 32;
+pub const VAULT_ENTRY_HEADER_SIZE: usize = 72;
+/// Longest path kept in an entry, the limit of the name queries of the driver.
+pub const MAX_VAULT_PATH_BYTES: usize = 1024 * 2;
+
+/// Retention of a vault created by the driver.
+pub const DEFAULT_RETENTION_DAYS: u32 = 30;
+/// Size cap of a vault created by the driver, 1 GiB.
+pub const DEFAULT_MAX_BYTES: u64 = 1 << 30;
+
+/// Name of the copy of entry `id` in the vault directory.
+pub fn copy_name(id: u64) -> String {
+    format!("{id:016x}")
+}
+
+/// True if the NT path is inside a vault. Copies are never preserved again, a `preserve` rule
+/// denies their delete so the vault cannot be emptied by the process it watches.
+pub fn is_vault_path(path: &str) -> bool {
+    path.split('\\')
+        .any(|component| component.eq_ignore_ascii_case(VAULT_DIRECTORY))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VaultHeader {
+    pub retention_days: u32,
+    pub entry_count: u32,
+    pub max_bytes: u64,
+    pub used_bytes: u64,
+}
+
+impl Default for VaultHeader {
+    /// The header of a new vault.
+    fn default() -> Self {
+        Self {
+            retention_days: DEFAULT_RETENTION_DAYS,
+            entry_count: 0,
+            max_bytes: DEFAULT_MAX_BYTES,
+            used_bytes: 0,
+        }
+    }
+}
+
+impl VaultHeader {
+    /// True if the cap leaves no room for any copy.
+    pub fn is_full(&self) -> bool {
+        self.max_bytes != 0 && self.used_bytes >= self.max_bytes
+    }
+
+    /// True if a copy of `size` bytes fits under the cap.
+    pub fn has_room(&self, size: u64) -> bool {
+        self.max_bytes == 0 || self.used_bytes.saturating_add(size) <= self.max_bytes
+    }
+
+    /// True if an entry of `time` is past the retention at `now`.
+    pub fn is_expired(&self, time: u64, now: u64) -> bool {
+        self.retention_days != 0
+            && now.saturating_sub(time) > self.retention_days as u64 * TICKS_PER_DAY
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        if buffer.len() < VAULT_HEADER_SIZE {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&VAULT_MAGIC.to_le_bytes());
+        buffer[4..6].copy_from_slice(&VAULT_VERSION.to_le_bytes());
+        buffer[6..8].copy_from_slice(&(VAULT_HEADER_SIZE as u16).to_le_bytes());
+        buffer[8..12].copy_from_slice(&self.retention_days.to_le_bytes());
+        buffer[12..16].copy_from_slice(&self.entry_count.to_le_bytes());
+        buffer[16..24].copy_from_slice(&self.max_bytes.to_le_bytes());
+        buffer[24..32].copy_from_slice(&self.used_bytes.to_le_bytes());
+
+        Some(VAULT_HEADER_SIZE)
+    }
+
+    /// Returns the header and its size, the entries start there.
+    pub fn decode(buffer: &[u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < VAULT_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        if read_u32(buffer, 0) != VAULT_MAGIC || read_u16(buffer, 4) != VAULT_VERSION {
+            return Err(DecodeError::InvalidValue);
+        }
+
+        let len = read_u16(buffer, 6) as usize;
+        if len < VAULT_HEADER_SIZE {
+            return Err(DecodeError::InvalidValue);
+        }
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let header = Self {
+            retention_days: read_u32(buffer, 8),
+            entry_count: read_u32(buffer, 12),
+            max_bytes: read_u64(buffer, 16),
+            used_bytes: read_u64(buffer, 24),
+        };
+        Ok((header, len))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VaultEntry<'a> {
+    pub id: u64,
+    pub time: u64,
+    pub size: u64,
+    pub process_id: u32,
+    pub rule_id: u32,
+    pub sha256: [u8; 32],
+    /// NT path the file was deleted from, e.g. `\Device\HarddiskVolume3\Data\x.txt`.
+    pub path: &'a [u8],
+    /// NT image path of the deleting process.
+    pub process: &'a [u8],
+}
+
+impl<'a> VaultEntry<'a> {
+    pub fn encoded_len(&self) -> usize {
+        VAULT_ENTRY_HEADER_SIZE + self.path.len() + self.process.len()
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        if buffer.len() < len
+            || self.path.len() > MAX_VAULT_PATH_BYTES
+            || self.process.len() > MAX_VAULT_PATH_BYTES
+        {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.process_id.to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.id.to_le_bytes());
+        buffer[16..24].copy_from_slice(&self.time.to_le_bytes());
+        buffer[24..32].copy_from_slice(&self.size.to_le_bytes());
+        buffer[32..36].copy_from_slice(&self.rule_id.to_le_bytes());
+        buffer[36..38].copy_from_slice(&(self.path.len() as u16).to_le_bytes());
+        buffer[38..40].copy_from_slice(&(self.process.len() as u16).to_le_bytes());
+        buffer[40..VAULT_ENTRY_HEADER_SIZE].copy_from_slice(&self.sha256);
+
+        let path_end = VAULT_ENTRY_HEADER_SIZE + self.path.len();
+        buffer[VAULT_ENTRY_HEADER_SIZE..path_end].copy_from_slice(self.path);
+        buffer[path_end..len].copy_from_slice(self.process);
+
+        Some(len)
+    }
+
+    /// Parses one entry from the beginning of `buffer`, returning it together with the number
+    /// of bytes consumed.
+    pub fn decode(buffer: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < VAULT_ENTRY_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let len = read_u32(buffer, 0) as usize;
+        let path_len = read_u16(buffer, 36) as usize;
+        let process_len = read_u16(buffer, 38) as usize;
+        if path_len > MAX_VAULT_PATH_BYTES || process_len > MAX_VAULT_PATH_BYTES {
+            return Err(DecodeError::TooLong);
+        }
+        if !path_len.is_multiple_of(2) || !process_len.is_multiple_of(2) {
+            return Err(DecodeError::OddLength);
+        }
+        if len < VAULT_ENTRY_HEADER_SIZE + path_len + process_len {
+            return Err(DecodeError::InvalidValue);
+        }
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let mut sha256 = [0u8; 32];
+        sha256.copy_from_slice(&buffer[40..VAULT_ENTRY_HEADER_SIZE]);
+        let path_end = VAULT_ENTRY_HEADER_SIZE + path_len;
+        let entry = Self {
+            process_id: read_u32(buffer, 4),
+            id: read_u64(buffer, 8),
+            time: read_u64(buffer, 16),
+            size: read_u64(buffer, 24),
+            rule_id: read_u32(buffer, 32),
+            sha256,
+            path: &buffer[VAULT_ENTRY_HEADER_SIZE..path_end],
+            process: &buffer[path_end..path_end + process_len],
+        };
+        Ok((entry, len))
+    }
+}
+
+/// The entries of an index, following its header.
+pub struct VaultEntries<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> VaultEntries<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for VaultEntries<'a> {
+    type Item = Result<VaultEntry<'a>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.buffer.len() {
+            return None;
+        }
+
+        match VaultEntry::decode(&self.buffer[self.offset..]) {
+            Ok((entry, len)) => {
+                self.offset += len;
+                Some(Ok(entry))
+            },
+            Err(e) => {
+                self.offset = self.buffer.len();
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+/// Parses a whole index file.
+pub fn decode_index(index: &[u8]) -> Result<(VaultHeader, Vec<VaultEntry<'_>>), DecodeError> {
+    let (header, len) = VaultHeader::decode(index)?;
+    let entries = VaultEntries::new(&index[len..]).collect::<Result<Vec<_>, _>>()?;
+    Ok((header, entries))
+}
+
+/// Writes an index holding `entries`, with the count and the bytes used recomputed from them.
+pub fn encode_index(header: &VaultHeader, entries: &[VaultEntry]) -> Vec<u8> {
+    let header = VaultHeader {
+        entry_count: entries.len() as u32,
+        used_bytes: entries.iter().map(|entry| entry.size).sum(),
+        ..*header
+    };
+    let len = VAULT_HEADER_SIZE + entries.iter().map(VaultEntry::encoded_len).sum::<usize>();
+
+    let mut index = alloc::vec![0u8; len];
+    let mut offset = header.encode(&mut index).unwrap_or_default();
+    for entry in entries {
+        offset += entry.encode(&mut index[offset..]).unwrap_or_default();
+    }
+    index.truncate(offset);
+    index
+}
+
+/// Which of `entries`, oldest first, the retention of `header` drops at `now`: those past the
+/// retention, then the oldest ones until the rest fits under the size cap. Applied by
+/// `vault purge` only, never by the driver.
+pub fn expired(header: &VaultHeader, entries: &[VaultEntry], now: u64) -> Vec<bool> {
+    let mut expired: Vec<bool> = entries
+        .iter()
+        .map(|entry| header.is_expired(entry.time, now))
+        .collect();
+
+    let mut used: u64 = entries
+        .iter()
+        .zip(&expired)
+        .filter(|(_, expired)| !**expired)
+        .map(|(entry, _)| entry.size)
+        .sum();
+    for (entry, expired) in entries.iter().zip(expired.iter_mut()) {
+        if header.max_bytes == 0 || used <= header.max_bytes {
+            break;
+        }
+        if !*expired {
+            *expired = true;
+            used -= entry.size;
+        }
+    }
+
+    expired
+}
@@ -0,0 +1,330 @@
+//! Volume attach policy evaluated in `DelProtectInstanceSetup` and the wire format of
+//! `IOCTL_DELPROTECT_SET_VOLUME_POLICY` / `IOCTL_DELPROTECT_LIST_INSTANCES`. The same policy
+//! layout is stored in the `VolumePolicy` REG_BINARY value of the service `Parameters` key.
+//!
+//! Policy layout (little endian):
+//!
+//! ```text
+//! 0   u32  filesystems    bitmask of FLT_FILESYSTEM_TYPE values, 0 = any
+//! 4   u32  media          bitmask of MEDIA_*, 0 = any
+//! 8   u64  device_types   bitmask of DEVICE_TYPE values, 0 = any
+//! 16  u32  drive_letters  bit 0 = A: ... bit 25 = Z:
+//! 20  u16  guid count
+//! 22  u16  reserved
+//! 24  ...  volume GUIDs, 36 ASCII characters each ("xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx")
+//! ```
+
+use crate::{
+    input::DecodeError,
+    wire::{read_u16, read_u32, read_u64},
+};
+
+// FLT_FILESYSTEM_TYPE values used by the client to name filesystems
+pub const FLT_FSTYPE_UNKNOWN: u32 = 0;
+pub const FLT_FSTYPE_RAW: u32 = 1;
+pub const FLT_FSTYPE_NTFS: u32 = 2;
+pub const FLT_FSTYPE_FAT: u32 = 3;
+pub const FLT_FSTYPE_CDFS: u32 = 4;
+pub const FLT_FSTYPE_UDFS: u32 = 5;
+pub const FLT_FSTYPE_LANMAN: u32 = 6;
+pub const FLT_FSTYPE_WEBDAV: u32 = 7;
+pub const FLT_FSTYPE_RDPDR: u32 = 8;
+pub const FLT_FSTYPE_NFS: u32 = 9;
+pub const FLT_FSTYPE_MUP: u32 = 13;
+pub const FLT_FSTYPE_EXFAT: u32 = 22;
+pub const FLT_FSTYPE_CSVFS: u32 = 27;
+pub const FLT_FSTYPE_REFS: u32 = 28;
+
+pub const FILESYSTEM_NAMES: [(&str, u32); 14] = [
+    ("unknown", FLT_FSTYPE_UNKNOWN),
+    ("raw", FLT_FSTYPE_RAW),
+    ("ntfs", FLT_FSTYPE_NTFS),
+    ("fat", FLT_FSTYPE_FAT),
+    ("cdfs", FLT_FSTYPE_CDFS),
+    ("udfs", FLT_FSTYPE_UDFS),
+    ("lanman", FLT_FSTYPE_LANMAN),
+    ("webdav", FLT_FSTYPE_WEBDAV),
+    ("rdpdr", FLT_FSTYPE_RDPDR),
+    ("nfs", FLT_FSTYPE_NFS),
+    ("mup", FLT_FSTYPE_MUP),
+    ("exfat", FLT_FSTYPE_EXFAT),
+    ("csvfs", FLT_FSTYPE_CSVFS),
+    ("refs", FLT_FSTYPE_REFS),
+];
+
+// DEVICE_TYPE values a minifilter instance can be attached to
+pub const FILE_DEVICE_CD_ROM_FILE_SYSTEM: u32 = 0x03;
+pub const FILE_DEVICE_DISK_FILE_SYSTEM: u32 = 0x08;
+pub const FILE_DEVICE_NETWORK_FILE_SYSTEM: u32 = 0x14;
+pub const FILE_DEVICE_TAPE_FILE_SYSTEM: u32 = 0x20;
+pub const FILE_DEVICE_DFS_FILE_SYSTEM: u32 = 0x35;
+
+pub const DEVICE_TYPE_NAMES: [(&str, u32); 5] = [
+    ("cdrom", FILE_DEVICE_CD_ROM_FILE_SYSTEM),
+    ("disk", FILE_DEVICE_DISK_FILE_SYSTEM),
+    ("network", FILE_DEVICE_NETWORK_FILE_SYSTEM),
+    ("tape", FILE_DEVICE_TAPE_FILE_SYSTEM),
+    ("dfs", FILE_DEVICE_DFS_FILE_SYSTEM),
+];
+
+pub const MEDIA_FIXED: u32 = 0x1;
+pub const MEDIA_REMOVABLE: u32 = 0x2;
+pub const MEDIA_NETWORK: u32 = 0x4;
+
+pub const MEDIA_NAMES: [(&str, u32); 3] = [
+    ("fixed", MEDIA_FIXED),
+    ("removable", MEDIA_REMOVABLE),
+    ("network", MEDIA_NETWORK),
+];
+
+pub const GUID_STRING_LEN: usize = 36;
+pub const MAX_VOLUME_GUIDS: usize = 16;
+pub const VOLUME_POLICY_HEADER_SIZE: usize = 24;
+pub const INSTANCE_HEADER_SIZE: usize = 16;
+/// Longest volume name reported by `IOCTL_DELPROTECT_LIST_INSTANCES`, in bytes.
+pub const MAX_VOLUME_NAME_BYTES: usize = 260 * 2;
+
+/// Everything `DelProtectInstanceSetup` knows about a volume that is being attached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VolumeInfo {
+    pub filesystem_type: u32,
+    pub device_type: u32,
+    pub removable: bool,
+    /// Uppercase ASCII letter, 0 if the volume has no drive letter.
+    pub drive_letter: u8,
+    /// Uppercase ASCII GUID without braces, all zeroes if the volume has no GUID name.
+    pub guid: [u8; GUID_STRING_LEN],
+}
+
+impl VolumeInfo {
+    /// Network redirectors are reported as network media regardless of the device type.
+    pub fn media(&self) -> u32 {
+        let redirector = matches!(
+            self.filesystem_type,
+            FLT_FSTYPE_LANMAN
+                | FLT_FSTYPE_WEBDAV
+                | FLT_FSTYPE_RDPDR
+                | FLT_FSTYPE_NFS
+                | FLT_FSTYPE_MUP
+        );
+
+        if redirector || self.device_type == FILE_DEVICE_NETWORK_FILE_SYSTEM {
+            MEDIA_NETWORK
+        } else if self.removable {
+            MEDIA_REMOVABLE
+        } else {
+            MEDIA_FIXED
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VolumePolicy {
+    pub filesystems: u32,
+    pub media: u32,
+    pub device_types: u64,
+    pub drive_letters: u32,
+    pub guid_count: usize,
+    pub guids: [[u8; GUID_STRING_LEN]; MAX_VOLUME_GUIDS],
+}
+
+impl Default for VolumePolicy {
+    /// Attach to every volume, like the driver always did.
+    fn default() -> Self {
+        Self {
+            filesystems: 0,
+            media: 0,
+            device_types: 0,
+            drive_letters: 0,
+            guid_count: 0,
+            guids: [[0; GUID_STRING_LEN]; MAX_VOLUME_GUIDS],
+        }
+    }
+}
+
+impl VolumePolicy {
+    /// Returns true when the volume passes every configured criterion. When drive letters
+    /// or GUIDs are configured, the volume has to match at least one of them.
+    pub fn should_attach(&self, volume: &VolumeInfo) -> bool {
+        if self.filesystems != 0 && !mask_contains(self.filesystems as u64, volume.filesystem_type)
+        {
+            return false;
+        }
+        if self.device_types != 0 && !mask_contains(self.device_types, volume.device_type) {
+            return false;
+        }
+        if self.media != 0 && self.media & volume.media() == 0 {
+            return false;
+        }
+
+        if self.drive_letters == 0 && self.guid_count == 0 {
+            return true;
+        }
+
+        let letter_match = volume.drive_letter.is_ascii_uppercase()
+            && self.drive_letters & (1 << (volume.drive_letter - b'A')) != 0;
+        let guid_match = volume.guid != [0; GUID_STRING_LEN]
+            && self.guids[..self.guid_count].contains(&volume.guid);
+
+        letter_match || guid_match
+    }
+
+    pub fn add_guid(&mut self, guid: &str) -> bool {
+        let Some(guid) = parse_guid(guid.as_bytes()) else {
+            return false;
+        };
+        if self.guid_count >= MAX_VOLUME_GUIDS {
+            return false;
+        }
+
+        self.guids[self.guid_count] = guid;
+        self.guid_count += 1;
+        true
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        VOLUME_POLICY_HEADER_SIZE + self.guid_count * GUID_STRING_LEN
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        if buffer.len() < len || self.guid_count > MAX_VOLUME_GUIDS {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&self.filesystems.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.media.to_le_bytes());
+        buffer[8..16].copy_from_slice(&self.device_types.to_le_bytes());
+        buffer[16..20].copy_from_slice(&self.drive_letters.to_le_bytes());
+        buffer[20..22].copy_from_slice(&(self.guid_count as u16).to_le_bytes());
+        buffer[22..24].copy_from_slice(&0u16.to_le_bytes());
+        for (i, guid) in self.guids[..self.guid_count].iter().enumerate() {
+            let offset = VOLUME_POLICY_HEADER_SIZE + i * GUID_STRING_LEN;
+            buffer[offset..offset + GUID_STRING_LEN].copy_from_slice(guid);
+        }
+
+        Some(len)
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        if buffer.len() < VOLUME_POLICY_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let guid_count = read_u16(buffer, 20) as usize;
+        if guid_count > MAX_VOLUME_GUIDS {
+            return Err(DecodeError::TooLong);
+        }
+        if buffer.len() < VOLUME_POLICY_HEADER_SIZE + guid_count * GUID_STRING_LEN {
+            return Err(DecodeError::Truncated);
+        }
+
+        let mut policy = Self {
+            filesystems: read_u32(buffer, 0),
+            media: read_u32(buffer, 4),
+            device_types: read_u64(buffer, 8),
+            drive_letters: read_u32(buffer, 16),
+            guid_count,
+            ..Self::default()
+        };
+        for i in 0..guid_count {
+            let offset = VOLUME_POLICY_HEADER_SIZE + i * GUID_STRING_LEN;
+            policy.guids[i] = parse_guid(&buffer[offset..offset + GUID_STRING_LEN])
+                .ok_or(DecodeError::InvalidValue)?;
+        }
+
+        Ok(policy)
+    }
+}
+
+fn mask_contains(mask: u64, value: u32) -> bool {
+    value < 64 && mask & (1 << value) != 0
+}
+
+/// Validates a GUID given as ASCII, with or without braces, and returns it uppercased and
+/// without braces.
+pub fn parse_guid(text: &[u8]) -> Option<[u8; GUID_STRING_LEN]> {
+    let text = match text {
+        [b'{', inner @ .., b'}'] => inner,
+        _ => text,
+    };
+    if text.len() != GUID_STRING_LEN {
+        return None;
+    }
+
+    let mut guid = [0u8; GUID_STRING_LEN];
+    for (i, c) in text.iter().enumerate() {
+        let dash = matches!(i, 8 | 13 | 18 | 23);
+        match (dash, *c) {
+            (true, b'-') => guid[i] = b'-',
+            (false, c) if c.is_ascii_hexdigit() => guid[i] = c.to_ascii_uppercase(),
+            _ => return None,
+        }
+    }
+
+    Some(guid)
+}
+
+/// One attached instance as reported by `IOCTL_DELPROTECT_LIST_INSTANCES`.
+///
+/// ```text
+/// 0   u32  filesystem type
+/// 4   u32  device type
+/// 8   u32  media (MEDIA_*)
+/// 12  u8   drive letter, 0 if none
+/// 13  u8   reserved
+/// 14  u16  volume name length in bytes
+/// 16  ...  volume name, UTF-16LE
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstanceRecord<'a> {
+    pub filesystem_type: u32,
+    pub device_type: u32,
+    pub media: u32,
+    pub drive_letter: u8,
+    pub volume_name: &'a [u8],
+}
+
+impl<'a> InstanceRecord<'a> {
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = INSTANCE_HEADER_SIZE + self.volume_name.len();
+        if buffer.len() < len || self.volume_name.len() > MAX_VOLUME_NAME_BYTES {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&self.filesystem_type.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.device_type.to_le_bytes());
+        buffer[8..12].copy_from_slice(&self.media.to_le_bytes());
+        buffer[12] = self.drive_letter;
+        buffer[13] = 0;
+        buffer[14..16].copy_from_slice(&(self.volume_name.len() as u16).to_le_bytes());
+        buffer[INSTANCE_HEADER_SIZE..len].copy_from_slice(self.volume_name);
+
+        Some(len)
+    }
+
+    pub fn decode(buffer: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        if buffer.len() < INSTANCE_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        let name_len = read_u16(buffer, 14) as usize;
+        let len = INSTANCE_HEADER_SIZE + name_len;
+        if !name_len.is_multiple_of(2) {
+            return Err(DecodeError::OddLength);
+        }
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+
+        let record = Self {
+            filesystem_type: read_u32(buffer, 0),
+            device_type: read_u32(buffer, 4),
+            media: read_u32(buffer, 8),
+            drive_letter: buffer[12],
+            volume_name: &buffer[INSTANCE_HEADER_SIZE..len],
+        };
+
+        Ok((record, len))
+    }
+}
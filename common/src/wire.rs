@@ -0,0 +1,89 @@
+//! Little endian helpers shared by the wire formats of this crate.
+
+use alloc::string::String;
+
+use crate::input::DecodeError;
+
+pub const LIST_HEADER_SIZE: usize = 16;
+
+/// Header written in front of the records returned by the list IOCTLs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ListHeader {
+    /// Number of entries the driver had, including the ones that did not fit in the buffer.
+    pub total: u32,
+    /// Number of records following the header.
+    pub returned: u32,
+    /// Expired rules purged by `IOCTL_DELPROTECT_LIST_RULES`, or events dropped because the
+    /// queue was full since the previous `IOCTL_DELPROTECT_GET_EVENTS`.
+    pub removed: u32,
+}
+
+impl ListHeader {
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        if buffer.len() < LIST_HEADER_SIZE {
+            return None;
+        }
+
+        buffer[0..4].copy_from_slice(&self.total.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.returned.to_le_bytes());
+        buffer[8..12].copy_from_slice(&self.removed.to_le_bytes());
+        buffer[12..16].copy_from_slice(&0u32.to_le_bytes());
+
+        Some(LIST_HEADER_SIZE)
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, DecodeError> {
+        if buffer.len() < LIST_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+
+        Ok(Self {
+            total: read_u32(buffer, 0),
+            returned: read_u32(buffer, 4),
+            removed: read_u32(buffer, 8),
+        })
+    }
+}
+
+/// Writes `s` as UTF-16LE into `buffer`, truncating it if it does not fit, and returns the
+/// number of bytes written.
+pub fn write_utf16(s: &str, buffer: &mut [u8]) -> usize {
+    let mut len = 0;
+    for unit in s.encode_utf16() {
+        if len + 2 > buffer.len() {
+            break;
+        }
+        buffer[len..len + 2].copy_from_slice(&unit.to_le_bytes());
+        len += 2;
+    }
+    len
+}
+
+/// Decodes UTF-16LE bytes, unpaired surrogates become U+FFFD. `None` if there is no memory.
+pub fn read_utf16(bytes: &[u8]) -> Option<String> {
+    let mut s = String::new();
+    s.try_reserve_exact(bytes.len() / 2 * 3).ok()?;
+    let units = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]));
+    for c in char::decode_utf16(units) {
+        s.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    Some(s)
+}
+
+pub(crate) fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+pub(crate) fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buffer[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+pub(crate) fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buffer[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
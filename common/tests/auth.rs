@@ -0,0 +1,122 @@
+//! Known answers of FIPS 180-4 (the SHA-256 examples of the NIST CSRC) and RFC 4231.
+
+use common::auth::{hmac_sha256, response, verify_response, Sha256, CHALLENGE_SIZE, RESPONSE_SIZE};
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn sha256(message: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(message);
+    hex(&hasher.finish())
+}
+
+#[test]
+fn sha256_of_the_fips_180_4_examples() {
+    assert_eq!(
+        sha256(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        sha256(b""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    // 448 bits, the padding needs a second block
+    assert_eq!(
+        sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+    assert_eq!(
+        sha256(
+            b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmno\
+              ijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu"
+        ),
+        "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1"
+    );
+}
+
+#[test]
+fn sha256_of_a_million_a_in_uneven_updates() {
+    let message = vec![b'a'; 1_000_000];
+    let mut hasher = Sha256::new();
+    // chunks which never line up with the 64 byte blocks
+    for chunk in message.chunks(997) {
+        hasher.update(chunk);
+    }
+
+    assert_eq!(
+        hex(&hasher.finish()),
+        "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+    );
+}
+
+#[test]
+fn hmac_sha256_of_rfc_4231() {
+    let cases: [(Vec<u8>, &[u8], &str); 6] = [
+        (
+            vec![0x0b; 20],
+            b"Hi There",
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+        ),
+        (
+            b"Jefe".to_vec(),
+            b"what do ya want for nothing?",
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        ),
+        (
+            vec![0xaa; 20],
+            &[0xdd; 50],
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+        ),
+        (
+            (1..=25).collect(),
+            &[0xcd; 50],
+            "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+        ),
+        // keys longer than a block are hashed first
+        (
+            vec![0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First",
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+        ),
+        (
+            vec![0xaa; 131],
+            b"This is a test using a larger than block-size key and a larger than block-size \
+              data. The key needs to be hashed before being used by the HMAC algorithm.",
+            "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+        ),
+    ];
+
+    for (key, message, expected) in cases {
+        assert_eq!(hex(&hmac_sha256(&key, message)), expected);
+    }
+}
+
+#[test]
+fn hmac_sha256_truncated_to_128_bits() {
+    // test case 5, which only specifies the first 128 bits
+    let mac = hmac_sha256(&[0x0c; 20], b"Test With Truncation");
+
+    assert_eq!(hex(&mac[..16]), "a3b6167473100ee06e0c796c2955552b");
+}
+
+#[test]
+fn response_is_only_verified_with_the_same_secret_and_challenge() {
+    let secret = b"0123456789abcdef";
+    let challenge = [0x5a; CHALLENGE_SIZE];
+    let answer = response(secret, &challenge);
+
+    assert!(verify_response(secret, &challenge, &answer));
+    assert!(!verify_response(b"fedcba9876543210", &challenge, &answer));
+    assert!(!verify_response(secret, &[0xa5; CHALLENGE_SIZE], &answer));
+
+    let mut flipped = answer;
+    flipped[RESPONSE_SIZE - 1] ^= 1;
+    assert!(!verify_response(secret, &challenge, &flipped));
+    assert!(!verify_response(
+        secret,
+        &challenge,
+        &answer[..RESPONSE_SIZE - 1]
+    ));
+}
@@ -0,0 +1,116 @@
+use common::{
+    input::{
+        check_input, check_output, exact, exe_name_utf16, exe_name_utf8, flags, non_empty,
+        DecodeError, MAX_INPUT_SIZE,
+    },
+    rule::MAX_PROCESS_NAME_BYTES,
+};
+
+fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+#[test]
+fn input_is_checked_before_a_slice_is_built() {
+    assert_eq!(check_input(false, 0), Ok(()));
+    assert_eq!(check_input(true, 0), Ok(()));
+    assert_eq!(check_input(false, MAX_INPUT_SIZE), Ok(()));
+
+    assert_eq!(check_input(true, 1), Err(DecodeError::NullBuffer));
+    assert_eq!(
+        check_input(false, MAX_INPUT_SIZE + 1),
+        Err(DecodeError::TooLong)
+    );
+    // the size is checked first, a NULL buffer cannot be that long either
+    assert_eq!(
+        check_input(true, MAX_INPUT_SIZE + 1),
+        Err(DecodeError::TooLong)
+    );
+}
+
+#[test]
+fn output_has_to_hold_the_smallest_answer() {
+    assert_eq!(check_output(false, 16, 16), Ok(()));
+    assert_eq!(check_output(false, 0, 0), Ok(()));
+
+    assert_eq!(
+        check_output(false, 15, 16),
+        Err(DecodeError::OutputTooSmall)
+    );
+    assert_eq!(check_output(true, 16, 16), Err(DecodeError::OutputTooSmall));
+    assert_eq!(check_output(true, 0, 0), Err(DecodeError::OutputTooSmall));
+    assert!(DecodeError::OutputTooSmall.is_buffer_too_small());
+    assert!(!DecodeError::Truncated.is_buffer_too_small());
+}
+
+#[test]
+fn utf8_names_lose_their_terminating_nulls() {
+    assert_eq!(exe_name_utf8(b"cmd.exe"), Ok("cmd.exe"));
+    assert_eq!(exe_name_utf8(b"cmd.exe\0\0"), Ok("cmd.exe"));
+}
+
+#[test]
+fn malformed_utf8_names_are_rejected() {
+    let longest = vec![b'a'; MAX_PROCESS_NAME_BYTES];
+    let too_long = vec![b'a'; MAX_PROCESS_NAME_BYTES + 1];
+
+    assert_eq!(exe_name_utf8(&longest).map(str::len), Ok(longest.len()));
+    assert_eq!(exe_name_utf8(b""), Err(DecodeError::Empty));
+    assert_eq!(exe_name_utf8(b"\0\0\0"), Err(DecodeError::Empty));
+    assert_eq!(exe_name_utf8(&too_long), Err(DecodeError::TooLong));
+    assert_eq!(
+        exe_name_utf8(&[b'c', 0xff, b'd']),
+        Err(DecodeError::InvalidEncoding)
+    );
+}
+
+#[test]
+fn utf16_names_lose_their_terminating_nulls() {
+    let mut name = utf16("explorer.exe");
+    assert_eq!(exe_name_utf16(&name).as_deref(), Ok("explorer.exe"));
+
+    name.extend_from_slice(&[0, 0, 0, 0]);
+    assert_eq!(exe_name_utf16(&name).as_deref(), Ok("explorer.exe"));
+    // a unit with a zero low byte is not a terminator
+    assert_eq!(
+        exe_name_utf16(&utf16("a\u{100}")).as_deref(),
+        Ok("a\u{100}")
+    );
+}
+
+#[test]
+fn malformed_utf16_names_are_rejected() {
+    let longest = utf16(&"a".repeat(MAX_PROCESS_NAME_BYTES / 2));
+    let too_long = utf16(&"a".repeat(MAX_PROCESS_NAME_BYTES / 2 + 1));
+
+    assert!(exe_name_utf16(&longest).is_ok());
+    assert_eq!(exe_name_utf16(&[]), Err(DecodeError::Empty));
+    assert_eq!(exe_name_utf16(&[0, 0, 0, 0]), Err(DecodeError::Empty));
+    assert_eq!(exe_name_utf16(b"c\0m"), Err(DecodeError::OddLength));
+    // odd length is refused even when the extra byte is a null
+    assert_eq!(exe_name_utf16(b"c\0\0"), Err(DecodeError::OddLength));
+    assert_eq!(exe_name_utf16(&too_long), Err(DecodeError::TooLong));
+    // an unpaired high surrogate
+    assert_eq!(
+        exe_name_utf16(&[0x00, 0xd8, b'a', 0]),
+        Err(DecodeError::InvalidEncoding)
+    );
+}
+
+#[test]
+fn flags_are_optional_but_exactly_four_bytes() {
+    assert_eq!(flags(&[]), Ok(0));
+    assert_eq!(flags(&5u32.to_le_bytes()), Ok(5));
+    assert_eq!(flags(&[1, 0]), Err(DecodeError::Truncated));
+    assert_eq!(flags(&[0; 5]), Err(DecodeError::TooLong));
+}
+
+#[test]
+fn fixed_size_inputs_match_exactly() {
+    assert_eq!(exact(&[1; 4], 4), Ok(&[1u8; 4][..]));
+    assert_eq!(exact(&[], 4), Err(DecodeError::Empty));
+    assert_eq!(exact(&[1; 3], 4), Err(DecodeError::Truncated));
+    assert_eq!(exact(&[1; 5], 4), Err(DecodeError::TooLong));
+    assert_eq!(non_empty(&[]), Err(DecodeError::Empty));
+    assert_eq!(non_empty(&[1]), Ok(&[1u8][..]));
+}
@@ -0,0 +1,52 @@
+use common::protection::{
+    cleared_attributes, describe, weakened_security, ATTRIBUTE_NAMES, SECURITY_NAMES,
+};
+
+const OWNER: u32 = 0x01;
+const GROUP: u32 = 0x02;
+const DACL: u32 = 0x04;
+const SACL: u32 = 0x08;
+const LABEL: u32 = 0x10;
+/// UNPROTECTED_DACL_SECURITY_INFORMATION, sent along with the DACL by `icacls /inheritance:e`.
+const UNPROTECTED_DACL: u32 = 0x2000_0000;
+
+const READONLY: u32 = 0x01;
+const HIDDEN: u32 = 0x02;
+const SYSTEM: u32 = 0x04;
+const ARCHIVE: u32 = 0x20;
+const NORMAL: u32 = 0x80;
+
+#[test]
+fn owner_dacl_and_label_changes_weaken_the_file() {
+    assert_eq!(weakened_security(OWNER), OWNER);
+    assert_eq!(weakened_security(DACL | UNPROTECTED_DACL), DACL);
+    assert_eq!(
+        weakened_security(OWNER | GROUP | DACL | LABEL),
+        OWNER | DACL | LABEL
+    );
+    assert_eq!(weakened_security(GROUP | SACL), 0);
+}
+
+#[test]
+fn only_clearing_readonly_or_system_weakens_the_file() {
+    // attrib -r
+    assert_eq!(cleared_attributes(READONLY | ARCHIVE, ARCHIVE), READONLY);
+    assert_eq!(
+        cleared_attributes(READONLY | SYSTEM | HIDDEN, NORMAL),
+        READONLY | SYSTEM
+    );
+    // attrib +r, or -h on a read-only file
+    assert_eq!(cleared_attributes(ARCHIVE, READONLY | ARCHIVE), 0);
+    assert_eq!(cleared_attributes(READONLY | HIDDEN, READONLY), 0);
+    // FileAttributes 0 changes nothing, e.g. SetFileTime
+    assert_eq!(cleared_attributes(READONLY | SYSTEM, 0), 0);
+}
+
+#[test]
+fn changes_are_described_in_table_order() {
+    let names: Vec<_> = describe(&SECURITY_NAMES, LABEL | OWNER).collect();
+    assert_eq!(names, ["owner", "label"]);
+    let names: Vec<_> = describe(&ATTRIBUTE_NAMES, SYSTEM | READONLY).collect();
+    assert_eq!(names, ["readonly", "system"]);
+    assert_eq!(describe(&ATTRIBUTE_NAMES, 0).count(), 0);
+}
@@ -0,0 +1,193 @@
+use common::schedule::{
+    filetime_from_utc, utc_from_filetime, RuleState, TimeWindow, WeeklySchedule, EVERY_DAY, FRIDAY,
+    MONDAY, SATURDAY, SUNDAY, TICKS_PER_MINUTE,
+};
+
+/// 2026-10-19, a Monday.
+fn monday(hour: u32, minute: u32) -> u64 {
+    filetime_from_utc(2026, 10, 19, hour, minute).unwrap()
+}
+
+fn schedule(days: u8, start: (u16, u16), end: (u16, u16)) -> WeeklySchedule {
+    WeeklySchedule {
+        days,
+        start_minute: start.0 * 60 + start.1,
+        end_minute: end.0 * 60 + end.1,
+    }
+}
+
+#[test]
+fn known_filetimes_round_trip() {
+    for (utc, ticks) in [
+        ((1601, 1, 1, 0, 0), 0),
+        ((1970, 1, 1, 0, 0), 116_444_736_000_000_000),
+        ((2000, 1, 1, 0, 0), 125_911_584_000_000_000),
+        ((2024, 2, 29, 12, 34), 133_536_836_400_000_000),
+        ((2026, 10, 19, 23, 59), 134_369_279_400_000_000),
+        ((9999, 12, 31, 23, 59), 2_650_467_743_400_000_000),
+    ] {
+        let (year, month, day, hour, minute) = utc;
+        assert_eq!(
+            filetime_from_utc(year, month, day, hour, minute),
+            Some(ticks)
+        );
+        assert_eq!(utc_from_filetime(ticks), utc);
+    }
+    // the seconds below a minute are dropped
+    assert_eq!(
+        utc_from_filetime(116_444_736_000_000_000 + TICKS_PER_MINUTE - 1),
+        (1970, 1, 1, 0, 0)
+    );
+}
+
+#[test]
+fn invalid_dates_have_no_filetime() {
+    for (year, month, day, hour, minute) in [
+        (1600, 12, 31, 0, 0),
+        (2026, 0, 1, 0, 0),
+        (2026, 13, 1, 0, 0),
+        (2026, 4, 31, 0, 0),
+        (2026, 2, 29, 0, 0),
+        (1900, 2, 29, 0, 0),
+        (2026, 1, 0, 0, 0),
+        (2026, 1, 1, 24, 0),
+        (2026, 1, 1, 0, 60),
+    ] {
+        assert_eq!(filetime_from_utc(year, month, day, hour, minute), None);
+    }
+    assert!(filetime_from_utc(2000, 2, 29, 0, 0).is_some());
+}
+
+#[test]
+fn hours_include_the_start_and_exclude_the_end() {
+    let office = schedule(MONDAY, (8, 0), (18, 0));
+
+    assert!(!office.contains(monday(7, 59)));
+    assert!(office.contains(monday(8, 0)));
+    assert!(office.contains(monday(17, 59)));
+    assert!(!office.contains(monday(18, 0)));
+}
+
+#[test]
+fn hours_wrap_past_midnight_into_the_next_day() {
+    // Friday 22:00 to Saturday 06:00
+    let night = schedule(FRIDAY, (22, 0), (6, 0));
+    let friday = |hour| filetime_from_utc(2026, 10, 23, hour, 0).unwrap();
+    let saturday = |hour| filetime_from_utc(2026, 10, 24, hour, 0).unwrap();
+
+    assert!(!night.contains(friday(21)));
+    assert!(night.contains(friday(22)));
+    assert!(night.contains(friday(23)));
+    assert!(night.contains(saturday(0)));
+    assert!(night.contains(saturday(5)));
+    assert!(!night.contains(saturday(6)));
+    assert!(!night.contains(saturday(22)));
+    // the early hours of Friday belong to a Thursday night
+    assert!(!night.contains(friday(1)));
+}
+
+#[test]
+fn night_of_the_last_day_wraps_into_sunday() {
+    let night = schedule(SATURDAY, (23, 0), (1, 0));
+    let sunday = filetime_from_utc(2026, 10, 25, 0, 30).unwrap();
+
+    assert!(night.contains(sunday));
+    assert!(!night.contains(sunday + 60 * TICKS_PER_MINUTE));
+}
+
+#[test]
+fn equal_start_and_end_cover_the_whole_day() {
+    let sunday = schedule(SUNDAY, (9, 0), (9, 0));
+    let day = |day, hour, minute| filetime_from_utc(2026, 10, day, hour, minute).unwrap();
+
+    assert!(sunday.contains(day(25, 0, 0)));
+    assert!(sunday.contains(day(25, 8, 59)));
+    assert!(sunday.contains(day(25, 23, 59)));
+    assert!(!sunday.contains(day(24, 23, 59)));
+    assert!(!sunday.contains(day(26, 0, 0)));
+}
+
+#[test]
+fn days_change_at_midnight_utc() {
+    let mondays = schedule(MONDAY, (0, 0), (0, 0));
+
+    assert!(!mondays.contains(monday(0, 0) - 1));
+    assert!(mondays.contains(monday(0, 0)));
+    assert!(mondays.contains(monday(23, 59)));
+    assert!(!mondays.contains(monday(0, 0) + 24 * 60 * TICKS_PER_MINUTE));
+    // 1601-01-01, day 0 of the epoch, was a Monday
+    assert!(mondays.contains(0));
+}
+
+#[test]
+fn no_days_means_no_schedule() {
+    let always = WeeklySchedule::default();
+
+    assert!(always.contains(0));
+    assert!(always.contains(monday(3, 0)));
+}
+
+#[test]
+fn schedules_out_of_range_are_invalid() {
+    assert!(schedule(EVERY_DAY, (0, 0), (23, 59)).is_valid());
+    assert!(!schedule(EVERY_DAY, (24, 0), (1, 0)).is_valid());
+    assert!(!schedule(EVERY_DAY, (0, 0), (24, 0)).is_valid());
+    assert!(!schedule(0x80, (0, 0), (1, 0)).is_valid());
+}
+
+#[test]
+fn validity_period_bounds_the_rule() {
+    let window = TimeWindow {
+        not_before: monday(8, 0),
+        not_after: monday(18, 0),
+        schedule: WeeklySchedule::default(),
+    };
+
+    assert_eq!(window.evaluate(monday(7, 59)), RuleState::Pending);
+    assert_eq!(window.evaluate(monday(8, 0)), RuleState::Active);
+    assert_eq!(window.evaluate(monday(17, 59)), RuleState::Active);
+    // not_after is exclusive
+    assert_eq!(window.evaluate(monday(18, 0)), RuleState::Expired);
+    assert_eq!(window.evaluate(u64::MAX), RuleState::Expired);
+}
+
+#[test]
+fn unset_bounds_never_expire() {
+    let window = TimeWindow::default();
+
+    assert_eq!(window.evaluate(0), RuleState::Active);
+    assert_eq!(window.evaluate(u64::MAX), RuleState::Active);
+}
+
+#[test]
+fn schedule_idles_inside_the_validity_period() {
+    let window = TimeWindow {
+        not_before: monday(0, 0),
+        not_after: monday(0, 0) + 7 * 24 * 60 * TICKS_PER_MINUTE,
+        schedule: schedule(MONDAY, (8, 0), (18, 0)),
+    };
+
+    assert_eq!(window.evaluate(monday(12, 0)), RuleState::Active);
+    assert_eq!(window.evaluate(monday(20, 0)), RuleState::Idle);
+    // expiry wins over the schedule
+    assert_eq!(
+        window.evaluate(window.not_after + 8 * 60 * TICKS_PER_MINUTE),
+        RuleState::Expired
+    );
+}
+
+#[test]
+fn not_before_must_precede_not_after() {
+    let mut window = TimeWindow {
+        not_before: monday(8, 0),
+        not_after: monday(8, 0),
+        schedule: WeeklySchedule::default(),
+    };
+    assert!(!window.is_valid());
+
+    window.not_after = monday(8, 1);
+    assert!(window.is_valid());
+    window.not_before = 0;
+    window.not_after = 0;
+    assert!(window.is_valid());
+}
@@ -0,0 +1,48 @@
+use common::truncation::{is_destructive, SizeInformation};
+
+const ALL: [SizeInformation; 3] = [
+    SizeInformation::Allocation,
+    SizeInformation::EndOfFile,
+    SizeInformation::ValidDataLength,
+];
+
+#[test]
+fn classes_round_trip() {
+    for information in ALL {
+        assert_eq!(
+            SizeInformation::from_class(information as u32),
+            Some(information)
+        );
+    }
+    assert_eq!(SizeInformation::EndOfFile.as_str(), "end-of-file");
+    // FileBasicInformation, FileDispositionInformation, FileRenameInformation
+    for class in [4, 13, 10] {
+        assert_eq!(SizeInformation::from_class(class), None);
+    }
+}
+
+#[test]
+fn shrinking_below_the_end_of_file_is_destructive() {
+    for information in ALL {
+        assert!(is_destructive(information, 0, 4096));
+        assert!(is_destructive(information, 4095, 4096));
+    }
+}
+
+#[test]
+fn keeping_or_growing_the_file_is_not() {
+    for information in ALL {
+        assert!(!is_destructive(information, 4096, 4096));
+        assert!(!is_destructive(information, 1 << 20, 4096));
+        // nothing to lose in an empty file
+        assert!(!is_destructive(information, 0, 0));
+    }
+}
+
+#[test]
+fn negative_sizes_are_not_destructive() {
+    for information in ALL {
+        assert!(!is_destructive(information, -1, 4096));
+        assert!(!is_destructive(information, i64::MIN, 4096));
+    }
+}
@@ -0,0 +1,122 @@
+//! Journal streams in `fixtures/`: `v2_delete.bin` is a delete of `report.docx` in the root
+//! directory (a FILE_DELETE record and its FILE_DELETE | CLOSE record), `v3_rename.bin` a
+//! rename of `draft.txt` to `final.txt` with 128 bit references, `j_extract.bin` a `$J`
+//! extract starting with 4096 zero bytes and `truncated.bin` a record followed by a cut one.
+
+use common::{
+    input::DecodeError,
+    schedule::filetime_from_utc,
+    usn::{
+        UsnRecord, UsnRecords, USN_REASON_CLOSE, USN_REASON_FILE_CREATE, USN_REASON_FILE_DELETE,
+        USN_REASON_RENAME_NEW_NAME, USN_REASON_RENAME_OLD_NAME,
+    },
+};
+
+const V2_DELETE: &[u8] = include_bytes!("fixtures/v2_delete.bin");
+const V3_RENAME: &[u8] = include_bytes!("fixtures/v3_rename.bin");
+const J_EXTRACT: &[u8] = include_bytes!("fixtures/j_extract.bin");
+const TRUNCATED: &[u8] = include_bytes!("fixtures/truncated.bin");
+
+const ROOT: u128 = 0x0005_0000_0000_0005;
+
+fn name(record: &UsnRecord) -> String {
+    String::from_utf16(&record.file_name_units().collect::<Vec<_>>()).unwrap()
+}
+
+fn records(stream: &[u8]) -> Vec<UsnRecord<'_>> {
+    UsnRecords::new(stream).map(Result::unwrap).collect()
+}
+
+#[test]
+fn v2_record_fields_are_decoded() {
+    let (record, length) = UsnRecord::decode(V2_DELETE).unwrap();
+
+    assert_eq!(length, 88);
+    assert_eq!(record.major_version, 2);
+    assert_eq!(record.file_reference, 0x0001_0000_0000_1234);
+    assert_eq!(record.parent_reference, ROOT);
+    assert_eq!(record.usn, 0x1000);
+    assert_eq!(
+        record.time_stamp,
+        filetime_from_utc(2026, 10, 21, 9, 30).unwrap() + 12 * 10_000_000
+    );
+    assert_eq!(record.reason, USN_REASON_FILE_DELETE);
+    assert_eq!(record.file_attributes, 0x20);
+    assert_eq!(name(&record), "report.docx");
+}
+
+#[test]
+fn v2_stream_yields_every_record() {
+    let records = records(V2_DELETE);
+
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1].usn, 0x1060);
+    assert!(records[1].has_reason(USN_REASON_FILE_DELETE));
+    assert!(records[1].has_reason(USN_REASON_CLOSE));
+}
+
+#[test]
+fn v3_records_keep_128_bit_references() {
+    let records = records(V3_RENAME);
+
+    assert_eq!(records.len(), 3);
+    assert!(records.iter().all(|r| r.major_version == 3));
+    assert_eq!(
+        records[0].file_reference,
+        0x0000_0000_0000_0011_0000_0000_0000_2233
+    );
+    assert_eq!(
+        records[0].parent_reference,
+        0x0000_0000_0000_0001_0000_0000_0000_0005
+    );
+    assert_eq!(records[0].reason, USN_REASON_RENAME_OLD_NAME);
+    assert_eq!(name(&records[0]), "draft.txt");
+    assert_eq!(records[1].reason, USN_REASON_RENAME_NEW_NAME);
+    assert_eq!(name(&records[1]), "final.txt");
+}
+
+#[test]
+fn zeroes_of_a_j_extract_are_skipped() {
+    let records = records(J_EXTRACT);
+
+    let names: Vec<String> = records.iter().map(name).collect();
+    assert_eq!(names, ["work", "a.txt", "b.txt"]);
+    assert!(records[0].has_reason(USN_REASON_FILE_CREATE));
+    assert_eq!(records[1].parent_reference, records[0].file_reference);
+    assert_eq!(records[2].major_version, 3);
+}
+
+#[test]
+fn truncated_record_ends_the_stream_with_an_error() {
+    let mut records = UsnRecords::new(TRUNCATED);
+
+    assert_eq!(name(&records.next().unwrap().unwrap()), "whole.txt");
+    let offset = records.offset();
+    assert_eq!(records.next(), Some(Err(DecodeError::Truncated)));
+    assert_eq!(records.next(), None);
+    assert_eq!(records.offset(), offset);
+}
+
+#[test]
+fn unknown_version_is_rejected() {
+    let mut record = V2_DELETE[..88].to_vec();
+    record[4] = 4;
+
+    assert_eq!(UsnRecord::decode(&record), Err(DecodeError::InvalidValue));
+}
+
+#[test]
+fn name_outside_of_the_record_is_rejected() {
+    let mut record = V2_DELETE[..88].to_vec();
+    record[56] = 80;
+
+    assert_eq!(UsnRecord::decode(&record), Err(DecodeError::InvalidValue));
+}
+
+#[test]
+fn unaligned_length_is_rejected() {
+    let mut record = V2_DELETE[..88].to_vec();
+    record[0] = 84;
+
+    assert_eq!(UsnRecord::decode(&record), Err(DecodeError::InvalidValue));
+}
@@ -0,0 +1,168 @@
+use common::{
+    input::DecodeError,
+    schedule::{filetime_from_utc, TICKS_PER_DAY},
+    vault::{
+        copy_name, decode_index, encode_index, expired, is_vault_path, VaultEntry, VaultHeader,
+        DEFAULT_MAX_BYTES, DEFAULT_RETENTION_DAYS, VAULT_ENTRY_HEADER_SIZE, VAULT_HEADER_SIZE,
+    },
+};
+
+fn utf16(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+fn entry<'a>(id: u64, time: u64, size: u64, path: &'a [u8], process: &'a [u8]) -> VaultEntry<'a> {
+    VaultEntry {
+        id,
+        time,
+        size,
+        process_id: 4242,
+        rule_id: 7,
+        sha256: [id as u8; 32],
+        path,
+        process,
+    }
+}
+
+#[test]
+fn entry_round_trips() {
+    let path = utf16(r"\Device\HarddiskVolume3\Data\report.docx");
+    let process = utf16(r"\Device\HarddiskVolume3\Windows\System32\cmd.exe");
+    let time = filetime_from_utc(2026, 10, 19, 9, 30).unwrap();
+    let original = entry(time, time, 12_345, &path, &process);
+
+    let mut buffer = vec![0u8; original.encoded_len() + 8];
+    let len = original.encode(&mut buffer).unwrap();
+    assert_eq!(len, VAULT_ENTRY_HEADER_SIZE + path.len() + process.len());
+
+    let (decoded, decoded_len) = VaultEntry::decode(&buffer).unwrap();
+    assert_eq!(decoded, original);
+    assert_eq!(decoded_len, len);
+}
+
+#[test]
+fn malformed_entries_are_rejected() {
+    let path = utf16(r"\Device\HarddiskVolume3\x");
+    let original = entry(1, 1, 1, &path, &[]);
+    let mut buffer = vec![0u8; original.encoded_len()];
+    original.encode(&mut buffer).unwrap();
+
+    assert_eq!(
+        VaultEntry::decode(&buffer[..buffer.len() - 1]),
+        Err(DecodeError::Truncated)
+    );
+
+    let mut odd = buffer.clone();
+    odd[36] = 3;
+    assert_eq!(VaultEntry::decode(&odd), Err(DecodeError::OddLength));
+
+    // a size shorter than its strings would make the next entry overlap this one
+    let mut short = buffer.clone();
+    short[0..4].copy_from_slice(&(VAULT_ENTRY_HEADER_SIZE as u32).to_le_bytes());
+    assert_eq!(VaultEntry::decode(&short), Err(DecodeError::InvalidValue));
+}
+
+#[test]
+fn header_of_a_new_vault_has_the_defaults() {
+    let header = VaultHeader::default();
+    let mut buffer = [0u8; VAULT_HEADER_SIZE];
+    header.encode(&mut buffer).unwrap();
+
+    assert_eq!(&buffer[0..4], b"DPVI");
+    assert_eq!(
+        VaultHeader::decode(&buffer),
+        Ok((header, VAULT_HEADER_SIZE))
+    );
+    assert_eq!(header.retention_days, DEFAULT_RETENTION_DAYS);
+    assert_eq!(header.max_bytes, DEFAULT_MAX_BYTES);
+
+    buffer[0] = b'X';
+    assert_eq!(VaultHeader::decode(&buffer), Err(DecodeError::InvalidValue));
+}
+
+#[test]
+fn index_is_rebuilt_with_recomputed_totals() {
+    let paths: Vec<Vec<u8>> = ["a.txt", "b.txt", "c.txt"].map(utf16).to_vec();
+    let process = utf16("cmd.exe");
+    let entries: Vec<VaultEntry> = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| entry(i as u64 + 1, 0, 100 * (i as u64 + 1), path, &process))
+        .collect();
+    let stale = VaultHeader {
+        retention_days: 7,
+        entry_count: 99,
+        max_bytes: 0,
+        used_bytes: 1,
+    };
+
+    // purging the middle entry keeps the others in order
+    let kept = [entries[0], entries[2]];
+    let index = encode_index(&stale, &kept);
+    let (header, decoded) = decode_index(&index).unwrap();
+
+    assert_eq!(header.retention_days, 7);
+    assert_eq!(header.entry_count, 2);
+    assert_eq!(header.used_bytes, 400);
+    assert_eq!(decoded, kept);
+}
+
+#[test]
+fn retention_drops_old_entries_then_the_oldest_above_the_cap() {
+    let now = filetime_from_utc(2026, 10, 19, 0, 0).unwrap();
+    let process = utf16("cmd.exe");
+    let entries = [
+        entry(1, now - 40 * TICKS_PER_DAY, 10, &[], &process),
+        entry(2, now - 20 * TICKS_PER_DAY, 500, &[], &process),
+        entry(3, now - 10 * TICKS_PER_DAY, 300, &[], &process),
+        entry(4, now - TICKS_PER_DAY, 400, &[], &process),
+    ];
+    let header = VaultHeader {
+        retention_days: 30,
+        max_bytes: 800,
+        ..VaultHeader::default()
+    };
+
+    assert_eq!(expired(&header, &entries, now), [true, true, false, false]);
+
+    let keep_all = VaultHeader {
+        retention_days: 0,
+        max_bytes: 0,
+        ..header
+    };
+    assert_eq!(expired(&keep_all, &entries, now), [false; 4]);
+}
+
+#[test]
+fn size_cap_refuses_what_does_not_fit() {
+    let header = VaultHeader {
+        max_bytes: 1000,
+        used_bytes: 900,
+        ..VaultHeader::default()
+    };
+    assert!(header.has_room(100));
+    assert!(!header.has_room(101));
+    assert!(!header.is_full());
+
+    let unlimited = VaultHeader {
+        max_bytes: 0,
+        used_bytes: u64::MAX,
+        ..header
+    };
+    assert!(unlimited.has_room(u64::MAX));
+    assert!(!unlimited.is_full());
+}
+
+#[test]
+fn copies_are_named_after_their_id_and_recognized() {
+    assert_eq!(copy_name(0x01dc_4a2b_0000_0001), "01dc4a2b00000001");
+    assert!(is_vault_path(
+        r"\Device\HarddiskVolume3\$DelProtectVault\01dc4a2b00000001"
+    ));
+    assert!(is_vault_path(
+        r"\Device\HarddiskVolume3\$delprotectvault\index.dat"
+    ));
+    assert!(!is_vault_path(
+        r"\Device\HarddiskVolume3\Data\$DelProtectVault.txt"
+    ));
+}
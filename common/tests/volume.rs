@@ -0,0 +1,272 @@
+use common::{
+    input::DecodeError,
+    volume::{
+        parse_guid, InstanceRecord, VolumeInfo, VolumePolicy, FILE_DEVICE_CD_ROM_FILE_SYSTEM,
+        FILE_DEVICE_DISK_FILE_SYSTEM, FILE_DEVICE_NETWORK_FILE_SYSTEM, FLT_FSTYPE_FAT,
+        FLT_FSTYPE_LANMAN, FLT_FSTYPE_NTFS, FLT_FSTYPE_REFS, GUID_STRING_LEN, INSTANCE_HEADER_SIZE,
+        MAX_VOLUME_GUIDS, MEDIA_FIXED, MEDIA_NETWORK, MEDIA_REMOVABLE, VOLUME_POLICY_HEADER_SIZE,
+    },
+};
+
+const GUID: &str = "5C1F6A32-0D4E-4B7A-9F21-3E8D2C6B1A90";
+const OTHER_GUID: &str = "0A0B0C0D-1111-2222-3333-444455556666";
+
+fn guid(text: &str) -> [u8; GUID_STRING_LEN] {
+    parse_guid(text.as_bytes()).unwrap()
+}
+
+/// A fixed NTFS volume C: with the name `GUID`.
+fn fixed_ntfs() -> VolumeInfo {
+    VolumeInfo {
+        filesystem_type: FLT_FSTYPE_NTFS,
+        device_type: FILE_DEVICE_DISK_FILE_SYSTEM,
+        removable: false,
+        drive_letter: b'C',
+        guid: guid(GUID),
+    }
+}
+
+fn letters(letters: &str) -> u32 {
+    letters
+        .bytes()
+        .fold(0, |mask, letter| mask | 1 << (letter - b'A'))
+}
+
+#[test]
+fn guids_are_uppercased_without_braces() {
+    let expected = *b"5C1F6A32-0D4E-4B7A-9F21-3E8D2C6B1A90";
+
+    assert_eq!(parse_guid(GUID.as_bytes()), Some(expected));
+    assert_eq!(
+        parse_guid(b"{5c1f6a32-0d4e-4b7a-9f21-3e8d2c6b1a90}"),
+        Some(expected)
+    );
+}
+
+#[test]
+fn malformed_guids_are_rejected() {
+    for text in [
+        "",
+        "5C1F6A32-0D4E-4B7A-9F21-3E8D2C6B1A9",
+        "5C1F6A32-0D4E-4B7A-9F21-3E8D2C6B1A900",
+        "5C1F6A32:0D4E-4B7A-9F21-3E8D2C6B1A90",
+        "5C1F6A320-D4E-4B7A-9F21-3E8D2C6B1A90",
+        "5C1F6A32-0D4E-4B7A-9F21-3E8D2C6B1AG0",
+        "{5C1F6A32-0D4E-4B7A-9F21-3E8D2C6B1A90",
+        "5C1F6A32-0D4E-4B7A-9F21-3E8D2C6B1A90}",
+        "{{5C1F6A32-0D4E-4B7A-9F21-3E8D2C6B1A90}}",
+    ] {
+        assert_eq!(parse_guid(text.as_bytes()), None, "{text}");
+    }
+}
+
+#[test]
+fn guid_list_holds_at_most_the_limit() {
+    let mut policy = VolumePolicy::default();
+    assert!(!policy.add_guid("not a guid"));
+    assert_eq!(policy.guid_count, 0);
+
+    for _ in 0..MAX_VOLUME_GUIDS {
+        assert!(policy.add_guid(GUID));
+    }
+
+    assert!(!policy.add_guid(OTHER_GUID));
+    assert_eq!(policy.guid_count, MAX_VOLUME_GUIDS);
+}
+
+#[test]
+fn default_policy_attaches_to_every_volume() {
+    let policy = VolumePolicy::default();
+    let network = VolumeInfo {
+        filesystem_type: FLT_FSTYPE_LANMAN,
+        device_type: FILE_DEVICE_NETWORK_FILE_SYSTEM,
+        drive_letter: 0,
+        guid: [0; GUID_STRING_LEN],
+        ..fixed_ntfs()
+    };
+
+    assert!(policy.should_attach(&fixed_ntfs()));
+    assert!(policy.should_attach(&network));
+}
+
+#[test]
+fn media_follows_the_redirector_then_the_removable_flag() {
+    let volume = fixed_ntfs();
+    assert_eq!(volume.media(), MEDIA_FIXED);
+    assert_eq!(
+        VolumeInfo {
+            removable: true,
+            ..volume
+        }
+        .media(),
+        MEDIA_REMOVABLE
+    );
+    // a redirector is network media even on a disk device
+    assert_eq!(
+        VolumeInfo {
+            filesystem_type: FLT_FSTYPE_LANMAN,
+            removable: true,
+            ..volume
+        }
+        .media(),
+        MEDIA_NETWORK
+    );
+    assert_eq!(
+        VolumeInfo {
+            device_type: FILE_DEVICE_NETWORK_FILE_SYSTEM,
+            ..volume
+        }
+        .media(),
+        MEDIA_NETWORK
+    );
+}
+
+#[test]
+fn every_configured_criterion_has_to_match() {
+    let policy = VolumePolicy {
+        filesystems: 1 << FLT_FSTYPE_NTFS | 1 << FLT_FSTYPE_REFS,
+        media: MEDIA_FIXED,
+        device_types: 1 << FILE_DEVICE_DISK_FILE_SYSTEM,
+        ..VolumePolicy::default()
+    };
+    let volume = fixed_ntfs();
+
+    assert!(policy.should_attach(&volume));
+    assert!(policy.should_attach(&VolumeInfo {
+        filesystem_type: FLT_FSTYPE_REFS,
+        ..volume
+    }));
+    assert!(!policy.should_attach(&VolumeInfo {
+        filesystem_type: FLT_FSTYPE_FAT,
+        ..volume
+    }));
+    assert!(!policy.should_attach(&VolumeInfo {
+        removable: true,
+        ..volume
+    }));
+    assert!(!policy.should_attach(&VolumeInfo {
+        device_type: FILE_DEVICE_CD_ROM_FILE_SYSTEM,
+        ..volume
+    }));
+    // types past the mask never match
+    assert!(!policy.should_attach(&VolumeInfo {
+        filesystem_type: 64,
+        ..volume
+    }));
+}
+
+#[test]
+fn letters_and_guids_are_matched_either_way() {
+    let mut policy = VolumePolicy {
+        drive_letters: letters("DZ"),
+        ..VolumePolicy::default()
+    };
+    assert!(policy.add_guid(&format!("{{{OTHER_GUID}}}")));
+    let volume = fixed_ntfs();
+
+    assert!(!policy.should_attach(&volume));
+    assert!(policy.should_attach(&VolumeInfo {
+        drive_letter: b'D',
+        ..volume
+    }));
+    assert!(policy.should_attach(&VolumeInfo {
+        drive_letter: b'Z',
+        ..volume
+    }));
+    assert!(policy.should_attach(&VolumeInfo {
+        guid: guid(OTHER_GUID),
+        ..volume
+    }));
+    // a volume without a letter or GUID name matches neither
+    assert!(!policy.should_attach(&VolumeInfo {
+        drive_letter: 0,
+        guid: [0; GUID_STRING_LEN],
+        ..volume
+    }));
+}
+
+#[test]
+fn policy_round_trips() {
+    let mut policy = VolumePolicy {
+        filesystems: 1 << FLT_FSTYPE_NTFS,
+        media: MEDIA_FIXED | MEDIA_REMOVABLE,
+        device_types: 1 << FILE_DEVICE_DISK_FILE_SYSTEM,
+        drive_letters: letters("CD"),
+        ..VolumePolicy::default()
+    };
+    policy.add_guid(GUID);
+    policy.add_guid(OTHER_GUID);
+    let mut buffer = [0u8; 256];
+
+    let len = policy.encode(&mut buffer).unwrap();
+
+    assert_eq!(len, VOLUME_POLICY_HEADER_SIZE + 2 * GUID_STRING_LEN);
+    assert_eq!(VolumePolicy::decode(&buffer[..len]), Ok(policy));
+    assert_eq!(policy.encode(&mut buffer[..len - 1]), None);
+}
+
+#[test]
+fn malformed_policies_are_rejected() {
+    let mut policy = VolumePolicy::default();
+    policy.add_guid(GUID);
+    let mut buffer = [0u8; 64];
+    let len = policy.encode(&mut buffer).unwrap();
+
+    assert_eq!(
+        VolumePolicy::decode(&buffer[..VOLUME_POLICY_HEADER_SIZE - 1]),
+        Err(DecodeError::Truncated)
+    );
+    assert_eq!(
+        VolumePolicy::decode(&buffer[..len - 1]),
+        Err(DecodeError::Truncated)
+    );
+
+    let mut corrupt = buffer;
+    corrupt[VOLUME_POLICY_HEADER_SIZE + 8] = b'_';
+    assert_eq!(
+        VolumePolicy::decode(&corrupt[..len]),
+        Err(DecodeError::InvalidValue)
+    );
+
+    let mut too_many = [0u8; VOLUME_POLICY_HEADER_SIZE];
+    too_many[20..22].copy_from_slice(&(MAX_VOLUME_GUIDS as u16 + 1).to_le_bytes());
+    assert_eq!(VolumePolicy::decode(&too_many), Err(DecodeError::TooLong));
+}
+
+#[test]
+fn header_alone_is_the_default_policy() {
+    assert_eq!(
+        VolumePolicy::decode(&[0; VOLUME_POLICY_HEADER_SIZE]),
+        Ok(VolumePolicy::default())
+    );
+}
+
+#[test]
+fn instance_record_round_trips() {
+    let name: Vec<u8> = r"\Device\HarddiskVolume3"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let record = InstanceRecord {
+        filesystem_type: FLT_FSTYPE_NTFS,
+        device_type: FILE_DEVICE_DISK_FILE_SYSTEM,
+        media: MEDIA_FIXED,
+        drive_letter: b'C',
+        volume_name: &name,
+    };
+    let mut buffer = [0u8; 128];
+
+    let len = record.encode(&mut buffer).unwrap();
+
+    assert_eq!(len, INSTANCE_HEADER_SIZE + name.len());
+    assert_eq!(InstanceRecord::decode(&buffer[..len]), Ok((record, len)));
+    assert_eq!(
+        InstanceRecord::decode(&buffer[..len - 2]),
+        Err(DecodeError::Truncated)
+    );
+    buffer[14] += 1;
+    assert_eq!(
+        InstanceRecord::decode(&buffer[..len]),
+        Err(DecodeError::OddLength)
+    );
+}
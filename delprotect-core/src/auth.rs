@@ -0,0 +1,220 @@
+//! Tamper protection. Once a secret is configured the driver is locked: configuration changes
+//! and unloading need a control device handle which answered a challenge, see `common::auth`.
+//! Handles are identified by an opaque key, the FILE_OBJECT in the driver.
+
+use alloc::vec::Vec;
+use common::{
+    auth::{
+        is_valid_secret, verify_response, ChallengeMessage, AUTH_FLAG_AUTHORIZED, AUTH_FLAG_LOCKED,
+        AUTH_FLAG_UNLOAD_ALLOWED, CHALLENGE_SIZE,
+    },
+    logging::TARGET_IOCTL,
+    status::{
+        NtStatus, STATUS_ACCESS_DENIED, STATUS_INSUFFICIENT_RESOURCES, STATUS_INVALID_PARAMETER,
+    },
+};
+
+/// Handles which asked for a challenge at the same time, more are refused.
+pub const MAX_SESSIONS: usize = 16;
+
+/// Challenge state of one control device handle.
+struct Session {
+    file_object: usize,
+    challenge: Option<[u8; CHALLENGE_SIZE]>,
+    authorized: bool,
+}
+
+pub struct Auth {
+    secret: Option<Vec<u8>>,
+    sessions: Vec<Session>,
+    /// Set by `IOCTL_DELPROTECT_ALLOW_UNLOAD`, consumed by the next filter unload callback.
+    unload_allowed: bool,
+}
+
+impl Auth {
+    /// Starts locked if a valid `secret` was persisted, unlocked otherwise. Returns `None` if
+    /// there is no memory for the sessions.
+    pub fn new(secret: Option<&[u8]>) -> Option<Self> {
+        let mut sessions = Vec::new();
+        if let Err(e) = sessions.try_reserve_exact(MAX_SESSIONS) {
+            log::info!(target: TARGET_IOCTL, "fail to reserve memory for sessions. Err: {:?}", e);
+            return None;
+        }
+
+        let secret = match secret.filter(|secret| is_valid_secret(secret)) {
+            Some(secret) => {
+                log::info!(target: TARGET_IOCTL, "tamper protection is locked");
+                Some(copy_secret(secret)?)
+            },
+            None => None,
+        };
+
+        Some(Self {
+            secret,
+            sessions,
+            unload_allowed: false,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// True if the driver is unlocked or the handle answered its challenge.
+    pub fn is_authorized(&self, file_object: usize) -> bool {
+        self.secret.is_none()
+            || self
+                .sessions
+                .iter()
+                .any(|s| s.file_object == file_object && s.authorized)
+    }
+
+    pub fn is_unload_allowed(&self) -> bool {
+        self.unload_allowed
+    }
+
+    /// Returns whether the unload was allowed and clears it, an allowed unload which is then
+    /// refused or fails does not let a later one through.
+    pub fn take_unload_allowed(&mut self) -> bool {
+        core::mem::take(&mut self.unload_allowed)
+    }
+
+    /// Issues `challenge` for the handle, replacing any previous unanswered one.
+    pub fn challenge(
+        &mut self,
+        file_object: usize,
+        challenge: [u8; CHALLENGE_SIZE],
+        output: &mut [u8],
+    ) -> Result<usize, NtStatus> {
+        let mut flags = 0;
+        if self.secret.is_some() {
+            flags |= AUTH_FLAG_LOCKED;
+        }
+        if self.unload_allowed {
+            flags |= AUTH_FLAG_UNLOAD_ALLOWED;
+        }
+
+        match self.find_session(file_object) {
+            Some(session) => {
+                if session.authorized {
+                    flags |= AUTH_FLAG_AUTHORIZED;
+                }
+                session.challenge = Some(challenge);
+            },
+            None => {
+                if self.sessions.len() >= MAX_SESSIONS {
+                    return Err(STATUS_INSUFFICIENT_RESOURCES);
+                }
+                self.sessions.push(Session {
+                    file_object,
+                    challenge: Some(challenge),
+                    authorized: false,
+                });
+            },
+        }
+
+        ChallengeMessage { flags, challenge }
+            .encode(output)
+            .ok_or(STATUS_INVALID_PARAMETER)
+    }
+
+    /// Checks the response to the last challenge of the handle. The challenge is consumed even
+    /// if the response is wrong, so every guess needs a new one.
+    pub fn authenticate(&mut self, file_object: usize, response: &[u8]) -> Result<(), NtStatus> {
+        let Some(secret) = &self.secret else {
+            return Ok(());
+        };
+        let Some(session) = self
+            .sessions
+            .iter_mut()
+            .find(|s| s.file_object == file_object)
+        else {
+            return Err(STATUS_INVALID_PARAMETER);
+        };
+        let Some(challenge) = session.challenge.take() else {
+            return Err(STATUS_INVALID_PARAMETER);
+        };
+
+        session.authorized = verify_response(secret, &challenge, response);
+        if session.authorized {
+            Ok(())
+        } else {
+            log::info!(target: TARGET_IOCTL, "wrong response to the challenge");
+            Err(STATUS_ACCESS_DENIED)
+        }
+    }
+
+    /// Checks that the handle may configure `secret`. The first secret is accepted from anyone
+    /// able to open the device, which is why it has to be set at install time; replacing it
+    /// needs an authorized handle.
+    pub fn check_secret(&self, file_object: usize, secret: &[u8]) -> Result<(), NtStatus> {
+        if !is_valid_secret(secret) {
+            return Err(STATUS_INVALID_PARAMETER);
+        }
+        if !self.is_authorized(file_object) {
+            return Err(STATUS_ACCESS_DENIED);
+        }
+        Ok(())
+    }
+
+    /// Replaces the secret before it is persisted and returns the previous one, for
+    /// `restore_secret`. Checks the handle again, another handle may have changed the secret
+    /// in between.
+    pub fn commit_secret(
+        &mut self,
+        file_object: usize,
+        secret: &[u8],
+    ) -> Result<Option<Vec<u8>>, NtStatus> {
+        self.check_secret(file_object, secret)?;
+        let copy = copy_secret(secret).ok_or(STATUS_INSUFFICIENT_RESOURCES)?;
+
+        let previous = self.secret.replace(copy);
+        // other handles were authorized with the previous secret
+        self.sessions.retain(|s| s.file_object == file_object);
+        if let Some(session) = self.find_session(file_object) {
+            session.authorized = true;
+        }
+        log::info!(target: TARGET_IOCTL, "tamper protection secret changed");
+        Ok(previous)
+    }
+
+    /// Puts `previous` back after `committed` could not be persisted, unless another secret
+    /// was committed since. Handles authorized with `previous` have to answer a challenge
+    /// again.
+    pub fn restore_secret(&mut self, committed: &[u8], previous: Option<Vec<u8>>) {
+        if self.secret.as_deref() != Some(committed) {
+            return;
+        }
+        self.secret = previous;
+        log::info!(target: TARGET_IOCTL, "tamper protection secret restored");
+    }
+
+    /// Lets the next `fltmc unload` through. Needs an authorized handle when locked.
+    pub fn allow_unload(&mut self, file_object: usize) -> Result<(), NtStatus> {
+        if !self.is_authorized(file_object) {
+            return Err(STATUS_ACCESS_DENIED);
+        }
+
+        self.unload_allowed = true;
+        log::info!(target: TARGET_IOCTL, "unload allowed");
+        Ok(())
+    }
+
+    /// Forgets the handle, called on IRP_MJ_CLOSE.
+    pub fn close(&mut self, file_object: usize) {
+        self.sessions.retain(|s| s.file_object != file_object);
+    }
+
+    fn find_session(&mut self, file_object: usize) -> Option<&mut Session> {
+        self.sessions
+            .iter_mut()
+            .find(|s| s.file_object == file_object)
+    }
+}
+
+fn copy_secret(secret: &[u8]) -> Option<Vec<u8>> {
+    let mut copy = Vec::new();
+    copy.try_reserve_exact(secret.len()).ok()?;
+    copy.extend_from_slice(secret);
+    Some(copy)
+}
@@ -0,0 +1,147 @@
+//! Canary files, see `common::canary`.
+
+use alloc::{string::String, vec::Vec};
+use common::{
+    canary::{CanaryRecord, CANARY_FLAG_DESCENDANTS, MAX_CANARY_PATH_BYTES},
+    status::{
+        NtStatus, STATUS_INSUFFICIENT_RESOURCES, STATUS_INVALID_PARAMETER,
+        STATUS_OBJECT_NAME_COLLISION,
+    },
+    wire::{read_utf16, write_utf16, ListHeader, LIST_HEADER_SIZE},
+};
+
+/// Canaries above this count are refused.
+pub const MAX_CANARY_COUNT: usize = 64;
+
+pub struct Canary {
+    pub id: u32,
+    /// NT path of the file.
+    pub path: String,
+    pub flags: u32,
+    pub trips: u32,
+    pub last_trip: u64,
+}
+
+impl Canary {
+    pub fn locks_down_descendants(&self) -> bool {
+        self.flags & CANARY_FLAG_DESCENDANTS != 0
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let mut path = [0u8; MAX_CANARY_PATH_BYTES];
+        let path_len = write_utf16(&self.path, &mut path);
+
+        CanaryRecord {
+            id: self.id,
+            flags: self.flags,
+            trips: self.trips,
+            last_trip: self.last_trip,
+            path: &path[..path_len],
+        }
+        .encode(buffer)
+    }
+}
+
+/// Canaries in the order they were added, each with an id unique for the lifetime of the store.
+pub struct CanaryStore {
+    canaries: Vec<Canary>,
+    next_id: u32,
+}
+
+impl Default for CanaryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CanaryStore {
+    pub fn new() -> Self {
+        Self {
+            canaries: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.canaries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Canary> {
+        self.canaries.iter()
+    }
+
+    /// Adds the canary of the record sent by the client and returns its id. A path which is
+    /// already a canary is refused with STATUS_OBJECT_NAME_COLLISION.
+    pub fn add_record(&mut self, record: &CanaryRecord) -> Result<u32, NtStatus> {
+        if record.path.is_empty() {
+            return Err(STATUS_INVALID_PARAMETER);
+        }
+        let path = read_utf16(record.path).ok_or(STATUS_INSUFFICIENT_RESOURCES)?;
+        if self.find(&path).is_some() {
+            return Err(STATUS_OBJECT_NAME_COLLISION);
+        }
+        if self.canaries.len() >= MAX_CANARY_COUNT || self.canaries.try_reserve(1).is_err() {
+            return Err(STATUS_INSUFFICIENT_RESOURCES);
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.canaries.push(Canary {
+            id,
+            path,
+            flags: record.flags,
+            trips: 0,
+            last_trip: 0,
+        });
+        Ok(id)
+    }
+
+    /// Returns false if there is no canary with this id.
+    pub fn remove(&mut self, id: u32) -> bool {
+        let before = self.canaries.len();
+        self.canaries.retain(|canary| canary.id != id);
+        self.canaries.len() != before
+    }
+
+    /// The canary at `file_name`, an NT path, compared without regard to case.
+    pub fn find(&self, file_name: &str) -> Option<&Canary> {
+        self.canaries
+            .iter()
+            .find(|canary| eq_ignore_case(&canary.path, file_name))
+    }
+
+    /// Counts a trip of the canary and returns it.
+    pub fn trip(&mut self, id: u32, now: u64) -> Option<&Canary> {
+        let canary = self.canaries.iter_mut().find(|canary| canary.id == id)?;
+        canary.trips = canary.trips.saturating_add(1);
+        canary.last_trip = now;
+        Some(canary)
+    }
+
+    pub fn list(&self, output: &mut [u8]) -> usize {
+        let mut header = ListHeader {
+            total: self.canaries.len() as u32,
+            ..ListHeader::default()
+        };
+        let mut offset = LIST_HEADER_SIZE;
+
+        for canary in &self.canaries {
+            match canary.encode(&mut output[offset..]) {
+                Some(len) => {
+                    offset += len;
+                    header.returned += 1;
+                },
+                None => break,
+            }
+        }
+
+        header.encode(output);
+        offset
+    }
+}
+
+fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
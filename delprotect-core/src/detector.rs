@@ -0,0 +1,436 @@
+//! Sliding-window detector of mass deletes, see `common::detector`. Every delete, rename and
+//! overwrite of a process is counted in one of `SLOTS` slots of a tenth of the window, so the
+//! window slides a tenth at a time. The directories are remembered with the last time they were
+//! touched, up to `MAX_TRACKED_DIRECTORIES` per process.
+
+use alloc::{string::String, vec::Vec};
+use common::{
+    detector::{
+        DetectorSettings, LockdownCause, LockdownRecord, MAX_LOCKDOWN_NAME_BYTES,
+        MAX_TRACKED_DIRECTORIES,
+    },
+    logging::TARGET_POLICY,
+    schedule::TICKS_PER_SECOND,
+    wire::{write_utf16, ListHeader, LIST_HEADER_SIZE},
+};
+
+/// Slots a window is counted in.
+pub const SLOTS: usize = 10;
+/// Processes counted at the same time, the one idle the longest makes room for a new one.
+pub const MAX_TRACKED_PROCESSES: usize = 64;
+/// Locked down processes. Above this count a new lockdown is refused, a canary or demotion one
+/// releases the oldest mass-delete lockdown not passed on to descendants instead.
+pub const MAX_LOCKDOWNS: usize = 64;
+
+/// The operations the detector counts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Delete,
+    Rename,
+    /// An open with FILE_SUPERSEDE, FILE_OVERWRITE or FILE_OVERWRITE_IF.
+    Overwrite,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Delete => "delete",
+            Self::Rename => "rename",
+            Self::Overwrite => "overwrite",
+        }
+    }
+}
+
+/// What the detector made of an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Activity {
+    /// Counted, or not counted because the detector is disabled.
+    Normal,
+    /// The process went over the thresholds with this operation and is locked down now.
+    Tripped { operations: u32, directories: u32 },
+    /// The process touched this canary and is locked down now.
+    CanaryTripped { canary_id: u32 },
+    /// The process was locked down before, the operation was not counted.
+    LockedDown,
+}
+
+impl Activity {
+    pub fn is_locked_down(&self) -> bool {
+        !matches!(self, Self::Normal)
+    }
+}
+
+/// A process denied deletes, renames and overwrites until it exits or is released. The image
+/// name is kept so that another process getting the same id later is not denied.
+pub struct Lockdown {
+    pub process_id: u32,
+    pub image_name: String,
+    pub cause: LockdownCause,
+    /// The locked down parent this lockdown was inherited from, 0 if none. Inherited lockdowns
+    /// match on the process id alone, the name the creation notification gives is not an NT
+    /// image path.
+    pub parent_id: u32,
+    /// The processes created by this one are locked down as well.
+    pub descendants: bool,
+    pub time: u64,
+    pub operations: u32,
+    pub directories: u32,
+}
+
+impl Lockdown {
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let mut name = [0u8; MAX_LOCKDOWN_NAME_BYTES];
+        let name_len = write_utf16(&self.image_name, &mut name);
+
+        LockdownRecord {
+            process_id: self.process_id,
+            cause: self.cause,
+            parent_id: self.parent_id,
+            time: self.time,
+            operations: self.operations,
+            directories: self.directories,
+            process: &name[..name_len],
+        }
+        .encode(buffer)
+    }
+}
+
+/// A lockdown `Detector::lock_down` found no room for.
+pub enum Dropped {
+    /// Released to make room for a canary or demotion lockdown.
+    Released(Lockdown),
+    /// Not added.
+    Refused(Lockdown),
+}
+
+impl Dropped {
+    pub fn lockdown(&self) -> &Lockdown {
+        match self {
+            Self::Released(lockdown) | Self::Refused(lockdown) => lockdown,
+        }
+    }
+}
+
+/// The counters of one process.
+struct Tracked {
+    process_id: u32,
+    /// Index of the last slot counted in, slots are `window / SLOTS` ticks long.
+    slot: u64,
+    counts: [u32; SLOTS],
+    /// Hash of the directory and the last time it was touched.
+    directories: [Option<(u32, u64)>; MAX_TRACKED_DIRECTORIES],
+}
+
+impl Tracked {
+    fn new(process_id: u32, slot: u64) -> Self {
+        Self {
+            process_id,
+            slot,
+            counts: [0; SLOTS],
+            directories: [None; MAX_TRACKED_DIRECTORIES],
+        }
+    }
+
+    /// Counts one operation in `slot` and returns the operations and directories in the
+    /// window ending there.
+    fn count(&mut self, slot: u64, directory: u32, now: u64, since: u64) -> (u32, u32) {
+        // a clock going back counts in the current slot
+        let slot = slot.max(self.slot);
+        let elapsed = slot - self.slot;
+        if elapsed >= SLOTS as u64 {
+            self.counts = [0; SLOTS];
+        } else {
+            for skipped in 1..=elapsed {
+                self.counts[((self.slot + skipped) % SLOTS as u64) as usize] = 0;
+            }
+        }
+        self.slot = slot;
+        let current = &mut self.counts[(slot % SLOTS as u64) as usize];
+        *current = current.saturating_add(1);
+
+        let known = self
+            .directories
+            .iter()
+            .position(|entry| matches!(entry, Some((hash, _)) if *hash == directory));
+        // an unknown directory replaces a free entry, else the one touched the longest ago
+        let index = known.unwrap_or_else(|| {
+            self.directories
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.map_or(0, |(_, last)| last.saturating_add(1)))
+                .map_or(0, |(index, _)| index)
+        });
+        self.directories[index] = Some((directory, now));
+
+        let operations = self
+            .counts
+            .iter()
+            .fold(0u32, |sum, count| sum.saturating_add(*count));
+        let directories = self
+            .directories
+            .iter()
+            .filter(|entry| matches!(entry, Some((_, last)) if *last >= since))
+            .count() as u32;
+        (operations, directories)
+    }
+
+    /// Time of the last operation counted, to find the process idle the longest.
+    fn last_active(&self) -> u64 {
+        self.directories
+            .iter()
+            .filter_map(|entry| entry.map(|(_, last)| last))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+pub struct Detector {
+    settings: DetectorSettings,
+    tracked: Vec<Tracked>,
+    lockdowns: Vec<Lockdown>,
+    /// The last lockdown dropped, until `take_dropped`.
+    dropped: Option<Dropped>,
+}
+
+impl Detector {
+    pub fn new(settings: DetectorSettings) -> Self {
+        Self {
+            settings,
+            tracked: Vec::new(),
+            lockdowns: Vec::new(),
+            dropped: None,
+        }
+    }
+
+    pub fn settings(&self) -> &DetectorSettings {
+        &self.settings
+    }
+
+    /// New settings restart the counting, lockdowns stay.
+    pub fn set_settings(&mut self, settings: DetectorSettings) {
+        self.settings = settings;
+        self.tracked.clear();
+    }
+
+    /// True if operations have to be reported: the detector is enabled or processes are locked
+    /// down. The callbacks skip the name queries otherwise.
+    pub fn is_watching(&self) -> bool {
+        self.settings.is_enabled() || !self.lockdowns.is_empty()
+    }
+
+    pub fn lockdowns(&self) -> &[Lockdown] {
+        &self.lockdowns
+    }
+
+    pub fn is_locked_down(&self, process_id: u32, image_name: &str) -> bool {
+        self.lockdowns.iter().any(|lockdown| {
+            lockdown.process_id == process_id
+                && (lockdown.parent_id != 0 || lockdown.image_name.eq_ignore_ascii_case(image_name))
+        })
+    }
+
+    /// Counts an operation of the process on `file_name`, an NT path. A process which cannot
+    /// be tracked for lack of memory is not counted.
+    pub fn record(
+        &mut self,
+        operation: Operation,
+        process_id: u32,
+        image_name: &str,
+        file_name: &str,
+        now: u64,
+    ) -> Activity {
+        if self.is_locked_down(process_id, image_name) {
+            return Activity::LockedDown;
+        }
+        if !self.settings.is_enabled() {
+            return Activity::Normal;
+        }
+
+        let window = self.settings.window_seconds as u64 * TICKS_PER_SECOND;
+        let slot = now / (window / SLOTS as u64);
+        let since = now.saturating_sub(window);
+        let Some(tracked) = self.track(process_id, slot) else {
+            return Activity::Normal;
+        };
+        let (operations, directories) = tracked.count(slot, directory_hash(file_name), now, since);
+
+        if !self.settings.is_exceeded(operations, directories) {
+            return Activity::Normal;
+        }
+
+        log::info!(
+            target: TARGET_POLICY,
+            "lock down {} ({}): {} operations in {} directories, last a {} of {}",
+            image_name,
+            process_id,
+            operations,
+            directories,
+            operation.as_str(),
+            file_name
+        );
+        self.tracked
+            .retain(|tracked| tracked.process_id != process_id);
+        self.lock_down(Lockdown {
+            process_id,
+            image_name: String::from(image_name),
+            cause: LockdownCause::MassDelete,
+            parent_id: 0,
+            descendants: false,
+            time: now,
+            operations,
+            directories,
+        });
+        Activity::Tripped {
+            operations,
+            directories,
+        }
+    }
+
+    /// Releases a process, or every one for process id 0. Returns how many were released.
+    pub fn release(&mut self, process_id: u32) -> usize {
+        let before = self.lockdowns.len();
+        self.lockdowns
+            .retain(|lockdown| process_id != 0 && lockdown.process_id != process_id);
+        before - self.lockdowns.len()
+    }
+
+    /// Locks down a process created by a locked down process which passes its lockdown on.
+    pub fn process_created(&mut self, parent_id: u32, process_id: u32, image_name: &str, now: u64) {
+        let Some(parent) = self
+            .lockdowns
+            .iter()
+            .find(|lockdown| lockdown.process_id == parent_id && lockdown.descendants)
+        else {
+            return;
+        };
+
+        log::info!(
+            target: TARGET_POLICY,
+            "lock down {} ({}), created by locked down {}",
+            image_name,
+            process_id,
+            parent_id
+        );
+        let cause = parent.cause;
+        self.lock_down(Lockdown {
+            process_id,
+            image_name: String::from(image_name),
+            cause,
+            parent_id,
+            descendants: true,
+            time: now,
+            operations: 0,
+            directories: 0,
+        });
+    }
+
+    /// Forgets an exited process.
+    pub fn process_exited(&mut self, process_id: u32) {
+        self.tracked
+            .retain(|tracked| tracked.process_id != process_id);
+        self.lockdowns
+            .retain(|lockdown| lockdown.process_id != process_id);
+    }
+
+    pub fn list(&self, output: &mut [u8]) -> usize {
+        let mut header = ListHeader {
+            total: self.lockdowns.len() as u32,
+            ..ListHeader::default()
+        };
+        let mut offset = LIST_HEADER_SIZE;
+
+        for lockdown in &self.lockdowns {
+            match lockdown.encode(&mut output[offset..]) {
+                Some(len) => {
+                    offset += len;
+                    header.returned += 1;
+                },
+                None => break,
+            }
+        }
+
+        header.encode(output);
+        offset
+    }
+
+    fn track(&mut self, process_id: u32, slot: u64) -> Option<&mut Tracked> {
+        if let Some(index) = self
+            .tracked
+            .iter()
+            .position(|tracked| tracked.process_id == process_id)
+        {
+            return Some(&mut self.tracked[index]);
+        }
+
+        if self.tracked.len() >= MAX_TRACKED_PROCESSES {
+            let idle = self
+                .tracked
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, tracked)| tracked.last_active())
+                .map(|(index, _)| index)?;
+            self.tracked.swap_remove(idle);
+        } else if self.tracked.try_reserve(1).is_err() {
+            return None;
+        }
+        self.tracked.push(Tracked::new(process_id, slot));
+        self.tracked.last_mut()
+    }
+
+    /// Adds a lockdown. At `MAX_LOCKDOWNS`, or without memory, a mass-delete lockdown is
+    /// refused; a canary or demotion lockdown, or one inherited from them, takes the place of
+    /// the oldest mass-delete lockdown not passed on to descendants if there is one. Canary and
+    /// demotion lockdowns are never released to make room. What was dropped is kept for
+    /// `take_dropped`.
+    pub fn lock_down(&mut self, lockdown: Lockdown) {
+        if self.lockdowns.len() < MAX_LOCKDOWNS && self.lockdowns.try_reserve(1).is_ok() {
+            self.lockdowns.push(lockdown);
+            return;
+        }
+
+        let oldest = match lockdown.cause {
+            LockdownCause::MassDelete => None,
+            LockdownCause::Canary | LockdownCause::Demoted => {
+                self.lockdowns.iter().position(|lockdown| {
+                    lockdown.cause == LockdownCause::MassDelete
+                        && lockdown.parent_id == 0
+                        && !lockdown.descendants
+                })
+            },
+        };
+        let dropped = match oldest {
+            Some(index) => {
+                let released = self.lockdowns.remove(index);
+                self.lockdowns.push(lockdown);
+                Dropped::Released(released)
+            },
+            None => Dropped::Refused(lockdown),
+        };
+        log::warn!(
+            target: TARGET_POLICY,
+            "too many lockdowns, {} ({}) {}",
+            dropped.lockdown().image_name,
+            dropped.lockdown().process_id,
+            match dropped {
+                Dropped::Released(_) => "released",
+                Dropped::Refused(_) => "not locked down",
+            }
+        );
+        self.dropped = Some(dropped);
+    }
+
+    /// The lockdown the last `lock_down` dropped, once.
+    pub fn take_dropped(&mut self) -> Option<Dropped> {
+        self.dropped.take()
+    }
+}
+
+/// FNV-1a of the directory part of an NT path, ignoring ASCII case.
+fn directory_hash(file_name: &str) -> u32 {
+    let directory = file_name
+        .rfind('\\')
+        .map_or(file_name, |end| &file_name[..end]);
+    directory.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ byte.to_ascii_lowercase() as u32).wrapping_mul(0x0100_0193)
+    })
+}
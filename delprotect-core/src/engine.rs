@@ -0,0 +1,938 @@
+use alloc::{format, string::String, vec::Vec};
+use common::{
+    auth::{CHALLENGE_MESSAGE_SIZE, CHALLENGE_SIZE, RESPONSE_SIZE},
+    canary::CanaryRecord,
+    detector::{DetectorSettings, LockdownCause, DETECTOR_SETTINGS_SIZE},
+    evaluate::{EvaluateReply, EvaluateRequest, EVALUATE_REPLY_SIZE, MAX_EVALUATE_ANCESTORS},
+    event::{EventKind, Severity},
+    input, ioctl_codes,
+    logging::{LogLevel, TARGET_IOCTL, TARGET_LIFECYCLE, TARGET_POLICY},
+    options::{
+        OptionsUpdate, OPTION_DENY_DELETE_ACCESS, OPTION_DENY_MANUAL_DETACH, OPTION_GUARD_SECURITY,
+        OPTION_GUARD_TRUNCATION,
+    },
+    rule::{RuleAction, RuleRecord, LIST_FLAG_PURGE_EXPIRED},
+    schedule::TimeWindow,
+    stats::{STATS_FLAG_RESET, STATS_HEADER_SIZE},
+    status::{
+        NtStatus, STATUS_ACCESS_DENIED, STATUS_FLT_DO_NOT_DETACH, STATUS_INSUFFICIENT_RESOURCES,
+        STATUS_INVALID_DEVICE_REQUEST, STATUS_INVALID_PARAMETER, STATUS_NOT_FOUND, STATUS_SUCCESS,
+    },
+    volume::{VolumeInfo, VolumePolicy},
+    wire::{read_utf16, LIST_HEADER_SIZE},
+};
+
+use log::Level;
+
+use crate::{
+    auth::Auth,
+    canary::CanaryStore,
+    detector::{Activity, Detector, Dropped, Lockdown, Operation},
+    events::{Event, EventQueue},
+    filter,
+    host::Host,
+    instances::Instances,
+    ioctl::{status_from, Caller, IoctlBuffer, Persist, Reply},
+    lineage::{Ancestor, Lineage},
+    log_limited, logging,
+    rules::RuleStore,
+};
+
+/// Most processes a termination or suspension can be queued for at once, see
+/// `Engine::claim_response`.
+pub const MAX_RESPONDING_PROCESSES: usize = 64;
+
+/// State persisted by the host and loaded before the engine starts.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// OPTION_* flags from `common::options`.
+    pub options: u32,
+    pub volume_policy: VolumePolicy,
+    /// Tamper protection secret, the engine starts unlocked without a valid one.
+    pub secret: Option<Vec<u8>>,
+    /// Applied by the host with `logging::set_level` before the engine starts.
+    pub log_level: LogLevel,
+    pub detector: DetectorSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Deny {
+        rule_id: u32,
+    },
+    /// Copy the file into the vault, then let the delete go on.
+    Preserve {
+        rule_id: u32,
+    },
+    /// The process is locked down by the detector or a canary, the rules were not asked.
+    LockedDown,
+}
+
+impl Decision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Self::Allow)
+    }
+}
+
+/// Everything the driver decides, without the kernel. Methods take `&mut self`; the host
+/// serializes calls with a single lock.
+pub struct Engine {
+    rules: RuleStore,
+    events: EventQueue,
+    instances: Instances,
+    auth: Auth,
+    detector: Detector,
+    canaries: CanaryStore,
+    lineage: Lineage,
+    options: u32,
+    /// Id of the last vault entry, see `next_vault_id`.
+    vault_id: u64,
+    /// Handle and process which opened the vaults, see `may_change_vault`.
+    vault_opener: Option<(usize, u32)>,
+    /// A vault may exist on some volume, see `note_vault`.
+    vaults: bool,
+    /// Processes a termination or suspension was queued for, see `claim_response`.
+    responding: Vec<u32>,
+}
+
+impl Engine {
+    /// Returns `None` if there is no memory for the rules, events or sessions.
+    pub fn new(config: Config) -> Option<Self> {
+        Some(Self {
+            rules: RuleStore::new()?,
+            events: EventQueue::new()?,
+            instances: Instances::new(config.volume_policy),
+            auth: Auth::new(config.secret.as_deref())?,
+            detector: Detector::new(config.detector),
+            canaries: CanaryStore::new(),
+            lineage: Lineage::new(),
+            options: config.options,
+            vault_id: 0,
+            vault_opener: None,
+            vaults: false,
+            responding: Vec::new(),
+        })
+    }
+
+    pub fn rules(&self) -> &RuleStore {
+        &self.rules
+    }
+
+    pub fn rules_mut(&mut self) -> &mut RuleStore {
+        &mut self.rules
+    }
+
+    pub fn events(&self) -> &EventQueue {
+        &self.events
+    }
+
+    pub fn instances(&self) -> &Instances {
+        &self.instances
+    }
+
+    pub fn auth(&self) -> &Auth {
+        &self.auth
+    }
+
+    pub fn detector(&self) -> &Detector {
+        &self.detector
+    }
+
+    pub fn detector_mut(&mut self) -> &mut Detector {
+        &mut self.detector
+    }
+
+    pub fn canaries(&self) -> &CanaryStore {
+        &self.canaries
+    }
+
+    pub fn canaries_mut(&mut self) -> &mut CanaryStore {
+        &mut self.canaries
+    }
+
+    pub fn lineage(&self) -> &Lineage {
+        &self.lineage
+    }
+
+    /// True if process creations have to be shown to `process_created` with the image of the
+    /// creator: some rule inherits or some lineage is tracked. The host skips the query
+    /// otherwise.
+    pub fn tracks_lineage(&self) -> bool {
+        self.rules.any_inherits() || !self.lineage.is_empty()
+    }
+
+    /// True if deletes, renames and overwrites have to be shown to `watch`: the detector is
+    /// watching or there are canaries. The callbacks skip the name queries otherwise.
+    pub fn is_watching(&self) -> bool {
+        self.detector.is_watching() || !self.canaries.is_empty()
+    }
+
+    /// Shows an operation of the process on `file_name`, an NT path, to the canaries and the
+    /// detector. Touching a canary locks the process down before the detector counts it.
+    pub fn watch(
+        &mut self,
+        operation: Operation,
+        process_id: u32,
+        image_name: &str,
+        file_name: &str,
+        now: u64,
+    ) -> Activity {
+        if self.detector.is_locked_down(process_id, image_name) {
+            return Activity::LockedDown;
+        }
+
+        let Some(canary) = self.canaries.find(file_name) else {
+            let activity = self
+                .detector
+                .record(operation, process_id, image_name, file_name, now);
+            self.report_dropped(now);
+            return activity;
+        };
+        let canary_id = canary.id;
+        let descendants = canary.locks_down_descendants();
+        self.canaries.trip(canary_id, now);
+
+        log::info!(
+            target: TARGET_POLICY,
+            "lock down {} ({}): {} of canary {}",
+            image_name,
+            process_id,
+            operation.as_str(),
+            file_name
+        );
+        self.detector.lock_down(Lockdown {
+            process_id,
+            image_name: String::from(image_name),
+            cause: LockdownCause::Canary,
+            parent_id: 0,
+            descendants,
+            time: now,
+            operations: 0,
+            directories: 0,
+        });
+        self.report_dropped(now);
+        Activity::CanaryTripped { canary_id }
+    }
+
+    /// Called by the host when `creator_id` creates a process, `image_name` is the name the
+    /// notification gives. `creator_image` is the NT image path of the creator, it may be
+    /// empty unless `tracks_lineage`.
+    pub fn process_created(
+        &mut self,
+        creator_id: u32,
+        creator_image: &str,
+        process_id: u32,
+        image_name: &str,
+        now: u64,
+    ) {
+        self.detector
+            .process_created(creator_id, process_id, image_name, now);
+        self.report_dropped(now);
+
+        let rules = &self.rules;
+        self.lineage
+            .process_created(creator_id, creator_image, process_id, image_name, |image| {
+                rules.starts_lineage(image)
+            });
+    }
+
+    /// Locks down a process a `deny-and-demote` rule denied a delete, for the rest of its life
+    /// or until it is released.
+    pub fn demote(&mut self, process_id: u32, image_name: &str, rule_id: u32, now: u64) {
+        log::info!(
+            target: TARGET_POLICY,
+            "demote {} ({}) by rule {}",
+            image_name,
+            process_id,
+            rule_id
+        );
+        self.detector.lock_down(Lockdown {
+            process_id,
+            image_name: String::from(image_name),
+            cause: LockdownCause::Demoted,
+            parent_id: 0,
+            descendants: false,
+            time: now,
+            operations: 0,
+            directories: 0,
+        });
+        self.report_dropped(now);
+    }
+
+    /// Queues a `lockdown-dropped` event for the lockdown the detector had no room for.
+    fn report_dropped(&mut self, now: u64) {
+        let Some(dropped) = self.detector.take_dropped() else {
+            return;
+        };
+        let lockdown = dropped.lockdown();
+        let detail = match dropped {
+            Dropped::Released(_) => format!("{} lockdown released", lockdown.cause.as_str()),
+            Dropped::Refused(_) => format!("{} lockdown refused", lockdown.cause.as_str()),
+        };
+        let event = Event::new(EventKind::LockdownDropped, Severity::High, now)
+            .process(lockdown.process_id, &lockdown.image_name)
+            .detail(&detail);
+        self.push_event(event);
+    }
+
+    /// Claims the termination or suspension of a process a rule denied, so that it is queued
+    /// once however many of its operations are denied before it runs. Fails if it was already
+    /// claimed or if MAX_RESPONDING_PROCESSES are claimed.
+    pub fn claim_response(&mut self, process_id: u32) -> bool {
+        if self.responding.contains(&process_id) {
+            return false;
+        }
+        if self.responding.len() >= MAX_RESPONDING_PROCESSES
+            || self.responding.try_reserve(1).is_err()
+        {
+            log::warn!(
+                target: TARGET_POLICY,
+                "no room to respond to {}, {} processes are waiting",
+                process_id,
+                self.responding.len()
+            );
+            return false;
+        }
+        self.responding.push(process_id);
+        true
+    }
+
+    /// Gives back a claim whose response could not be queued, the next denial tries again.
+    pub fn release_response(&mut self, process_id: u32) {
+        self.responding.retain(|&id| id != process_id);
+    }
+
+    pub fn process_exited(&mut self, process_id: u32) {
+        self.detector.process_exited(process_id);
+        self.lineage.process_exited(process_id);
+        self.release_response(process_id);
+    }
+
+    pub fn options(&self) -> u32 {
+        self.options
+    }
+
+    /// True if opens asking for DELETE access are decided like deletes.
+    pub fn denies_delete_access(&self) -> bool {
+        self.options & OPTION_DENY_DELETE_ACCESS != 0
+    }
+
+    /// True if size changes which cut a file are decided like deletes.
+    pub fn guards_truncation(&self) -> bool {
+        self.options & OPTION_GUARD_TRUNCATION != 0
+    }
+
+    /// True if changes weakening the security descriptor or attributes of a file are decided
+    /// like deletes.
+    pub fn guards_security(&self) -> bool {
+        self.options & OPTION_GUARD_SECURITY != 0
+    }
+
+    pub fn push_event(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// Id of a new vault entry: the time of the delete, moved past the last id so that two
+    /// deletes in the same tick or after the clock went back get different copies.
+    pub fn next_vault_id(&mut self, now: u64) -> u64 {
+        self.vault_id = now.max(self.vault_id + 1);
+        self.vault_id
+    }
+
+    /// Called when a volume is found with a vault or a `preserve` rule is about to create one.
+    /// Until then no open can change a file of a vault and creates skip the name query.
+    pub fn note_vault(&mut self) {
+        self.vaults = true;
+    }
+
+    pub fn has_vaults(&self) -> bool {
+        self.vaults
+    }
+
+    /// True if the process opened the vaults with `IOCTL_DELPROTECT_OPEN_VAULT` and still holds
+    /// that handle. Other user mode processes cannot change a file of a vault.
+    pub fn may_change_vault(&self, process_id: u32) -> bool {
+        self.vault_opener
+            .is_some_and(|(_, opener)| opener == process_id)
+    }
+
+    /// Decides a delete requested by a process with this NT image path, e.g.
+    /// `\Device\HarddiskVolume3\Windows\System32\cmd.exe`, created by `ancestors`, see
+    /// `Lineage::ancestors`.
+    pub fn check_delete(&self, image_name: &str, ancestors: &[Ancestor], now: u64) -> Decision {
+        log_limited!(
+            now,
+            target: TARGET_POLICY,
+            Level::Debug,
+            "Delete operation from {}",
+            image_name
+        );
+        match self.rules.find_blocking(image_name, ancestors, now) {
+            Some(rule) => {
+                log_limited!(
+                    now,
+                    target: TARGET_POLICY,
+                    Level::Debug,
+                    "DELETE {} by rule {} for {}",
+                    rule.action.as_str(),
+                    rule.id,
+                    rule.process_name
+                );
+                match rule.action {
+                    RuleAction::Preserve => Decision::Preserve { rule_id: rule.id },
+                    RuleAction::Deny
+                    | RuleAction::DenyAndTerminate
+                    | RuleAction::DenyAndSuspend
+                    | RuleAction::DenyAndDemote => Decision::Deny { rule_id: rule.id },
+                }
+            },
+            None => Decision::Allow,
+        }
+    }
+
+    /// True if an immutable rule decides the writes of the process, whatever the file. Writes
+    /// of other processes are let through before the name of the file is known.
+    pub fn decides_writes(&self, process_id: u32, image_name: &str, now: u64) -> bool {
+        let ancestors = self.lineage.ancestors(process_id);
+        self.rules.any_immutable_for(image_name, &ancestors, now)
+    }
+
+    /// Decides a write, writable section or open for write access of `file_name` requested by
+    /// a process, like `check_delete` but only for the immutable rules covering the file. They
+    /// always deny.
+    pub fn check_write(
+        &self,
+        image_name: &str,
+        file_name: &str,
+        ancestors: &[Ancestor],
+        now: u64,
+    ) -> Decision {
+        match self
+            .rules
+            .find_immutable(image_name, file_name, ancestors, now)
+        {
+            Some(rule) => {
+                log_limited!(
+                    now,
+                    target: TARGET_POLICY,
+                    Level::Debug,
+                    "WRITE of {} denied by rule {} for {}",
+                    file_name,
+                    rule.id,
+                    rule.process_name
+                );
+                Decision::Deny { rule_id: rule.id }
+            },
+            None => Decision::Allow,
+        }
+    }
+
+    /// The trace of the rules naming the process of a delete, for its audit event. Empty if
+    /// there is no memory for it.
+    pub fn trace_delete(&self, image_name: &str, ancestors: &[Ancestor], now: u64) -> Vec<u8> {
+        let mut trace = Vec::new();
+        let len = self.rules.explain_len(image_name, ancestors, now, false);
+        if trace.try_reserve_exact(len).is_err() {
+            return trace;
+        }
+        trace.resize(len, 0);
+
+        let (_, written) = self
+            .rules
+            .explain(image_name, ancestors, now, false, &mut trace);
+        trace.truncate(written);
+        trace
+    }
+
+    /// Called from the instance setup callback. Returns false if the volume must not be
+    /// attached.
+    pub fn attach_instance(
+        &mut self,
+        instance: usize,
+        volume_name: String,
+        info: VolumeInfo,
+    ) -> bool {
+        self.instances.attach(instance, volume_name, info)
+    }
+
+    /// Called only for manual detach (`fltmc detach`, FilterDetach). Audits the attempt and
+    /// returns the status for the filter manager.
+    pub fn query_detach(
+        &mut self,
+        instance: usize,
+        process_id: u32,
+        image_name: &str,
+        now: u64,
+    ) -> NtStatus {
+        let deny = self.options & OPTION_DENY_MANUAL_DETACH != 0;
+        let (status, severity) = if deny {
+            (STATUS_FLT_DO_NOT_DETACH, Severity::High)
+        } else {
+            (STATUS_SUCCESS, Severity::Warning)
+        };
+
+        log::info!(
+            target: TARGET_LIFECYCLE,
+            "detach requested by {}, {}",
+            image_name,
+            if deny { "denied" } else { "allowed" }
+        );
+
+        let mut event = Event::new(EventKind::DetachAttempt, severity, now)
+            .process(process_id, image_name)
+            .status(status);
+        if let Some(attached) = self.instances.find(instance) {
+            event = event.target(&attached.volume_name);
+        }
+        self.events.push(event);
+
+        status
+    }
+
+    /// Called when an instance is gone, whatever the reason.
+    pub fn detach_instance(&mut self, instance: usize) {
+        self.instances.detach(instance);
+    }
+
+    /// Called from the filter unload callback. A mandatory unload (e.g. shutdown) cannot be
+    /// refused, any other needs tamper protection to be unlocked or the unload to be allowed.
+    /// An allowed unload only lets this one through.
+    pub fn query_unload(
+        &mut self,
+        mandatory: bool,
+        process_id: u32,
+        image_name: &str,
+        now: u64,
+    ) -> NtStatus {
+        let allowed = self.auth.take_unload_allowed();
+        if mandatory || !self.auth.is_locked() || allowed {
+            return STATUS_SUCCESS;
+        }
+
+        log::info!(target: TARGET_LIFECYCLE, "unload refused, tamper protection is locked");
+        self.events.push(
+            Event::new(EventKind::UnloadAttempt, Severity::High, now)
+                .process(process_id, image_name)
+                .status(STATUS_FLT_DO_NOT_DETACH),
+        );
+        STATUS_FLT_DO_NOT_DETACH
+    }
+
+    /// Forgets a control device handle, called on IRP_MJ_CLOSE.
+    pub fn close(&mut self, file_object: usize) {
+        self.auth.close(file_object);
+        if self
+            .vault_opener
+            .is_some_and(|(handle, _)| handle == file_object)
+        {
+            self.vault_opener = None;
+        }
+    }
+
+    /// Replaces the secret of `Persist::Secret` before the host stores it. Returns the previous
+    /// secret, for `restore_secret` if it cannot be stored.
+    pub fn commit_secret(
+        &mut self,
+        file_object: usize,
+        secret: &[u8],
+    ) -> Result<Option<Vec<u8>>, NtStatus> {
+        self.auth.commit_secret(file_object, secret)
+    }
+
+    /// Rolls back `commit_secret` once storing `committed` failed.
+    pub fn restore_secret(&mut self, committed: &[u8], previous: Option<Vec<u8>>) {
+        self.auth.restore_secret(committed, previous)
+    }
+
+    /// Runs one IOCTL. Mutating codes need a privileged caller and, while tamper protection is
+    /// locked, an authorized handle; the authorization codes check the handle themselves.
+    pub fn handle_ioctl(
+        &mut self,
+        code: u32,
+        buffer: &mut dyn IoctlBuffer,
+        caller: &Caller,
+        host: &dyn Host,
+    ) -> Result<Reply, NtStatus> {
+        log::info!(target: TARGET_IOCTL, "device_io.IoControlCode: {} ", code);
+        if ioctl_codes::is_mutating(code) && !caller.privileged {
+            log::info!(target: TARGET_IOCTL, "IOCTL refused, caller is not elevated");
+            return Err(STATUS_ACCESS_DENIED);
+        }
+        if requires_authorization(code) && !self.auth.is_authorized(caller.file_object) {
+            log::info!(target: TARGET_IOCTL, "IOCTL refused, handle is not authorized");
+            return Err(STATUS_ACCESS_DENIED);
+        }
+
+        match code {
+            ioctl_codes::IOCTL_DELPROTECT_ADD_EXE_UTF8 => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_ADD_EXE_UTF8 ");
+                let proc_name = buffer
+                    .input()
+                    .and_then(input::exe_name_utf8)
+                    .map_err(status_from)?;
+
+                log::info!(target: TARGET_IOCTL, "proc_name: {}", proc_name);
+                self.rules.push(proc_name, TimeWindow::default())?;
+                Ok(Reply::default())
+            },
+            ioctl_codes::IOCTL_DELPROTECT_ADD_EXE_UTF16 => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_ADD_EXE_UTF16 ");
+                let proc_name = buffer
+                    .input()
+                    .and_then(input::exe_name_utf16)
+                    .map_err(status_from)?;
+
+                log::info!(target: TARGET_IOCTL, "proc_name: {}", proc_name);
+                self.rules.push(&proc_name, TimeWindow::default())?;
+                Ok(Reply::default())
+            },
+            ioctl_codes::IOCTL_DELPROTECT_ADD_RULE => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_ADD_RULE ");
+                let (record, _) = buffer
+                    .input()
+                    .and_then(input::non_empty)
+                    .and_then(RuleRecord::decode)
+                    .map_err(status_from)?;
+                self.rules.add_record(&record)?;
+                Ok(Reply::default())
+            },
+            ioctl_codes::IOCTL_DELPROTECT_LIST_RULES => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_LIST_RULES ");
+                // input and output share the buffer, read the flags before writing
+                let flags = buffer.input().and_then(input::flags).map_err(status_from)?;
+
+                // purging changes the rule list although the code only needs read access
+                let purge = flags & LIST_FLAG_PURGE_EXPIRED != 0;
+                if purge && !(caller.privileged && self.auth.is_authorized(caller.file_object)) {
+                    return Err(STATUS_ACCESS_DENIED);
+                }
+
+                let output = buffer.output(LIST_HEADER_SIZE).map_err(status_from)?;
+                Ok(Reply::written(self.rules.list(output, host.now(), purge)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_SET_VOLUME_POLICY => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_SET_VOLUME_POLICY ");
+                let policy = buffer
+                    .input()
+                    .and_then(input::non_empty)
+                    .and_then(VolumePolicy::decode)
+                    .map_err(status_from)?;
+
+                let mut encoded = Vec::new();
+                if encoded.try_reserve_exact(policy.encoded_len()).is_err() {
+                    return Err(STATUS_INSUFFICIENT_RESOURCES);
+                }
+                encoded.resize(policy.encoded_len(), 0);
+                let len = policy
+                    .encode(&mut encoded)
+                    .ok_or(STATUS_INVALID_PARAMETER)?;
+                encoded.truncate(len);
+
+                self.instances.set_policy(policy);
+                Ok(Reply::persist(Persist::VolumePolicy(encoded)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_LIST_INSTANCES => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_LIST_INSTANCES ");
+                let output = buffer.output(LIST_HEADER_SIZE).map_err(status_from)?;
+                Ok(Reply::written(self.instances.list(output)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_GET_EVENTS => {
+                let output = buffer.output(LIST_HEADER_SIZE).map_err(status_from)?;
+                Ok(Reply::written(self.events.drain(output)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_GET_OPTIONS => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_GET_OPTIONS ");
+                let output = buffer.output(4).map_err(status_from)?;
+
+                output[..4].copy_from_slice(&self.options.to_le_bytes());
+                Ok(Reply::written(4))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_SET_OPTIONS => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_SET_OPTIONS ");
+                let update = buffer
+                    .input()
+                    .and_then(input::non_empty)
+                    .and_then(OptionsUpdate::decode)
+                    .map_err(status_from)?;
+
+                self.options = update.apply(self.options);
+                log::info!(target: TARGET_IOCTL, "options: 0x{:08x}", self.options);
+                Ok(Reply::persist(Persist::Options(self.options)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_GET_CHALLENGE => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_GET_CHALLENGE ");
+                let output = buffer.output(CHALLENGE_MESSAGE_SIZE).map_err(status_from)?;
+
+                let mut challenge = [0u8; CHALLENGE_SIZE];
+                if let Err(status) = host.fill_random(&mut challenge) {
+                    log::info!(
+                        target: TARGET_IOCTL,
+                        "failed to generate a challenge 0x{:08x}",
+                        status
+                    );
+                    return Err(status);
+                }
+                let written = self.auth.challenge(caller.file_object, challenge, output)?;
+                Ok(Reply::written(written))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_AUTHENTICATE => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_AUTHENTICATE ");
+                let response = buffer
+                    .input()
+                    .and_then(|bytes| input::exact(bytes, RESPONSE_SIZE))
+                    .map_err(status_from)?;
+
+                let result = self.auth.authenticate(caller.file_object, response);
+                if result == Err(STATUS_ACCESS_DENIED) {
+                    self.events.push(
+                        Event::new(EventKind::AuthFailure, Severity::High, host.now())
+                            .process(caller.process_id, &caller.image_name)
+                            .status(STATUS_ACCESS_DENIED),
+                    );
+                }
+                result.map(|_| Reply::default())
+            },
+            ioctl_codes::IOCTL_DELPROTECT_SET_SECRET => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_SET_SECRET ");
+                let secret = buffer
+                    .input()
+                    .and_then(input::non_empty)
+                    .map_err(status_from)?;
+                self.auth.check_secret(caller.file_object, secret)?;
+
+                let mut copy = Vec::new();
+                if copy.try_reserve_exact(secret.len()).is_err() {
+                    return Err(STATUS_INSUFFICIENT_RESOURCES);
+                }
+                copy.extend_from_slice(secret);
+                Ok(Reply::persist(Persist::Secret(copy)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_ALLOW_UNLOAD => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_ALLOW_UNLOAD ");
+                self.auth.allow_unload(caller.file_object)?;
+                Ok(Reply::default())
+            },
+            ioctl_codes::IOCTL_DELPROTECT_CLEAR => {
+                self.rules.clear();
+                Ok(Reply::default())
+            },
+            ioctl_codes::IOCTL_DELPROTECT_EVALUATE => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_EVALUATE ");
+                // input and output share the buffer, copy what is needed before writing
+                let (operation, image_name, target, ancestor_names, time) = {
+                    let request = buffer
+                        .input()
+                        .and_then(input::non_empty)
+                        .and_then(EvaluateRequest::decode)
+                        .map_err(status_from)?;
+                    // the SID is ignored, the rules do not look at users
+                    let image_name =
+                        read_utf16(request.process).ok_or(STATUS_INSUFFICIENT_RESOURCES)?;
+                    let mut ancestor_names = Vec::new();
+                    ancestor_names
+                        .try_reserve_exact(MAX_EVALUATE_ANCESTORS)
+                        .map_err(|_| STATUS_INSUFFICIENT_RESOURCES)?;
+                    for name in request.ancestors() {
+                        ancestor_names.push(read_utf16(name).ok_or(STATUS_INSUFFICIENT_RESOURCES)?);
+                    }
+                    let target = read_utf16(request.target).ok_or(STATUS_INSUFFICIENT_RESOURCES)?;
+                    log::info!(
+                        target: TARGET_IOCTL,
+                        "evaluate {} of {}",
+                        request.operation.as_str(),
+                        target
+                    );
+                    (
+                        request.operation,
+                        image_name,
+                        target,
+                        ancestor_names,
+                        request.time,
+                    )
+                };
+                let mut ancestors = Vec::new();
+                ancestors
+                    .try_reserve_exact(ancestor_names.len())
+                    .map_err(|_| STATUS_INSUFFICIENT_RESOURCES)?;
+                ancestors.extend(ancestor_names.iter().map(|name| Ancestor {
+                    process_id: 0,
+                    image_name: name,
+                }));
+                let now = if time != 0 { time } else { host.now() };
+
+                // the same decision the pre-operation callbacks make, lockdowns are per process
+                // and not asked for here
+                let (decision, rule_id) =
+                    filter::evaluate(self, operation, &image_name, &target, &ancestors, now);
+                let output = buffer.output(EVALUATE_REPLY_SIZE).map_err(status_from)?;
+                let (trace_count, trace_len) = self.rules.explain(
+                    &image_name,
+                    &ancestors,
+                    now,
+                    true,
+                    &mut output[EVALUATE_REPLY_SIZE..],
+                );
+                EvaluateReply {
+                    decision,
+                    trace_count,
+                    rule_id,
+                    time: now,
+                }
+                .encode(output);
+                Ok(Reply::written(EVALUATE_REPLY_SIZE + trace_len))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_SET_LOG_LEVEL => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_SET_LOG_LEVEL ");
+                let level = buffer
+                    .input()
+                    .and_then(LogLevel::decode)
+                    .map_err(status_from)?;
+
+                logging::set_level(level);
+                // may not be logged any more at the new level
+                log::info!(target: TARGET_IOCTL, "log level: {}", level.as_str());
+                Ok(Reply::persist(Persist::LogLevel(level)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_GET_LOG_LEVEL => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_GET_LOG_LEVEL ");
+                let output = buffer.output(4).map_err(status_from)?;
+
+                output[..4].copy_from_slice(&(logging::level() as u32).to_le_bytes());
+                Ok(Reply::written(4))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_GET_STATS => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_GET_STATS ");
+                // input and output share the buffer, read the flags before writing
+                let flags = buffer.input().and_then(input::flags).map_err(status_from)?;
+
+                // resetting changes the counters although the code only needs read access
+                let reset = flags & STATS_FLAG_RESET != 0;
+                if reset && !(caller.privileged && self.auth.is_authorized(caller.file_object)) {
+                    return Err(STATUS_ACCESS_DENIED);
+                }
+
+                let output = buffer.output(STATS_HEADER_SIZE).map_err(status_from)?;
+                let (rule_count, written) =
+                    self.rules.stats(&mut output[STATS_HEADER_SIZE..], reset);
+                let mut header = host.stats().snapshot(reset.then(|| host.now()));
+                header.rule_count = rule_count;
+                header.encode(output);
+                Ok(Reply::written(STATS_HEADER_SIZE + written))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_SET_DETECTOR => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_SET_DETECTOR ");
+                let settings = buffer
+                    .input()
+                    .and_then(DetectorSettings::decode)
+                    .map_err(status_from)?;
+
+                log::info!(
+                    target: TARGET_IOCTL,
+                    "detector: {} s, {} operations, {} directories",
+                    settings.window_seconds,
+                    settings.max_operations,
+                    settings.max_directories
+                );
+                self.detector.set_settings(settings);
+                Ok(Reply::persist(Persist::Detector(settings)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_GET_DETECTOR => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_GET_DETECTOR ");
+                let output = buffer.output(DETECTOR_SETTINGS_SIZE).map_err(status_from)?;
+                Ok(Reply::written(
+                    self.detector.settings().encode(output).unwrap_or_default(),
+                ))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_LIST_LOCKDOWNS => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_LIST_LOCKDOWNS ");
+                let output = buffer.output(LIST_HEADER_SIZE).map_err(status_from)?;
+                Ok(Reply::written(self.detector.list(output)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_RELEASE_LOCKDOWN => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_RELEASE_LOCKDOWN ");
+                let process_id = buffer
+                    .input()
+                    .and_then(|bytes| input::exact(bytes, 4))
+                    .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .map_err(status_from)?;
+
+                let released = self.detector.release(process_id);
+                log::info!(
+                    target: TARGET_IOCTL,
+                    "released {} lockdown(s) for process {}",
+                    released,
+                    process_id
+                );
+                if released == 0 {
+                    return Err(STATUS_NOT_FOUND);
+                }
+                Ok(Reply::default())
+            },
+            ioctl_codes::IOCTL_DELPROTECT_ADD_CANARY => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_ADD_CANARY ");
+                // input and output share the buffer, add before writing the id
+                let id = {
+                    let (record, _) = buffer
+                        .input()
+                        .and_then(input::non_empty)
+                        .and_then(CanaryRecord::decode)
+                        .map_err(status_from)?;
+                    self.canaries.add_record(&record)?
+                };
+
+                log::info!(target: TARGET_IOCTL, "canary {} added", id);
+                let output = buffer.output(4).map_err(status_from)?;
+                output[..4].copy_from_slice(&id.to_le_bytes());
+                Ok(Reply::written(4))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_LIST_CANARIES => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_LIST_CANARIES ");
+                let output = buffer.output(LIST_HEADER_SIZE).map_err(status_from)?;
+                Ok(Reply::written(self.canaries.list(output)))
+            },
+            ioctl_codes::IOCTL_DELPROTECT_REMOVE_CANARY => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_REMOVE_CANARY ");
+                let id = buffer
+                    .input()
+                    .and_then(|bytes| input::exact(bytes, 4))
+                    .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .map_err(status_from)?;
+
+                if !self.canaries.remove(id) {
+                    return Err(STATUS_NOT_FOUND);
+                }
+                log::info!(target: TARGET_IOCTL, "canary {} removed", id);
+                Ok(Reply::default())
+            },
+            ioctl_codes::IOCTL_DELPROTECT_OPEN_VAULT => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_DELPROTECT_OPEN_VAULT ");
+                self.vault_opener = Some((caller.file_object, caller.process_id));
+                log::info!(
+                    target: TARGET_IOCTL,
+                    "vaults opened by {} ({})",
+                    caller.image_name,
+                    caller.process_id
+                );
+                Ok(Reply::default())
+            },
+            _ => {
+                log::info!(target: TARGET_IOCTL, "IOCTL_ other ");
+                Err(STATUS_INVALID_DEVICE_REQUEST)
+            },
+        }
+    }
+}
+
+/// IOCTLs which change the configuration. They need an authorized handle while tamper
+/// protection is locked. The authorization IOCTLs check the handle themselves.
+fn requires_authorization(code: u32) -> bool {
+    ioctl_codes::is_mutating(code)
+        && !matches!(
+            code,
+            ioctl_codes::IOCTL_DELPROTECT_GET_CHALLENGE
+                | ioctl_codes::IOCTL_DELPROTECT_AUTHENTICATE
+                | ioctl_codes::IOCTL_DELPROTECT_SET_SECRET
+                | ioctl_codes::IOCTL_DELPROTECT_ALLOW_UNLOAD
+        )
+}
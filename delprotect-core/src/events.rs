@@ -0,0 +1,183 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use common::{
+    event::{EventKind, EventRecord, Severity},
+    logging::TARGET_LIFECYCLE,
+    rule::Response,
+    status::{NtStatus, STATUS_SUCCESS},
+    wire::{ListHeader, LIST_HEADER_SIZE},
+};
+
+/// Events which were not read yet are dropped, oldest first, above this count.
+pub const MAX_EVENT_COUNT: usize = 256;
+
+/// An audit event waiting to be read by the client. Strings are kept as UTF-16LE bytes, the
+/// format they are returned in.
+pub struct Event {
+    kind: EventKind,
+    severity: Severity,
+    time: u64,
+    process_id: u32,
+    rule_id: u32,
+    status: NtStatus,
+    response: Response,
+    process: Vec<u8>,
+    target: Vec<u8>,
+    detail: Vec<u8>,
+    /// Encoded `RuleTrace` records of the decision.
+    trace: Vec<u8>,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, severity: Severity, time: u64) -> Self {
+        Self {
+            kind,
+            severity,
+            time,
+            process_id: 0,
+            rule_id: 0,
+            status: STATUS_SUCCESS,
+            response: Response::None,
+            process: Vec::new(),
+            target: Vec::new(),
+            detail: Vec::new(),
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn process(mut self, process_id: u32, image_name: &str) -> Self {
+        self.process_id = process_id;
+        self.process = to_utf16(image_name);
+        self
+    }
+
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = to_utf16(target);
+        self
+    }
+
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = to_utf16(detail);
+        self
+    }
+
+    pub fn trace(mut self, trace: Vec<u8>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    pub fn rule(mut self, rule_id: u32) -> Self {
+        self.rule_id = rule_id;
+        self
+    }
+
+    pub fn status(mut self, status: NtStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn response(mut self, response: Response) -> Self {
+        self.response = response;
+        self
+    }
+
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        EventRecord {
+            kind: self.kind as u16,
+            severity: self.severity,
+            time: self.time,
+            process_id: self.process_id,
+            rule_id: self.rule_id,
+            status: self.status,
+            response: self.response,
+            process: &self.process,
+            target: &self.target,
+            detail: &self.detail,
+            trace: &self.trace,
+        }
+        .encode(buffer)
+    }
+}
+
+/// Strings which cannot be allocated are left empty, an event is still better than none.
+fn to_utf16(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    if bytes.try_reserve_exact(s.len() * 2).is_ok() {
+        bytes.extend(s.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+    }
+    bytes
+}
+
+/// Bounded queue of events waiting for `IOCTL_DELPROTECT_GET_EVENTS`.
+pub struct EventQueue {
+    events: VecDeque<Event>,
+    /// Events dropped since the last drain.
+    dropped: u32,
+}
+
+impl EventQueue {
+    /// Returns `None` if there is no memory for `MAX_EVENT_COUNT` events.
+    pub fn new() -> Option<Self> {
+        let mut events = VecDeque::new();
+        if let Err(e) = events.try_reserve_exact(MAX_EVENT_COUNT) {
+            log::info!(
+                target: TARGET_LIFECYCLE,
+                "fail to reserve a {} bytes of memory. Err: {:?}",
+                ::core::mem::size_of::<Event>() * MAX_EVENT_COUNT,
+                e
+            );
+            return None;
+        }
+
+        Some(Self { events, dropped: 0 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        self.events.iter()
+    }
+
+    pub fn push(&mut self, event: Event) {
+        if self.events.len() >= MAX_EVENT_COUNT {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+
+    /// Moves as many queued events as fit into `output`, behind a `ListHeader`. Returns the
+    /// number of bytes written.
+    pub fn drain(&mut self, output: &mut [u8]) -> usize {
+        let mut header = ListHeader {
+            total: self.events.len() as u32,
+            ..ListHeader::default()
+        };
+        let mut offset = LIST_HEADER_SIZE;
+
+        while let Some(event) = self.events.front() {
+            match event.encode(&mut output[offset..]) {
+                Some(len) => {
+                    offset += len;
+                    header.returned += 1;
+                    self.events.pop_front();
+                },
+                None => break,
+            }
+        }
+        header.removed = self.dropped;
+        self.dropped = 0;
+
+        header.encode(output);
+        offset
+    }
+}
@@ -0,0 +1,1108 @@
+//! Decision flow of the delete pre-operation callbacks. The driver extracts the few fields it
+//! needs from the callback data and completes the operation as told; process and file names,
+//! the clock and memory come through the `host` traits, so every path including the failing
+//! ones runs on any host.
+
+use alloc::{format, string::String, vec::Vec};
+use common::{
+    evaluate::{EvaluateDecision, EvaluateOperation},
+    event::{EventKind, Severity},
+    logging::TARGET_POLICY,
+    protection::{
+        cleared_attributes, describe, weakened_security, ATTRIBUTE_NAMES, SECURITY_NAMES,
+    },
+    rule::Response,
+    stats::Counter,
+    status::{NtStatus, STATUS_ACCESS_DENIED},
+    truncation::{is_destructive, SizeInformation},
+    vault::{copy_name, is_vault_path},
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use log::Level;
+
+use crate::{
+    detector::{Activity, Operation},
+    engine::{Decision, Engine},
+    events::Event,
+    host::{
+        Allocator, Clock, FileInfoProvider, FileNameProvider, ProcessIdentity, Requestor,
+        Responder, Vault, MAX_NAME_UNITS,
+    },
+    lineage::{self, Ancestor},
+    log_limited,
+    stats::Stats,
+    vault::{preserve, Preservation, Preserved},
+};
+
+/// CreateOptions flag of wdm.h, the file is deleted when its last handle is closed.
+pub const FILE_DELETE_ON_CLOSE: u32 = 0x0000_1000;
+/// Standard access right of winnt.h, needed to delete or rename the file.
+pub const DELETE: u32 = 0x0001_0000;
+/// Specific access rights of winnt.h which let a handle change the content of the file.
+pub const FILE_WRITE_DATA: u32 = 0x0002;
+pub const FILE_APPEND_DATA: u32 = 0x0004;
+/// Standard access rights of winnt.h which let a handle change the security of the file.
+pub const WRITE_DAC: u32 = 0x0004_0000;
+pub const WRITE_OWNER: u32 = 0x0008_0000;
+/// Access mask bit of winnt.h asking for every right the caller is allowed.
+pub const MAXIMUM_ALLOWED: u32 = 0x0200_0000;
+/// Page protections of winnt.h of a section whose changes are written back to the file.
+/// Copy-on-write sections never are.
+pub const PAGE_READWRITE: u32 = 0x04;
+pub const PAGE_EXECUTE_READWRITE: u32 = 0x40;
+/// CreateDisposition values of wdm.h, kept in the high byte of the create options.
+pub const FILE_SUPERSEDE: u32 = 0;
+pub const FILE_OVERWRITE: u32 = 4;
+pub const FILE_OVERWRITE_IF: u32 = 5;
+
+/// What the callback returns to the filter manager.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreOp {
+    /// FLT_PREOP_SUCCESS_NO_CALLBACK, the operation goes on.
+    PassThrough,
+    /// FLT_PREOP_COMPLETE with this status.
+    Complete(NtStatus),
+}
+
+/// Outcome of a pre-operation callback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Verdict {
+    pub pre_op: PreOp,
+    /// `None` if the operation was let through without asking the engine: not a delete, a
+    /// kernel mode request or a process which could not be identified. Renames and overwrites
+    /// only get `Decision::LockedDown`, the rules are about deletes, and writes for immutable
+    /// ones.
+    pub decision: Option<Decision>,
+}
+
+impl Verdict {
+    fn skip() -> Self {
+        Self {
+            pre_op: PreOp::PassThrough,
+            decision: None,
+        }
+    }
+}
+
+/// The engine behind the lock of the host.
+pub trait EngineLock {
+    /// Runs `f` with the lock held, `None` if the engine does not exist. The host publishes
+    /// the engine before it releases the lock, see `Published`.
+    fn with_engine<R>(&self, f: impl FnOnce(&mut Engine) -> R) -> Option<R>;
+
+    /// What the engine last published, read without the lock.
+    fn published(&self) -> &Published;
+}
+
+/// The part of the engine the callbacks read on every write or create without taking the
+/// lock. The host keeps one next to the engine and calls `publish` each time `f` of
+/// `with_engine` ran, so a change of the rules shows once the lock is released.
+#[derive(Debug, Default)]
+pub struct Published {
+    immutable_rules: AtomicBool,
+    vaults: AtomicBool,
+}
+
+impl Published {
+    pub const fn new() -> Self {
+        Self {
+            immutable_rules: AtomicBool::new(false),
+            vaults: AtomicBool::new(false),
+        }
+    }
+
+    /// Called with the lock held.
+    pub fn publish(&self, engine: &Engine) {
+        self.immutable_rules
+            .store(engine.rules().any_immutable(), Ordering::Release);
+        self.vaults.store(engine.has_vaults(), Ordering::Release);
+    }
+
+    /// True if writes have to be decided at all.
+    pub fn has_immutable_rules(&self) -> bool {
+        self.immutable_rules.load(Ordering::Acquire)
+    }
+
+    /// True if creates have to be checked against the vaults.
+    pub fn has_vaults(&self) -> bool {
+        self.vaults.load(Ordering::Acquire)
+    }
+}
+
+/// The platform services one callback runs with.
+pub struct Platform<'a> {
+    pub identity: &'a dyn ProcessIdentity,
+    pub files: &'a dyn FileNameProvider,
+    pub info: &'a dyn FileInfoProvider,
+    pub clock: &'a dyn Clock,
+    pub allocator: &'a dyn Allocator,
+    pub stats: &'a Stats,
+    /// The vault on the volume of the file.
+    pub vault: &'a dyn Vault,
+    pub responder: &'a dyn Responder,
+}
+
+/// IRP_MJ_CREATE. Only opens with FILE_DELETE_ON_CLOSE from user mode are decided, they run in
+/// the context of the requesting process, and with `OPTION_DENY_DELETE_ACCESS` the ones asking
+/// for DELETE access. While a rule is immutable, opens asking for write access or overwriting
+/// the file are decided for it. Overwriting opens are shown to the canaries and the detector.
+/// Once a vault may exist, see `Engine::note_vault`, opens which could change a file of a vault
+/// are denied unless its process opened the vaults with `IOCTL_DELPROTECT_OPEN_VAULT`.
+pub fn pre_create(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    kernel_mode: bool,
+    create_options: u32,
+    desired_access: u32,
+) -> Verdict {
+    platform.stats.count(Counter::CreatesInspected);
+    if kernel_mode {
+        return Verdict::skip();
+    }
+
+    let verdict = decide_open(platform, engine, create_options, desired_access);
+    let changes = DELETE | FILE_WRITE_DATA | FILE_APPEND_DATA | WRITE_DAC | WRITE_OWNER;
+    if verdict.pre_op == PreOp::PassThrough
+        && (overwrites(create_options)
+            || create_options & FILE_DELETE_ON_CLOSE != 0
+            || desired_access & (changes | MAXIMUM_ALLOWED) != 0)
+        && engine.published().has_vaults()
+        && changes_vault(platform, engine)
+    {
+        platform.stats.count(Counter::Blocked);
+        return Verdict {
+            pre_op: PreOp::Complete(STATUS_ACCESS_DENIED),
+            decision: None,
+        };
+    }
+    verdict
+}
+
+/// The rules, canaries and detector part of `pre_create`.
+fn decide_open(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    create_options: u32,
+    desired_access: u32,
+) -> Verdict {
+    if create_options & FILE_DELETE_ON_CLOSE != 0 {
+        platform.stats.count(Counter::DeleteOnClose);
+        return decide(platform, engine, Requestor::Current, Destruction::Delete);
+    }
+
+    let denies_delete_access = || {
+        engine
+            .with_engine(|engine| engine.denies_delete_access())
+            .unwrap_or(false)
+    };
+    // MAXIMUM_ALLOWED alone is not decided, most of them only read, and a delete or write
+    // through the handle is decided when it happens
+    if desired_access & DELETE != 0 && denies_delete_access() {
+        platform.stats.count(Counter::DeleteAccessOpens);
+        let verdict = decide(
+            platform,
+            engine,
+            Requestor::Current,
+            Destruction::OpenForDelete,
+        );
+        if verdict.pre_op != PreOp::PassThrough {
+            return verdict;
+        }
+    }
+
+    let overwrites = overwrites(create_options);
+    let writes = overwrites || desired_access & (FILE_WRITE_DATA | FILE_APPEND_DATA) != 0;
+    if writes && has_immutable_rules(engine) {
+        platform.stats.count(Counter::Writes);
+        let verdict = decide(
+            platform,
+            engine,
+            Requestor::Current,
+            Destruction::OpenForWrite,
+        );
+        if verdict.pre_op != PreOp::PassThrough {
+            return verdict;
+        }
+    }
+
+    if overwrites {
+        watch(platform, engine, Requestor::Current, Operation::Overwrite)
+    } else {
+        Verdict::skip()
+    }
+}
+
+/// True if the CreateDisposition of the create options replaces the content of the file.
+fn overwrites(create_options: u32) -> bool {
+    matches!(
+        create_options >> 24,
+        FILE_SUPERSEDE | FILE_OVERWRITE | FILE_OVERWRITE_IF
+    )
+}
+
+/// IRP_MJ_WRITE, decided while a rule is immutable. `thread` is the thread of the callback
+/// data. Paging writes are let through: they only flush what a cached write or a writable
+/// section, both decided before, put in memory, from a system thread. Writes of a process no
+/// immutable rule names go on without the name of the file, the others use the name kept when
+/// the handle was opened, see `FileNameProvider::keep_opened_name`, and query it without one.
+pub fn pre_write(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    kernel_mode: bool,
+    paging_io: bool,
+    thread: usize,
+) -> Verdict {
+    if kernel_mode || paging_io || !has_immutable_rules(engine) {
+        return Verdict::skip();
+    }
+
+    platform.stats.count(Counter::Writes);
+    decide(
+        platform,
+        engine,
+        Requestor::Thread(thread),
+        Destruction::Write,
+    )
+}
+
+/// IRP_MJ_ACQUIRE_FOR_SECTION_SYNCHRONIZATION, decided while a rule is immutable. It runs in the
+/// context of the process creating the section. Only sections being created whose changes are
+/// written back to the file are decided, image sections and read-only or copy-on-write mappings
+/// go on. The name of the file is never queried here, the file system holds its locks: a
+/// section of a handle without a kept name, opened before the rules were immutable, goes on.
+pub fn pre_acquire_section(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    kernel_mode: bool,
+    create_section: bool,
+    page_protection: u32,
+) -> Verdict {
+    let writable = page_protection & (PAGE_READWRITE | PAGE_EXECUTE_READWRITE) != 0;
+    if kernel_mode || !create_section || !writable || !has_immutable_rules(engine) {
+        return Verdict::skip();
+    }
+
+    platform.stats.count(Counter::Writes);
+    decide(
+        platform,
+        engine,
+        Requestor::Current,
+        Destruction::WritableSection,
+    )
+}
+
+/// IRP_MJ_SET_SECURITY. With `OPTION_GUARD_SECURITY` a change of the owner, DACL or label, see
+/// `common::protection`, is decided like a delete of the thread's process. Kernel mode requests
+/// are let through.
+pub fn pre_set_security(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    kernel_mode: bool,
+    thread: usize,
+    security_information: u32,
+) -> Verdict {
+    let security = weakened_security(security_information);
+    if kernel_mode || security == 0 || !guards_security(engine) {
+        return Verdict::skip();
+    }
+
+    platform.stats.count(Counter::SecurityChanges);
+    decide(
+        platform,
+        engine,
+        Requestor::Thread(thread),
+        Destruction::SetSecurity { security },
+    )
+}
+
+/// IRP_MJ_SET_INFORMATION with FileBasicInformation. With `OPTION_GUARD_SECURITY` clearing
+/// READONLY or SYSTEM is decided like a delete of the thread's process. Kernel mode requests
+/// and files whose attributes cannot be queried are let through.
+pub fn pre_set_basic(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    kernel_mode: bool,
+    thread: usize,
+    file_attributes: u32,
+) -> Verdict {
+    if kernel_mode || file_attributes == 0 || !guards_security(engine) {
+        return Verdict::skip();
+    }
+
+    let attributes = match platform.info.query_attributes() {
+        Ok(attributes) => attributes,
+        Err(status) => {
+            log::info!(
+                target: TARGET_POLICY,
+                "cannot query attributes 0x{:08x}",
+                status
+            );
+            return Verdict::skip();
+        },
+    };
+    let cleared = cleared_attributes(attributes, file_attributes);
+    if cleared == 0 {
+        return Verdict::skip();
+    }
+
+    platform.stats.count(Counter::SecurityChanges);
+    decide(
+        platform,
+        engine,
+        Requestor::Thread(thread),
+        Destruction::ClearAttributes { cleared },
+    )
+}
+
+/// IRP_MJ_SET_INFORMATION with FileDispositionInformation(Ex). `thread` is the thread of the
+/// callback data, the callback may run in another process.
+pub fn pre_set_disposition(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    thread: usize,
+    delete: bool,
+) -> Verdict {
+    if !delete {
+        return Verdict::skip();
+    }
+
+    platform.stats.count(Counter::SetInformationDeletes);
+    decide(
+        platform,
+        engine,
+        Requestor::Thread(thread),
+        Destruction::Delete,
+    )
+}
+
+/// IRP_MJ_SET_INFORMATION with FileEndOfFileInformation, FileAllocationInformation or
+/// FileValidDataLengthInformation. With `OPTION_GUARD_TRUNCATION` the changes which cut the
+/// file, see `common::truncation`, are decided like deletes of the thread's process. Kernel mode
+/// requests, among them the end of file updates of the cache manager, and files whose size
+/// cannot be queried are let through.
+pub fn pre_set_size(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    kernel_mode: bool,
+    thread: usize,
+    information: SizeInformation,
+    new_size: i64,
+) -> Verdict {
+    if kernel_mode || !guards_truncation(engine) {
+        return Verdict::skip();
+    }
+
+    let end_of_file = match platform.info.query_end_of_file() {
+        Ok(end_of_file) => end_of_file,
+        Err(status) => {
+            log::info!(
+                target: TARGET_POLICY,
+                "cannot query end of file 0x{:08x}",
+                status
+            );
+            return Verdict::skip();
+        },
+    };
+    if !is_destructive(information, new_size, end_of_file) {
+        return Verdict::skip();
+    }
+
+    platform.stats.count(Counter::Truncations);
+    let truncation = Destruction::Truncate {
+        information,
+        new_size: new_size as u64,
+    };
+    decide(platform, engine, Requestor::Thread(thread), truncation)
+}
+
+/// IRP_MJ_SET_INFORMATION with FileRenameInformation(Ex), only shown to the canaries and the
+/// detector.
+pub fn pre_rename(platform: &Platform, engine: &impl EngineLock, thread: usize) -> Verdict {
+    watch(
+        platform,
+        engine,
+        Requestor::Thread(thread),
+        Operation::Rename,
+    )
+}
+
+/// A process which cannot be identified is let through, the rules only name processes. A
+/// process locked down, or locked down by this delete, is denied before the rules are asked.
+/// The rules see the lineage of the process as well. A denied or preserved delete is audited
+/// with the trace of the rules naming the process or an ancestor. A delete which cannot be
+/// preserved is denied. A rule denying with a response demotes the process under the same
+/// lock, or has it terminated or suspended once the callback returned.
+///
+/// An open which only asks for DELETE access is not shown to the canaries and the detector,
+/// they count the delete if one follows, and a preserving rule lets it go on to preserve that
+/// delete. A truncation is shown to them as an overwrite and preserved like a delete. Writes
+/// are only decided by immutable rules, without a trace, and not shown to them either. Neither
+/// are security changes, a preserving rule lets them go on like opens.
+fn decide(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    requestor: Requestor,
+    destruction: Destruction,
+) -> Verdict {
+    let operation = destruction.operation();
+    let image_name = query_name(platform.allocator, Some(platform.stats), |buffer| {
+        platform.identity.query_image_name(requestor, buffer)
+    });
+    let Some(image_name) = image_name else {
+        return Verdict::skip();
+    };
+
+    let now = platform.clock.now();
+    let process_id = platform.identity.process_id(requestor);
+    let through_handle = matches!(
+        destruction,
+        Destruction::Write | Destruction::WritableSection
+    );
+    if through_handle
+        && !engine
+            .with_engine(|engine| engine.decides_writes(process_id, &image_name, now))
+            .unwrap_or(false)
+    {
+        return Verdict::skip();
+    }
+
+    let query_file_name = || {
+        query_name(platform.allocator, Some(platform.stats), |buffer| {
+            platform.files.query_file_name(buffer)
+        })
+        .unwrap_or_default()
+    };
+    // canaries, the directories the detector counts and the paths of immutable rules need the
+    // file name before the rules
+    let watched = if through_handle {
+        match platform.files.opened_name() {
+            Some(name) => Some(name),
+            None if destruction == Destruction::WritableSection => return Verdict::skip(),
+            None => Some(query_file_name()),
+        }
+    } else {
+        ((operation.is_some() && is_watching(engine)) || destruction.is_write())
+            .then(query_file_name)
+    };
+
+    let Some(decided) = engine.with_engine(|engine| {
+        let activity = match (&watched, operation) {
+            (Some(file_name), Some(operation)) => {
+                engine.watch(operation, process_id, &image_name, file_name, now)
+            },
+            _ => Activity::Normal,
+        };
+        let mut decided = Decided {
+            activity,
+            decision: Decision::LockedDown,
+            trace: Vec::new(),
+            lineage: String::new(),
+            failure_status: STATUS_ACCESS_DENIED,
+            response: Response::None,
+            queue_response: false,
+            vault_id: 0,
+        };
+        if activity.is_locked_down() {
+            return decided;
+        }
+
+        let ancestors = engine.lineage().ancestors(process_id);
+        let file_name = watched.as_deref().unwrap_or_default();
+        decided.decision =
+            decide_rules(engine, destruction, &image_name, file_name, &ancestors, now);
+        let rule_id = match decided.decision {
+            Decision::Allow | Decision::LockedDown => return decided,
+            Decision::Deny { rule_id } | Decision::Preserve { rule_id } => rule_id,
+        };
+        // traced under the same lock, so it shows the rules which made the decision
+        if !destruction.is_write() {
+            decided.trace = engine.trace_delete(&image_name, &ancestors, now);
+        }
+        decided.lineage = lineage::describe(&ancestors);
+        if let Some(description) = destruction.describe() {
+            let separator = if decided.lineage.is_empty() { "" } else { ", " };
+            decided.lineage = format!("{description}{separator}{}", decided.lineage);
+        }
+        engine.rules_mut().record_hit(rule_id, now);
+        if let Some(rule) = engine.rules().get(rule_id) {
+            decided.failure_status = rule.failure_status;
+            decided.response = rule.action.response();
+        }
+
+        if decided.response == Response::Demote {
+            engine.demote(process_id, &image_name, rule_id, now);
+        }
+        if let (Decision::Deny { .. }, Response::Terminate | Response::Suspend) =
+            (decided.decision, decided.response)
+        {
+            decided.queue_response = engine.claim_response(process_id);
+        }
+        if let Decision::Preserve { .. } = decided.decision {
+            decided.vault_id = engine.next_vault_id(now);
+            engine.note_vault();
+        }
+        decided
+    }) else {
+        return Verdict::skip();
+    };
+    let Decided {
+        activity,
+        decision,
+        trace,
+        lineage,
+        failure_status,
+        response,
+        queue_response,
+        vault_id,
+    } = decided;
+    let file_name = || watched.clone().unwrap_or_else(query_file_name);
+
+    let pre_op = match decision {
+        // the delete, if one follows, is counted, and size changes are not deletes
+        Decision::Allow if destruction != Destruction::Delete => PreOp::PassThrough,
+        Decision::Allow => {
+            platform.stats.count(Counter::Allowed);
+            PreOp::PassThrough
+        },
+        Decision::LockedDown => {
+            let operation = Watched {
+                operation: operation.unwrap_or(Operation::Delete),
+                now,
+                process_id,
+                image_name: &image_name,
+                file_name: watched.as_deref().unwrap_or_default(),
+            };
+            deny_locked_down(platform, engine, &operation, activity)
+        },
+        Decision::Deny { rule_id } => {
+            let file_name = file_name();
+            let denial = Denial {
+                destruction,
+                requestor,
+                now,
+                image_name: &image_name,
+                file_name: &file_name,
+                lineage: &lineage,
+                rule_id,
+                failure_status,
+                response,
+                queue_response,
+            };
+            deny(platform, engine, &denial, trace)
+        },
+        Decision::Preserve { rule_id } => {
+            let file_name = file_name();
+            let preservation = Preservation {
+                id: vault_id,
+                time: now,
+                rule_id,
+                process_id,
+                image_name: &image_name,
+                file_name: &file_name,
+            };
+
+            match preserve(platform.vault, &preservation) {
+                Ok(Preserved::Missing) => {
+                    log::info!(
+                        target: TARGET_POLICY,
+                        "nothing to preserve of {}, it does not exist",
+                        file_name
+                    );
+                    PreOp::PassThrough
+                },
+                Ok(Preserved::Copied) => {
+                    platform.stats.count(Counter::Preserved);
+                    log_limited!(
+                        now,
+                        target: TARGET_POLICY,
+                        Level::Info,
+                        "Preserve {} deleted by {} (rule {})",
+                        file_name,
+                        image_name,
+                        rule_id
+                    );
+
+                    let event = Event::new(EventKind::DeletePreserved, Severity::Info, now)
+                        .process(process_id, &image_name)
+                        .target(&file_name)
+                        .detail(&copy_name(vault_id))
+                        .rule(rule_id)
+                        .trace(trace);
+                    engine.with_engine(|engine| engine.push_event(event));
+                    PreOp::PassThrough
+                },
+                Err(status) => {
+                    log::info!(
+                        target: TARGET_POLICY,
+                        "cannot preserve {} 0x{:08x}",
+                        file_name,
+                        status
+                    );
+                    let denial = Denial {
+                        destruction,
+                        requestor,
+                        now,
+                        image_name: &image_name,
+                        file_name: &file_name,
+                        lineage: &lineage,
+                        rule_id,
+                        failure_status,
+                        response: Response::None,
+                        queue_response: false,
+                    };
+                    deny(platform, engine, &denial, trace)
+                },
+            }
+        },
+    };
+
+    if destruction == Destruction::OpenForWrite && pre_op == PreOp::PassThrough {
+        if let Some(file_name) = &watched {
+            platform.files.keep_opened_name(file_name);
+        }
+    }
+    Verdict {
+        pre_op,
+        decision: Some(decision),
+    }
+}
+
+/// The rules part of `decide`: the immutable rules covering the file for a write, the other
+/// rules for the rest. A preserving rule lets what loses no content go on.
+fn decide_rules(
+    engine: &Engine,
+    destruction: Destruction,
+    image_name: &str,
+    file_name: &str,
+    ancestors: &[Ancestor],
+    now: u64,
+) -> Decision {
+    let decision = if destruction.is_write() {
+        engine.check_write(image_name, file_name, ancestors, now)
+    } else {
+        engine.check_delete(image_name, ancestors, now)
+    };
+    match decision {
+        Decision::Preserve { .. } if !destruction.can_preserve() => Decision::Allow,
+        decision => decision,
+    }
+}
+
+/// How the callbacks would decide `operation` of `target` by a process with this NT image path
+/// and ancestors at `now`, for `IOCTL_DELPROTECT_EVALUATE`. The options pick what is decided
+/// like the callbacks do, the canaries are looked up without being tripped. Returns the
+/// decision and the id of the rule or canary which made it.
+pub(crate) fn evaluate(
+    engine: &Engine,
+    operation: EvaluateOperation,
+    image_name: &str,
+    target: &str,
+    ancestors: &[Ancestor],
+    now: u64,
+) -> (EvaluateDecision, u32) {
+    // the size does not change the decision, only the event
+    let truncation = Destruction::Truncate {
+        information: SizeInformation::EndOfFile,
+        new_size: 0,
+    };
+    let (destruction, shown_after) = match operation {
+        EvaluateOperation::Delete => (Some(Destruction::Delete), None),
+        EvaluateOperation::OpenForDelete => (
+            engine
+                .denies_delete_access()
+                .then_some(Destruction::OpenForDelete),
+            None,
+        ),
+        EvaluateOperation::Truncate => (engine.guards_truncation().then_some(truncation), None),
+        EvaluateOperation::Write => (Some(Destruction::Write), None),
+        EvaluateOperation::SetSecurity => (
+            engine
+                .guards_security()
+                .then_some(Destruction::SetSecurity { security: 0 }),
+            None,
+        ),
+        EvaluateOperation::Rename => (None, Some(Operation::Rename)),
+        // `pre_create` decides it for the immutable rules before it shows it to the canaries
+        EvaluateOperation::Overwrite => {
+            (Some(Destruction::OpenForWrite), Some(Operation::Overwrite))
+        },
+    };
+    let canary = |shown: Option<Operation>| {
+        shown
+            .and_then(|_| engine.canaries().find(target))
+            .map(|canary| (EvaluateDecision::Canary, canary.id))
+    };
+
+    if let Some(destruction) = destruction {
+        if let Some(decided) = canary(destruction.operation()) {
+            return decided;
+        }
+        match decide_rules(engine, destruction, image_name, target, ancestors, now) {
+            Decision::Deny { rule_id } => return (EvaluateDecision::Deny, rule_id),
+            Decision::Preserve { rule_id } => return (EvaluateDecision::Preserve, rule_id),
+            Decision::Allow | Decision::LockedDown => {},
+        }
+    }
+    canary(shown_after).unwrap_or((EvaluateDecision::Allow, 0))
+}
+
+/// What `decide` is asked about.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Destruction {
+    Delete,
+    /// An open asking for DELETE access, see `OPTION_DENY_DELETE_ACCESS`.
+    OpenForDelete,
+    /// A size change which cuts the file, see `common::truncation`.
+    Truncate {
+        information: SizeInformation,
+        new_size: u64,
+    },
+    /// IRP_MJ_WRITE, see `common::rule::RULE_FLAG_IMMUTABLE`.
+    Write,
+    /// A section whose changes are written back to the file.
+    WritableSection,
+    /// An open asking for write access or overwriting the file.
+    OpenForWrite,
+    /// A security descriptor change, `common::protection::SECURITY_NAMES` bits.
+    SetSecurity {
+        security: u32,
+    },
+    /// A FileBasicInformation clearing `common::protection::ATTRIBUTE_NAMES` bits.
+    ClearAttributes {
+        cleared: u32,
+    },
+}
+
+impl Destruction {
+    /// What the canaries and the detector are shown, `None` for nothing.
+    fn operation(&self) -> Option<Operation> {
+        match self {
+            Self::Delete => Some(Operation::Delete),
+            Self::OpenForDelete => None,
+            Self::Truncate { .. } => Some(Operation::Overwrite),
+            Self::Write | Self::WritableSection | Self::OpenForWrite => None,
+            Self::SetSecurity { .. } | Self::ClearAttributes { .. } => None,
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Self::Write | Self::WritableSection | Self::OpenForWrite
+        )
+    }
+
+    /// False for what loses no content, a preserving rule lets it go on.
+    fn can_preserve(&self) -> bool {
+        matches!(self, Self::Delete | Self::Truncate { .. })
+    }
+
+    /// What the event of a denial is and what its log line calls the operation.
+    fn denied(&self) -> (EventKind, &'static str) {
+        match self {
+            Self::Write | Self::WritableSection | Self::OpenForWrite => {
+                (EventKind::WriteDenied, "write")
+            },
+            Self::SetSecurity { .. } | Self::ClearAttributes { .. } => {
+                (EventKind::SecurityChangeDenied, "security change")
+            },
+            Self::Delete | Self::OpenForDelete | Self::Truncate { .. } => {
+                (EventKind::DeleteDenied, "delete")
+            },
+        }
+    }
+
+    /// What the detail of the event starts with, `None` for a delete.
+    fn describe(&self) -> Option<String> {
+        match self {
+            Self::Delete => None,
+            Self::OpenForDelete => Some(String::from("open with DELETE access")),
+            Self::Truncate {
+                information,
+                new_size,
+            } => Some(format!("{} set to {new_size} bytes", information.as_str())),
+            Self::Write => Some(String::from("write")),
+            Self::WritableSection => Some(String::from("writable section")),
+            Self::OpenForWrite => Some(String::from("open for write access")),
+            Self::SetSecurity { security } => {
+                let names: Vec<_> = describe(&SECURITY_NAMES, *security).collect();
+                Some(format!("{} changed", names.join(", ")))
+            },
+            Self::ClearAttributes { cleared } => {
+                let names: Vec<_> = describe(&ATTRIBUTE_NAMES, *cleared).collect();
+                Some(format!("{} cleared", names.join(", ")))
+            },
+        }
+    }
+}
+
+/// What `decide` found out under the engine lock.
+struct Decided {
+    activity: Activity,
+    decision: Decision,
+    /// Trace of the rules and lineage of the process, empty unless a rule decided.
+    trace: Vec<u8>,
+    lineage: String,
+    /// Status and response of the deciding rule.
+    failure_status: NtStatus,
+    response: Response,
+    /// The termination or suspension was claimed for the process, see `Engine::claim_response`.
+    queue_response: bool,
+    /// Id of the copy of a preserved delete.
+    vault_id: u64,
+}
+
+/// A delete, or an operation decided like one, a rule denied.
+struct Denial<'a> {
+    destruction: Destruction,
+    requestor: Requestor,
+    now: u64,
+    image_name: &'a str,
+    file_name: &'a str,
+    /// The processes which created the process, see `lineage::describe`, after what an open
+    /// or a truncation did, see `Destruction::describe`.
+    lineage: &'a str,
+    rule_id: u32,
+    failure_status: NtStatus,
+    response: Response,
+    /// Queue `response`, it is only audited if an earlier denial queued it.
+    queue_response: bool,
+}
+
+/// Fails the operation with the status of the rule, queues the termination or suspension the rule
+/// asks for and audits both.
+fn deny(platform: &Platform, engine: &impl EngineLock, denial: &Denial, trace: Vec<u8>) -> PreOp {
+    platform.stats.count(Counter::Blocked);
+    let process_id = platform.identity.process_id(denial.requestor);
+    if denial.queue_response {
+        if let Err(status) = platform
+            .responder
+            .queue_response(denial.requestor, denial.response)
+        {
+            engine.with_engine(|engine| engine.release_response(process_id));
+            platform.stats.count(Counter::ResponseFailures);
+            log::warn!(
+                target: TARGET_POLICY,
+                "cannot {} {} 0x{:08x}",
+                denial.response.as_str(),
+                denial.image_name,
+                status
+            );
+        }
+    }
+    let (kind, operation) = denial.destruction.denied();
+    log_limited!(
+        denial.now,
+        target: TARGET_POLICY,
+        Level::Info,
+        "Prevent {} of {} by {} (rule {})",
+        operation,
+        denial.file_name,
+        denial.image_name,
+        denial.rule_id
+    );
+
+    let event = Event::new(kind, Severity::Warning, denial.now)
+        .process(process_id, denial.image_name)
+        .target(denial.file_name)
+        .detail(denial.lineage)
+        .rule(denial.rule_id)
+        .status(denial.failure_status)
+        .response(denial.response)
+        .trace(trace);
+    engine.with_engine(|engine| engine.push_event(event));
+    PreOp::Complete(denial.failure_status)
+}
+
+/// True if the file is in a vault and the process did not open the vaults.
+fn changes_vault(platform: &Platform, engine: &impl EngineLock) -> bool {
+    let Some(file_name) = file_name(platform.files, platform.allocator) else {
+        return false;
+    };
+    if !is_vault_path(&file_name) {
+        return false;
+    }
+
+    let process_id = platform.identity.process_id(Requestor::Current);
+    let allowed = engine
+        .with_engine(|engine| engine.may_change_vault(process_id))
+        .unwrap_or(false);
+    if !allowed {
+        log::info!(
+            target: TARGET_POLICY,
+            "Prevent change of {} by process {}",
+            file_name,
+            process_id
+        );
+    }
+    !allowed
+}
+
+fn guards_truncation(engine: &impl EngineLock) -> bool {
+    engine
+        .with_engine(|engine| engine.guards_truncation())
+        .unwrap_or(false)
+}
+
+fn guards_security(engine: &impl EngineLock) -> bool {
+    engine
+        .with_engine(|engine| engine.guards_security())
+        .unwrap_or(false)
+}
+
+fn has_immutable_rules(engine: &impl EngineLock) -> bool {
+    engine.published().has_immutable_rules()
+}
+
+fn is_watching(engine: &impl EngineLock) -> bool {
+    engine
+        .with_engine(|engine| engine.is_watching())
+        .unwrap_or(false)
+}
+
+/// Shows a rename or an overwrite to the canaries and the detector, it is only denied if the
+/// process is locked down. Nothing is queried while the engine is not watching.
+fn watch(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    requestor: Requestor,
+    operation: Operation,
+) -> Verdict {
+    if !is_watching(engine) {
+        return Verdict::skip();
+    }
+
+    let image_name = query_name(platform.allocator, Some(platform.stats), |buffer| {
+        platform.identity.query_image_name(requestor, buffer)
+    });
+    let Some(image_name) = image_name else {
+        return Verdict::skip();
+    };
+    let file_name = query_name(platform.allocator, Some(platform.stats), |buffer| {
+        platform.files.query_file_name(buffer)
+    })
+    .unwrap_or_default();
+
+    let watched = Watched {
+        operation,
+        now: platform.clock.now(),
+        process_id: platform.identity.process_id(requestor),
+        image_name: &image_name,
+        file_name: &file_name,
+    };
+    let activity = engine.with_engine(|engine| {
+        engine.watch(
+            operation,
+            watched.process_id,
+            &image_name,
+            &file_name,
+            watched.now,
+        )
+    });
+    match activity {
+        Some(activity) if activity.is_locked_down() => Verdict {
+            pre_op: deny_locked_down(platform, engine, &watched, activity),
+            decision: Some(Decision::LockedDown),
+        },
+        _ => Verdict::skip(),
+    }
+}
+
+/// An operation shown to the canaries and the detector.
+struct Watched<'a> {
+    operation: Operation,
+    now: u64,
+    process_id: u32,
+    image_name: &'a str,
+    file_name: &'a str,
+}
+
+/// Fails an operation of a locked down process with STATUS_ACCESS_DENIED. The operation which
+/// tripped the detector or a canary raises an event, the ones after it are only counted.
+fn deny_locked_down(
+    platform: &Platform,
+    engine: &impl EngineLock,
+    watched: &Watched,
+    activity: Activity,
+) -> PreOp {
+    platform.stats.count(Counter::LockdownDenied);
+    log_limited!(
+        watched.now,
+        target: TARGET_POLICY,
+        Level::Info,
+        "Prevent {} of {} by locked down {}",
+        watched.operation.as_str(),
+        watched.file_name,
+        watched.image_name
+    );
+
+    let (kind, detail) = match activity {
+        Activity::Tripped {
+            operations,
+            directories,
+        } => (
+            EventKind::MassDelete,
+            format!("{operations} operations in {directories} directories"),
+        ),
+        Activity::CanaryTripped { canary_id } => (
+            EventKind::CanaryTripped,
+            format!("{} of canary {canary_id}", watched.operation.as_str()),
+        ),
+        Activity::Normal | Activity::LockedDown => return PreOp::Complete(STATUS_ACCESS_DENIED),
+    };
+
+    let event = Event::new(kind, Severity::High, watched.now)
+        .process(watched.process_id, watched.image_name)
+        .target(watched.file_name)
+        .detail(&detail)
+        .status(STATUS_ACCESS_DENIED);
+    engine.with_engine(|engine| engine.push_event(event));
+    PreOp::Complete(STATUS_ACCESS_DENIED)
+}
+
+/// NT image path of the process, `None` if it cannot be queried or is empty.
+pub fn image_name(
+    identity: &dyn ProcessIdentity,
+    allocator: &dyn Allocator,
+    requestor: Requestor,
+) -> Option<String> {
+    query_name(allocator, None, |buffer| {
+        identity.query_image_name(requestor, buffer)
+    })
+}
+
+pub fn file_name(files: &dyn FileNameProvider, allocator: &dyn Allocator) -> Option<String> {
+    query_name(allocator, None, |buffer| files.query_file_name(buffer))
+}
+
+/// Failures are counted in `stats` if given.
+fn query_name(
+    allocator: &dyn Allocator,
+    stats: Option<&Stats>,
+    query: impl FnOnce(&mut [u16]) -> Result<usize, NtStatus>,
+) -> Option<String> {
+    let Some(mut buffer) = allocator.name_buffer(MAX_NAME_UNITS) else {
+        log::info!(
+            target: TARGET_POLICY,
+            "fail to reserve a {} bytes of memory",
+            MAX_NAME_UNITS * 2
+        );
+        if let Some(stats) = stats {
+            stats.count(Counter::AllocationFailures);
+        }
+        return None;
+    };
+
+    match query(&mut buffer) {
+        Ok(0) => None,
+        Ok(len) => Some(String::from_utf16_lossy(&buffer[..len.min(buffer.len())])),
+        Err(status) => {
+            log::info!(target: TARGET_POLICY, "failed to query a name 0x{:08x}", status);
+            if let Some(stats) = stats {
+                stats.count(Counter::NameQueryFailures);
+            }
+            None
+        },
+    }
+}
@@ -0,0 +1,111 @@
+//! Services the engine and the pre-operation flow need from the platform. The driver implements
+//! them with kernel routines, tests with the scripted fakes of `delprotect-fake`.
+
+use alloc::{string::String, vec::Vec};
+use common::{rule::Response, status::NtStatus, vault::VaultHeader};
+
+use crate::stats::Stats;
+
+/// Longest name queried from the platform, in UTF-16 units. Longer names are truncated.
+pub const MAX_NAME_UNITS: usize = 1024;
+
+/// The process an operation is attributed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Requestor {
+    /// The process the callback runs in.
+    Current,
+    /// The process owning this thread (the PETHREAD of the callback data), for operations
+    /// which may run in an arbitrary context.
+    Thread(usize),
+}
+
+pub trait Clock {
+    /// Current time as FILETIME ticks, UTC.
+    fn now(&self) -> u64;
+}
+
+/// Memory for names whose length comes from the outside. Running out of it is an expected
+/// outcome, not a panic.
+pub trait Allocator {
+    /// A zeroed buffer of `units` UTF-16 units, `None` if there is no memory.
+    fn name_buffer(&self, units: usize) -> Option<Vec<u16>>;
+}
+
+pub trait ProcessIdentity {
+    fn process_id(&self, requestor: Requestor) -> u32;
+
+    /// Writes the NT image path of the process, e.g.
+    /// `\Device\HarddiskVolume3\Windows\System32\cmd.exe`, into `buffer` and returns its
+    /// length in units. Fails if the process cannot be found, opened or queried.
+    fn query_image_name(&self, requestor: Requestor, buffer: &mut [u16])
+        -> Result<usize, NtStatus>;
+}
+
+/// Name of the file targeted by the operation being decided.
+pub trait FileNameProvider {
+    /// Writes the normalized name of the file into `buffer` and returns its length in units.
+    fn query_file_name(&self, buffer: &mut [u16]) -> Result<usize, NtStatus>;
+
+    /// The name kept with `keep_opened_name` for the handle of the operation, `None` if none
+    /// was kept.
+    fn opened_name(&self) -> Option<String>;
+
+    /// Keeps the name an open for write access was decided with, so that the writes and
+    /// sections of its handle are decided without a query. The host attaches it to the handle
+    /// (a stream handle context in the kernel) once the create succeeded.
+    fn keep_opened_name(&self, name: &str);
+}
+
+/// Size and attributes of the file targeted by the operation being decided.
+pub trait FileInfoProvider {
+    /// The end of file, in bytes, before the operation.
+    fn query_end_of_file(&self) -> Result<u64, NtStatus>;
+
+    /// The FILE_ATTRIBUTE_* bits before the operation.
+    fn query_attributes(&self) -> Result<u32, NtStatus>;
+}
+
+/// The vault on the volume of the file being decided, see `common::vault`. Called by the
+/// pre-operation callbacks without the engine lock.
+pub trait Vault {
+    /// The header of the index, `VaultHeader::default()` if the vault has none yet.
+    fn read_header(&self) -> Result<VaultHeader, NtStatus>;
+
+    /// Size of the file at the NT path `path`, STATUS_OBJECT_NAME_NOT_FOUND if it does not
+    /// exist.
+    fn file_size(&self, path: &str) -> Result<u64, NtStatus>;
+
+    /// Copies the file at the NT path `path` into the vault as `copy_name` and returns its
+    /// size and the SHA-256 of its content.
+    fn copy_file(&self, path: &str, copy_name: &str) -> Result<(u64, [u8; 32]), NtStatus>;
+
+    /// Deletes a copy whose entry was refused.
+    fn remove_copy(&self, copy_name: &str);
+
+    /// Appends an encoded `VaultEntry` to the index. `update` gets the header read under the
+    /// same lock and may refuse the entry, the header is written back only if it accepts.
+    fn append_entry(
+        &self,
+        entry: &[u8],
+        update: &mut dyn FnMut(&mut VaultHeader) -> Result<(), NtStatus>,
+    ) -> Result<(), NtStatus>;
+}
+
+/// Carries out what a rule does to the process whose delete it denied, see
+/// `common::rule::Response`. Demotions are engine state, they never come here.
+pub trait Responder {
+    /// Queues the termination or suspension of the process of `requestor`. It runs after the
+    /// callback returned, so a failure there is only counted. Fails if it cannot be queued.
+    /// Called once per process, see `Engine::claim_response`.
+    fn queue_response(&self, requestor: Requestor, response: Response) -> Result<(), NtStatus>;
+}
+
+/// Services the IOCTL handlers need. They are called with the engine lock held, so kernel
+/// implementations must work at the IRQL of that lock.
+pub trait Host: Clock {
+    /// Fills `buffer` with cryptographically secure random bytes.
+    fn fill_random(&self, buffer: &mut [u8]) -> Result<(), NtStatus>;
+
+    /// The counters the pre-operation callbacks update.
+    fn stats(&self) -> &Stats;
+}
@@ -0,0 +1,119 @@
+use alloc::{string::String, vec::Vec};
+use common::{
+    logging::TARGET_LIFECYCLE,
+    volume::{InstanceRecord, VolumeInfo, VolumePolicy, MAX_VOLUME_NAME_BYTES},
+    wire::{write_utf16, ListHeader, LIST_HEADER_SIZE},
+};
+
+/// A volume the filter is attached to. `instance` is the opaque PFLT_INSTANCE of the driver,
+/// only compared for identity.
+pub struct AttachedInstance {
+    pub instance: usize,
+    pub volume_name: String,
+    pub info: VolumeInfo,
+}
+
+impl AttachedInstance {
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let mut name = [0u8; MAX_VOLUME_NAME_BYTES];
+        let name_len = write_utf16(&self.volume_name, &mut name);
+
+        InstanceRecord {
+            filesystem_type: self.info.filesystem_type,
+            device_type: self.info.device_type,
+            media: self.info.media(),
+            drive_letter: self.info.drive_letter,
+            volume_name: &name[..name_len],
+        }
+        .encode(buffer)
+    }
+}
+
+/// Attach policy and the instances it let through.
+#[derive(Default)]
+pub struct Instances {
+    policy: VolumePolicy,
+    attached: Vec<AttachedInstance>,
+}
+
+impl Instances {
+    pub fn new(policy: VolumePolicy) -> Self {
+        Self {
+            policy,
+            attached: Vec::new(),
+        }
+    }
+
+    pub fn policy(&self) -> &VolumePolicy {
+        &self.policy
+    }
+
+    /// Volumes which are already attached stay attached, the policy is used for every
+    /// attachment from now on.
+    pub fn set_policy(&mut self, policy: VolumePolicy) {
+        self.policy = policy;
+    }
+
+    /// Asks the policy about a volume and remembers the instance if it may attach. An instance
+    /// which cannot be remembered is still attached, it is only missing from the listing.
+    pub fn attach(&mut self, instance: usize, volume_name: String, info: VolumeInfo) -> bool {
+        if !self.policy.should_attach(&info) {
+            log::info!(
+                target: TARGET_LIFECYCLE,
+                "skip volume {} (fs type {})",
+                volume_name,
+                info.filesystem_type
+            );
+            return false;
+        }
+
+        log::info!(
+            target: TARGET_LIFECYCLE,
+            "attach to volume {} (fs type {})",
+            volume_name,
+            info.filesystem_type
+        );
+        if self.attached.try_reserve(1).is_ok() {
+            self.attached.push(AttachedInstance {
+                instance,
+                volume_name,
+                info,
+            });
+        }
+        true
+    }
+
+    pub fn find(&self, instance: usize) -> Option<&AttachedInstance> {
+        self.attached.iter().find(|a| a.instance == instance)
+    }
+
+    pub fn detach(&mut self, instance: usize) {
+        self.attached
+            .retain(|attached| attached.instance != instance);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AttachedInstance> {
+        self.attached.iter()
+    }
+
+    pub fn list(&self, output: &mut [u8]) -> usize {
+        let mut header = ListHeader {
+            total: self.attached.len() as u32,
+            ..ListHeader::default()
+        };
+        let mut offset = LIST_HEADER_SIZE;
+
+        for attached in self.attached.iter() {
+            match attached.encode(&mut output[offset..]) {
+                Some(len) => {
+                    offset += len;
+                    header.returned += 1;
+                },
+                None => break,
+            }
+        }
+
+        header.encode(output);
+        offset
+    }
+}
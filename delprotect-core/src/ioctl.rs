@@ -0,0 +1,118 @@
+//! IOCTL plumbing shared by the driver and other hosts of the engine. Handlers only see the
+//! request through `IoctlBuffer` and decode it with `common::input` and the wire formats.
+
+use alloc::{string::String, vec::Vec};
+use common::{
+    detector::DetectorSettings,
+    input::{check_input, check_output, DecodeError},
+    logging::{LogLevel, TARGET_IOCTL},
+    status::{NtStatus, STATUS_BUFFER_TOO_SMALL, STATUS_INVALID_PARAMETER},
+};
+
+/// Input and output of a METHOD_BUFFERED request. Both share one buffer, so the input has to be
+/// decoded before the output is borrowed; the borrow checker enforces it.
+pub trait IoctlBuffer {
+    /// The input, possibly empty. Fails for a NULL buffer with a length and for inputs above
+    /// `MAX_INPUT_SIZE`.
+    fn input(&self) -> Result<&[u8], DecodeError>;
+
+    /// The output, at least `min_len` bytes long.
+    fn output(&mut self, min_len: usize) -> Result<&mut [u8], DecodeError>;
+}
+
+/// A METHOD_BUFFERED request backed by a `Vec`, for hosts without an I/O manager. Like the
+/// system buffer it is as long as the longer of input and output.
+pub struct BufferedRequest {
+    buffer: Vec<u8>,
+    input_len: usize,
+    output_len: usize,
+}
+
+impl BufferedRequest {
+    pub fn new(input: &[u8], output_len: usize) -> Self {
+        let mut buffer = Vec::with_capacity(input.len().max(output_len));
+        buffer.extend_from_slice(input);
+        buffer.resize(input.len().max(output_len), 0);
+
+        Self {
+            buffer,
+            input_len: input.len(),
+            output_len,
+        }
+    }
+
+    /// The first `written` bytes of the output.
+    pub fn reply(&self, written: usize) -> &[u8] {
+        &self.buffer[..written.min(self.output_len)]
+    }
+}
+
+impl IoctlBuffer for BufferedRequest {
+    fn input(&self) -> Result<&[u8], DecodeError> {
+        check_input(false, self.input_len)?;
+        Ok(&self.buffer[..self.input_len])
+    }
+
+    fn output(&mut self, min_len: usize) -> Result<&mut [u8], DecodeError> {
+        check_output(false, self.output_len, min_len)?;
+        Ok(&mut self.buffer[..self.output_len])
+    }
+}
+
+pub fn status_from(error: DecodeError) -> NtStatus {
+    log::info!(target: TARGET_IOCTL, "invalid IOCTL buffer: {}", error.as_str());
+    if error.is_buffer_too_small() {
+        STATUS_BUFFER_TOO_SMALL
+    } else {
+        STATUS_INVALID_PARAMETER
+    }
+}
+
+/// The handle, privileges and process of the thread which sent a device control request,
+/// collected by the host before the engine is locked.
+#[derive(Clone, Debug, Default)]
+pub struct Caller {
+    pub file_object: usize,
+    pub privileged: bool,
+    pub process_id: u32,
+    pub image_name: String,
+}
+
+/// State the host has to persist once the engine is unlocked, registry writes cannot be done
+/// under the engine lock.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Persist {
+    Options(u32),
+    LogLevel(LogLevel),
+    Detector(DetectorSettings),
+    /// The policy encoded the way it is stored, see `common::volume`.
+    VolumePolicy(Vec<u8>),
+    /// Committed with `Engine::commit_secret` first, then stored; a secret which cannot be
+    /// stored is rolled back with `Engine::restore_secret`, it would unlock the driver after the
+    /// next boot.
+    Secret(Vec<u8>),
+}
+
+/// Outcome of a successful IOCTL.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reply {
+    /// Bytes written to the output.
+    pub written: usize,
+    pub persist: Option<Persist>,
+}
+
+impl Reply {
+    pub fn written(written: usize) -> Self {
+        Self {
+            written,
+            persist: None,
+        }
+    }
+
+    pub fn persist(persist: Persist) -> Self {
+        Self {
+            written: 0,
+            persist: Some(persist),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Platform independent part of DelProtect: rule store, delete decisions, process lineage,
+//! mass-delete detection and canaries, attach policy, audit events, statistics, the recovery
+//! vault, tamper protection and the IOCTL handlers. The driver translates kernel callbacks into
+//! calls on `Engine` and keeps everything that needs the kernel: names, registry, files, locks.
+//! Nothing here depends on Windows, so the whole engine builds and runs on any host.
+
+#![no_std]
+extern crate alloc;
+
+pub mod auth;
+pub mod canary;
+pub mod detector;
+pub mod engine;
+pub mod events;
+pub mod filter;
+pub mod host;
+pub mod instances;
+pub mod ioctl;
+pub mod lineage;
+pub mod logging;
+pub mod rules;
+pub mod stats;
+pub mod vault;
+
+pub use engine::{Config, Decision, Engine};
+pub use filter::{EngineLock, Platform, PreOp, Published, Verdict};
+pub use host::{
+    Allocator, Clock, FileInfoProvider, FileNameProvider, Host, ProcessIdentity, Requestor,
+    Responder, Vault,
+};
+pub use stats::Stats;
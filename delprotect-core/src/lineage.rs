@@ -0,0 +1,224 @@
+//! Lineage of the processes created by a process an `inherit` rule names, see
+//! `common::rule::RULE_FLAG_INHERIT`. A process is tracked from the creation notification of
+//! its first child on, and every process it creates after that; a process which exits stays
+//! as long as one of its descendants lives, so `cmd /c powershell` does not lose the chain
+//! when cmd.exe is gone.
+
+use alloc::{string::String, vec::Vec};
+use common::logging::TARGET_POLICY;
+use core::fmt::Write;
+
+/// Processes above this count start no new lineage, until some exit. The processes created by
+/// a tracked one are still tracked above it, up to `MAX_LINEAGE_ENTRIES`, or they would escape
+/// the `inherit` rules.
+pub const MAX_LINEAGE_PROCESSES: usize = 256;
+/// Entries of the table, exited ancestors included. The processes created by a tracked one are
+/// dropped above it, with a warning since the `inherit` rules no longer see them.
+pub const MAX_LINEAGE_ENTRIES: usize = 4 * MAX_LINEAGE_PROCESSES;
+/// Ancestors looked at, nearest first.
+pub const MAX_LINEAGE_DEPTH: usize = 16;
+
+struct Process {
+    /// Unique for the lifetime of the lineage, unlike process ids which are reused.
+    key: u64,
+    process_id: u32,
+    /// Key of the creating process, 0 for a process tracked since its first child.
+    parent: u64,
+    image_name: String,
+    exited: bool,
+    /// Tracked processes this one created.
+    children: u32,
+}
+
+/// A process which created, directly or not, the process asked about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ancestor<'a> {
+    pub process_id: u32,
+    pub image_name: &'a str,
+}
+
+pub struct Lineage {
+    /// In the order of their keys, a creator before the processes it created.
+    processes: Vec<Process>,
+    next_key: u64,
+}
+
+impl Default for Lineage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lineage {
+    pub fn new() -> Self {
+        Self {
+            processes: Vec::new(),
+            next_key: 1,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+
+    /// Processes tracked, exited ones included.
+    pub fn len(&self) -> usize {
+        self.processes.len()
+    }
+
+    /// Tracks the process `process_id` created by `creator_id` if the creator is tracked or
+    /// `starts_lineage` says its image begins one.
+    pub fn process_created(
+        &mut self,
+        creator_id: u32,
+        creator_image: &str,
+        process_id: u32,
+        image_name: &str,
+        starts_lineage: impl Fn(&str) -> bool,
+    ) {
+        // a live entry with the new id missed its exit notification
+        self.process_exited(process_id);
+
+        let parent = match self.live(creator_id) {
+            Some(creator) => creator.key,
+            None if creator_id != 0 && starts_lineage(creator_image) => {
+                match self.insert(creator_id, 0, creator_image) {
+                    Some(key) => key,
+                    None => return,
+                }
+            },
+            None => return,
+        };
+        self.insert(process_id, parent, image_name);
+    }
+
+    /// Forgets the process, or keeps it as an exited ancestor of the processes it created.
+    pub fn process_exited(&mut self, process_id: u32) {
+        let Some(process) = self
+            .processes
+            .iter_mut()
+            .find(|process| !process.exited && process.process_id == process_id)
+        else {
+            return;
+        };
+        process.exited = true;
+
+        // exited processes nobody descends from any more: from the last created up, so that a
+        // creator sees the children leaving before its own turn
+        for index in (0..self.processes.len()).rev() {
+            let process = &self.processes[index];
+            if !process.exited || process.children != 0 {
+                continue;
+            }
+            let parent = process.parent;
+            if let Some(parent) = self.position(parent) {
+                self.processes[parent].children -= 1;
+            }
+        }
+        self.processes
+            .retain(|process| !process.exited || process.children != 0);
+    }
+
+    /// The processes which created `process_id`, nearest first. Empty if it is not tracked.
+    pub fn ancestors(&self, process_id: u32) -> Vec<Ancestor<'_>> {
+        let mut ancestors = Vec::new();
+        let Some(mut process) = self.live(process_id) else {
+            return ancestors;
+        };
+        if ancestors.try_reserve_exact(MAX_LINEAGE_DEPTH).is_err() {
+            return ancestors;
+        }
+
+        while ancestors.len() < MAX_LINEAGE_DEPTH {
+            let Some(parent) = self.processes.iter().find(|p| p.key == process.parent) else {
+                break;
+            };
+            ancestors.push(Ancestor {
+                process_id: parent.process_id,
+                image_name: &parent.image_name,
+            });
+            process = parent;
+        }
+        ancestors
+    }
+
+    pub fn clear(&mut self) {
+        self.processes.clear();
+    }
+
+    fn position(&self, key: u64) -> Option<usize> {
+        self.processes
+            .binary_search_by_key(&key, |process| process.key)
+            .ok()
+    }
+
+    fn live(&self, process_id: u32) -> Option<&Process> {
+        self.processes
+            .iter()
+            .find(|process| !process.exited && process.process_id == process_id)
+    }
+
+    /// Returns the key of the new entry, `None` if there is no memory or the lineage is full:
+    /// MAX_LINEAGE_PROCESSES for a process starting one, `parent` 0, MAX_LINEAGE_ENTRIES for
+    /// the others.
+    fn insert(&mut self, process_id: u32, parent: u64, image_name: &str) -> Option<u64> {
+        let limit = if parent == 0 {
+            MAX_LINEAGE_PROCESSES
+        } else {
+            MAX_LINEAGE_ENTRIES
+        };
+        let mut name = String::new();
+        if self.processes.len() >= limit
+            || self.processes.try_reserve(1).is_err()
+            || name.try_reserve_exact(image_name.len()).is_err()
+        {
+            if parent == 0 {
+                log::info!(
+                    target: TARGET_POLICY,
+                    "lineage full, {} ({}) is not tracked",
+                    image_name,
+                    process_id
+                );
+            } else {
+                log::warn!(
+                    target: TARGET_POLICY,
+                    "lineage full, {} ({}) is dropped from the lineage of its creator",
+                    image_name,
+                    process_id
+                );
+            }
+            return None;
+        }
+        name.push_str(image_name);
+        if let Some(parent) = self.position(parent) {
+            self.processes[parent].children += 1;
+        }
+
+        let key = self.next_key;
+        self.next_key += 1;
+        self.processes.push(Process {
+            key,
+            process_id,
+            parent,
+            image_name: name,
+            exited: false,
+            children: 0,
+        });
+        Some(key)
+    }
+}
+
+/// `created by <image> (<pid>) < <image> (<pid>)...`, nearest first, for the detail of an
+/// event. Empty without ancestors.
+pub fn describe(ancestors: &[Ancestor]) -> String {
+    let mut text = String::new();
+    for (index, ancestor) in ancestors.iter().enumerate() {
+        let separator = if index == 0 { "created by" } else { " <" };
+        let _ = write!(
+            text,
+            "{separator} {} ({})",
+            ancestor.image_name, ancestor.process_id
+        );
+    }
+    text
+}
@@ -0,0 +1,126 @@
+//! Runtime log level and rate limiting of the messages on the delete path. The level is the
+//! `log` max level, so disabled messages cost one comparison; the delete path runs for every
+//! delete on every volume and is limited further by a token bucket, which works without the
+//! engine lock.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use common::{logging::LogLevel, schedule::TICKS_PER_SECOND};
+use log::LevelFilter;
+
+/// Messages of the delete path: a burst of 20, then 5 per second.
+pub static DELETE_PATH: TokenBucket = TokenBucket::new(20, 5);
+
+pub fn set_level(level: LogLevel) {
+    log::set_max_level(level_filter(level));
+}
+
+pub fn level() -> LogLevel {
+    LogLevel::from_u32(log::max_level() as u32).unwrap_or_default()
+}
+
+pub fn level_filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::Off => LevelFilter::Off,
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Debug => LevelFilter::Debug,
+        LogLevel::Trace => LevelFilter::Trace,
+    }
+}
+
+/// Lets `burst` messages through at once and `per_second` on average. Times are FILETIME
+/// ticks, a clock going backwards restarts the refill from the new time.
+pub struct TokenBucket {
+    burst: u32,
+    ticks_per_token: u64,
+    tokens: AtomicU32,
+    /// Time up to which tokens were added, 0 before the first message.
+    refilled: AtomicU64,
+    /// Messages dropped since the last one let through.
+    dropped: AtomicU32,
+}
+
+impl TokenBucket {
+    pub const fn new(burst: u32, per_second: u32) -> Self {
+        Self {
+            burst,
+            ticks_per_token: TICKS_PER_SECOND / per_second as u64,
+            tokens: AtomicU32::new(burst),
+            refilled: AtomicU64::new(0),
+            dropped: AtomicU32::new(0),
+        }
+    }
+
+    /// Takes a token for a message at `now`. `None` if the message has to be dropped,
+    /// otherwise the number of messages dropped before it.
+    pub fn try_take(&self, now: u64) -> Option<u32> {
+        self.refill(now);
+
+        let taken = self
+            .tokens
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |tokens| {
+                tokens.checked_sub(1)
+            });
+        match taken {
+            Ok(_) => Some(self.dropped.swap(0, Ordering::Relaxed)),
+            Err(_) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+        }
+    }
+
+    fn refill(&self, now: u64) {
+        let refilled = self.refilled.load(Ordering::Relaxed);
+        if refilled == 0 || now < refilled {
+            let _ =
+                self.refilled
+                    .compare_exchange(refilled, now, Ordering::Relaxed, Ordering::Relaxed);
+            return;
+        }
+
+        let earned = (now - refilled) / self.ticks_per_token;
+        if earned == 0 {
+            return;
+        }
+
+        // only the caller which moves the refill time adds the tokens it earned
+        let next = refilled + earned * self.ticks_per_token;
+        if self
+            .refilled
+            .compare_exchange(refilled, next, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            let earned = earned.min(self.burst as u64) as u32;
+            let _ = self
+                .tokens
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |tokens| {
+                    Some(tokens.saturating_add(earned).min(self.burst))
+                });
+        }
+    }
+}
+
+/// `log!` for messages of the delete path. Above the rate of `DELETE_PATH` messages are
+/// dropped, the next one let through is preceded by their count. `$now` is only evaluated if
+/// the level is enabled.
+#[macro_export]
+macro_rules! log_limited {
+    ($now:expr, target: $target:expr, $level:expr, $($arg:tt)+) => {
+        if ::log::log_enabled!(target: $target, $level) {
+            if let Some(dropped) = $crate::logging::DELETE_PATH.try_take($now) {
+                if dropped != 0 {
+                    ::log::log!(
+                        target: $target,
+                        $level,
+                        "{} messages dropped by the rate limit",
+                        dropped
+                    );
+                }
+                ::log::log!(target: $target, $level, $($arg)+);
+            }
+        }
+    };
+}
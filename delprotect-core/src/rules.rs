@@ -0,0 +1,490 @@
+use alloc::{collections::VecDeque, string::String};
+use common::{
+    explain::{
+        window_predicates, RuleOutcome, RuleTrace, PREDICATE_ALL, PREDICATE_PROCESS,
+        RULE_TRACE_HEADER_SIZE,
+    },
+    logging::TARGET_POLICY,
+    rule::{
+        is_failure_status, RuleAction, RuleRecord, MAX_PROCESS_NAME_BYTES, MAX_RULE_PATH_BYTES,
+        RULE_FLAG_IMMUTABLE, RULE_FLAG_INHERIT,
+    },
+    schedule::{RuleState, TimeWindow},
+    stats::RuleStats,
+    status::{
+        NtStatus, STATUS_ACCESS_DENIED, STATUS_INSUFFICIENT_RESOURCES, STATUS_INVALID_PARAMETER,
+    },
+    wire::{read_utf16, write_utf16, ListHeader, LIST_HEADER_SIZE},
+};
+
+use crate::lineage::Ancestor;
+
+/// Rules above this count push out the oldest one.
+pub const MAX_RULE_COUNT: usize = 32;
+
+pub struct Rule {
+    pub id: u32,
+    pub process_name: String,
+    pub action: RuleAction,
+    /// RULE_FLAG_* bits of `common::rule`.
+    pub flags: u16,
+    /// NT path of the file or directory an immutable rule denies writes under, empty otherwise.
+    pub path: String,
+    pub window: TimeWindow,
+    /// What a delete the rule denies fails with, one of `common::rule::FAILURE_STATUSES`.
+    pub failure_status: NtStatus,
+    /// Deletes the rule decided and the time of the last one, 0 if none.
+    pub hits: u64,
+    pub last_hit: u64,
+}
+
+impl Rule {
+    /// Builds a rule from the record sent by the client. Returns `None` if the record is
+    /// malformed or there is no memory for the name.
+    pub fn from_record(id: u32, record: &RuleRecord) -> Option<Self> {
+        if record.process.is_empty()
+            || record.process.len() > MAX_PROCESS_NAME_BYTES
+            || !record.window.is_valid()
+        {
+            return None;
+        }
+
+        let units = record
+            .process
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]));
+
+        let mut process_name = String::new();
+        if process_name
+            .try_reserve_exact(record.process.len() * 2)
+            .is_err()
+        {
+            return None;
+        }
+        for c in char::decode_utf16(units) {
+            process_name.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+        let path = read_utf16(record.path)?;
+
+        Some(Self {
+            id,
+            process_name,
+            action: record.action,
+            flags: record.flags,
+            path,
+            window: record.window,
+            failure_status: record.failure_status,
+            hits: 0,
+            last_hit: 0,
+        })
+    }
+
+    /// Serializes the rule for `IOCTL_DELPROTECT_LIST_RULES`. Returns the number of bytes
+    /// written or `None` if `buffer` is too small.
+    pub fn encode(&self, state: RuleState, buffer: &mut [u8]) -> Option<usize> {
+        let mut name = [0u8; MAX_PROCESS_NAME_BYTES];
+        let name_len = write_utf16(&self.process_name, &mut name);
+        let mut path = [0u8; MAX_RULE_PATH_BYTES];
+        let path_len = write_utf16(&self.path, &mut path);
+
+        RuleRecord {
+            id: self.id,
+            state,
+            action: self.action,
+            flags: self.flags,
+            window: self.window,
+            failure_status: self.failure_status,
+            process: &name[..name_len],
+            path: &path[..path_len],
+        }
+        .encode(buffer)
+    }
+
+    pub fn inherits(&self) -> bool {
+        self.flags & RULE_FLAG_INHERIT != 0
+    }
+
+    pub fn is_immutable(&self) -> bool {
+        self.flags & RULE_FLAG_IMMUTABLE != 0
+    }
+
+    /// True if the NT image path contains the process name of the rule.
+    pub fn names(&self, image_name: &str) -> bool {
+        image_name.contains(self.process_name.as_str())
+    }
+
+    /// True if the NT path of a file is the path of the rule or below it, compared without
+    /// regard to case. An empty path covers nothing.
+    pub fn covers(&self, file_name: &str) -> bool {
+        let path = self.path.trim_end_matches('\\');
+        if path.is_empty() {
+            return false;
+        }
+
+        let mut file_name = file_name.chars().flat_map(char::to_lowercase);
+        let prefix = path.chars().flat_map(char::to_lowercase);
+        for c in prefix {
+            if file_name.next() != Some(c) {
+                return false;
+            }
+        }
+        matches!(file_name.next(), None | Some('\\'))
+    }
+
+    /// The generation of the process the rule names: 0 if it names the process with this NT
+    /// image path, n if it inherits and names its n-th ancestor, `None` otherwise.
+    pub fn generation(&self, image_name: &str, ancestors: &[Ancestor]) -> Option<u16> {
+        if self.names(image_name) {
+            return Some(0);
+        }
+        if !self.inherits() {
+            return None;
+        }
+        ancestors
+            .iter()
+            .position(|ancestor| self.names(ancestor.image_name))
+            .map(|index| index as u16 + 1)
+    }
+
+    /// The `common::explain` predicates which hold for a process with this NT image path and
+    /// these ancestors, nearest first, at `now`.
+    pub fn predicates(&self, image_name: &str, ancestors: &[Ancestor], now: u64) -> u8 {
+        let mut predicates = window_predicates(&self.window, now);
+        if self.generation(image_name, ancestors).is_some() {
+            predicates |= PREDICATE_PROCESS;
+        }
+        predicates
+    }
+
+    /// True if the rule decides deletes of a process with this NT image path and these
+    /// ancestors at `now`.
+    pub fn blocks(&self, image_name: &str, ancestors: &[Ancestor], now: u64) -> bool {
+        self.predicates(image_name, ancestors, now) == PREDICATE_ALL
+    }
+
+    fn encode_trace(
+        &self,
+        step: u16,
+        predicates: u8,
+        outcome: RuleOutcome,
+        generation: u16,
+        buffer: &mut [u8],
+    ) -> Option<usize> {
+        let mut name = [0u8; MAX_PROCESS_NAME_BYTES];
+        let name_len = write_utf16(&self.process_name, &mut name);
+
+        RuleTrace {
+            rule_id: self.id,
+            step,
+            predicates,
+            outcome,
+            generation,
+            name: &name[..name_len],
+        }
+        .encode(buffer)
+    }
+}
+
+/// Rules in the order they were added, each with an id unique for the lifetime of the store.
+pub struct RuleStore {
+    rules: VecDeque<Rule>,
+    next_id: u32,
+}
+
+impl RuleStore {
+    /// Returns `None` if there is no memory for `MAX_RULE_COUNT` rules.
+    pub fn new() -> Option<Self> {
+        let mut rules = VecDeque::new();
+        if let Err(e) = rules.try_reserve_exact(MAX_RULE_COUNT) {
+            log::info!(
+                target: TARGET_POLICY,
+                "fail to reserve a {} bytes of memory. Err: {:?}",
+                ::core::mem::size_of::<Rule>() * MAX_RULE_COUNT,
+                e
+            );
+            return None;
+        }
+
+        Some(Self { rules, next_id: 1 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter()
+    }
+
+    /// Adds a rule denying deletes by `process_name` with STATUS_ACCESS_DENIED and returns its
+    /// id.
+    pub fn push(&mut self, process_name: &str, window: TimeWindow) -> Result<u32, NtStatus> {
+        self.push_action(process_name, window, RuleAction::Deny, STATUS_ACCESS_DENIED)
+    }
+
+    /// Adds a rule for `process_name` and returns its id. The failure status has to be on the
+    /// allow-list of `common::rule`.
+    pub fn push_action(
+        &mut self,
+        process_name: &str,
+        window: TimeWindow,
+        action: RuleAction,
+        failure_status: NtStatus,
+    ) -> Result<u32, NtStatus> {
+        if !is_failure_status(failure_status) {
+            return Err(STATUS_INVALID_PARAMETER);
+        }
+
+        let mut name = String::new();
+        if let Err(e) = name.try_reserve_exact(process_name.len()) {
+            log::info!(
+                target: TARGET_POLICY,
+                "fail to reserve a {} bytes of memory. Err: {:?}",
+                process_name.len(),
+                e
+            );
+            return Err(STATUS_INSUFFICIENT_RESOURCES);
+        }
+        name.push_str(process_name);
+
+        Ok(self.insert(Rule {
+            id: self.next_id,
+            process_name: name,
+            action,
+            flags: 0,
+            path: String::new(),
+            window,
+            failure_status,
+            hits: 0,
+            last_hit: 0,
+        }))
+    }
+
+    /// Adds a rule sent with `IOCTL_DELPROTECT_ADD_RULE` and returns its id.
+    pub fn add_record(&mut self, record: &RuleRecord) -> Result<u32, NtStatus> {
+        let rule = Rule::from_record(self.next_id, record).ok_or(STATUS_INVALID_PARAMETER)?;
+        log::info!(
+            target: TARGET_POLICY,
+            "add rule {} to {} deletes by {}{}{}{}",
+            rule.id,
+            rule.action.as_str(),
+            rule.process_name,
+            if rule.inherits() { " and its descendants" } else { "" },
+            if rule.is_immutable() { ", and deny their writes under " } else { "" },
+            rule.path
+        );
+        Ok(self.insert(rule))
+    }
+
+    fn insert(&mut self, rule: Rule) -> u32 {
+        if self.rules.len() >= MAX_RULE_COUNT {
+            self.rules.pop_front();
+        }
+
+        let id = rule.id;
+        self.rules.push_back(rule);
+        self.next_id += 1;
+        id
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.id == id)
+    }
+
+    /// The first rule deciding deletes by `image_name`, created by `ancestors`, at `now`.
+    pub fn find_blocking(
+        &self,
+        image_name: &str,
+        ancestors: &[Ancestor],
+        now: u64,
+    ) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .find(|rule| rule.blocks(image_name, ancestors, now))
+    }
+
+    /// The first rule with `RULE_FLAG_IMMUTABLE` covering `file_name` and deciding for
+    /// `image_name`, created by `ancestors`, at `now`.
+    pub fn find_immutable(
+        &self,
+        image_name: &str,
+        file_name: &str,
+        ancestors: &[Ancestor],
+        now: u64,
+    ) -> Option<&Rule> {
+        self.rules.iter().find(|rule| {
+            rule.is_immutable() && rule.covers(file_name) && rule.blocks(image_name, ancestors, now)
+        })
+    }
+
+    /// True if some rule with `RULE_FLAG_IMMUTABLE` decides for `image_name`, created by
+    /// `ancestors`, at `now`, whatever path it covers.
+    pub fn any_immutable_for(&self, image_name: &str, ancestors: &[Ancestor], now: u64) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.is_immutable() && rule.blocks(image_name, ancestors, now))
+    }
+
+    /// True if writes have to be decided at all.
+    pub fn any_immutable(&self) -> bool {
+        self.rules.iter().any(Rule::is_immutable)
+    }
+
+    /// True if some rule with `RULE_FLAG_INHERIT` names the image, the processes it creates
+    /// have to be tracked.
+    pub fn starts_lineage(&self, image_name: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.inherits() && rule.names(image_name))
+    }
+
+    pub fn any_inherits(&self) -> bool {
+        self.rules.iter().any(Rule::inherits)
+    }
+
+    /// Counts a delete decided by rule `id` at `now`.
+    pub fn record_hit(&mut self, id: u32, now: u64) {
+        if let Some(rule) = self.rules.iter_mut().find(|rule| rule.id == id) {
+            rule.hits += 1;
+            rule.last_hit = now;
+        }
+    }
+
+    /// Writes one `RuleStats` record per rule into `output`, as many as fit, and zeroes the
+    /// hits of every rule with `reset`. Returns the number of records and of bytes written.
+    pub fn stats(&mut self, output: &mut [u8], reset: bool) -> (u32, usize) {
+        let mut count = 0;
+        let mut offset = 0;
+        for rule in self.rules.iter() {
+            let record = RuleStats {
+                rule_id: rule.id,
+                hits: rule.hits,
+                last_hit: rule.last_hit,
+            };
+            match record.encode(&mut output[offset..]) {
+                Some(len) => {
+                    offset += len;
+                    count += 1;
+                },
+                None => break,
+            }
+        }
+
+        if reset {
+            for rule in self.rules.iter_mut() {
+                rule.hits = 0;
+                rule.last_hit = 0;
+            }
+        }
+        (count, offset)
+    }
+
+    /// Writes the trace of a delete by `image_name`, created by `ancestors`, at `now` into
+    /// `output`, one record per rule in evaluation order, as many as fit. Without `all` only
+    /// the rules naming the process or one of its ancestors they inherit from are traced.
+    /// Returns the number of records and of bytes written.
+    pub fn explain(
+        &self,
+        image_name: &str,
+        ancestors: &[Ancestor],
+        now: u64,
+        all: bool,
+        output: &mut [u8],
+    ) -> (u16, usize) {
+        let mut count = 0;
+        let mut offset = 0;
+        let mut decided = false;
+
+        for (step, rule) in self.rules.iter().enumerate() {
+            let predicates = rule.predicates(image_name, ancestors, now);
+            if !all && predicates & PREDICATE_PROCESS == 0 {
+                continue;
+            }
+
+            let outcome = if predicates != PREDICATE_ALL {
+                RuleOutcome::NoMatch
+            } else if decided {
+                RuleOutcome::Shadowed
+            } else {
+                decided = true;
+                RuleOutcome::Decided
+            };
+            let generation = rule.generation(image_name, ancestors).unwrap_or_default();
+            let trace = rule.encode_trace(
+                step as u16 + 1,
+                predicates,
+                outcome,
+                generation,
+                &mut output[offset..],
+            );
+            match trace {
+                Some(len) => {
+                    offset += len;
+                    count += 1;
+                },
+                None => break,
+            }
+        }
+
+        (count, offset)
+    }
+
+    /// Bytes `explain` needs for every traced record.
+    pub fn explain_len(
+        &self,
+        image_name: &str,
+        ancestors: &[Ancestor],
+        now: u64,
+        all: bool,
+    ) -> usize {
+        self.rules
+            .iter()
+            .filter(|rule| {
+                all || rule.predicates(image_name, ancestors, now) & PREDICATE_PROCESS != 0
+            })
+            .map(|rule| {
+                let name_len = rule.process_name.encode_utf16().count() * 2;
+                RULE_TRACE_HEADER_SIZE + name_len.min(MAX_PROCESS_NAME_BYTES)
+            })
+            .sum()
+    }
+
+    /// Writes a `ListHeader` followed by as many rules as fit into `output`. Expired rules are
+    /// reported with `RuleState::Expired` and removed afterwards if `purge_expired` is set.
+    pub fn list(&mut self, output: &mut [u8], now: u64, purge_expired: bool) -> usize {
+        let mut header = ListHeader {
+            total: self.rules.len() as u32,
+            ..ListHeader::default()
+        };
+        let mut offset = LIST_HEADER_SIZE;
+
+        for rule in self.rules.iter() {
+            let state = rule.window.evaluate(now);
+            match rule.encode(state, &mut output[offset..]) {
+                Some(len) => {
+                    offset += len;
+                    header.returned += 1;
+                },
+                None => break,
+            }
+        }
+
+        if purge_expired {
+            let before = self.rules.len();
+            self.rules
+                .retain(|rule| rule.window.evaluate(now) != RuleState::Expired);
+            header.removed = (before - self.rules.len()) as u32;
+        }
+
+        header.encode(output);
+        offset
+    }
+}
@@ -0,0 +1,61 @@
+//! Driver-wide counters. The pre-operation callbacks count before and without the engine lock,
+//! so every counter is atomic and the driver keeps them in a static.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use common::stats::{Counter, StatsHeader, COUNTER_COUNT};
+
+pub struct Stats {
+    counters: [AtomicU64; COUNTER_COUNT],
+    since: AtomicU64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            counters: [const { AtomicU64::new(0) }; COUNTER_COUNT],
+            since: AtomicU64::new(0),
+        }
+    }
+
+    pub fn count(&self, counter: Counter) {
+        self.counters[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize].load(Ordering::Relaxed)
+    }
+
+    /// Zeroes the counters, they count from `now` on.
+    pub fn reset(&self, now: u64) {
+        for counter in &self.counters {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.since.store(now, Ordering::Relaxed);
+    }
+
+    /// The current values. With `reset_at` the counters are zeroed as they are read, so no
+    /// increment is lost between reading and resetting.
+    pub fn snapshot(&self, reset_at: Option<u64>) -> StatsHeader {
+        let mut header = StatsHeader {
+            since: self.since.load(Ordering::Relaxed),
+            ..StatsHeader::default()
+        };
+        for (value, counter) in header.counters.iter_mut().zip(&self.counters) {
+            *value = match reset_at {
+                Some(_) => counter.swap(0, Ordering::Relaxed),
+                None => counter.load(Ordering::Relaxed),
+            };
+        }
+        if let Some(now) = reset_at {
+            self.since.store(now, Ordering::Relaxed);
+        }
+        header
+    }
+}
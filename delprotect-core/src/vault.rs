@@ -0,0 +1,122 @@
+//! The `preserve` rule action: the file is copied into the vault of its volume and indexed
+//! before the delete goes on. Any failure denies the delete, a file is never deleted without
+//! its copy. A file which does not exist has nothing to preserve: a delete-on-close open may
+//! create its file.
+
+use alloc::vec::Vec;
+use common::{
+    logging::TARGET_POLICY,
+    status::{
+        NtStatus, STATUS_ACCESS_DENIED, STATUS_DISK_FULL, STATUS_INSUFFICIENT_RESOURCES,
+        STATUS_OBJECT_NAME_NOT_FOUND,
+    },
+    vault::{copy_name, is_vault_path, VaultEntry},
+    wire::write_utf16,
+};
+
+use crate::host::Vault;
+
+/// A delete a `preserve` rule decided.
+pub struct Preservation<'a> {
+    /// From `Engine::next_vault_id`.
+    pub id: u64,
+    pub time: u64,
+    pub rule_id: u32,
+    pub process_id: u32,
+    pub image_name: &'a str,
+    /// NT path of the file.
+    pub file_name: &'a str,
+}
+
+/// What `preserve` did with the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preserved {
+    /// Copied and indexed.
+    Copied,
+    /// The file does not exist, there is nothing to copy.
+    Missing,
+}
+
+/// Copies the file and appends its entry to the index. Returns the status to deny the delete
+/// with: STATUS_ACCESS_DENIED for a file of the vault itself, STATUS_DISK_FULL if the file
+/// does not fit under the size cap, otherwise what the vault failed with.
+pub fn preserve(vault: &dyn Vault, delete: &Preservation) -> Result<Preserved, NtStatus> {
+    if delete.file_name.is_empty() || is_vault_path(delete.file_name) {
+        return Err(STATUS_ACCESS_DENIED);
+    }
+
+    let size = match vault.file_size(delete.file_name) {
+        Ok(size) => size,
+        Err(STATUS_OBJECT_NAME_NOT_FOUND) => return Ok(Preserved::Missing),
+        Err(status) => return Err(status),
+    };
+    // checked again with the copied size under the index lock, this only avoids copying a
+    // file which cannot fit
+    if !vault.read_header()?.has_room(size) {
+        return Err(STATUS_DISK_FULL);
+    }
+
+    let name = copy_name(delete.id);
+    let (size, sha256) = match vault.copy_file(delete.file_name, &name) {
+        Ok(copied) => copied,
+        Err(STATUS_OBJECT_NAME_NOT_FOUND) => return Ok(Preserved::Missing),
+        Err(status) => return Err(status),
+    };
+
+    let result = encode_entry(delete, size, sha256).and_then(|entry| {
+        vault.append_entry(&entry, &mut |header| {
+            if !header.has_room(size) {
+                return Err(STATUS_DISK_FULL);
+            }
+            header.entry_count += 1;
+            header.used_bytes += size;
+            Ok(())
+        })
+    });
+    if let Err(status) = result {
+        log::info!(
+            target: TARGET_POLICY,
+            "vault entry of {} refused 0x{:08x}",
+            delete.file_name,
+            status
+        );
+        vault.remove_copy(&name);
+    }
+    result.map(|()| Preserved::Copied)
+}
+
+fn encode_entry(delete: &Preservation, size: u64, sha256: [u8; 32]) -> Result<Vec<u8>, NtStatus> {
+    let path = utf16(delete.file_name)?;
+    let process = utf16(delete.image_name)?;
+    let entry = VaultEntry {
+        id: delete.id,
+        time: delete.time,
+        size,
+        process_id: delete.process_id,
+        rule_id: delete.rule_id,
+        sha256,
+        path: &path,
+        process: &process,
+    };
+
+    let mut buffer = Vec::new();
+    buffer
+        .try_reserve_exact(entry.encoded_len())
+        .map_err(|_| STATUS_INSUFFICIENT_RESOURCES)?;
+    buffer.resize(entry.encoded_len(), 0);
+    entry
+        .encode(&mut buffer)
+        .ok_or(STATUS_INSUFFICIENT_RESOURCES)?;
+    Ok(buffer)
+}
+
+fn utf16(s: &str) -> Result<Vec<u8>, NtStatus> {
+    let mut bytes = Vec::new();
+    let len = s.encode_utf16().count() * 2;
+    bytes
+        .try_reserve_exact(len)
+        .map_err(|_| STATUS_INSUFFICIENT_RESOURCES)?;
+    bytes.resize(len, 0);
+    write_utf16(s, &mut bytes);
+    Ok(bytes)
+}
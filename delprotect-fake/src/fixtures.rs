@@ -0,0 +1,85 @@
+//! Names, handles and shortcuts the flows under `tests/` share: the processes and the file they
+//! act on, a factory for an engine holding one rule for `cmd.exe`, and IOCTLs sent straight to
+//! `Engine::handle_ioctl`.
+
+use common::{rule::RuleAction, schedule::TimeWindow, status::NtStatus};
+use delprotect_core::{
+    filter::EngineLock,
+    ioctl::{BufferedRequest, Caller, Reply},
+    Config,
+};
+
+use crate::{FakeEngine, FakePlatform};
+
+pub const CMD: &str = r"\Device\HarddiskVolume3\Windows\System32\cmd.exe";
+pub const EXPLORER: &str = r"\Device\HarddiskVolume3\Windows\explorer.exe";
+pub const TARGET: &str = r"\Device\HarddiskVolume3\Data\x.txt";
+/// The thread doing the operation, looked up by `FakePlatform::process`.
+pub const THREAD: usize = 0xffff_a000_1234_5678;
+
+/// `text` as the UTF-16LE bytes of the wire formats, not terminated.
+pub fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// A handle opened by an administrator.
+pub fn privileged() -> Caller {
+    Caller {
+        privileged: true,
+        ..Caller::default()
+    }
+}
+
+/// An engine with `options` and a rule applying `action` to `cmd.exe` at any time, failing the
+/// operations it denies with `failure_status`.
+pub fn engine(options: u32, action: RuleAction, failure_status: NtStatus) -> FakeEngine {
+    let engine = FakeEngine::new(Config {
+        options,
+        ..Config::default()
+    });
+    engine
+        .with_engine(|engine| {
+            engine
+                .rules_mut()
+                .push_action("cmd.exe", TimeWindow::default(), action, failure_status)
+        })
+        .unwrap()
+        .unwrap();
+    engine
+}
+
+/// Sends `code` with `input` and room for `output_len` bytes, the platform answering with its
+/// defaults. Returns the reply and what was written to the output buffer.
+pub fn ioctl(
+    engine: &FakeEngine,
+    code: u32,
+    input: &[u8],
+    output_len: usize,
+    caller: &Caller,
+) -> Result<(Reply, Vec<u8>), NtStatus> {
+    ioctl_on(
+        engine,
+        &FakePlatform::new(),
+        code,
+        input,
+        output_len,
+        caller,
+    )
+}
+
+/// `ioctl` against a scripted `platform`.
+pub fn ioctl_on(
+    engine: &FakeEngine,
+    platform: &FakePlatform,
+    code: u32,
+    input: &[u8],
+    output_len: usize,
+    caller: &Caller,
+) -> Result<(Reply, Vec<u8>), NtStatus> {
+    let mut buffer = BufferedRequest::new(input, output_len);
+    let reply = engine
+        .with_engine(|engine| engine.handle_ioctl(code, &mut buffer, caller, platform))
+        .unwrap()?;
+    let output = buffer.reply(reply.written).to_vec();
+    Ok((reply, output))
+}
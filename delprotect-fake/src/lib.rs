@@ -0,0 +1,450 @@
+//! Scripted fakes of the `delprotect_core::host` traits. A test tells `FakePlatform` what each
+//! process is called, what the file name query returns, what the file holds and how many
+//! allocations succeed, runs a flow of `delprotect_core` against it and checks the outcome, the
+//! calls made and what ended up in the vault.
+
+pub mod fixtures;
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+};
+
+use common::{
+    auth::Sha256,
+    rule::Response,
+    status::NtStatus,
+    vault::{VaultHeader, VAULT_HEADER_SIZE},
+};
+use delprotect_core::{
+    filter::{EngineLock, Platform, Published},
+    Allocator, Clock, Config, Engine, FileInfoProvider, FileNameProvider, Host, ProcessIdentity,
+    Requestor, Responder, Stats, Vault,
+};
+
+/// A call made by the code under test, in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Call {
+    ProcessId(Requestor),
+    ImageName(Requestor),
+    FileName,
+    EndOfFile,
+    Attributes,
+    Now,
+    Allocate(usize),
+    Random(usize),
+    FileSize,
+    CopyFile(String),
+    RemoveCopy(String),
+    AppendEntry,
+    QueueResponse(Requestor, Response),
+}
+
+struct Process {
+    id: u32,
+    image_name: Result<String, NtStatus>,
+}
+
+/// The vault of the volume, in memory.
+#[derive(Default)]
+struct FakeVault {
+    /// `None` until the first entry, like a volume without a vault.
+    header: Option<VaultHeader>,
+    entries: Vec<u8>,
+    copies: BTreeMap<String, Vec<u8>>,
+}
+
+pub struct FakePlatform {
+    processes: HashMap<Requestor, Process>,
+    file_name: Result<String, NtStatus>,
+    /// The name kept with the handle, see `FileNameProvider::keep_opened_name`.
+    opened_name: RefCell<Option<String>>,
+    now: Cell<u64>,
+    /// Allocations which still succeed, unlimited if `None`.
+    allocations: Cell<Option<usize>>,
+    random: Result<u8, NtStatus>,
+    stats: Stats,
+    /// Content of the file, the copy fails with the status.
+    content: Result<Vec<u8>, NtStatus>,
+    /// FILE_ATTRIBUTE_* bits of the file, both queries of `FileInfoProvider` fail with the
+    /// status.
+    info: Result<u32, NtStatus>,
+    vault: RefCell<FakeVault>,
+    /// Queuing a response fails with the status.
+    response: Result<(), NtStatus>,
+    calls: RefCell<Vec<Call>>,
+}
+
+impl Default for FakePlatform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakePlatform {
+    /// No known processes, no file name, time 0, every allocation succeeds.
+    pub fn new() -> Self {
+        Self {
+            processes: HashMap::new(),
+            file_name: Err(common::status::STATUS_INVALID_PARAMETER),
+            opened_name: RefCell::new(None),
+            now: Cell::new(0),
+            allocations: Cell::new(None),
+            random: Ok(0x5a),
+            stats: Stats::new(),
+            content: Ok(Vec::new()),
+            info: Ok(0),
+            vault: RefCell::new(FakeVault::default()),
+            response: Ok(()),
+            calls: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn process(mut self, requestor: Requestor, id: u32, image_name: &str) -> Self {
+        self.processes.insert(
+            requestor,
+            Process {
+                id,
+                image_name: Ok(image_name.to_string()),
+            },
+        );
+        self
+    }
+
+    /// The image name query for `requestor` fails with `status`, e.g. STATUS_NOT_FOUND for a
+    /// thread without a process.
+    pub fn failing_process(mut self, requestor: Requestor, status: NtStatus) -> Self {
+        self.processes.insert(
+            requestor,
+            Process {
+                id: 0,
+                image_name: Err(status),
+            },
+        );
+        self
+    }
+
+    pub fn file_name(mut self, file_name: &str) -> Self {
+        self.file_name = Ok(file_name.to_string());
+        self
+    }
+
+    pub fn failing_file_name(mut self, status: NtStatus) -> Self {
+        self.file_name = Err(status);
+        self
+    }
+
+    /// A handle opened for write access with this name kept.
+    pub fn opened_name(self, name: &str) -> Self {
+        *self.opened_name.borrow_mut() = Some(name.to_string());
+        self
+    }
+
+    /// The name `pre_create` kept with the handle, `None` if it kept none.
+    pub fn kept_name(&self) -> Option<String> {
+        self.opened_name.borrow().clone()
+    }
+
+    /// Only the next `count` allocations succeed.
+    pub fn allocations(self, count: usize) -> Self {
+        self.allocations.set(Some(count));
+        self
+    }
+
+    /// Random bytes are all `byte`.
+    pub fn random(mut self, byte: u8) -> Self {
+        self.random = Ok(byte);
+        self
+    }
+
+    pub fn failing_random(mut self, status: NtStatus) -> Self {
+        self.random = Err(status);
+        self
+    }
+
+    /// What a copy of the file into the vault reads.
+    pub fn file_content(mut self, content: &[u8]) -> Self {
+        self.content = Ok(content.to_vec());
+        self
+    }
+
+    /// Reading the file for its copy, and its size, fails with `status`.
+    pub fn failing_copy(mut self, status: NtStatus) -> Self {
+        self.content = Err(status);
+        self
+    }
+
+    /// What the attribute query returns, 0 by default.
+    pub fn file_attributes(mut self, attributes: u32) -> Self {
+        self.info = Ok(attributes);
+        self
+    }
+
+    /// The end of file and attribute queries fail with `status`. The end of file is the
+    /// length of the content otherwise.
+    pub fn failing_file_info(mut self, status: NtStatus) -> Self {
+        self.info = Err(status);
+        self
+    }
+
+    /// Terminations and suspensions cannot be queued.
+    pub fn failing_response(mut self, status: NtStatus) -> Self {
+        self.response = Err(status);
+        self
+    }
+
+    /// A vault with this header and no entries.
+    pub fn vault_header(self, header: VaultHeader) -> Self {
+        self.vault.borrow_mut().header = Some(header);
+        self
+    }
+
+    /// The index file of the vault, empty if there is none.
+    pub fn vault_index(&self) -> Vec<u8> {
+        let vault = self.vault.borrow();
+        let Some(header) = vault.header else {
+            return Vec::new();
+        };
+        let mut index = vec![0u8; VAULT_HEADER_SIZE];
+        header.encode(&mut index);
+        index.extend_from_slice(&vault.entries);
+        index
+    }
+
+    /// The copies in the vault by name.
+    pub fn vault_copies(&self) -> BTreeMap<String, Vec<u8>> {
+        self.vault.borrow().copies.clone()
+    }
+
+    pub fn set_now(&self, now: u64) {
+        self.now.set(now);
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.borrow().clone()
+    }
+
+    pub fn called(&self, call: &Call) -> bool {
+        self.calls.borrow().contains(call)
+    }
+
+    pub fn clear_calls(&self) {
+        self.calls.borrow_mut().clear();
+    }
+
+    /// The services of a callback backed by this fake, counting in its `Stats`.
+    pub fn platform(&self) -> Platform<'_> {
+        Platform {
+            identity: self,
+            files: self,
+            info: self,
+            clock: self,
+            allocator: self,
+            stats: &self.stats,
+            vault: self,
+            responder: self,
+        }
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.borrow_mut().push(call);
+    }
+}
+
+impl Clock for FakePlatform {
+    fn now(&self) -> u64 {
+        self.record(Call::Now);
+        self.now.get()
+    }
+}
+
+impl Allocator for FakePlatform {
+    fn name_buffer(&self, units: usize) -> Option<Vec<u16>> {
+        self.record(Call::Allocate(units));
+        match self.allocations.get() {
+            Some(0) => None,
+            Some(left) => {
+                self.allocations.set(Some(left - 1));
+                Some(vec![0; units])
+            },
+            None => Some(vec![0; units]),
+        }
+    }
+}
+
+impl ProcessIdentity for FakePlatform {
+    fn process_id(&self, requestor: Requestor) -> u32 {
+        self.record(Call::ProcessId(requestor));
+        self.processes.get(&requestor).map_or(0, |p| p.id)
+    }
+
+    fn query_image_name(
+        &self,
+        requestor: Requestor,
+        buffer: &mut [u16],
+    ) -> Result<usize, NtStatus> {
+        self.record(Call::ImageName(requestor));
+        match self.processes.get(&requestor).map(|p| &p.image_name) {
+            Some(Ok(name)) => Ok(copy_name(name, buffer)),
+            Some(Err(status)) => Err(*status),
+            None => Err(common::status::STATUS_INVALID_PARAMETER),
+        }
+    }
+}
+
+impl FileNameProvider for FakePlatform {
+    fn query_file_name(&self, buffer: &mut [u16]) -> Result<usize, NtStatus> {
+        self.record(Call::FileName);
+        match &self.file_name {
+            Ok(name) => Ok(copy_name(name, buffer)),
+            Err(status) => Err(*status),
+        }
+    }
+
+    fn opened_name(&self) -> Option<String> {
+        self.opened_name.borrow().clone()
+    }
+
+    fn keep_opened_name(&self, name: &str) {
+        *self.opened_name.borrow_mut() = Some(name.to_string());
+    }
+}
+
+impl FileInfoProvider for FakePlatform {
+    fn query_end_of_file(&self) -> Result<u64, NtStatus> {
+        self.record(Call::EndOfFile);
+        self.info?;
+        Ok(self
+            .content
+            .as_ref()
+            .map_or(0, |content| content.len() as u64))
+    }
+
+    fn query_attributes(&self) -> Result<u32, NtStatus> {
+        self.record(Call::Attributes);
+        self.info
+    }
+}
+
+impl Host for FakePlatform {
+    fn fill_random(&self, buffer: &mut [u8]) -> Result<(), NtStatus> {
+        self.record(Call::Random(buffer.len()));
+        let byte = self.random?;
+        buffer.fill(byte);
+        Ok(())
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
+
+impl Vault for FakePlatform {
+    fn read_header(&self) -> Result<VaultHeader, NtStatus> {
+        Ok(self.vault.borrow().header.unwrap_or_default())
+    }
+
+    fn file_size(&self, _path: &str) -> Result<u64, NtStatus> {
+        self.record(Call::FileSize);
+        match &self.content {
+            Ok(content) => Ok(content.len() as u64),
+            Err(status) => Err(*status),
+        }
+    }
+
+    fn copy_file(&self, _path: &str, copy_name: &str) -> Result<(u64, [u8; 32]), NtStatus> {
+        self.record(Call::CopyFile(copy_name.to_string()));
+        let content = self.content.clone()?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(&content);
+        let size = content.len() as u64;
+        self.vault
+            .borrow_mut()
+            .copies
+            .insert(copy_name.to_string(), content);
+        Ok((size, hasher.finish()))
+    }
+
+    fn remove_copy(&self, copy_name: &str) {
+        self.record(Call::RemoveCopy(copy_name.to_string()));
+        self.vault.borrow_mut().copies.remove(copy_name);
+    }
+
+    fn append_entry(
+        &self,
+        entry: &[u8],
+        update: &mut dyn FnMut(&mut VaultHeader) -> Result<(), NtStatus>,
+    ) -> Result<(), NtStatus> {
+        self.record(Call::AppendEntry);
+        let mut vault = self.vault.borrow_mut();
+        let mut header = vault.header.unwrap_or_default();
+        update(&mut header)?;
+
+        vault.header = Some(header);
+        vault.entries.extend_from_slice(entry);
+        Ok(())
+    }
+}
+
+impl Responder for FakePlatform {
+    fn queue_response(&self, requestor: Requestor, response: Response) -> Result<(), NtStatus> {
+        self.record(Call::QueueResponse(requestor, response));
+        self.response
+    }
+}
+
+/// Copies like the kernel does: truncated to the buffer, no terminating null.
+fn copy_name(name: &str, buffer: &mut [u16]) -> usize {
+    let mut len = 0;
+    for (dst, src) in buffer.iter_mut().zip(name.encode_utf16()) {
+        *dst = src;
+        len += 1;
+    }
+    len
+}
+
+/// The engine behind a `RefCell`, the single threaded stand-in for the driver mutex.
+pub struct FakeEngine {
+    engine: RefCell<Option<Engine>>,
+    published: Published,
+}
+
+impl FakeEngine {
+    pub fn new(config: Config) -> Self {
+        Self {
+            engine: RefCell::new(Some(
+                Engine::new(config).expect("allocation cannot fail on the host"),
+            )),
+            published: Published::new(),
+        }
+    }
+
+    /// No engine, like a callback racing the driver initialization.
+    pub fn missing() -> Self {
+        Self {
+            engine: RefCell::new(None),
+            published: Published::new(),
+        }
+    }
+}
+
+impl Default for FakeEngine {
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+impl EngineLock for FakeEngine {
+    fn with_engine<R>(&self, f: impl FnOnce(&mut Engine) -> R) -> Option<R> {
+        let mut engine = self.engine.borrow_mut();
+        let engine = engine.as_mut()?;
+        let result = f(engine);
+        self.published.publish(engine);
+        Some(result)
+    }
+
+    fn published(&self) -> &Published {
+        &self.published
+    }
+}
@@ -0,0 +1,164 @@
+use common::{
+    auth::{response, CHALLENGE_MESSAGE_SIZE, CHALLENGE_SIZE},
+    ioctl_codes::{
+        IOCTL_DELPROTECT_ALLOW_UNLOAD, IOCTL_DELPROTECT_AUTHENTICATE,
+        IOCTL_DELPROTECT_GET_CHALLENGE, IOCTL_DELPROTECT_SET_SECRET,
+    },
+    status::{STATUS_ACCESS_DENIED, STATUS_FLT_DO_NOT_DETACH, STATUS_SUCCESS},
+};
+use delprotect_core::{
+    filter::EngineLock,
+    ioctl::{Caller, Persist, Reply},
+    Config,
+};
+use delprotect_fake::{fixtures::ioctl_on, FakeEngine, FakePlatform};
+
+const OLD: &[u8] = b"0123456789abcdef";
+const NEW: &[u8] = b"fedcba9876543210";
+const OTHER: &[u8] = b"ffffffffffffffff";
+
+fn handle(file_object: usize) -> Caller {
+    Caller {
+        file_object,
+        privileged: true,
+        ..Caller::default()
+    }
+}
+
+fn ioctl(engine: &FakeEngine, code: u32, input: &[u8], caller: &Caller) -> Result<Reply, i32> {
+    let platform = FakePlatform::new().random(0x5a);
+    ioctl_on(
+        engine,
+        &platform,
+        code,
+        input,
+        CHALLENGE_MESSAGE_SIZE,
+        caller,
+    )
+    .map(|(reply, _)| reply)
+}
+
+/// Answers a challenge for the handle with `secret`.
+fn authenticate(engine: &FakeEngine, caller: &Caller, secret: &[u8]) -> Result<Reply, i32> {
+    ioctl(engine, IOCTL_DELPROTECT_GET_CHALLENGE, &[], caller)?;
+    let answer = response(secret, &[0x5a; CHALLENGE_SIZE]);
+    ioctl(engine, IOCTL_DELPROTECT_AUTHENTICATE, &answer, caller)
+}
+
+fn locked(secret: &[u8]) -> FakeEngine {
+    FakeEngine::new(Config {
+        secret: Some(secret.to_vec()),
+        ..Config::default()
+    })
+}
+
+#[test]
+fn secret_is_committed_before_the_host_persists_it() {
+    let engine = FakeEngine::default();
+    let reply = ioctl(&engine, IOCTL_DELPROTECT_SET_SECRET, NEW, &handle(7)).unwrap();
+    assert_eq!(reply.persist, Some(Persist::Secret(NEW.to_vec())));
+    assert!(!engine
+        .with_engine(|engine| engine.auth().is_locked())
+        .unwrap());
+
+    let previous = engine
+        .with_engine(|engine| engine.commit_secret(7, NEW))
+        .unwrap();
+
+    assert_eq!(previous, Ok(None));
+    engine
+        .with_engine(|engine| {
+            assert!(engine.auth().is_locked());
+            assert!(!engine.auth().is_authorized(8));
+        })
+        .unwrap();
+}
+
+#[test]
+fn secret_which_cannot_be_persisted_is_rolled_back() {
+    let engine = locked(OLD);
+    authenticate(&engine, &handle(7), OLD).unwrap();
+
+    let previous = engine
+        .with_engine(|engine| engine.commit_secret(7, NEW))
+        .unwrap()
+        .unwrap();
+    assert_eq!(previous.as_deref(), Some(OLD));
+    engine
+        .with_engine(|engine| engine.restore_secret(NEW, previous))
+        .unwrap();
+
+    assert_eq!(
+        authenticate(&engine, &handle(8), NEW),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    authenticate(&engine, &handle(8), OLD).unwrap();
+}
+
+#[test]
+fn first_secret_which_cannot_be_persisted_unlocks_again() {
+    let engine = FakeEngine::default();
+
+    let previous = engine
+        .with_engine(|engine| engine.commit_secret(7, NEW))
+        .unwrap()
+        .unwrap();
+    engine
+        .with_engine(|engine| engine.restore_secret(NEW, previous))
+        .unwrap();
+
+    assert!(!engine
+        .with_engine(|engine| engine.auth().is_locked())
+        .unwrap());
+}
+
+#[test]
+fn rollback_keeps_a_secret_committed_since() {
+    let engine = locked(OLD);
+    authenticate(&engine, &handle(7), OLD).unwrap();
+
+    let previous = engine
+        .with_engine(|engine| engine.commit_secret(7, NEW))
+        .unwrap()
+        .unwrap();
+    engine
+        .with_engine(|engine| engine.commit_secret(7, OTHER))
+        .unwrap()
+        .unwrap();
+    engine
+        .with_engine(|engine| engine.restore_secret(NEW, previous))
+        .unwrap();
+
+    authenticate(&engine, &handle(8), OTHER).unwrap();
+}
+
+#[test]
+fn commit_checks_the_handle_again() {
+    let engine = locked(OLD);
+
+    let result = engine
+        .with_engine(|engine| engine.commit_secret(7, NEW))
+        .unwrap();
+
+    assert_eq!(result, Err(STATUS_ACCESS_DENIED));
+    authenticate(&engine, &handle(8), OLD).unwrap();
+}
+
+#[test]
+fn allowed_unload_only_lets_the_next_one_through() {
+    let engine = locked(OLD);
+    authenticate(&engine, &handle(7), OLD).unwrap();
+    ioctl(&engine, IOCTL_DELPROTECT_ALLOW_UNLOAD, &[], &handle(7)).unwrap();
+
+    let query = |mandatory| {
+        engine
+            .with_engine(|engine| engine.query_unload(mandatory, 4, "fltmc.exe", 0))
+            .unwrap()
+    };
+    assert_eq!(query(false), STATUS_SUCCESS);
+    assert_eq!(query(false), STATUS_FLT_DO_NOT_DETACH);
+    assert_eq!(query(true), STATUS_SUCCESS);
+    assert!(!engine
+        .with_engine(|engine| engine.auth().is_unload_allowed())
+        .unwrap());
+}
@@ -0,0 +1,277 @@
+use common::{
+    canary::{CanaryRecord, CANARY_FLAG_DESCENDANTS},
+    detector::{LockdownCause, LockdownRecord},
+    event::{EventKind, EventRecord, Severity},
+    ioctl_codes::{
+        IOCTL_DELPROTECT_ADD_CANARY, IOCTL_DELPROTECT_LIST_CANARIES,
+        IOCTL_DELPROTECT_LIST_LOCKDOWNS, IOCTL_DELPROTECT_REMOVE_CANARY,
+    },
+    schedule::TICKS_PER_SECOND,
+    stats::Counter,
+    status::{
+        STATUS_ACCESS_DENIED, STATUS_INVALID_PARAMETER, STATUS_NOT_FOUND,
+        STATUS_OBJECT_NAME_COLLISION,
+    },
+    wire::{ListHeader, LIST_HEADER_SIZE},
+};
+use delprotect_core::{
+    filter::{pre_create, pre_rename, pre_set_disposition, EngineLock, FILE_SUPERSEDE},
+    ioctl::Caller,
+    Decision, Host, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl, privileged, utf16, CMD, EXPLORER, THREAD},
+    FakeEngine, FakePlatform,
+};
+
+const CANARY: &str = r"\Device\HarddiskVolume3\Users\Public\budget.xlsx";
+const OTHER: &str = r"\Device\HarddiskVolume3\Users\Public\notes.txt";
+const NOW: u64 = 1000 * TICKS_PER_SECOND;
+
+fn add_canary(engine: &FakeEngine, path: &str, flags: u32) -> Result<u32, i32> {
+    let path = utf16(path);
+    let record = CanaryRecord {
+        id: 0,
+        flags,
+        trips: 0,
+        last_trip: 0,
+        path: &path,
+    };
+    let mut input = vec![0u8; record.encoded_len()];
+    record.encode(&mut input).unwrap();
+
+    let (_, output) = ioctl(
+        engine,
+        IOCTL_DELPROTECT_ADD_CANARY,
+        &input,
+        4,
+        &privileged(),
+    )?;
+    Ok(u32::from_le_bytes(output[..4].try_into().unwrap()))
+}
+
+fn engine_with_canary(flags: u32) -> FakeEngine {
+    let engine = FakeEngine::default();
+    assert_eq!(add_canary(&engine, CANARY, flags), Ok(1));
+    engine
+}
+
+fn cmd(file_name: &str) -> FakePlatform {
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .process(Requestor::Current, 42, CMD)
+        .file_name(file_name);
+    platform.set_now(NOW);
+    platform
+}
+
+fn last_event(engine: &FakeEngine) -> Vec<u8> {
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    buffer.truncate(len);
+    buffer
+}
+
+#[test]
+fn canaries_are_added_listed_and_removed() {
+    let engine = engine_with_canary(0);
+    assert_eq!(add_canary(&engine, OTHER, CANARY_FLAG_DESCENDANTS), Ok(2));
+    assert_eq!(
+        add_canary(&engine, &CANARY.to_uppercase(), 0),
+        Err(STATUS_OBJECT_NAME_COLLISION)
+    );
+    assert_eq!(add_canary(&engine, "", 0), Err(STATUS_INVALID_PARAMETER));
+    assert_eq!(
+        add_canary(&engine, OTHER, 0x8),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_LIST_CANARIES,
+        &[],
+        4096,
+        &Caller::default(),
+    )
+    .unwrap();
+    let header = ListHeader::decode(&output).unwrap();
+    assert_eq!((header.total, header.returned), (2, 2));
+    let (first, len) = CanaryRecord::decode(&output[LIST_HEADER_SIZE..]).unwrap();
+    assert_eq!((first.id, first.flags), (1, 0));
+    assert_eq!(first.path, utf16(CANARY).as_slice());
+    let (second, _) = CanaryRecord::decode(&output[LIST_HEADER_SIZE + len..]).unwrap();
+    assert_eq!((second.id, second.flags), (2, CANARY_FLAG_DESCENDANTS));
+
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_REMOVE_CANARY,
+            &1u32.to_le_bytes(),
+            0,
+            &Caller::default()
+        )
+        .map(|(reply, _)| reply.written),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    ioctl(
+        &engine,
+        IOCTL_DELPROTECT_REMOVE_CANARY,
+        &1u32.to_le_bytes(),
+        0,
+        &privileged(),
+    )
+    .unwrap();
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_REMOVE_CANARY,
+            &1u32.to_le_bytes(),
+            0,
+            &privileged()
+        )
+        .map(|(reply, _)| reply.written),
+        Err(STATUS_NOT_FOUND)
+    );
+
+    // ids are not reused
+    assert_eq!(add_canary(&engine, CANARY, 0), Ok(3));
+}
+
+#[test]
+fn deleting_a_canary_is_denied_with_an_event() {
+    let engine = engine_with_canary(0);
+    let platform = cmd(CANARY);
+
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert_eq!(verdict.decision, Some(Decision::LockedDown));
+    assert_eq!(platform.stats().get(Counter::LockdownDenied), 1);
+
+    let buffer = last_event(&engine);
+    let (event, _) = EventRecord::decode(&buffer).unwrap();
+    assert_eq!(event.kind, EventKind::CanaryTripped as u16);
+    assert_eq!(event.severity, Severity::High);
+    assert_eq!(event.process_id, 42);
+    assert_eq!(event.detail, utf16("delete of canary 1").as_slice());
+
+    let (trips, last_trip) = engine
+        .with_engine(|engine| {
+            let canary = engine.canaries().iter().next().unwrap();
+            (canary.trips, canary.last_trip)
+        })
+        .unwrap();
+    assert_eq!((trips, last_trip), (1, NOW));
+}
+
+#[test]
+fn process_touching_a_canary_is_locked_down() {
+    let engine = engine_with_canary(0);
+    let rename = pre_rename(&cmd(CANARY).platform(), &engine, THREAD);
+    assert_eq!(rename.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+
+    // any other file is denied to the process from now on, without another event
+    let platform = cmd(OTHER);
+    let overwrite = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_SUPERSEDE << 24,
+        0,
+    );
+    assert_eq!(overwrite.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert_eq!(overwrite.decision, Some(Decision::LockedDown));
+    let events = engine
+        .with_engine(|engine| engine.events().iter().count())
+        .unwrap();
+    assert_eq!(events, 1);
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_LIST_LOCKDOWNS,
+        &[],
+        4096,
+        &Caller::default(),
+    )
+    .unwrap();
+    let (record, _) = LockdownRecord::decode(&output[LIST_HEADER_SIZE..]).unwrap();
+    assert_eq!(record.process_id, 42);
+    assert_eq!(record.cause, LockdownCause::Canary);
+    assert_eq!(record.parent_id, 0);
+
+    // other processes go on
+    let other = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 8, EXPLORER)
+        .file_name(OTHER);
+    let verdict = pre_rename(&other.platform(), &engine, THREAD);
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+}
+
+#[test]
+fn descendants_are_locked_down_only_if_the_canary_says_so() {
+    for (flags, locked) in [(0, false), (CANARY_FLAG_DESCENDANTS, true)] {
+        let engine = engine_with_canary(flags);
+        pre_set_disposition(&cmd(CANARY).platform(), &engine, THREAD, true);
+
+        engine
+            .with_engine(|engine| engine.process_created(42, CMD, 43, "powershell.exe", NOW))
+            .unwrap();
+        let child = FakePlatform::new()
+            .process(Requestor::Thread(THREAD), 43, EXPLORER)
+            .file_name(OTHER);
+        let verdict = pre_set_disposition(&child.platform(), &engine, THREAD, true);
+        assert_eq!(verdict.decision == Some(Decision::LockedDown), locked);
+
+        // and their descendants in turn
+        engine
+            .with_engine(|engine| engine.process_created(43, EXPLORER, 44, "cmd.exe", NOW))
+            .unwrap();
+        let locked_grandchild = engine
+            .with_engine(|engine| engine.detector().is_locked_down(44, CMD))
+            .unwrap();
+        assert_eq!(locked_grandchild, locked);
+    }
+}
+
+#[test]
+fn exited_descendant_is_released() {
+    let engine = engine_with_canary(CANARY_FLAG_DESCENDANTS);
+    pre_set_disposition(&cmd(CANARY).platform(), &engine, THREAD, true);
+    engine
+        .with_engine(|engine| {
+            engine.process_created(42, CMD, 43, "powershell.exe", NOW);
+            engine.process_exited(43);
+            engine.process_exited(42);
+        })
+        .unwrap();
+
+    let locked = engine
+        .with_engine(|engine| {
+            engine.detector().is_locked_down(42, CMD) || engine.detector().is_locked_down(43, CMD)
+        })
+        .unwrap();
+    assert!(!locked);
+}
+
+#[test]
+fn removed_canary_is_deleted_like_any_file() {
+    let engine = engine_with_canary(0);
+    ioctl(
+        &engine,
+        IOCTL_DELPROTECT_REMOVE_CANARY,
+        &1u32.to_le_bytes(),
+        0,
+        &privileged(),
+    )
+    .unwrap();
+
+    let platform = cmd(CANARY);
+    let verdict = pre_rename(&platform.platform(), &engine, THREAD);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    // nothing is being watched, the name is not even asked for
+    assert!(platform.calls().is_empty());
+}
@@ -0,0 +1,176 @@
+use common::{
+    event::{EventKind, EventRecord},
+    options::OPTION_DENY_DELETE_ACCESS,
+    rule::RuleAction,
+    stats::Counter,
+    status::STATUS_CANNOT_DELETE,
+};
+use delprotect_core::{
+    filter::{pre_create, pre_set_disposition, EngineLock, FILE_DELETE_ON_CLOSE, MAXIMUM_ALLOWED},
+    Decision, Host, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{engine, CMD, EXPLORER, TARGET, THREAD},
+    Call, FakePlatform,
+};
+
+/// FILE_READ_ATTRIBUTES | DELETE | SYNCHRONIZE, what DeleteFileW and MoveFileW open with.
+const DELETE_OPEN: u32 = 0x0011_0080;
+
+fn opened_by(image_name: &str) -> FakePlatform {
+    FakePlatform::new()
+        .process(Requestor::Current, 42, image_name)
+        .process(Requestor::Thread(THREAD), 42, image_name)
+        .file_name(TARGET)
+}
+
+#[test]
+fn delete_access_is_only_decided_with_the_option() {
+    let engine = engine(0, RuleAction::Deny, STATUS_CANNOT_DELETE);
+    let platform = opened_by(CMD);
+
+    let verdict = pre_create(&platform.platform(), &engine, false, 0, DELETE_OPEN);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    // the name is only queried to keep the vault safe, the process is not identified
+    assert!(!platform
+        .calls()
+        .iter()
+        .any(|call| matches!(call, Call::ProcessId(_) | Call::ImageName(_))));
+    assert_eq!(platform.stats().get(Counter::DeleteAccessOpens), 0);
+}
+
+#[test]
+fn delete_access_open_by_blocked_process_is_denied() {
+    let engine = engine(
+        OPTION_DENY_DELETE_ACCESS,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = opened_by(CMD);
+
+    let verdict = pre_create(&platform.platform(), &engine, false, 0, DELETE_OPEN);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_CANNOT_DELETE));
+    assert_eq!(verdict.decision, Some(Decision::Deny { rule_id: 1 }));
+    assert_eq!(platform.stats().get(Counter::DeleteAccessOpens), 1);
+    assert_eq!(platform.stats().get(Counter::Blocked), 1);
+
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    let (event, _) = EventRecord::decode(&buffer[..len]).unwrap();
+    assert_eq!(event.kind, EventKind::DeleteDenied as u16);
+    assert_eq!(event.status, STATUS_CANNOT_DELETE);
+    let detail: Vec<u8> = "open with DELETE access"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    assert_eq!(event.detail, detail.as_slice());
+}
+
+#[test]
+fn maximum_allowed_open_is_left_to_the_delete() {
+    let engine = engine(
+        OPTION_DENY_DELETE_ACCESS,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = opened_by(CMD);
+
+    let verdict = pre_create(&platform.platform(), &engine, false, 0, MAXIMUM_ALLOWED);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(verdict.decision, None);
+    assert_eq!(platform.stats().get(Counter::DeleteAccessOpens), 0);
+
+    let delete = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    assert_eq!(delete.pre_op, PreOp::Complete(STATUS_CANNOT_DELETE));
+}
+
+#[test]
+fn delete_access_open_by_other_process_goes_on_uncounted() {
+    let engine = engine(
+        OPTION_DENY_DELETE_ACCESS,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = opened_by(EXPLORER);
+
+    let verdict = pre_create(&platform.platform(), &engine, false, 0, DELETE_OPEN);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(platform.stats().get(Counter::DeleteAccessOpens), 1);
+    // the delete which may follow is the one counted
+    assert_eq!(platform.stats().get(Counter::Allowed), 0);
+}
+
+#[test]
+fn preserving_rule_lets_the_open_go_on_to_preserve_the_delete() {
+    let engine = engine(
+        OPTION_DENY_DELETE_ACCESS,
+        RuleAction::Preserve,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = opened_by(CMD);
+
+    let open = pre_create(&platform.platform(), &engine, false, 0, DELETE_OPEN);
+    assert_eq!(open.pre_op, PreOp::PassThrough);
+    assert!(!platform
+        .calls()
+        .iter()
+        .any(|call| matches!(call, Call::CopyFile(_))));
+    let hits = engine
+        .with_engine(|engine| engine.rules().get(1).unwrap().hits)
+        .unwrap();
+    assert_eq!(hits, 0);
+
+    let delete = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    assert_eq!(delete.decision, Some(Decision::Preserve { rule_id: 1 }));
+    assert_eq!(platform.vault_copies().len(), 1);
+}
+
+#[test]
+fn delete_on_close_is_decided_once() {
+    let engine = engine(
+        OPTION_DENY_DELETE_ACCESS,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = opened_by(CMD);
+
+    let verdict = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        DELETE_OPEN,
+    );
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_CANNOT_DELETE));
+    assert_eq!(platform.stats().get(Counter::DeleteOnClose), 1);
+    assert_eq!(platform.stats().get(Counter::DeleteAccessOpens), 0);
+    assert_eq!(platform.stats().get(Counter::Blocked), 1);
+}
+
+#[test]
+fn open_without_delete_access_is_not_decided() {
+    let engine = engine(
+        OPTION_DENY_DELETE_ACCESS,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = opened_by(CMD);
+
+    // FILE_GENERIC_READ
+    let verdict = pre_create(&platform.platform(), &engine, false, 0, 0x0012_0089);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    // the name is only queried to keep the vault safe, the process is not identified
+    assert!(!platform
+        .calls()
+        .iter()
+        .any(|call| matches!(call, Call::ProcessId(_) | Call::ImageName(_))));
+}
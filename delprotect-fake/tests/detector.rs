@@ -0,0 +1,503 @@
+use common::{
+    detector::{DetectorSettings, LockdownCause, LockdownRecord, DETECTOR_SETTINGS_SIZE},
+    event::{EventKind, EventRecord, Severity},
+    ioctl_codes::{
+        IOCTL_DELPROTECT_GET_DETECTOR, IOCTL_DELPROTECT_LIST_LOCKDOWNS,
+        IOCTL_DELPROTECT_RELEASE_LOCKDOWN, IOCTL_DELPROTECT_SET_DETECTOR,
+    },
+    schedule::TICKS_PER_SECOND,
+    stats::Counter,
+    status::{STATUS_ACCESS_DENIED, STATUS_INVALID_PARAMETER, STATUS_NOT_FOUND},
+    wire::{ListHeader, LIST_HEADER_SIZE},
+};
+use delprotect_core::{
+    detector::{
+        Activity, Detector, Dropped, Lockdown, Operation, MAX_LOCKDOWNS, MAX_TRACKED_PROCESSES,
+    },
+    filter::{pre_create, pre_rename, pre_set_disposition, EngineLock, FILE_OVERWRITE_IF},
+    ioctl::{Caller, Persist},
+    Config, Decision, Host, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl, privileged, utf16, CMD, EXPLORER, THREAD},
+    FakeEngine, FakePlatform,
+};
+
+const START: u64 = 1000 * TICKS_PER_SECOND;
+
+/// More than 5 operations in more than 2 directories within 10 seconds.
+const SETTINGS: DetectorSettings = DetectorSettings {
+    window_seconds: 10,
+    max_operations: 5,
+    max_directories: 2,
+};
+
+fn file(index: usize) -> String {
+    format!(r"\Device\HarddiskVolume3\data\d{}\f{index}.txt", index % 3)
+}
+
+fn engine_detecting() -> FakeEngine {
+    FakeEngine::new(Config {
+        detector: SETTINGS,
+        ..Config::default()
+    })
+}
+
+/// Deletes from cmd.exe until the detector trips, returns the platform of the last one.
+fn trip(engine: &FakeEngine) -> FakePlatform {
+    for index in 0..6 {
+        let platform = FakePlatform::new()
+            .process(Requestor::Thread(THREAD), 42, CMD)
+            .file_name(&file(index));
+        platform.set_now(START);
+        let verdict = pre_set_disposition(&platform.platform(), engine, THREAD, true);
+        if index < 5 {
+            assert_eq!(verdict.pre_op, PreOp::PassThrough);
+        } else {
+            assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+            assert_eq!(verdict.decision, Some(Decision::LockedDown));
+            return platform;
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn detector_trips_above_both_thresholds() {
+    let mut detector = Detector::new(SETTINGS);
+    for index in 0..5 {
+        let activity = detector.record(Operation::Delete, 42, CMD, &file(index), START);
+        assert_eq!(activity, Activity::Normal);
+    }
+
+    assert_eq!(
+        detector.record(Operation::Rename, 42, CMD, &file(5), START),
+        Activity::Tripped {
+            operations: 6,
+            directories: 3
+        }
+    );
+    assert!(detector.is_locked_down(42, CMD));
+    assert_eq!(
+        detector.record(Operation::Overwrite, 42, CMD, &file(6), START),
+        Activity::LockedDown
+    );
+    // another process reusing the id is not locked down
+    assert!(!detector.is_locked_down(42, EXPLORER));
+}
+
+#[test]
+fn detector_ignores_many_operations_in_few_directories() {
+    let mut detector = Detector::new(SETTINGS);
+    for index in 0..100 {
+        let file_name = format!(r"\Device\HarddiskVolume3\build\obj{}\f{index}.o", index % 2);
+        let activity = detector.record(Operation::Delete, 42, CMD, &file_name, START);
+        assert_eq!(activity, Activity::Normal);
+    }
+}
+
+#[test]
+fn detector_counts_the_whole_window() {
+    let mut detector = Detector::new(SETTINGS);
+    for index in 0..5 {
+        detector.record(Operation::Delete, 42, CMD, &file(index), START);
+    }
+
+    let later = START + 9 * TICKS_PER_SECOND;
+    assert!(detector
+        .record(Operation::Delete, 42, CMD, &file(5), later)
+        .is_locked_down());
+}
+
+#[test]
+fn detector_forgets_operations_out_of_the_window() {
+    let mut detector = Detector::new(SETTINGS);
+    for index in 0..5 {
+        detector.record(Operation::Delete, 42, CMD, &file(index), START);
+    }
+
+    let later = START + 11 * TICKS_PER_SECOND;
+    for index in 5..10 {
+        let activity = detector.record(Operation::Delete, 42, CMD, &file(index), later);
+        assert_eq!(activity, Activity::Normal);
+    }
+}
+
+#[test]
+fn disabled_detector_counts_nothing() {
+    let mut detector = Detector::new(DetectorSettings::default());
+    assert!(!detector.is_watching());
+    for index in 0..1000 {
+        let activity = detector.record(Operation::Delete, 42, CMD, &file(index), START);
+        assert_eq!(activity, Activity::Normal);
+    }
+    assert!(detector.lockdowns().is_empty());
+}
+
+fn lockdown(process_id: u32, cause: LockdownCause) -> Lockdown {
+    Lockdown {
+        process_id,
+        image_name: String::from(CMD),
+        cause,
+        parent_id: 0,
+        descendants: false,
+        time: START,
+        operations: 0,
+        directories: 0,
+    }
+}
+
+/// A detector holding `MAX_LOCKDOWNS` lockdowns of processes 1 and up, the first one for
+/// `first`, the others for `rest`.
+fn full_detector(first: LockdownCause, rest: LockdownCause) -> Detector {
+    let mut detector = Detector::new(SETTINGS);
+    detector.lock_down(lockdown(1, first));
+    for process_id in 2..=MAX_LOCKDOWNS as u32 {
+        detector.lock_down(lockdown(process_id, rest));
+    }
+    assert!(detector.take_dropped().is_none());
+    detector
+}
+
+#[test]
+fn detector_makes_room_for_new_processes() {
+    let mut detector = Detector::new(SETTINGS);
+    for process_id in 0..MAX_TRACKED_PROCESSES as u32 + 10 {
+        let now = START + process_id as u64;
+        detector.record(Operation::Delete, process_id, CMD, &file(0), now);
+    }
+
+    // the busy process is still counted after the others came and went
+    let now = START + TICKS_PER_SECOND;
+    for index in 0..5 {
+        detector.record(Operation::Delete, 1000, CMD, &file(index), now);
+    }
+    assert!(detector
+        .record(Operation::Delete, 1000, CMD, &file(5), now)
+        .is_locked_down());
+}
+
+#[test]
+fn tripping_delete_is_denied_with_a_mass_delete_event() {
+    let engine = engine_detecting();
+    let platform = trip(&engine);
+
+    assert_eq!(platform.stats().get(Counter::LockdownDenied), 1);
+    assert_eq!(platform.stats().get(Counter::Blocked), 0);
+
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    let (event, _) = EventRecord::decode(&buffer[..len]).unwrap();
+    assert_eq!(event.kind, EventKind::MassDelete as u16);
+    assert_eq!(event.severity, Severity::High);
+    assert_eq!(event.process_id, 42);
+    assert_eq!(event.status, STATUS_ACCESS_DENIED);
+    let detail: Vec<u8> = "6 operations in 3 directories"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    assert_eq!(event.detail, detail.as_slice());
+}
+
+#[test]
+fn locked_down_process_cannot_rename_or_overwrite() {
+    let engine = engine_detecting();
+    trip(&engine);
+
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .process(Requestor::Current, 42, CMD)
+        .file_name(&file(7));
+    let rename = pre_rename(&platform.platform(), &engine, THREAD);
+    assert_eq!(rename.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert_eq!(rename.decision, Some(Decision::LockedDown));
+
+    let overwrite = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_OVERWRITE_IF << 24,
+        0,
+    );
+    assert_eq!(overwrite.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert_eq!(platform.stats().get(Counter::LockdownDenied), 2);
+
+    // only the tripping operation raised an event
+    let mass_deletes = engine
+        .with_engine(|engine| engine.events().iter().count())
+        .unwrap();
+    assert_eq!(mass_deletes, 1);
+
+    // other processes go on
+    let other = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 8, EXPLORER)
+        .file_name(&file(7));
+    let verdict = pre_rename(&other.platform(), &engine, THREAD);
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(verdict.decision, None);
+}
+
+#[test]
+fn renames_are_not_looked_at_while_not_watching() {
+    let engine = FakeEngine::default();
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .file_name(&file(0));
+
+    let verdict = pre_rename(&platform.platform(), &engine, THREAD);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+}
+
+#[test]
+fn released_process_is_let_through_again() {
+    let engine = engine_detecting();
+    trip(&engine);
+
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_RELEASE_LOCKDOWN,
+            &7u32.to_le_bytes(),
+            0,
+            &privileged()
+        )
+        .map(|(reply, _)| reply.written),
+        Err(STATUS_NOT_FOUND)
+    );
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_RELEASE_LOCKDOWN,
+            &42u32.to_le_bytes(),
+            0,
+            &Caller::default()
+        )
+        .map(|(reply, _)| reply.written),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    ioctl(
+        &engine,
+        IOCTL_DELPROTECT_RELEASE_LOCKDOWN,
+        &42u32.to_le_bytes(),
+        0,
+        &privileged(),
+    )
+    .unwrap();
+
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .file_name(&file(0));
+    platform.set_now(START);
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(verdict.decision, Some(Decision::Allow));
+}
+
+#[test]
+fn exited_process_is_released() {
+    let engine = engine_detecting();
+    trip(&engine);
+
+    engine
+        .with_engine(|engine| engine.detector_mut().process_exited(42))
+        .unwrap();
+
+    let locked = engine
+        .with_engine(|engine| engine.detector().is_locked_down(42, CMD))
+        .unwrap();
+    assert!(!locked);
+}
+
+#[test]
+fn lockdowns_are_listed() {
+    let engine = engine_detecting();
+    trip(&engine);
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_LIST_LOCKDOWNS,
+        &[],
+        4096,
+        &Caller::default(),
+    )
+    .unwrap();
+    let header = ListHeader::decode(&output).unwrap();
+    assert_eq!((header.total, header.returned), (1, 1));
+
+    let (record, _) = LockdownRecord::decode(&output[LIST_HEADER_SIZE..]).unwrap();
+    assert_eq!(record.process_id, 42);
+    assert_eq!(record.time, START);
+    assert_eq!((record.operations, record.directories), (6, 3));
+    let process: Vec<u8> = CMD.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    assert_eq!(record.process, process.as_slice());
+}
+
+#[test]
+fn detector_settings_are_checked_and_persisted() {
+    let engine = FakeEngine::default();
+    let mut input = [0u8; DETECTOR_SETTINGS_SIZE];
+    SETTINGS.encode(&mut input).unwrap();
+
+    let (reply, _) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_SET_DETECTOR,
+        &input,
+        0,
+        &privileged(),
+    )
+    .unwrap();
+    assert_eq!(reply.persist, Some(Persist::Detector(SETTINGS)));
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_GET_DETECTOR,
+        &[],
+        DETECTOR_SETTINGS_SIZE,
+        &Caller::default(),
+    )
+    .unwrap();
+    assert_eq!(DetectorSettings::decode(&output), Ok(SETTINGS));
+
+    for invalid in [
+        DetectorSettings {
+            window_seconds: 3601,
+            ..SETTINGS
+        },
+        DetectorSettings {
+            max_operations: 0,
+            ..SETTINGS
+        },
+        DetectorSettings {
+            max_directories: 64,
+            ..SETTINGS
+        },
+    ] {
+        invalid.encode(&mut input).unwrap();
+        assert_eq!(
+            ioctl(
+                &engine,
+                IOCTL_DELPROTECT_SET_DETECTOR,
+                &input,
+                0,
+                &privileged()
+            )
+            .map(|(reply, _)| reply.persist),
+            Err(STATUS_INVALID_PARAMETER)
+        );
+    }
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_SET_DETECTOR,
+            &input[..8],
+            0,
+            &privileged()
+        )
+        .map(|(reply, _)| reply.persist),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+}
+
+#[test]
+fn full_detector_refuses_a_mass_delete_lockdown() {
+    let mut detector = full_detector(LockdownCause::MassDelete, LockdownCause::MassDelete);
+
+    detector.lock_down(lockdown(1000, LockdownCause::MassDelete));
+
+    assert!(!detector.is_locked_down(1000, CMD));
+    assert!(detector.is_locked_down(1, CMD));
+    assert_eq!(detector.lockdowns().len(), MAX_LOCKDOWNS);
+    assert!(matches!(
+        detector.take_dropped(),
+        Some(Dropped::Refused(Lockdown {
+            process_id: 1000,
+            ..
+        }))
+    ));
+    assert!(detector.take_dropped().is_none());
+}
+
+#[test]
+fn demotion_releases_the_oldest_mass_delete_lockdown() {
+    let mut detector = full_detector(LockdownCause::Canary, LockdownCause::MassDelete);
+
+    detector.lock_down(lockdown(1000, LockdownCause::Demoted));
+
+    assert!(detector.is_locked_down(1000, CMD));
+    assert!(detector.is_locked_down(1, CMD));
+    assert!(!detector.is_locked_down(2, CMD));
+    assert_eq!(detector.lockdowns().len(), MAX_LOCKDOWNS);
+    assert!(matches!(
+        detector.take_dropped(),
+        Some(Dropped::Released(Lockdown { process_id: 2, .. }))
+    ));
+}
+
+#[test]
+fn canary_and_demotion_lockdowns_are_never_released() {
+    let mut detector = full_detector(LockdownCause::Demoted, LockdownCause::Canary);
+
+    detector.lock_down(lockdown(1000, LockdownCause::Canary));
+
+    assert!(!detector.is_locked_down(1000, CMD));
+    assert!((1..=MAX_LOCKDOWNS as u32).all(|process_id| detector.is_locked_down(process_id, CMD)));
+    assert!(matches!(
+        detector.take_dropped(),
+        Some(Dropped::Refused(Lockdown {
+            process_id: 1000,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn mass_delete_lockdowns_passed_on_are_not_released() {
+    let mut detector = Detector::new(SETTINGS);
+    for process_id in 1..=MAX_LOCKDOWNS as u32 {
+        detector.lock_down(Lockdown {
+            parent_id: 7,
+            ..lockdown(process_id, LockdownCause::MassDelete)
+        });
+    }
+
+    detector.lock_down(lockdown(1000, LockdownCause::Canary));
+
+    assert!(!detector.is_locked_down(1000, CMD));
+    assert!(matches!(detector.take_dropped(), Some(Dropped::Refused(_))));
+}
+
+#[test]
+fn dropped_lockdown_raises_an_event() {
+    let engine = engine_detecting();
+    engine
+        .with_engine(|engine| {
+            for process_id in 1..=MAX_LOCKDOWNS as u32 {
+                engine
+                    .detector_mut()
+                    .lock_down(lockdown(process_id, LockdownCause::MassDelete));
+            }
+            engine.demote(1000, CMD, 3, START);
+        })
+        .unwrap();
+
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    let (event, _) = EventRecord::decode(&buffer[..len]).unwrap();
+    assert_eq!(event.kind, EventKind::LockdownDropped as u16);
+    assert_eq!(event.process_id, 1);
+    assert_eq!(
+        event.detail,
+        utf16("mass-delete lockdown released").as_slice()
+    );
+    assert!(engine
+        .with_engine(|engine| engine.detector().is_locked_down(1000, CMD))
+        .unwrap());
+}
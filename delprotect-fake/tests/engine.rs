@@ -0,0 +1,371 @@
+use common::{
+    auth::{response, CHALLENGE_SIZE},
+    event::{EventKind, EventRecord},
+    ioctl_codes::{
+        IOCTL_DELPROTECT_ADD_EXE_UTF16, IOCTL_DELPROTECT_ADD_EXE_UTF8,
+        IOCTL_DELPROTECT_AUTHENTICATE, IOCTL_DELPROTECT_CLEAR, IOCTL_DELPROTECT_GET_CHALLENGE,
+        IOCTL_DELPROTECT_GET_EVENTS, IOCTL_DELPROTECT_GET_OPTIONS, IOCTL_DELPROTECT_LIST_RULES,
+        IOCTL_DELPROTECT_REMOVE_EXE_UTF8, IOCTL_DELPROTECT_SET_OPTIONS,
+    },
+    options::{OptionsUpdate, OPTION_DENY_MANUAL_DETACH, OPTION_GUARD_TRUNCATION},
+    rule::LIST_FLAG_PURGE_EXPIRED,
+    schedule::TimeWindow,
+    status::{
+        STATUS_ACCESS_DENIED, STATUS_BUFFER_TOO_SMALL, STATUS_FLT_DO_NOT_DETACH,
+        STATUS_INVALID_DEVICE_REQUEST, STATUS_INVALID_PARAMETER,
+    },
+    wire::{ListHeader, LIST_HEADER_SIZE},
+};
+use delprotect_core::{
+    filter::EngineLock,
+    ioctl::{Caller, Persist},
+    Config, Decision,
+};
+use delprotect_fake::{
+    fixtures::{ioctl, ioctl_on, privileged, utf16, CMD, EXPLORER},
+    FakeEngine, FakePlatform,
+};
+
+const SECRET: &[u8] = b"0123456789abcdef";
+
+fn decide(engine: &FakeEngine, image_name: &str) -> Decision {
+    engine
+        .with_engine(|engine| engine.check_delete(image_name, &[], 0))
+        .unwrap()
+}
+
+fn rule_count(engine: &FakeEngine) -> usize {
+    engine.with_engine(|engine| engine.rules().len()).unwrap()
+}
+
+#[test]
+fn engine_without_rules_allows() {
+    let engine = FakeEngine::default();
+
+    assert_eq!(decide(&engine, CMD), Decision::Allow);
+}
+
+#[test]
+fn executable_names_are_added_in_both_encodings() {
+    let engine = FakeEngine::default();
+
+    ioctl(
+        &engine,
+        IOCTL_DELPROTECT_ADD_EXE_UTF8,
+        b"cmd.exe\0",
+        0,
+        &privileged(),
+    )
+    .unwrap();
+    let mut name = utf16("explorer.exe");
+    name.extend_from_slice(&[0, 0]);
+    ioctl(
+        &engine,
+        IOCTL_DELPROTECT_ADD_EXE_UTF16,
+        &name,
+        0,
+        &privileged(),
+    )
+    .unwrap();
+
+    assert_eq!(decide(&engine, CMD), Decision::Deny { rule_id: 1 });
+    assert_eq!(decide(&engine, EXPLORER), Decision::Deny { rule_id: 2 });
+}
+
+#[test]
+fn malformed_executable_names_are_rejected() {
+    let engine = FakeEngine::default();
+
+    for (code, input) in [
+        (IOCTL_DELPROTECT_ADD_EXE_UTF8, &b""[..]),
+        (IOCTL_DELPROTECT_ADD_EXE_UTF8, &b"\0\0"[..]),
+        (IOCTL_DELPROTECT_ADD_EXE_UTF8, &[0xff, 0xfe][..]),
+        (IOCTL_DELPROTECT_ADD_EXE_UTF16, &b"c\0m"[..]),
+        // an unpaired surrogate
+        (IOCTL_DELPROTECT_ADD_EXE_UTF16, &[0x00, 0xd8][..]),
+    ] {
+        assert_eq!(
+            ioctl(&engine, code, input, 0, &privileged()).map(|_| ()),
+            Err(STATUS_INVALID_PARAMETER)
+        );
+    }
+    assert_eq!(rule_count(&engine), 0);
+}
+
+#[test]
+fn changes_need_a_privileged_caller() {
+    let engine = FakeEngine::default();
+
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_ADD_EXE_UTF8,
+            b"cmd.exe",
+            0,
+            &Caller::default()
+        )
+        .map(|_| ()),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    assert_eq!(rule_count(&engine), 0);
+
+    // reading is open to any caller
+    let (reply, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_GET_OPTIONS,
+        &[],
+        4,
+        &Caller::default(),
+    )
+    .unwrap();
+    assert_eq!(reply.written, 4);
+    assert_eq!(output, 0u32.to_le_bytes());
+}
+
+#[test]
+fn locked_engine_needs_an_authorized_handle() {
+    let engine = FakeEngine::new(Config {
+        secret: Some(SECRET.to_vec()),
+        ..Config::default()
+    });
+    let handle = Caller {
+        file_object: 7,
+        ..privileged()
+    };
+
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_ADD_EXE_UTF8,
+            b"cmd.exe",
+            0,
+            &handle
+        )
+        .map(|_| ()),
+        Err(STATUS_ACCESS_DENIED)
+    );
+
+    let platform = FakePlatform::new().random(0x5a);
+    ioctl_on(
+        &engine,
+        &platform,
+        IOCTL_DELPROTECT_GET_CHALLENGE,
+        &[],
+        64,
+        &handle,
+    )
+    .unwrap();
+    let answer = response(SECRET, &[0x5a; CHALLENGE_SIZE]);
+    ioctl(&engine, IOCTL_DELPROTECT_AUTHENTICATE, &answer, 0, &handle).unwrap();
+
+    ioctl(
+        &engine,
+        IOCTL_DELPROTECT_ADD_EXE_UTF8,
+        b"cmd.exe",
+        0,
+        &handle,
+    )
+    .unwrap();
+    assert_eq!(rule_count(&engine), 1);
+    // another handle of the same process is not authorized
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_CLEAR,
+            &[],
+            0,
+            &Caller {
+                file_object: 8,
+                ..privileged()
+            }
+        )
+        .map(|_| ()),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    assert_eq!(rule_count(&engine), 1);
+}
+
+#[test]
+fn clear_removes_every_rule() {
+    let engine = FakeEngine::default();
+    for name in [&b"cmd.exe"[..], b"explorer.exe"] {
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_ADD_EXE_UTF8,
+            name,
+            0,
+            &privileged(),
+        )
+        .unwrap();
+    }
+
+    ioctl(&engine, IOCTL_DELPROTECT_CLEAR, &[], 0, &privileged()).unwrap();
+
+    assert_eq!(rule_count(&engine), 0);
+    assert_eq!(decide(&engine, CMD), Decision::Allow);
+}
+
+#[test]
+fn options_are_applied_under_the_mask_and_persisted() {
+    let engine = FakeEngine::new(Config {
+        options: OPTION_DENY_MANUAL_DETACH,
+        ..Config::default()
+    });
+    let update = OptionsUpdate {
+        mask: OPTION_GUARD_TRUNCATION,
+        values: OPTION_GUARD_TRUNCATION | OPTION_DENY_MANUAL_DETACH,
+    };
+    let mut input = [0u8; 8];
+    update.encode(&mut input).unwrap();
+
+    let (reply, _) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_SET_OPTIONS,
+        &input,
+        0,
+        &privileged(),
+    )
+    .unwrap();
+
+    let options = OPTION_DENY_MANUAL_DETACH | OPTION_GUARD_TRUNCATION;
+    assert_eq!(reply.persist, Some(Persist::Options(options)));
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_GET_OPTIONS,
+        &[],
+        4,
+        &Caller::default(),
+    )
+    .unwrap();
+    assert_eq!(output, options.to_le_bytes());
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_GET_OPTIONS,
+            &[],
+            3,
+            &Caller::default()
+        )
+        .map(|_| ()),
+        Err(STATUS_BUFFER_TOO_SMALL)
+    );
+}
+
+#[test]
+fn purging_the_list_needs_a_privileged_caller() {
+    let engine = FakeEngine::default();
+    engine
+        .with_engine(|engine| {
+            engine.rules_mut().push(
+                "cmd.exe",
+                TimeWindow {
+                    not_after: 1,
+                    ..TimeWindow::default()
+                },
+            )
+        })
+        .unwrap()
+        .unwrap();
+    let purge = LIST_FLAG_PURGE_EXPIRED.to_le_bytes();
+
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_LIST_RULES,
+            &purge,
+            4096,
+            &Caller::default()
+        )
+        .map(|_| ()),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    assert_eq!(rule_count(&engine), 1);
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_LIST_RULES,
+            &[],
+            LIST_HEADER_SIZE - 1,
+            &Caller::default()
+        )
+        .map(|_| ()),
+        Err(STATUS_BUFFER_TOO_SMALL)
+    );
+
+    let platform = FakePlatform::new();
+    platform.set_now(2);
+    let (_, output) = ioctl_on(
+        &engine,
+        &platform,
+        IOCTL_DELPROTECT_LIST_RULES,
+        &purge,
+        4096,
+        &privileged(),
+    )
+    .unwrap();
+    let header = ListHeader::decode(&output).unwrap();
+    assert_eq!((header.total, header.removed), (1, 1));
+    assert_eq!(rule_count(&engine), 0);
+}
+
+#[test]
+fn events_are_drained_once() {
+    let engine = FakeEngine::new(Config {
+        options: OPTION_DENY_MANUAL_DETACH,
+        ..Config::default()
+    });
+    let status = engine
+        .with_engine(|engine| engine.query_detach(1, 42, CMD, 5))
+        .unwrap();
+    assert_eq!(status, STATUS_FLT_DO_NOT_DETACH);
+
+    // draining is a change, other callers cannot hide the events
+    assert_eq!(
+        ioctl(
+            &engine,
+            IOCTL_DELPROTECT_GET_EVENTS,
+            &[],
+            4096,
+            &Caller::default()
+        )
+        .map(|_| ()),
+        Err(STATUS_ACCESS_DENIED)
+    );
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_GET_EVENTS,
+        &[],
+        4096,
+        &privileged(),
+    )
+    .unwrap();
+    let header = ListHeader::decode(&output).unwrap();
+    assert_eq!((header.total, header.returned), (1, 1));
+    let (event, _) = EventRecord::decode(&output[LIST_HEADER_SIZE..]).unwrap();
+    assert_eq!(event.kind, EventKind::DetachAttempt as u16);
+    assert_eq!(event.process_id, 42);
+    assert_eq!(event.time, 5);
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_GET_EVENTS,
+        &[],
+        4096,
+        &privileged(),
+    )
+    .unwrap();
+    assert_eq!(ListHeader::decode(&output).unwrap().returned, 0);
+}
+
+#[test]
+fn unknown_codes_are_invalid_requests() {
+    let engine = FakeEngine::default();
+
+    for code in [IOCTL_DELPROTECT_REMOVE_EXE_UTF8, 0x0022_2000] {
+        assert_eq!(
+            ioctl(&engine, code, b"cmd.exe", 0, &privileged()).map(|_| ()),
+            Err(STATUS_INVALID_DEVICE_REQUEST)
+        );
+    }
+}
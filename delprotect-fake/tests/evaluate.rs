@@ -0,0 +1,378 @@
+use common::{
+    canary::CanaryRecord,
+    evaluate::{
+        EvaluateDecision, EvaluateOperation, EvaluateReply, EvaluateRequest, EVALUATE_REPLY_SIZE,
+    },
+    ioctl_codes::IOCTL_DELPROTECT_EVALUATE,
+    options::{OPTION_DENY_DELETE_ACCESS, OPTION_GUARD_SECURITY, OPTION_GUARD_TRUNCATION},
+    rule::{RuleAction, RuleRecord, RULE_FLAG_IMMUTABLE},
+    schedule::{filetime_from_utc, RuleState, TimeWindow},
+    status::{STATUS_ACCESS_DENIED, STATUS_BUFFER_TOO_SMALL, STATUS_INVALID_PARAMETER},
+};
+use delprotect_core::{
+    filter::{pre_create, pre_set_disposition, EngineLock, DELETE},
+    ioctl::Caller,
+    Config, Decision, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl_on, utf16, CMD, EXPLORER, TARGET, THREAD},
+    FakeEngine, FakePlatform,
+};
+
+fn request(process: &str, time: u64) -> Vec<u8> {
+    request_at(EvaluateOperation::Delete, process, TARGET, "", time)
+}
+
+fn request_for(operation: EvaluateOperation, process: &str, target: &str, sid: &str) -> Vec<u8> {
+    request_at(operation, process, target, sid, 0)
+}
+
+fn request_at(
+    operation: EvaluateOperation,
+    process: &str,
+    target: &str,
+    sid: &str,
+    time: u64,
+) -> Vec<u8> {
+    let process = utf16(process);
+    let target = utf16(target);
+    let sid = utf16(sid);
+    let request = EvaluateRequest {
+        operation,
+        time,
+        process: &process,
+        sid: &sid,
+        target: &target,
+        ancestors: &[],
+    };
+    let mut input = vec![0u8; request.encoded_len()];
+    request.encode(&mut input).unwrap();
+    input
+}
+
+/// The decision and the id of the rule or canary for `operation` of `target` by cmd.exe.
+fn decide(
+    engine: &FakeEngine,
+    operation: EvaluateOperation,
+    target: &str,
+) -> (EvaluateDecision, u32) {
+    let input = request_for(operation, CMD, target, "");
+    let reply = evaluate(engine, &FakePlatform::new(), &input, EVALUATE_REPLY_SIZE).unwrap();
+    (reply.decision, reply.rule_id)
+}
+
+/// Rule 1 for cmd.exe with `action`, under the options.
+fn cmd_engine(options: u32, action: RuleAction) -> FakeEngine {
+    let engine = FakeEngine::new(Config {
+        options,
+        ..Config::default()
+    });
+    engine
+        .with_engine(|engine| {
+            engine.rules_mut().push_action(
+                "cmd.exe",
+                TimeWindow::default(),
+                action,
+                STATUS_ACCESS_DENIED,
+            )
+        })
+        .unwrap()
+        .unwrap();
+    engine
+}
+
+fn evaluate(
+    engine: &FakeEngine,
+    platform: &FakePlatform,
+    input: &[u8],
+    output_len: usize,
+) -> Result<EvaluateReply, i32> {
+    let (_, output) = ioctl_on(
+        engine,
+        platform,
+        IOCTL_DELPROTECT_EVALUATE,
+        input,
+        output_len,
+        &Caller::default(),
+    )?;
+    Ok(EvaluateReply::decode(&output).unwrap())
+}
+
+/// Rule 1 for explorer.exe at any time, rule 2 for cmd.exe on weekdays 08:00-18:00.
+fn office_hours_engine() -> FakeEngine {
+    let engine = FakeEngine::default();
+    let mut window = TimeWindow::default();
+    window.schedule.days = 0x3e;
+    window.schedule.start_minute = 8 * 60;
+    window.schedule.end_minute = 18 * 60;
+    engine
+        .with_engine(|engine| {
+            engine
+                .rules_mut()
+                .push("explorer.exe", TimeWindow::default())?;
+            engine.rules_mut().push("cmd.exe", window)
+        })
+        .unwrap()
+        .unwrap();
+    engine
+}
+
+#[test]
+fn evaluate_matches_the_pre_operation_decision() {
+    let engine = office_hours_engine();
+    let monday_noon = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let sunday_noon = filetime_from_utc(2026, 10, 18, 12, 0).unwrap();
+
+    for (process, now) in [
+        (CMD, monday_noon),
+        (CMD, sunday_noon),
+        (EXPLORER, sunday_noon),
+    ] {
+        let platform = FakePlatform::new()
+            .process(Requestor::Thread(THREAD), 42, process)
+            .file_name(TARGET);
+        platform.set_now(now);
+
+        let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+        let reply = evaluate(&engine, &platform, &request(process, now), 64).unwrap();
+
+        assert_eq!(
+            reply.decision == EvaluateDecision::Deny,
+            verdict.pre_op == PreOp::Complete(STATUS_ACCESS_DENIED)
+        );
+        match verdict.decision {
+            Some(Decision::Deny { rule_id }) => {
+                assert_eq!(reply.rule_id, rule_id)
+            },
+            _ => assert_eq!(reply.rule_id, 0),
+        }
+        assert_eq!(reply.time, now);
+    }
+}
+
+#[test]
+fn evaluate_without_a_time_uses_the_clock() {
+    let engine = office_hours_engine();
+    let platform = FakePlatform::new();
+    let monday_noon = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    platform.set_now(monday_noon);
+
+    let reply = evaluate(&engine, &platform, &request(CMD, 0), EVALUATE_REPLY_SIZE).unwrap();
+
+    assert_eq!(
+        reply,
+        EvaluateReply {
+            decision: EvaluateDecision::Deny,
+            trace_count: 0,
+            rule_id: 2,
+            time: monday_noon,
+        }
+    );
+}
+
+#[test]
+fn evaluate_does_not_queue_events() {
+    let engine = office_hours_engine();
+    let platform = FakePlatform::new();
+
+    evaluate(&engine, &platform, &request(EXPLORER, 1), 64).unwrap();
+
+    assert!(engine
+        .with_engine(|engine| engine.events().is_empty())
+        .unwrap());
+}
+
+#[test]
+fn evaluate_rejects_malformed_requests() {
+    let engine = office_hours_engine();
+    let platform = FakePlatform::new();
+
+    let mut unknown_operation = request(CMD, 0);
+    unknown_operation[0] = 9;
+    assert_eq!(
+        evaluate(&engine, &platform, &unknown_operation, 64),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+
+    let truncated = request(CMD, 0);
+    assert_eq!(
+        evaluate(&engine, &platform, &truncated[..truncated.len() - 2], 64),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+
+    assert_eq!(
+        evaluate(
+            &engine,
+            &platform,
+            &request(CMD, 0),
+            EVALUATE_REPLY_SIZE - 1
+        ),
+        Err(STATUS_BUFFER_TOO_SMALL)
+    );
+}
+
+#[test]
+fn sid_is_ignored() {
+    let engine = office_hours_engine();
+    let for_user = request_for(
+        EvaluateOperation::Delete,
+        CMD,
+        TARGET,
+        "S-1-5-21-1004336348-1177238915-682003330-1001",
+    );
+    let for_anyone = request_for(EvaluateOperation::Delete, CMD, TARGET, "");
+
+    assert_eq!(
+        evaluate(&engine, &FakePlatform::new(), &for_user, 64),
+        evaluate(&engine, &FakePlatform::new(), &for_anyone, 64)
+    );
+}
+
+#[test]
+fn canaries_deny_what_they_watch_without_being_tripped() {
+    let engine = FakeEngine::new(Config {
+        options: OPTION_GUARD_TRUNCATION,
+        ..Config::default()
+    });
+    let path = utf16(TARGET);
+    let record = CanaryRecord {
+        id: 0,
+        flags: 0,
+        trips: 0,
+        last_trip: 0,
+        path: &path,
+    };
+    let id = engine
+        .with_engine(|engine| engine.canaries_mut().add_record(&record))
+        .unwrap()
+        .unwrap();
+
+    for operation in [
+        EvaluateOperation::Delete,
+        EvaluateOperation::Truncate,
+        EvaluateOperation::Rename,
+        EvaluateOperation::Overwrite,
+    ] {
+        assert_eq!(
+            decide(&engine, operation, TARGET),
+            (EvaluateDecision::Canary, id),
+            "{}",
+            operation.as_str()
+        );
+        assert_eq!(
+            decide(&engine, operation, r"\Device\HarddiskVolume3\Data\y.txt"),
+            (EvaluateDecision::Allow, 0)
+        );
+    }
+    for operation in [EvaluateOperation::Write, EvaluateOperation::OpenForDelete] {
+        assert_eq!(
+            decide(&engine, operation, TARGET),
+            (EvaluateDecision::Allow, 0)
+        );
+    }
+    engine
+        .with_engine(|engine| {
+            assert_eq!(engine.canaries().find(TARGET).unwrap().trips, 0);
+            assert!(engine.detector().lockdowns().is_empty());
+        })
+        .unwrap();
+}
+
+#[test]
+fn operations_are_only_decided_with_their_option() {
+    for (operation, option) in [
+        (EvaluateOperation::OpenForDelete, OPTION_DENY_DELETE_ACCESS),
+        (EvaluateOperation::Truncate, OPTION_GUARD_TRUNCATION),
+        (EvaluateOperation::SetSecurity, OPTION_GUARD_SECURITY),
+    ] {
+        let without = cmd_engine(0, RuleAction::Deny);
+        let with = cmd_engine(option, RuleAction::Deny);
+
+        assert_eq!(
+            decide(&without, operation, TARGET),
+            (EvaluateDecision::Allow, 0)
+        );
+        assert_eq!(
+            decide(&with, operation, TARGET),
+            (EvaluateDecision::Deny, 1)
+        );
+    }
+    // the rules do not decide renames and overwrites
+    let engine = cmd_engine(0, RuleAction::Deny);
+    for operation in [EvaluateOperation::Rename, EvaluateOperation::Overwrite] {
+        assert_eq!(
+            decide(&engine, operation, TARGET),
+            (EvaluateDecision::Allow, 0)
+        );
+    }
+}
+
+#[test]
+fn open_for_delete_matches_the_pre_operation_decision() {
+    for options in [0, OPTION_DENY_DELETE_ACCESS] {
+        let engine = cmd_engine(options, RuleAction::Deny);
+        let platform = FakePlatform::new()
+            .process(Requestor::Current, 42, CMD)
+            .file_name(TARGET);
+
+        let verdict = pre_create(&platform.platform(), &engine, false, 1 << 24, DELETE);
+        let (decision, _) = decide(&engine, EvaluateOperation::OpenForDelete, TARGET);
+
+        assert_eq!(
+            decision == EvaluateDecision::Deny,
+            verdict.pre_op == PreOp::Complete(STATUS_ACCESS_DENIED)
+        );
+    }
+}
+
+#[test]
+fn preserving_rule_only_preserves_what_loses_content() {
+    let options = OPTION_DENY_DELETE_ACCESS | OPTION_GUARD_TRUNCATION | OPTION_GUARD_SECURITY;
+    let engine = cmd_engine(options, RuleAction::Preserve);
+
+    for (operation, decision) in [
+        (EvaluateOperation::Delete, EvaluateDecision::Preserve),
+        (EvaluateOperation::Truncate, EvaluateDecision::Preserve),
+        (EvaluateOperation::OpenForDelete, EvaluateDecision::Allow),
+        (EvaluateOperation::SetSecurity, EvaluateDecision::Allow),
+    ] {
+        assert_eq!(
+            decide(&engine, operation, TARGET).0,
+            decision,
+            "{}",
+            operation.as_str()
+        );
+    }
+}
+
+#[test]
+fn writes_are_decided_by_the_immutable_rules_covering_the_target() {
+    let engine = FakeEngine::default();
+    let process = utf16("cmd.exe");
+    let path = utf16(r"\Device\HarddiskVolume3\Data");
+    let record = RuleRecord {
+        id: 0,
+        state: RuleState::Active,
+        action: RuleAction::Deny,
+        flags: RULE_FLAG_IMMUTABLE,
+        window: TimeWindow::default(),
+        failure_status: STATUS_ACCESS_DENIED,
+        process: &process,
+        path: &path,
+    };
+    engine
+        .with_engine(|engine| engine.rules_mut().add_record(&record))
+        .unwrap()
+        .unwrap();
+
+    for operation in [EvaluateOperation::Write, EvaluateOperation::Overwrite] {
+        assert_eq!(
+            decide(&engine, operation, TARGET),
+            (EvaluateDecision::Deny, 1)
+        );
+        assert_eq!(
+            decide(&engine, operation, r"\Device\HarddiskVolume3\Users\y.txt"),
+            (EvaluateDecision::Allow, 0)
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use common::{
+    evaluate::{
+        EvaluateDecision, EvaluateOperation, EvaluateReply, EvaluateRequest, EVALUATE_REPLY_SIZE,
+    },
+    event::{EventKind, EventRecord},
+    explain::{
+        RuleOutcome, RuleTrace, RuleTraces, PREDICATE_ALL, PREDICATE_NOT_AFTER,
+        PREDICATE_NOT_BEFORE, PREDICATE_PROCESS, PREDICATE_SCHEDULE,
+    },
+    ioctl_codes::IOCTL_DELPROTECT_EVALUATE,
+    schedule::{filetime_from_utc, TimeWindow},
+    status::STATUS_ACCESS_DENIED,
+};
+use delprotect_core::{
+    filter::{pre_set_disposition, EngineLock},
+    ioctl::Caller,
+    Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl, utf16, CMD, TARGET, THREAD},
+    FakeEngine, FakePlatform,
+};
+
+/// Rule 1 for explorer.exe, rule 2 for cmd.exe which expired, rules 3 and 4 for cmd.exe and
+/// System32 which both block cmd.exe at any time.
+fn overlapping_engine(now: u64) -> FakeEngine {
+    let engine = FakeEngine::default();
+    let expired = TimeWindow {
+        not_after: now - 1,
+        ..TimeWindow::default()
+    };
+    engine
+        .with_engine(|engine| {
+            let rules = engine.rules_mut();
+            rules.push("explorer.exe", TimeWindow::default())?;
+            rules.push("cmd.exe", expired)?;
+            rules.push("cmd.exe", TimeWindow::default())?;
+            rules.push(r"\System32\", TimeWindow::default())
+        })
+        .unwrap()
+        .unwrap();
+    engine
+}
+
+fn traces(bytes: &[u8]) -> Vec<RuleTrace<'_>> {
+    RuleTraces::new(bytes).map(Result::unwrap).collect()
+}
+
+fn name(trace: &RuleTrace) -> String {
+    String::from_utf16_lossy(
+        &trace
+            .name
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[test]
+fn denied_delete_is_audited_with_the_rules_naming_the_process() {
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = overlapping_engine(now);
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .file_name(TARGET);
+    platform.set_now(now);
+
+    pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().next().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    let (event, _) = EventRecord::decode(&buffer[..len]).unwrap();
+    assert_eq!(event.kind, EventKind::DeleteDenied as u16);
+    assert_eq!(event.process_id, 42);
+    assert_eq!(event.rule_id, 3);
+    assert_eq!(event.status, STATUS_ACCESS_DENIED);
+    assert_eq!(event.target, utf16(TARGET));
+
+    let traces = traces(event.trace);
+    let summary: Vec<(u32, u16, RuleOutcome)> = traces
+        .iter()
+        .map(|t| (t.rule_id, t.step, t.outcome))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (2, 2, RuleOutcome::NoMatch),
+            (3, 3, RuleOutcome::Decided),
+            (4, 4, RuleOutcome::Shadowed),
+        ]
+    );
+    assert_eq!(traces[0].predicates, PREDICATE_ALL & !PREDICATE_NOT_AFTER);
+    assert_eq!(name(&traces[2]), r"\System32\");
+}
+
+#[test]
+fn allowed_delete_is_not_audited() {
+    let engine = overlapping_engine(filetime_from_utc(2026, 10, 19, 12, 0).unwrap());
+    let platform = FakePlatform::new()
+        .process(
+            Requestor::Thread(THREAD),
+            42,
+            r"\Device\HarddiskVolume3\Tools\notepad.exe",
+        )
+        .file_name(TARGET);
+
+    pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    assert!(engine
+        .with_engine(|engine| engine.events().is_empty())
+        .unwrap());
+}
+
+#[test]
+fn evaluate_traces_every_rule_that_fits() {
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = overlapping_engine(now);
+    let process = utf16(CMD);
+    let target = utf16(TARGET);
+    let request = EvaluateRequest {
+        operation: EvaluateOperation::Delete,
+        time: now,
+        process: &process,
+        sid: &[],
+        target: &target,
+        ancestors: &[],
+    };
+    let mut input = vec![0u8; request.encoded_len()];
+    request.encode(&mut input).unwrap();
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_EVALUATE,
+        &input,
+        1024,
+        &Caller::default(),
+    )
+    .unwrap();
+    let evaluated = EvaluateReply::decode(&output).unwrap();
+
+    assert_eq!(evaluated.decision, EvaluateDecision::Deny);
+    assert_eq!(evaluated.rule_id, 3);
+    assert_eq!(evaluated.trace_count, 4);
+    let traces = traces(&output[EVALUATE_REPLY_SIZE..]);
+    assert_eq!(traces.len(), 4);
+    assert_eq!(name(&traces[0]), "explorer.exe");
+    assert_eq!(
+        traces[0].predicates,
+        PREDICATE_NOT_BEFORE | PREDICATE_NOT_AFTER | PREDICATE_SCHEDULE
+    );
+    assert_eq!(traces[0].outcome, RuleOutcome::NoMatch);
+    assert!(traces[1..]
+        .iter()
+        .all(|t| t.predicates & PREDICATE_PROCESS != 0));
+}
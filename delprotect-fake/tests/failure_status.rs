@@ -0,0 +1,149 @@
+use common::{
+    event::{EventKind, EventRecord},
+    ioctl_codes::{IOCTL_DELPROTECT_ADD_RULE, IOCTL_DELPROTECT_LIST_RULES},
+    rule::{failure_status_name, parse_failure_status, RuleAction, RuleRecord, FAILURE_STATUSES},
+    schedule::{RuleState, TimeWindow},
+    status::{
+        NtStatus, STATUS_ACCESS_DENIED, STATUS_CANNOT_DELETE, STATUS_INVALID_PARAMETER,
+        STATUS_MEDIA_WRITE_PROTECTED, STATUS_SHARING_VIOLATION, STATUS_SUCCESS,
+    },
+    wire::LIST_HEADER_SIZE,
+};
+use delprotect_core::{
+    filter::{pre_set_disposition, EngineLock},
+    ioctl::Caller,
+    PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl, privileged, utf16, CMD, TARGET, THREAD},
+    FakeEngine, FakePlatform,
+};
+
+/// STATUS_PENDING, which would leave the I/O manager waiting for a completion.
+const STATUS_PENDING: NtStatus = 0x0000_0103;
+
+fn add_rule(engine: &FakeEngine, action: RuleAction, failure_status: NtStatus) -> Result<(), i32> {
+    let process = utf16("cmd.exe");
+    let record = RuleRecord {
+        id: 0,
+        state: RuleState::Active,
+        action,
+        flags: 0,
+        window: TimeWindow::default(),
+        failure_status,
+        process: &process,
+        path: &[],
+    };
+    let mut input = vec![0u8; record.encoded_len()];
+    record.encode(&mut input).unwrap();
+
+    ioctl(engine, IOCTL_DELPROTECT_ADD_RULE, &input, 0, &privileged()).map(|_| ())
+}
+
+fn cmd() -> FakePlatform {
+    FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .file_name(TARGET)
+}
+
+fn last_event_status(engine: &FakeEngine) -> NtStatus {
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    let (event, _) = EventRecord::decode(&buffer[..len]).unwrap();
+    assert_eq!(event.kind, EventKind::DeleteDenied as u16);
+    event.status
+}
+
+#[test]
+fn denied_delete_fails_with_the_status_of_the_rule() {
+    for status in [
+        STATUS_ACCESS_DENIED,
+        STATUS_CANNOT_DELETE,
+        STATUS_SHARING_VIOLATION,
+        STATUS_MEDIA_WRITE_PROTECTED,
+    ] {
+        let engine = FakeEngine::default();
+        add_rule(&engine, RuleAction::Deny, status).unwrap();
+
+        let verdict = pre_set_disposition(&cmd().platform(), &engine, THREAD, true);
+
+        assert_eq!(verdict.pre_op, PreOp::Complete(status));
+        assert_eq!(last_event_status(&engine), status);
+    }
+}
+
+#[test]
+fn unlisted_status_is_rejected() {
+    let engine = FakeEngine::default();
+    for status in [STATUS_SUCCESS, STATUS_PENDING, STATUS_INVALID_PARAMETER] {
+        assert_eq!(
+            add_rule(&engine, RuleAction::Deny, status),
+            Err(STATUS_INVALID_PARAMETER)
+        );
+    }
+    let pushed = engine
+        .with_engine(|engine| {
+            engine.rules_mut().push_action(
+                "cmd.exe",
+                TimeWindow::default(),
+                RuleAction::Deny,
+                STATUS_PENDING,
+            )
+        })
+        .unwrap();
+    assert_eq!(pushed, Err(STATUS_INVALID_PARAMETER));
+    let count = engine.with_engine(|engine| engine.rules().len()).unwrap();
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn failure_status_is_listed() {
+    let engine = FakeEngine::default();
+    add_rule(&engine, RuleAction::Deny, STATUS_CANNOT_DELETE).unwrap();
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_LIST_RULES,
+        &[0; 4],
+        4096,
+        &Caller::default(),
+    )
+    .unwrap();
+    let (record, _) = RuleRecord::decode(&output[LIST_HEADER_SIZE..]).unwrap();
+    assert_eq!(record.failure_status, STATUS_CANNOT_DELETE);
+}
+
+#[test]
+fn preserve_which_cannot_copy_fails_with_the_status_of_the_rule() {
+    let engine = FakeEngine::default();
+    add_rule(&engine, RuleAction::Preserve, STATUS_CANNOT_DELETE).unwrap();
+    let platform = cmd().failing_copy(STATUS_SHARING_VIOLATION);
+
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_CANNOT_DELETE));
+    assert_eq!(last_event_status(&engine), STATUS_CANNOT_DELETE);
+}
+
+#[test]
+fn failure_statuses_parse_by_name_and_value() {
+    for (status, name) in FAILURE_STATUSES {
+        assert_eq!(parse_failure_status(name), Some(status));
+        assert_eq!(
+            parse_failure_status(&format!("0x{:08X}", status as u32)),
+            Some(status)
+        );
+        assert_eq!(failure_status_name(status), Some(name));
+    }
+    assert_eq!(
+        parse_failure_status("0xC0000121"),
+        Some(STATUS_CANNOT_DELETE)
+    );
+    assert_eq!(parse_failure_status("0x00000103"), None);
+    assert_eq!(parse_failure_status("pending"), None);
+    assert_eq!(parse_failure_status("3221225506"), None);
+    assert_eq!(failure_status_name(STATUS_PENDING), None);
+}
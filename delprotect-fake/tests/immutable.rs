@@ -0,0 +1,453 @@
+use common::{
+    event::{EventKind, EventRecord},
+    ioctl_codes::{IOCTL_DELPROTECT_ADD_RULE, IOCTL_DELPROTECT_CLEAR, IOCTL_DELPROTECT_LIST_RULES},
+    rule::{RuleAction, RuleRecord, RULE_FLAG_IMMUTABLE},
+    schedule::{RuleState, TimeWindow},
+    stats::Counter,
+    status::{
+        NtStatus, STATUS_ACCESS_DENIED, STATUS_INVALID_PARAMETER, STATUS_MEDIA_WRITE_PROTECTED,
+    },
+    wire::LIST_HEADER_SIZE,
+};
+use delprotect_core::{
+    filter::{
+        pre_acquire_section, pre_create, pre_set_disposition, pre_write, EngineLock,
+        FILE_APPEND_DATA, FILE_OVERWRITE_IF, FILE_WRITE_DATA, MAXIMUM_ALLOWED, PAGE_READWRITE,
+    },
+    ioctl::Caller,
+    Decision, Host, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl, privileged, utf16, CMD, EXPLORER, THREAD},
+    Call, FakeEngine, FakePlatform,
+};
+
+const TARGET: &str = r"\Device\HarddiskVolume3\Release\setup.exe";
+/// The directory the immutable rules protect, TARGET is below it.
+const RELEASE: &str = r"\Device\HarddiskVolume3\Release";
+/// CreateDisposition of wdm.h of an open of an existing file, in the high byte of the options.
+const OPEN_EXISTING: u32 = 1 << 24;
+/// FILE_GENERIC_READ
+const READ_OPEN: u32 = 0x0012_0089;
+/// PAGE_READONLY, PAGE_WRITECOPY, PAGE_EXECUTE_READ
+const NOT_WRITTEN_BACK: [u32; 3] = [0x02, 0x08, 0x20];
+
+fn try_add_rule(
+    engine: &FakeEngine,
+    action: RuleAction,
+    flags: u16,
+    failure_status: NtStatus,
+    path: &str,
+) -> Result<(), NtStatus> {
+    let process = utf16("cmd.exe");
+    let path = utf16(path);
+    let record = RuleRecord {
+        id: 0,
+        state: RuleState::Active,
+        action,
+        flags,
+        window: TimeWindow::default(),
+        failure_status,
+        process: &process,
+        path: &path,
+    };
+    let mut input = vec![0u8; record.encoded_len()];
+    record.encode(&mut input).unwrap();
+
+    ioctl(engine, IOCTL_DELPROTECT_ADD_RULE, &input, 0, &privileged()).map(|_| ())
+}
+
+fn add_rule(engine: &FakeEngine, action: RuleAction, flags: u16, failure_status: NtStatus) {
+    let path = if flags & RULE_FLAG_IMMUTABLE != 0 {
+        RELEASE
+    } else {
+        ""
+    };
+    try_add_rule(engine, action, flags, failure_status, path).unwrap();
+}
+
+/// An engine with an immutable rule for cmd.exe under `path`.
+fn immutable_under(action: RuleAction, path: &str) -> FakeEngine {
+    let engine = FakeEngine::default();
+    try_add_rule(
+        &engine,
+        action,
+        RULE_FLAG_IMMUTABLE,
+        STATUS_MEDIA_WRITE_PROTECTED,
+        path,
+    )
+    .unwrap();
+    engine
+}
+
+fn immutable(action: RuleAction) -> FakeEngine {
+    immutable_under(action, RELEASE)
+}
+
+fn by(image_name: &str) -> FakePlatform {
+    FakePlatform::new()
+        .process(Requestor::Current, 42, image_name)
+        .process(Requestor::Thread(THREAD), 42, image_name)
+        .file_name(TARGET)
+}
+
+fn last_event(engine: &FakeEngine) -> Vec<u8> {
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    buffer.truncate(len);
+    buffer
+}
+
+fn assert_write_denied(engine: &FakeEngine, detail: &str) {
+    let buffer = last_event(engine);
+    let (event, _) = EventRecord::decode(&buffer).unwrap();
+    assert_eq!(event.kind, EventKind::WriteDenied as u16);
+    assert_eq!(event.status, STATUS_MEDIA_WRITE_PROTECTED);
+    assert_eq!(event.target, utf16(TARGET).as_slice());
+    assert_eq!(event.detail, utf16(detail).as_slice());
+    assert_eq!(event.rule_id, 1);
+    assert!(event.trace.is_empty());
+}
+
+#[test]
+fn writes_are_only_decided_with_an_immutable_rule() {
+    let engine = FakeEngine::default();
+    add_rule(&engine, RuleAction::Deny, 0, STATUS_ACCESS_DENIED);
+    let platform = by(CMD);
+
+    let write = pre_write(&platform.platform(), &engine, false, false, THREAD);
+    let open = pre_create(&platform.platform(), &engine, false, 0, FILE_WRITE_DATA);
+    let section = pre_acquire_section(&platform.platform(), &engine, false, true, PAGE_READWRITE);
+
+    for verdict in [write, open, section] {
+        assert_eq!(verdict.pre_op, PreOp::PassThrough);
+        assert_eq!(verdict.decision, None);
+    }
+    // the write open queries the name for the vault check, nothing identifies the process
+    assert!(!platform
+        .calls()
+        .iter()
+        .any(|call| matches!(call, Call::ProcessId(_) | Call::ImageName(_))));
+    assert_eq!(platform.stats().get(Counter::Writes), 0);
+}
+
+#[test]
+fn write_by_immutable_process_is_denied() {
+    let engine = immutable(RuleAction::Deny);
+    let platform = by(CMD);
+
+    let verdict = pre_write(&platform.platform(), &engine, false, false, THREAD);
+
+    assert_eq!(
+        verdict.pre_op,
+        PreOp::Complete(STATUS_MEDIA_WRITE_PROTECTED)
+    );
+    assert_eq!(verdict.decision, Some(Decision::Deny { rule_id: 1 }));
+    assert_eq!(platform.stats().get(Counter::Writes), 1);
+    assert_eq!(platform.stats().get(Counter::Blocked), 1);
+    assert_write_denied(&engine, "write");
+}
+
+#[test]
+fn paging_and_kernel_mode_writes_go_on() {
+    let engine = immutable(RuleAction::Deny);
+    let platform = by(CMD);
+
+    for (kernel_mode, paging_io) in [(false, true), (true, false)] {
+        let verdict = pre_write(
+            &platform.platform(),
+            &engine,
+            kernel_mode,
+            paging_io,
+            THREAD,
+        );
+        assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    }
+    assert!(platform.calls().is_empty());
+}
+
+#[test]
+fn opens_for_write_are_denied_and_reads_go_on() {
+    let engine = immutable(RuleAction::Deny);
+
+    // MAXIMUM_ALLOWED is left to the writes through the handle
+    for desired_access in [READ_OPEN, MAXIMUM_ALLOWED] {
+        let platform = by(CMD);
+        let read = pre_create(
+            &platform.platform(),
+            &engine,
+            false,
+            OPEN_EXISTING,
+            desired_access,
+        );
+        assert_eq!(read.pre_op, PreOp::PassThrough);
+        assert!(platform.calls().is_empty());
+    }
+
+    for (create_options, desired_access) in [
+        (OPEN_EXISTING, FILE_WRITE_DATA),
+        (OPEN_EXISTING, FILE_APPEND_DATA),
+        (FILE_OVERWRITE_IF << 24, READ_OPEN),
+    ] {
+        let platform = by(CMD);
+        let verdict = pre_create(
+            &platform.platform(),
+            &engine,
+            false,
+            create_options,
+            desired_access,
+        );
+        assert_eq!(
+            verdict.pre_op,
+            PreOp::Complete(STATUS_MEDIA_WRITE_PROTECTED)
+        );
+        assert_eq!(platform.stats().get(Counter::Writes), 1);
+        assert_write_denied(&engine, "open for write access");
+    }
+}
+
+#[test]
+fn only_writable_sections_being_created_are_denied() {
+    let engine = immutable(RuleAction::Deny);
+
+    let platform = by(CMD);
+    for page_protection in NOT_WRITTEN_BACK {
+        let verdict =
+            pre_acquire_section(&platform.platform(), &engine, false, true, page_protection);
+        assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    }
+    // the other synchronizations of an existing section
+    let verdict = pre_acquire_section(&platform.platform(), &engine, false, false, PAGE_READWRITE);
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+
+    let platform = by(CMD).opened_name(TARGET);
+    let verdict = pre_acquire_section(&platform.platform(), &engine, false, true, PAGE_READWRITE);
+    assert_eq!(
+        verdict.pre_op,
+        PreOp::Complete(STATUS_MEDIA_WRITE_PROTECTED)
+    );
+    assert!(!platform.called(&Call::FileName));
+    assert_write_denied(&engine, "writable section");
+}
+
+#[test]
+fn section_of_a_handle_without_a_kept_name_goes_on_unqueried() {
+    let engine = immutable(RuleAction::Deny);
+    let platform = by(CMD);
+
+    let verdict = pre_acquire_section(&platform.platform(), &engine, false, true, PAGE_READWRITE);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert!(!platform.called(&Call::FileName));
+}
+
+#[test]
+fn writes_through_a_handle_use_the_name_kept_at_open() {
+    let engine = immutable(RuleAction::Deny);
+    let platform = by(CMD).file_name(r"\Device\HarddiskVolume3\Users\notes.txt");
+
+    let open = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        OPEN_EXISTING,
+        FILE_WRITE_DATA,
+    );
+    assert_eq!(open.pre_op, PreOp::PassThrough);
+    assert_eq!(
+        platform.kept_name().as_deref(),
+        Some(r"\Device\HarddiskVolume3\Users\notes.txt")
+    );
+
+    platform.clear_calls();
+    let write = pre_write(&platform.platform(), &engine, false, false, THREAD);
+    assert_eq!(write.pre_op, PreOp::PassThrough);
+    assert_eq!(write.decision, Some(Decision::Allow));
+    assert!(!platform.called(&Call::FileName));
+
+    // a denied open keeps nothing
+    let denied = by(CMD);
+    pre_create(
+        &denied.platform(),
+        &engine,
+        false,
+        OPEN_EXISTING,
+        FILE_WRITE_DATA,
+    );
+    assert_eq!(denied.kept_name(), None);
+}
+
+#[test]
+fn write_by_other_process_goes_on_without_the_file_name() {
+    let engine = immutable(RuleAction::Deny);
+    let platform = by(EXPLORER);
+
+    let verdict = pre_write(&platform.platform(), &engine, false, false, THREAD);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(verdict.decision, None);
+    assert!(!platform.called(&Call::FileName));
+    assert_eq!(platform.stats().get(Counter::Writes), 1);
+    assert_eq!(platform.stats().get(Counter::Allowed), 0);
+}
+
+#[test]
+fn writes_are_decided_by_the_first_immutable_rule() {
+    let engine = FakeEngine::default();
+    add_rule(&engine, RuleAction::Deny, 0, STATUS_ACCESS_DENIED);
+    add_rule(
+        &engine,
+        RuleAction::Preserve,
+        RULE_FLAG_IMMUTABLE,
+        STATUS_MEDIA_WRITE_PROTECTED,
+    );
+    let platform = by(CMD);
+
+    let write = pre_write(&platform.platform(), &engine, false, false, THREAD);
+    assert_eq!(write.pre_op, PreOp::Complete(STATUS_MEDIA_WRITE_PROTECTED));
+    assert_eq!(write.decision, Some(Decision::Deny { rule_id: 2 }));
+
+    // deletes are still decided by the first rule
+    let delete = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    assert_eq!(delete.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert_eq!(delete.decision, Some(Decision::Deny { rule_id: 1 }));
+}
+
+#[test]
+fn preserving_immutable_rule_denies_writes() {
+    let engine = immutable(RuleAction::Preserve);
+    let platform = by(CMD);
+
+    let write = pre_write(&platform.platform(), &engine, false, false, THREAD);
+    assert_eq!(write.pre_op, PreOp::Complete(STATUS_MEDIA_WRITE_PROTECTED));
+    assert!(platform.vault_copies().is_empty());
+
+    let delete = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    assert_eq!(delete.decision, Some(Decision::Preserve { rule_id: 1 }));
+    assert_eq!(platform.vault_copies().len(), 1);
+}
+
+#[test]
+fn immutable_flag_is_listed() {
+    let engine = immutable(RuleAction::Deny);
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_LIST_RULES,
+        &[0; 4],
+        4096,
+        &Caller::default(),
+    )
+    .unwrap();
+    let (record, _) = RuleRecord::decode(&output[LIST_HEADER_SIZE..]).unwrap();
+    assert_eq!(record.flags, RULE_FLAG_IMMUTABLE);
+    assert_eq!(record.path, utf16(RELEASE).as_slice());
+    let is_immutable = engine
+        .with_engine(|engine| engine.rules().get(1).unwrap().is_immutable())
+        .unwrap();
+    assert!(is_immutable);
+}
+
+#[test]
+fn writes_outside_the_path_go_on() {
+    let engine = immutable(RuleAction::Deny);
+
+    for file_name in [
+        r"\Device\HarddiskVolume3\Users\notes.txt",
+        // a sibling sharing the prefix is not below the directory
+        r"\Device\HarddiskVolume3\ReleaseNotes\setup.exe",
+        r"\Device\HarddiskVolume3\Releas",
+    ] {
+        let platform = by(CMD).file_name(file_name);
+        let verdict = pre_write(&platform.platform(), &engine, false, false, THREAD);
+        assert_eq!(verdict.pre_op, PreOp::PassThrough);
+        assert_eq!(verdict.decision, Some(Decision::Allow));
+    }
+}
+
+#[test]
+fn path_covers_itself_and_everything_below_without_regard_to_case() {
+    for (path, file_name) in [
+        (RELEASE, RELEASE),
+        (RELEASE, r"\DEVICE\HARDDISKVOLUME3\release\Setup.EXE"),
+        (
+            RELEASE,
+            r"\Device\HarddiskVolume3\Release\tools\x64\sign.exe",
+        ),
+        (r"\Device\HarddiskVolume3\Release\", TARGET),
+        (TARGET, TARGET),
+    ] {
+        let engine = immutable_under(RuleAction::Deny, path);
+        let platform = by(CMD).file_name(file_name);
+        let verdict = pre_write(&platform.platform(), &engine, false, false, THREAD);
+        assert_eq!(
+            verdict.pre_op,
+            PreOp::Complete(STATUS_MEDIA_WRITE_PROTECTED),
+            "{file_name} under {path}"
+        );
+    }
+}
+
+#[test]
+fn write_of_a_file_whose_name_is_unknown_goes_on() {
+    let engine = immutable(RuleAction::Deny);
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .failing_file_name(STATUS_ACCESS_DENIED);
+
+    let verdict = pre_write(&platform.platform(), &engine, false, false, THREAD);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+}
+
+#[test]
+fn immutable_rules_need_a_path_and_only_they_have_one() {
+    let engine = FakeEngine::default();
+
+    let without_path = try_add_rule(
+        &engine,
+        RuleAction::Deny,
+        RULE_FLAG_IMMUTABLE,
+        STATUS_ACCESS_DENIED,
+        "",
+    );
+    let path_only = try_add_rule(&engine, RuleAction::Deny, 0, STATUS_ACCESS_DENIED, RELEASE);
+
+    assert_eq!(without_path, Err(STATUS_INVALID_PARAMETER));
+    assert_eq!(path_only, Err(STATUS_INVALID_PARAMETER));
+    assert!(engine
+        .with_engine(|engine| engine.rules().is_empty())
+        .unwrap());
+}
+
+#[test]
+fn immutable_rules_are_published_without_the_lock() {
+    let engine = FakeEngine::default();
+    let published = || engine.published().has_immutable_rules();
+    assert!(!published());
+
+    add_rule(&engine, RuleAction::Deny, 0, STATUS_ACCESS_DENIED);
+    assert!(!published());
+    add_rule(
+        &engine,
+        RuleAction::Deny,
+        RULE_FLAG_IMMUTABLE,
+        STATUS_ACCESS_DENIED,
+    );
+    // read while the lock is held, the flag does not take it
+    engine
+        .with_engine(|_| assert!(engine.published().has_immutable_rules()))
+        .unwrap();
+
+    ioctl(&engine, IOCTL_DELPROTECT_CLEAR, &[], 0, &privileged()).unwrap();
+    assert!(!published());
+
+    let platform = by(CMD);
+    let write = pre_write(&platform.platform(), &engine, false, false, THREAD);
+    assert_eq!(write.decision, None);
+    assert!(platform.calls().is_empty());
+}
@@ -0,0 +1,326 @@
+use common::{
+    evaluate::{
+        EvaluateDecision, EvaluateOperation, EvaluateReply, EvaluateRequest, EVALUATE_REPLY_SIZE,
+    },
+    event::{EventKind, EventRecord},
+    explain::{RuleOutcome, RuleTraces},
+    ioctl_codes::{
+        IOCTL_DELPROTECT_ADD_RULE, IOCTL_DELPROTECT_EVALUATE, IOCTL_DELPROTECT_LIST_RULES,
+    },
+    rule::{RuleAction, RuleRecord, RULE_FLAG_INHERIT},
+    schedule::{RuleState, TimeWindow, TICKS_PER_SECOND},
+    status::{STATUS_ACCESS_DENIED, STATUS_INVALID_PARAMETER},
+    wire::{ListHeader, LIST_HEADER_SIZE},
+};
+use delprotect_core::{
+    filter::{pre_set_disposition, EngineLock},
+    ioctl::Reply,
+    lineage::{Ancestor, Lineage, MAX_LINEAGE_ENTRIES, MAX_LINEAGE_PROCESSES},
+    Decision, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl_on, privileged, utf16, CMD, EXPLORER, TARGET, THREAD},
+    FakeEngine, FakePlatform,
+};
+
+const POWERSHELL: &str =
+    r"\Device\HarddiskVolume3\Windows\System32\WindowsPowerShell\v1.0\powershell.exe";
+const NOTEPAD: &str = r"\Device\HarddiskVolume3\Windows\System32\notepad.exe";
+const NOW: u64 = 1000 * TICKS_PER_SECOND;
+
+fn ioctl(
+    engine: &FakeEngine,
+    code: u32,
+    input: &[u8],
+    output_len: usize,
+) -> Result<(Reply, Vec<u8>), i32> {
+    let platform = FakePlatform::new();
+    platform.set_now(NOW);
+    ioctl_on(engine, &platform, code, input, output_len, &privileged())
+}
+
+fn add_rule(engine: &FakeEngine, process_name: &str, flags: u16) -> Result<(), i32> {
+    let process = utf16(process_name);
+    let record = RuleRecord {
+        id: 0,
+        state: RuleState::Active,
+        action: RuleAction::Deny,
+        flags,
+        window: TimeWindow::default(),
+        failure_status: STATUS_ACCESS_DENIED,
+        process: &process,
+        path: &[],
+    };
+    let mut input = vec![0u8; record.encoded_len()];
+    record.encode(&mut input).unwrap();
+    ioctl(engine, IOCTL_DELPROTECT_ADD_RULE, &input, 0).map(|_| ())
+}
+
+fn engine_blocking_cmd(flags: u16) -> FakeEngine {
+    let engine = FakeEngine::default();
+    add_rule(&engine, "cmd.exe", flags).unwrap();
+    engine
+}
+
+fn created(engine: &FakeEngine, creator_id: u32, creator: &str, process_id: u32, image: &str) {
+    engine
+        .with_engine(|engine| engine.process_created(creator_id, creator, process_id, image, NOW))
+        .unwrap();
+}
+
+fn exited(engine: &FakeEngine, process_id: u32) {
+    engine
+        .with_engine(|engine| engine.process_exited(process_id))
+        .unwrap();
+}
+
+fn delete_by(engine: &FakeEngine, process_id: u32, image: &str) -> Option<Decision> {
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), process_id, image)
+        .file_name(TARGET);
+    platform.set_now(NOW);
+    pre_set_disposition(&platform.platform(), engine, THREAD, true).decision
+}
+
+#[test]
+fn inherit_rule_blocks_the_processes_a_named_process_creates() {
+    let engine = engine_blocking_cmd(RULE_FLAG_INHERIT);
+    created(&engine, 42, CMD, 43, POWERSHELL);
+
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 43, POWERSHELL)
+        .file_name(TARGET);
+    platform.set_now(NOW);
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert_eq!(verdict.decision, Some(Decision::Deny { rule_id: 1 }));
+
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().next().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    let (event, _) = EventRecord::decode(&buffer[..len]).unwrap();
+    assert_eq!(event.kind, EventKind::DeleteDenied as u16);
+    assert_eq!(event.process_id, 43);
+    assert_eq!(
+        event.detail,
+        utf16(&format!("created by {CMD} (42)")).as_slice()
+    );
+    let traces: Vec<_> = RuleTraces::new(event.trace).map(Result::unwrap).collect();
+    assert_eq!(traces.len(), 1);
+    assert_eq!(traces[0].outcome, RuleOutcome::Decided);
+    assert_eq!(traces[0].generation, 1);
+}
+
+#[test]
+fn rule_without_inherit_blocks_only_the_named_process() {
+    let engine = engine_blocking_cmd(0);
+    created(&engine, 42, CMD, 43, POWERSHELL);
+
+    assert_eq!(delete_by(&engine, 43, POWERSHELL), Some(Decision::Allow));
+    assert_eq!(
+        delete_by(&engine, 42, CMD),
+        Some(Decision::Deny { rule_id: 1 })
+    );
+    // nothing to track without an inherit rule
+    let tracked = engine
+        .with_engine(|engine| (engine.tracks_lineage(), engine.lineage().len()))
+        .unwrap();
+    assert_eq!(tracked, (false, 0));
+}
+
+#[test]
+fn lineage_outlives_an_exited_ancestor() {
+    // cmd /c powershell: cmd.exe is gone by the time the script runs
+    let engine = engine_blocking_cmd(RULE_FLAG_INHERIT);
+    created(&engine, 42, CMD, 43, POWERSHELL);
+    exited(&engine, 42);
+    created(&engine, 43, POWERSHELL, 44, NOTEPAD);
+
+    assert_eq!(
+        delete_by(&engine, 44, NOTEPAD),
+        Some(Decision::Deny { rule_id: 1 })
+    );
+    let ancestors = engine
+        .with_engine(|engine| {
+            let ancestors = engine.lineage().ancestors(44);
+            ancestors
+                .iter()
+                .map(|ancestor| ancestor.process_id)
+                .collect::<Vec<_>>()
+        })
+        .unwrap();
+    assert_eq!(ancestors, [43, 42]);
+
+    // the whole chain goes away with its last process
+    exited(&engine, 43);
+    exited(&engine, 44);
+    let tracked = engine.with_engine(|engine| engine.lineage().len()).unwrap();
+    assert_eq!(tracked, 0);
+}
+
+#[test]
+fn reused_process_id_does_not_inherit() {
+    let engine = engine_blocking_cmd(RULE_FLAG_INHERIT);
+    created(&engine, 42, CMD, 43, POWERSHELL);
+    exited(&engine, 43);
+    created(&engine, 8, EXPLORER, 43, NOTEPAD);
+
+    assert_eq!(delete_by(&engine, 43, NOTEPAD), Some(Decision::Allow));
+}
+
+#[test]
+fn untracked_creators_start_no_lineage() {
+    let engine = engine_blocking_cmd(RULE_FLAG_INHERIT);
+    created(&engine, 8, EXPLORER, 43, POWERSHELL);
+
+    assert_eq!(delete_by(&engine, 43, POWERSHELL), Some(Decision::Allow));
+    let tracked = engine.with_engine(|engine| engine.lineage().len()).unwrap();
+    assert_eq!(tracked, 0);
+}
+
+#[test]
+fn full_lineage_starts_no_new_lineage() {
+    let mut lineage = Lineage::new();
+    for process_id in 0..MAX_LINEAGE_PROCESSES as u32 {
+        lineage.process_created(42, CMD, 100 + process_id, NOTEPAD, |_| true);
+    }
+    // cmd.exe and its processes
+    assert_eq!(lineage.len(), MAX_LINEAGE_PROCESSES + 1);
+
+    lineage.process_created(8, EXPLORER, 43, NOTEPAD, |_| true);
+
+    assert!(lineage.ancestors(43).is_empty());
+    assert_eq!(lineage.len(), MAX_LINEAGE_PROCESSES + 1);
+}
+
+#[test]
+fn full_lineage_still_tracks_the_processes_of_a_tracked_one() {
+    let mut lineage = Lineage::new();
+    for process_id in 0..MAX_LINEAGE_PROCESSES as u32 + 10 {
+        lineage.process_created(42, CMD, 100 + process_id, NOTEPAD, |_| true);
+    }
+
+    let last = 100 + MAX_LINEAGE_PROCESSES as u32 + 9;
+    assert_eq!(
+        lineage.ancestors(last),
+        [Ancestor {
+            process_id: 42,
+            image_name: CMD
+        }]
+    );
+    lineage.process_created(last, NOTEPAD, 43, POWERSHELL, |_| false);
+    assert_eq!(lineage.ancestors(43).len(), 2);
+}
+
+#[test]
+fn processes_of_a_tracked_one_are_dropped_at_the_entry_cap() {
+    let mut lineage = Lineage::new();
+    // cmd.exe and its processes fill the table
+    for process_id in 0..MAX_LINEAGE_ENTRIES as u32 + 10 {
+        lineage.process_created(42, CMD, 100 + process_id, NOTEPAD, |_| true);
+    }
+    assert_eq!(lineage.len(), MAX_LINEAGE_ENTRIES);
+
+    let dropped = 100 + MAX_LINEAGE_ENTRIES as u32;
+    assert!(lineage.ancestors(dropped).is_empty());
+
+    // room is made again by exits
+    lineage.process_exited(100);
+    lineage.process_created(42, CMD, 43, POWERSHELL, |_| false);
+    assert_eq!(lineage.ancestors(43).len(), 1);
+    assert_eq!(lineage.len(), MAX_LINEAGE_ENTRIES);
+}
+
+#[test]
+fn exited_chain_goes_away_with_its_last_descendant() {
+    let mut lineage = Lineage::new();
+    // 42 > 43 > 44 > 45 and 42 > 46
+    lineage.process_created(42, CMD, 43, CMD, |_| true);
+    lineage.process_created(43, CMD, 44, CMD, |_| false);
+    lineage.process_created(44, CMD, 45, NOTEPAD, |_| false);
+    lineage.process_created(42, CMD, 46, NOTEPAD, |_| false);
+    for process_id in [42, 43, 44] {
+        lineage.process_exited(process_id);
+    }
+    assert_eq!(lineage.len(), 5);
+    assert_eq!(lineage.ancestors(45).len(), 3);
+
+    lineage.process_exited(45);
+    // 42 stays for 46
+    assert_eq!(lineage.len(), 2);
+    assert_eq!(lineage.ancestors(46).len(), 1);
+
+    lineage.process_exited(46);
+    assert!(lineage.is_empty());
+}
+
+#[test]
+fn inherit_flag_is_listed_and_unknown_flags_are_rejected() {
+    let engine = engine_blocking_cmd(RULE_FLAG_INHERIT);
+    assert_eq!(
+        add_rule(&engine, "cmd.exe", 0x8),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+
+    let (_, output) = ioctl(&engine, IOCTL_DELPROTECT_LIST_RULES, &[0; 4], 4096).unwrap();
+    let header = ListHeader::decode(&output).unwrap();
+    assert_eq!(header.returned, 1);
+    let (record, _) = RuleRecord::decode(&output[LIST_HEADER_SIZE..]).unwrap();
+    assert_eq!(record.flags, RULE_FLAG_INHERIT);
+}
+
+#[test]
+fn evaluate_looks_at_the_ancestors_of_the_request() {
+    let engine = engine_blocking_cmd(RULE_FLAG_INHERIT);
+    let process = utf16(POWERSHELL);
+    let target = utf16(TARGET);
+    let ancestors: Vec<u8> = [EXPLORER, CMD]
+        .iter()
+        .flat_map(|name| name.encode_utf16().chain([0]))
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let request = EvaluateRequest {
+        operation: EvaluateOperation::Delete,
+        time: NOW,
+        process: &process,
+        sid: &[],
+        target: &target,
+        ancestors: &ancestors,
+    };
+    let mut input = vec![0u8; request.encoded_len()];
+    request.encode(&mut input).unwrap();
+
+    let (_, output) = ioctl(&engine, IOCTL_DELPROTECT_EVALUATE, &input, 1024).unwrap();
+    let reply = EvaluateReply::decode(&output).unwrap();
+    assert_eq!(reply.decision, EvaluateDecision::Deny);
+    assert_eq!(reply.rule_id, 1);
+    let trace = RuleTraces::new(&output[EVALUATE_REPLY_SIZE..])
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(trace.generation, 2);
+
+    // the last ancestor has to be terminated
+    input.truncate(input.len() - 2);
+    let len = (ancestors.len() - 2) as u16;
+    input[16..18].copy_from_slice(&len.to_le_bytes());
+    assert_eq!(
+        ioctl(&engine, IOCTL_DELPROTECT_EVALUATE, &input, 1024).map(|(reply, _)| reply.written),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+}
+
+#[test]
+fn inherit_rule_names_only_its_own_process_among_ancestors() {
+    let engine = engine_blocking_cmd(RULE_FLAG_INHERIT);
+    let ancestors = [Ancestor {
+        process_id: 8,
+        image_name: EXPLORER,
+    }];
+    let decision = engine
+        .with_engine(|engine| engine.check_delete(POWERSHELL, &ancestors, NOW))
+        .unwrap();
+    assert_eq!(decision, Decision::Allow);
+}
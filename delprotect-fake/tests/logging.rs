@@ -0,0 +1,108 @@
+use common::{
+    ioctl_codes::{IOCTL_DELPROTECT_GET_LOG_LEVEL, IOCTL_DELPROTECT_SET_LOG_LEVEL},
+    logging::LogLevel,
+    schedule::TICKS_PER_SECOND,
+    status::{STATUS_ACCESS_DENIED, STATUS_INVALID_PARAMETER},
+};
+use delprotect_core::{
+    ioctl::{Caller, Persist, Reply},
+    logging::TokenBucket,
+};
+use delprotect_fake::{
+    fixtures::{self, privileged},
+    FakeEngine,
+};
+
+const START: u64 = 1000 * TICKS_PER_SECOND;
+
+fn ioctl(code: u32, input: &[u8], caller: &Caller) -> Result<(Reply, Vec<u8>), i32> {
+    fixtures::ioctl(&FakeEngine::default(), code, input, 4, caller)
+}
+
+#[test]
+fn bucket_lets_a_burst_through_then_drops() {
+    let bucket = TokenBucket::new(3, 1);
+    for _ in 0..3 {
+        assert_eq!(bucket.try_take(START), Some(0));
+    }
+    assert_eq!(bucket.try_take(START), None);
+    assert_eq!(bucket.try_take(START + TICKS_PER_SECOND / 2), None);
+
+    // the next message let through reports the two dropped before it
+    assert_eq!(bucket.try_take(START + TICKS_PER_SECOND), Some(2));
+    assert_eq!(bucket.try_take(START + TICKS_PER_SECOND), None);
+}
+
+#[test]
+fn bucket_refills_up_to_the_burst() {
+    let bucket = TokenBucket::new(2, 4);
+    assert_eq!(bucket.try_take(START), Some(0));
+    assert_eq!(bucket.try_take(START), Some(0));
+
+    // an hour later only the burst is available again
+    let later = START + 3600 * TICKS_PER_SECOND;
+    assert_eq!(bucket.try_take(later), Some(0));
+    assert_eq!(bucket.try_take(later), Some(0));
+    assert_eq!(bucket.try_take(later), None);
+
+    // 4 per second, one every 250 ms
+    assert_eq!(bucket.try_take(later + TICKS_PER_SECOND / 4), Some(1));
+}
+
+#[test]
+fn bucket_survives_the_clock_going_backwards() {
+    let bucket = TokenBucket::new(1, 1);
+    assert_eq!(bucket.try_take(START), Some(0));
+    assert_eq!(bucket.try_take(START - 60 * TICKS_PER_SECOND), None);
+
+    // the refill restarts from the earlier time instead of waiting a minute
+    assert_eq!(bucket.try_take(START - 59 * TICKS_PER_SECOND), Some(1));
+}
+
+// the log max level is global, everything touching it stays in this one test
+#[test]
+fn log_level_is_set_read_and_persisted() {
+    let privileged = privileged();
+
+    let (reply, _) = ioctl(
+        IOCTL_DELPROTECT_SET_LOG_LEVEL,
+        &(LogLevel::Debug as u32).to_le_bytes(),
+        &privileged,
+    )
+    .unwrap();
+    assert_eq!(reply.persist, Some(Persist::LogLevel(LogLevel::Debug)));
+
+    let (_, output) = ioctl(IOCTL_DELPROTECT_GET_LOG_LEVEL, &[], &Caller::default()).unwrap();
+    assert_eq!(LogLevel::decode(&output), Ok(LogLevel::Debug));
+
+    assert_eq!(
+        ioctl(
+            IOCTL_DELPROTECT_SET_LOG_LEVEL,
+            &6u32.to_le_bytes(),
+            &privileged
+        )
+        .map(|(reply, _)| reply.persist),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+    assert_eq!(
+        ioctl(
+            IOCTL_DELPROTECT_SET_LOG_LEVEL,
+            &(LogLevel::Off as u32).to_le_bytes(),
+            &Caller::default()
+        )
+        .map(|(reply, _)| reply.persist),
+        Err(STATUS_ACCESS_DENIED)
+    );
+
+    let (_, output) = ioctl(IOCTL_DELPROTECT_GET_LOG_LEVEL, &[], &Caller::default()).unwrap();
+    assert_eq!(LogLevel::decode(&output), Ok(LogLevel::Debug));
+}
+
+#[test]
+fn log_level_names_round_trip() {
+    for level in LogLevel::ALL {
+        assert_eq!(LogLevel::parse(level.as_str()), Some(level));
+        assert_eq!(LogLevel::from_u32(level as u32), Some(level));
+    }
+    assert_eq!(LogLevel::parse("verbose"), None);
+}
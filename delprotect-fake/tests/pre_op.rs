@@ -0,0 +1,259 @@
+use common::{
+    schedule::TimeWindow,
+    status::{STATUS_ACCESS_DENIED, STATUS_NOT_FOUND},
+};
+use delprotect_core::{
+    filter::{pre_create, pre_set_disposition, EngineLock, FILE_DELETE_ON_CLOSE},
+    PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{CMD, EXPLORER, THREAD},
+    Call, FakeEngine, FakePlatform,
+};
+
+fn engine_blocking(process_name: &str, window: TimeWindow) -> FakeEngine {
+    let engine = FakeEngine::default();
+    engine
+        .with_engine(|engine| engine.rules_mut().push(process_name, window))
+        .unwrap()
+        .unwrap();
+    engine
+}
+
+#[test]
+fn create_from_kernel_mode_is_not_looked_at() {
+    let platform = FakePlatform::new().process(Requestor::Current, 4, CMD);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_create(&platform.platform(), &engine, true, FILE_DELETE_ON_CLOSE, 0).pre_op;
+
+    assert_eq!(pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+}
+
+#[test]
+fn create_without_delete_on_close_is_not_looked_at() {
+    let platform = FakePlatform::new().process(Requestor::Current, 4, CMD);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    // FILE_OPEN, FILE_READ_DATA
+    let pre_op = pre_create(&platform.platform(), &engine, false, 1 << 24, 0x1).pre_op;
+
+    assert_eq!(pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+}
+
+#[test]
+fn delete_on_close_by_blocked_process_is_denied() {
+    let platform = FakePlatform::new()
+        .process(Requestor::Current, 4, CMD)
+        .file_name(r"\Device\HarddiskVolume3\data\report.docx");
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    )
+    .pre_op;
+
+    assert_eq!(pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert!(platform.called(&Call::ImageName(Requestor::Current)));
+    assert!(platform.called(&Call::FileName));
+}
+
+#[test]
+fn delete_on_close_by_other_process_is_allowed_without_file_name() {
+    let platform = FakePlatform::new().process(Requestor::Current, 8, EXPLORER);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    )
+    .pre_op;
+
+    assert_eq!(pre_op, PreOp::PassThrough);
+    assert!(!platform.called(&Call::FileName));
+}
+
+#[test]
+fn disposition_is_attributed_to_the_thread() {
+    let platform = FakePlatform::new()
+        .process(Requestor::Current, 8, EXPLORER)
+        .process(Requestor::Thread(THREAD), 4, CMD);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_set_disposition(&platform.platform(), &engine, THREAD, true).pre_op;
+
+    assert_eq!(pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert!(platform.called(&Call::ImageName(Requestor::Thread(THREAD))));
+    assert!(!platform.called(&Call::ImageName(Requestor::Current)));
+}
+
+#[test]
+fn disposition_clearing_delete_is_not_looked_at() {
+    let platform = FakePlatform::new().process(Requestor::Thread(THREAD), 4, CMD);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_set_disposition(&platform.platform(), &engine, THREAD, false).pre_op;
+
+    assert_eq!(pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+}
+
+#[test]
+fn thread_without_process_is_allowed() {
+    let platform = FakePlatform::new().failing_process(Requestor::Thread(THREAD), STATUS_NOT_FOUND);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_set_disposition(&platform.platform(), &engine, THREAD, true).pre_op;
+
+    assert_eq!(pre_op, PreOp::PassThrough);
+    assert!(!platform.called(&Call::Now));
+}
+
+#[test]
+fn failed_image_name_query_is_allowed() {
+    let platform = FakePlatform::new().failing_process(Requestor::Current, STATUS_ACCESS_DENIED);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    )
+    .pre_op;
+
+    assert_eq!(pre_op, PreOp::PassThrough);
+}
+
+#[test]
+fn empty_image_name_is_allowed() {
+    let platform = FakePlatform::new().process(Requestor::Current, 4, "");
+    let engine = engine_blocking("", TimeWindow::default());
+
+    let pre_op = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    )
+    .pre_op;
+
+    assert_eq!(pre_op, PreOp::PassThrough);
+}
+
+#[test]
+fn allocation_failure_is_allowed_without_query() {
+    let platform = FakePlatform::new()
+        .process(Requestor::Current, 4, CMD)
+        .allocations(0);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    )
+    .pre_op;
+
+    assert_eq!(pre_op, PreOp::PassThrough);
+    assert!(!platform.called(&Call::ImageName(Requestor::Current)));
+}
+
+#[test]
+fn failed_file_name_query_still_denies() {
+    let platform = FakePlatform::new()
+        .process(Requestor::Current, 4, CMD)
+        .failing_file_name(STATUS_ACCESS_DENIED);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    )
+    .pre_op;
+
+    assert_eq!(pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+}
+
+#[test]
+fn file_name_allocation_failure_still_denies() {
+    let platform = FakePlatform::new()
+        .process(Requestor::Current, 4, CMD)
+        .file_name(r"\Device\HarddiskVolume3\data\report.docx")
+        .allocations(1);
+    let engine = engine_blocking("cmd.exe", TimeWindow::default());
+
+    let pre_op = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    )
+    .pre_op;
+
+    assert_eq!(pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert!(!platform.called(&Call::FileName));
+}
+
+#[test]
+fn rule_only_denies_inside_its_window() {
+    let window = TimeWindow {
+        not_before: 1_000,
+        not_after: 2_000,
+        ..TimeWindow::default()
+    };
+    let platform = FakePlatform::new()
+        .process(Requestor::Current, 4, CMD)
+        .file_name(r"\Device\HarddiskVolume3\data\report.docx");
+    let engine = engine_blocking("cmd.exe", window);
+
+    for (now, expected) in [
+        (999, PreOp::PassThrough),
+        (1_000, PreOp::Complete(STATUS_ACCESS_DENIED)),
+        (2_001, PreOp::PassThrough),
+    ] {
+        platform.set_now(now);
+        let pre_op = pre_create(
+            &platform.platform(),
+            &engine,
+            false,
+            FILE_DELETE_ON_CLOSE,
+            0,
+        )
+        .pre_op;
+        assert_eq!(pre_op, expected, "at {now}");
+    }
+}
+
+#[test]
+fn missing_engine_allows() {
+    let platform = FakePlatform::new().process(Requestor::Current, 4, CMD);
+
+    let pre_op = pre_create(
+        &platform.platform(),
+        &FakeEngine::missing(),
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    )
+    .pre_op;
+
+    assert_eq!(pre_op, PreOp::PassThrough);
+}
@@ -0,0 +1,236 @@
+use common::{
+    detector::{LockdownCause, LockdownRecord},
+    event::{EventKind, EventRecord},
+    ioctl_codes::{IOCTL_DELPROTECT_ADD_RULE, IOCTL_DELPROTECT_LIST_LOCKDOWNS},
+    rule::{Response, RuleAction, RuleRecord},
+    schedule::{RuleState, TimeWindow, TICKS_PER_SECOND},
+    stats::Counter,
+    status::{STATUS_ACCESS_DENIED, STATUS_INSUFFICIENT_RESOURCES},
+    wire::LIST_HEADER_SIZE,
+};
+use delprotect_core::{
+    engine::MAX_RESPONDING_PROCESSES,
+    filter::{pre_create, pre_rename, pre_set_disposition, EngineLock, FILE_SUPERSEDE},
+    ioctl::Caller,
+    Decision, Host, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl, privileged, utf16, CMD, TARGET, THREAD},
+    Call, FakeEngine, FakePlatform,
+};
+
+const OTHER: &str = r"\Device\HarddiskVolume3\Data\y.txt";
+const NOW: u64 = 1000 * TICKS_PER_SECOND;
+
+fn engine_with_rule(action: RuleAction) -> FakeEngine {
+    let engine = FakeEngine::default();
+    let process = utf16("cmd.exe");
+    let record = RuleRecord {
+        id: 0,
+        state: RuleState::Active,
+        action,
+        flags: 0,
+        window: TimeWindow::default(),
+        failure_status: STATUS_ACCESS_DENIED,
+        process: &process,
+        path: &[],
+    };
+    let mut input = vec![0u8; record.encoded_len()];
+    record.encode(&mut input).unwrap();
+
+    ioctl(&engine, IOCTL_DELPROTECT_ADD_RULE, &input, 0, &privileged()).unwrap();
+    engine
+}
+
+fn cmd(file_name: &str) -> FakePlatform {
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .process(Requestor::Current, 42, CMD)
+        .file_name(file_name);
+    platform.set_now(NOW);
+    platform
+}
+
+fn queued(platform: &FakePlatform) -> usize {
+    platform
+        .calls()
+        .iter()
+        .filter(|call| matches!(call, Call::QueueResponse(..)))
+        .count()
+}
+
+fn last_response(engine: &FakeEngine) -> Response {
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    let (event, _) = EventRecord::decode(&buffer[..len]).unwrap();
+    assert_eq!(event.kind, EventKind::DeleteDenied as u16);
+    event.response
+}
+
+#[test]
+fn terminate_and_suspend_are_queued_and_audited() {
+    for (action, response) in [
+        (RuleAction::DenyAndTerminate, Response::Terminate),
+        (RuleAction::DenyAndSuspend, Response::Suspend),
+    ] {
+        let engine = engine_with_rule(action);
+        let platform = cmd(TARGET);
+
+        let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+        assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+        assert_eq!(verdict.decision, Some(Decision::Deny { rule_id: 1 }));
+        assert!(platform.called(&Call::QueueResponse(Requestor::Thread(THREAD), response)));
+        assert_eq!(last_response(&engine), response);
+        assert_eq!(platform.stats().get(Counter::ResponseFailures), 0);
+    }
+}
+
+#[test]
+fn delete_is_denied_when_the_response_cannot_be_queued() {
+    let engine = engine_with_rule(RuleAction::DenyAndTerminate);
+    let platform = cmd(TARGET).failing_response(STATUS_INSUFFICIENT_RESOURCES);
+
+    let verdict = pre_create(&platform.platform(), &engine, false, 0x0000_1000, 0);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert!(platform.called(&Call::QueueResponse(
+        Requestor::Current,
+        Response::Terminate
+    )));
+    assert_eq!(platform.stats().get(Counter::ResponseFailures), 1);
+    assert_eq!(platform.stats().get(Counter::Blocked), 1);
+
+    // the claim was given back, the next denial tries again
+    pre_create(&platform.platform(), &engine, false, 0x0000_1000, 0);
+    assert_eq!(queued(&platform), 2);
+}
+
+#[test]
+fn response_is_queued_once_per_process() {
+    let engine = engine_with_rule(RuleAction::DenyAndTerminate);
+    let platform = cmd(TARGET);
+
+    for _ in 0..3 {
+        let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+        assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+        assert_eq!(last_response(&engine), Response::Terminate);
+    }
+    assert_eq!(queued(&platform), 1);
+    assert_eq!(platform.stats().get(Counter::Blocked), 3);
+
+    // a new process reusing the id is answered again
+    engine.with_engine(|engine| engine.process_exited(42));
+    pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    assert_eq!(queued(&platform), 2);
+}
+
+#[test]
+fn responding_processes_are_capped() {
+    let engine = engine_with_rule(RuleAction::DenyAndSuspend);
+    engine.with_engine(|engine| {
+        for process_id in 0..MAX_RESPONDING_PROCESSES as u32 {
+            assert!(engine.claim_response(1000 + process_id));
+        }
+        assert!(!engine.claim_response(42));
+
+        engine.process_exited(1000);
+        assert!(engine.claim_response(42));
+    });
+}
+
+#[test]
+fn plain_deny_has_no_response() {
+    let engine = engine_with_rule(RuleAction::Deny);
+    let platform = cmd(TARGET);
+
+    pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    assert!(!platform
+        .calls()
+        .iter()
+        .any(|call| matches!(call, Call::QueueResponse(..))));
+    assert_eq!(last_response(&engine), Response::None);
+}
+
+#[test]
+fn demoted_process_is_locked_down() {
+    let engine = engine_with_rule(RuleAction::DenyAndDemote);
+    let platform = cmd(TARGET);
+
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    assert_eq!(verdict.decision, Some(Decision::Deny { rule_id: 1 }));
+    // demoted under the engine lock, nothing is queued
+    assert!(!platform
+        .calls()
+        .iter()
+        .any(|call| matches!(call, Call::QueueResponse(..))));
+    assert_eq!(last_response(&engine), Response::Demote);
+
+    // renames and overwrites the rules never see are denied from now on, without events
+    let rename = pre_rename(&cmd(OTHER).platform(), &engine, THREAD);
+    assert_eq!(rename.decision, Some(Decision::LockedDown));
+    let overwrite = pre_create(
+        &cmd(OTHER).platform(),
+        &engine,
+        false,
+        FILE_SUPERSEDE << 24,
+        0,
+    );
+    assert_eq!(overwrite.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    let events = engine
+        .with_engine(|engine| engine.events().iter().count())
+        .unwrap();
+    assert_eq!(events, 1);
+
+    let (_, output) = ioctl(
+        &engine,
+        IOCTL_DELPROTECT_LIST_LOCKDOWNS,
+        &[],
+        4096,
+        &Caller::default(),
+    )
+    .unwrap();
+    let (record, _) = LockdownRecord::decode(&output[LIST_HEADER_SIZE..]).unwrap();
+    assert_eq!(record.process_id, 42);
+    assert_eq!(record.cause, LockdownCause::Demoted);
+
+    // released when the process exits
+    engine
+        .with_engine(|engine| engine.process_exited(42))
+        .unwrap();
+    let verdict = pre_rename(&cmd(OTHER).platform(), &engine, THREAD);
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+}
+
+#[test]
+fn response_actions_round_trip() {
+    for (action, name, response) in [
+        (RuleAction::Deny, "deny", Response::None),
+        (RuleAction::Preserve, "preserve", Response::None),
+        (
+            RuleAction::DenyAndTerminate,
+            "deny-and-terminate",
+            Response::Terminate,
+        ),
+        (
+            RuleAction::DenyAndSuspend,
+            "deny-and-suspend",
+            Response::Suspend,
+        ),
+        (
+            RuleAction::DenyAndDemote,
+            "deny-and-demote",
+            Response::Demote,
+        ),
+    ] {
+        assert_eq!(RuleAction::parse(name), Some(action));
+        assert_eq!(action.as_str(), name);
+        assert_eq!(RuleAction::from_u8(action as u8), Some(action));
+        assert_eq!(action.response(), response);
+    }
+    assert_eq!(RuleAction::from_u8(5), None);
+}
@@ -0,0 +1,307 @@
+use common::{
+    rule::{RuleAction, RuleRecord, RULE_FLAG_INHERIT},
+    schedule::{filetime_from_utc, RuleState, TimeWindow, TICKS_PER_MINUTE},
+    stats::RuleStats,
+    status::{STATUS_ACCESS_DENIED, STATUS_CANNOT_DELETE, STATUS_INVALID_PARAMETER},
+    wire::{ListHeader, LIST_HEADER_SIZE},
+};
+use delprotect_core::{
+    lineage::Ancestor,
+    rules::{RuleStore, MAX_RULE_COUNT},
+};
+use delprotect_fake::fixtures::{utf16, CMD, EXPLORER};
+
+fn noon() -> u64 {
+    filetime_from_utc(2026, 10, 19, 12, 0).unwrap()
+}
+
+/// Valid from an hour before noon to an hour after.
+fn around_noon() -> TimeWindow {
+    TimeWindow {
+        not_before: noon() - 60 * TICKS_PER_MINUTE,
+        not_after: noon() + 60 * TICKS_PER_MINUTE,
+        ..TimeWindow::default()
+    }
+}
+
+fn listed(store: &mut RuleStore, output_len: usize, purge: bool) -> (ListHeader, Vec<RuleState>) {
+    let mut output = vec![0u8; output_len];
+    let len = store.list(&mut output, noon(), purge);
+    let header = ListHeader::decode(&output).unwrap();
+
+    let mut states = Vec::new();
+    let mut offset = LIST_HEADER_SIZE;
+    while offset < len {
+        let (record, used) = RuleRecord::decode(&output[offset..len]).unwrap();
+        states.push(record.state);
+        offset += used;
+    }
+    (header, states)
+}
+
+#[test]
+fn ids_are_never_reused() {
+    let mut store = RuleStore::new().unwrap();
+    assert_eq!(store.push("cmd.exe", TimeWindow::default()), Ok(1));
+    assert_eq!(store.push("explorer.exe", TimeWindow::default()), Ok(2));
+
+    store.clear();
+
+    assert!(store.is_empty());
+    assert_eq!(store.push("cmd.exe", TimeWindow::default()), Ok(3));
+    assert!(store.get(1).is_none());
+    assert_eq!(store.get(3).unwrap().process_name, "cmd.exe");
+}
+
+#[test]
+fn full_store_pushes_out_the_oldest_rule() {
+    let mut store = RuleStore::new().unwrap();
+    for index in 0..MAX_RULE_COUNT {
+        store
+            .push(&format!("tool{index}.exe"), TimeWindow::default())
+            .unwrap();
+    }
+
+    let id = store.push("cmd.exe", TimeWindow::default()).unwrap();
+
+    assert_eq!(id, MAX_RULE_COUNT as u32 + 1);
+    assert_eq!(store.len(), MAX_RULE_COUNT);
+    assert!(store.get(1).is_none());
+    assert!(store.get(2).is_some());
+    assert_eq!(store.find_blocking(CMD, &[], noon()).unwrap().id, id);
+}
+
+#[test]
+fn first_matching_rule_decides() {
+    let mut store = RuleStore::new().unwrap();
+    store
+        .push_action(
+            "notepad.exe",
+            TimeWindow::default(),
+            RuleAction::Deny,
+            STATUS_ACCESS_DENIED,
+        )
+        .unwrap();
+    let preserve = store
+        .push_action(
+            "cmd.exe",
+            TimeWindow::default(),
+            RuleAction::Preserve,
+            STATUS_ACCESS_DENIED,
+        )
+        .unwrap();
+    store
+        .push_action(
+            "System32",
+            TimeWindow::default(),
+            RuleAction::Deny,
+            STATUS_CANNOT_DELETE,
+        )
+        .unwrap();
+
+    let rule = store.find_blocking(CMD, &[], noon()).unwrap();
+    assert_eq!(rule.id, preserve);
+    assert_eq!(rule.action, RuleAction::Preserve);
+    // the name is looked for anywhere in the NT image path
+    assert_eq!(
+        store
+            .find_blocking(
+                r"\Device\HarddiskVolume3\Windows\System32\svchost.exe",
+                &[],
+                noon()
+            )
+            .unwrap()
+            .id,
+        3
+    );
+    assert!(store.find_blocking(EXPLORER, &[], noon()).is_none());
+}
+
+#[test]
+fn rule_names_its_ancestors_only_with_inherit() {
+    let mut store = RuleStore::new().unwrap();
+    store.push("cmd.exe", TimeWindow::default()).unwrap();
+    let ancestors = [Ancestor {
+        process_id: 42,
+        image_name: CMD,
+    }];
+
+    assert!(store.find_blocking(EXPLORER, &ancestors, noon()).is_none());
+    assert!(!store.starts_lineage(CMD));
+
+    let process = utf16("cmd.exe");
+    let id = store
+        .add_record(&RuleRecord {
+            id: 0,
+            state: RuleState::Active,
+            action: RuleAction::Deny,
+            flags: RULE_FLAG_INHERIT,
+            window: TimeWindow::default(),
+            failure_status: STATUS_ACCESS_DENIED,
+            process: &process,
+            path: &[],
+        })
+        .unwrap();
+
+    let rule = store.find_blocking(EXPLORER, &ancestors, noon()).unwrap();
+    assert_eq!(rule.id, id);
+    assert_eq!(rule.generation(EXPLORER, &ancestors), Some(1));
+    assert!(store.starts_lineage(CMD));
+    assert!(store.any_inherits());
+}
+
+#[test]
+fn rule_decides_only_inside_its_validity_period() {
+    let mut store = RuleStore::new().unwrap();
+    store.push("cmd.exe", around_noon()).unwrap();
+
+    assert!(store.find_blocking(CMD, &[], noon()).is_some());
+    assert!(store
+        .find_blocking(CMD, &[], noon() - 61 * TICKS_PER_MINUTE)
+        .is_none());
+    assert!(store
+        .find_blocking(CMD, &[], noon() + 60 * TICKS_PER_MINUTE)
+        .is_none());
+}
+
+#[test]
+fn status_off_the_allow_list_is_rejected() {
+    let mut store = RuleStore::new().unwrap();
+
+    assert_eq!(
+        store.push_action(
+            "cmd.exe",
+            TimeWindow::default(),
+            RuleAction::Deny,
+            0xC000_0001u32 as i32
+        ),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+    assert!(store.is_empty());
+}
+
+#[test]
+fn malformed_records_are_rejected() {
+    let mut store = RuleStore::new().unwrap();
+    let process = utf16("cmd.exe");
+    let record = RuleRecord {
+        id: 0,
+        state: RuleState::Active,
+        action: RuleAction::Deny,
+        flags: 0,
+        window: TimeWindow::default(),
+        failure_status: STATUS_ACCESS_DENIED,
+        process: &process,
+        path: &[],
+    };
+
+    assert_eq!(
+        store.add_record(&RuleRecord {
+            process: &[],
+            ..record
+        }),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+    assert_eq!(
+        store.add_record(&RuleRecord {
+            window: TimeWindow {
+                not_before: noon(),
+                not_after: noon(),
+                ..TimeWindow::default()
+            },
+            ..record
+        }),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+    assert!(store.is_empty());
+    // a rejected record takes no id
+    assert_eq!(store.add_record(&record), Ok(1));
+}
+
+#[test]
+fn list_reports_the_state_and_purges_expired_rules() {
+    let mut store = RuleStore::new().unwrap();
+    store.push("cmd.exe", TimeWindow::default()).unwrap();
+    store
+        .push(
+            "explorer.exe",
+            TimeWindow {
+                not_after: noon(),
+                ..TimeWindow::default()
+            },
+        )
+        .unwrap();
+    store
+        .push(
+            "notepad.exe",
+            TimeWindow {
+                not_before: noon() + 1,
+                ..TimeWindow::default()
+            },
+        )
+        .unwrap();
+
+    let (header, states) = listed(&mut store, 4096, false);
+    assert_eq!((header.total, header.returned, header.removed), (3, 3, 0));
+    assert_eq!(
+        states,
+        [RuleState::Active, RuleState::Expired, RuleState::Pending]
+    );
+    assert_eq!(store.len(), 3);
+
+    let (header, _) = listed(&mut store, 4096, true);
+    assert_eq!(header.removed, 1);
+    assert_eq!(store.len(), 2);
+    assert!(store.get(2).is_none());
+}
+
+#[test]
+fn list_returns_as_many_rules_as_fit() {
+    let mut store = RuleStore::new().unwrap();
+    for _ in 0..3 {
+        store.push("cmd.exe", TimeWindow::default()).unwrap();
+    }
+    let mut output = vec![0u8; 4096];
+    let one = (store.list(&mut output, noon(), false) - LIST_HEADER_SIZE) / 3;
+
+    let (header, states) = listed(&mut store, LIST_HEADER_SIZE + 2 * one + 1, false);
+
+    assert_eq!((header.total, header.returned), (3, 2));
+    assert_eq!(states.len(), 2);
+}
+
+#[test]
+fn hits_are_counted_per_rule_and_reset() {
+    let mut store = RuleStore::new().unwrap();
+    let cmd = store.push("cmd.exe", TimeWindow::default()).unwrap();
+    let explorer = store.push("explorer.exe", TimeWindow::default()).unwrap();
+    store.record_hit(cmd, noon());
+    store.record_hit(cmd, noon() + 1);
+    // unknown ids are ignored
+    store.record_hit(99, noon());
+
+    let mut output = vec![0u8; 1024];
+    let (count, len) = store.stats(&mut output, true);
+    let stats: Vec<RuleStats> = output[..len]
+        .chunks_exact(len / count as usize)
+        .map(|record| RuleStats::decode(record).unwrap())
+        .collect();
+    assert_eq!(
+        stats,
+        [
+            RuleStats {
+                rule_id: cmd,
+                hits: 2,
+                last_hit: noon() + 1,
+            },
+            RuleStats {
+                rule_id: explorer,
+                hits: 0,
+                last_hit: 0,
+            },
+        ]
+    );
+
+    assert_eq!(store.get(cmd).unwrap().hits, 0);
+    assert_eq!(store.get(cmd).unwrap().last_hit, 0);
+}
@@ -0,0 +1,180 @@
+use common::{
+    event::{EventKind, EventRecord},
+    options::OPTION_GUARD_SECURITY,
+    rule::RuleAction,
+    stats::Counter,
+    status::{STATUS_CANNOT_DELETE, STATUS_SHARING_VIOLATION},
+};
+use delprotect_core::{
+    filter::{pre_set_basic, pre_set_disposition, pre_set_security, EngineLock},
+    Decision, Host, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{engine, utf16, CMD, EXPLORER, TARGET, THREAD},
+    Call, FakeEngine, FakePlatform,
+};
+
+/// OWNER_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION, what `takeown` and `icacls` set.
+const OWNER_AND_DACL: u32 = 0x05;
+/// GROUP_SECURITY_INFORMATION
+const GROUP: u32 = 0x02;
+const READONLY: u32 = 0x01;
+const ARCHIVE: u32 = 0x20;
+
+fn changed_by(image_name: &str) -> FakePlatform {
+    FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, image_name)
+        .file_name(TARGET)
+        .file_attributes(READONLY | ARCHIVE)
+}
+
+fn assert_denied(engine: &FakeEngine, detail: &str) {
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    let (event, _) = EventRecord::decode(&buffer[..len]).unwrap();
+    assert_eq!(event.kind, EventKind::SecurityChangeDenied as u16);
+    assert_eq!(event.status, STATUS_CANNOT_DELETE);
+    assert_eq!(event.target, utf16(TARGET).as_slice());
+    assert_eq!(event.detail, utf16(detail).as_slice());
+    assert_eq!(event.rule_id, 1);
+    assert!(!event.trace.is_empty());
+}
+
+#[test]
+fn security_changes_are_only_decided_with_the_option() {
+    let engine = engine(0, RuleAction::Deny, STATUS_CANNOT_DELETE);
+    let platform = changed_by(CMD);
+
+    let security = pre_set_security(&platform.platform(), &engine, false, THREAD, OWNER_AND_DACL);
+    let basic = pre_set_basic(&platform.platform(), &engine, false, THREAD, ARCHIVE);
+
+    assert_eq!(security.pre_op, PreOp::PassThrough);
+    assert_eq!(basic.pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+}
+
+#[test]
+fn owner_and_dacl_change_by_blocked_process_is_denied() {
+    let engine = engine(
+        OPTION_GUARD_SECURITY,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = changed_by(CMD);
+
+    let verdict = pre_set_security(&platform.platform(), &engine, false, THREAD, OWNER_AND_DACL);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_CANNOT_DELETE));
+    assert_eq!(verdict.decision, Some(Decision::Deny { rule_id: 1 }));
+    assert_eq!(platform.stats().get(Counter::SecurityChanges), 1);
+    assert_eq!(platform.stats().get(Counter::Blocked), 1);
+    assert_denied(&engine, "owner, dacl changed");
+}
+
+#[test]
+fn clearing_readonly_by_blocked_process_is_denied() {
+    let engine = engine(
+        OPTION_GUARD_SECURITY,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = changed_by(CMD);
+
+    let verdict = pre_set_basic(&platform.platform(), &engine, false, THREAD, ARCHIVE);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_CANNOT_DELETE));
+    assert!(platform.called(&Call::Attributes));
+    assert_denied(&engine, "readonly cleared");
+}
+
+#[test]
+fn harmless_changes_are_not_decided() {
+    let engine = engine(
+        OPTION_GUARD_SECURITY,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+
+    let platform = changed_by(CMD);
+    let group = pre_set_security(&platform.platform(), &engine, false, THREAD, GROUP);
+    // SetFileTime leaves the attributes alone
+    let times = pre_set_basic(&platform.platform(), &engine, false, THREAD, 0);
+    assert_eq!(group.pre_op, PreOp::PassThrough);
+    assert_eq!(times.pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+
+    // READONLY is kept
+    let kept = pre_set_basic(
+        &platform.platform(),
+        &engine,
+        false,
+        THREAD,
+        READONLY | ARCHIVE,
+    );
+    assert_eq!(kept.pre_op, PreOp::PassThrough);
+    assert_eq!(platform.calls(), vec![Call::Attributes]);
+    assert_eq!(platform.stats().get(Counter::SecurityChanges), 0);
+}
+
+#[test]
+fn kernel_mode_and_unknown_attributes_go_on() {
+    let engine = engine(
+        OPTION_GUARD_SECURITY,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+
+    let platform = changed_by(CMD);
+    let security = pre_set_security(&platform.platform(), &engine, true, THREAD, OWNER_AND_DACL);
+    let basic = pre_set_basic(&platform.platform(), &engine, true, THREAD, ARCHIVE);
+    assert_eq!(security.pre_op, PreOp::PassThrough);
+    assert_eq!(basic.pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+
+    let platform = changed_by(CMD).failing_file_info(STATUS_SHARING_VIOLATION);
+    let verdict = pre_set_basic(&platform.platform(), &engine, false, THREAD, ARCHIVE);
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(platform.calls(), vec![Call::Attributes]);
+}
+
+#[test]
+fn change_by_other_process_goes_on_uncounted() {
+    let engine = engine(
+        OPTION_GUARD_SECURITY,
+        RuleAction::Deny,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = changed_by(EXPLORER);
+
+    let verdict = pre_set_security(&platform.platform(), &engine, false, THREAD, OWNER_AND_DACL);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(verdict.decision, Some(Decision::Allow));
+    assert_eq!(platform.stats().get(Counter::SecurityChanges), 1);
+    assert_eq!(platform.stats().get(Counter::Allowed), 0);
+}
+
+#[test]
+fn preserving_rule_lets_the_change_go_on_to_preserve_the_delete() {
+    let engine = engine(
+        OPTION_GUARD_SECURITY,
+        RuleAction::Preserve,
+        STATUS_CANNOT_DELETE,
+    );
+    let platform = changed_by(CMD);
+
+    let change = pre_set_security(&platform.platform(), &engine, false, THREAD, OWNER_AND_DACL);
+    assert_eq!(change.pre_op, PreOp::PassThrough);
+    assert!(platform.vault_copies().is_empty());
+    let hits = engine
+        .with_engine(|engine| engine.rules().get(1).unwrap().hits)
+        .unwrap();
+    assert_eq!(hits, 0);
+
+    let delete = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    assert_eq!(delete.decision, Some(Decision::Preserve { rule_id: 1 }));
+    assert_eq!(platform.vault_copies().len(), 1);
+}
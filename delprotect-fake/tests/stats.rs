@@ -0,0 +1,181 @@
+use common::{
+    ioctl_codes::IOCTL_DELPROTECT_GET_STATS,
+    schedule::TimeWindow,
+    stats::{
+        Counter, RuleStats, StatsHeader, RULE_STATS_SIZE, STATS_FLAG_RESET, STATS_HEADER_SIZE,
+    },
+    status::{STATUS_ACCESS_DENIED, STATUS_NOT_FOUND},
+};
+use delprotect_core::{
+    filter::{pre_create, pre_set_disposition, EngineLock, FILE_DELETE_ON_CLOSE},
+    ioctl::Caller,
+    Host, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl_on, privileged, CMD, EXPLORER, THREAD},
+    FakeEngine, FakePlatform,
+};
+
+fn engine_blocking_cmd() -> FakeEngine {
+    let engine = FakeEngine::default();
+    engine
+        .with_engine(|engine| {
+            engine
+                .rules_mut()
+                .push("notepad.exe", TimeWindow::default())?;
+            engine.rules_mut().push("cmd.exe", TimeWindow::default())
+        })
+        .unwrap()
+        .unwrap();
+    engine
+}
+
+fn get_stats(
+    engine: &FakeEngine,
+    platform: &FakePlatform,
+    flags: u32,
+    caller: &Caller,
+) -> Result<(StatsHeader, Vec<RuleStats>), i32> {
+    let (_, output) = ioctl_on(
+        engine,
+        platform,
+        IOCTL_DELPROTECT_GET_STATS,
+        &flags.to_le_bytes(),
+        4096,
+        caller,
+    )?;
+    let (header, offset) = StatsHeader::decode(&output).unwrap();
+    let rules = output[offset..]
+        .chunks_exact(RULE_STATS_SIZE)
+        .map(|record| RuleStats::decode(record).unwrap())
+        .collect();
+    Ok((header, rules))
+}
+
+#[test]
+fn callbacks_count_what_they_see() {
+    let engine = engine_blocking_cmd();
+    let platform = FakePlatform::new()
+        .process(Requestor::Current, 4, CMD)
+        .process(Requestor::Thread(THREAD), 8, EXPLORER)
+        .file_name(r"\Device\HarddiskVolume3\data\report.docx");
+
+    pre_create(&platform.platform(), &engine, false, 0, 0);
+    pre_create(&platform.platform(), &engine, true, FILE_DELETE_ON_CLOSE, 0);
+    pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    );
+    pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    pre_set_disposition(&platform.platform(), &engine, THREAD, false);
+
+    let stats = platform.stats();
+    assert_eq!(stats.get(Counter::CreatesInspected), 3);
+    assert_eq!(stats.get(Counter::DeleteOnClose), 1);
+    assert_eq!(stats.get(Counter::SetInformationDeletes), 1);
+    assert_eq!(stats.get(Counter::Blocked), 1);
+    assert_eq!(stats.get(Counter::Allowed), 1);
+    assert_eq!(stats.get(Counter::NameQueryFailures), 0);
+}
+
+#[test]
+fn failures_are_counted() {
+    let engine = engine_blocking_cmd();
+    let platform = FakePlatform::new()
+        .failing_process(Requestor::Thread(THREAD), STATUS_NOT_FOUND)
+        .process(Requestor::Current, 4, CMD);
+
+    pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    // the image name buffer is allocated, the file name one is not
+    let platform = platform.allocations(1);
+    pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    );
+
+    let stats = platform.stats();
+    assert_eq!(stats.get(Counter::NameQueryFailures), 1);
+    assert_eq!(stats.get(Counter::AllocationFailures), 1);
+    assert_eq!(stats.get(Counter::Blocked), 1);
+}
+
+#[test]
+fn stats_report_rule_hits_and_reset_them() {
+    let engine = engine_blocking_cmd();
+    let platform = FakePlatform::new().process(Requestor::Current, 4, CMD);
+    platform.set_now(1000);
+    pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    );
+    platform.set_now(2000);
+    pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    );
+
+    let (header, rules) = get_stats(&engine, &platform, STATS_FLAG_RESET, &privileged()).unwrap();
+    assert_eq!(header.counter(Counter::Blocked), 2);
+    assert_eq!(header.rule_count, 2);
+    assert_eq!(
+        rules,
+        [
+            RuleStats {
+                rule_id: 1,
+                hits: 0,
+                last_hit: 0,
+            },
+            RuleStats {
+                rule_id: 2,
+                hits: 2,
+                last_hit: 2000,
+            },
+        ]
+    );
+
+    let (header, rules) = get_stats(&engine, &platform, 0, &Caller::default()).unwrap();
+    assert_eq!(header.counter(Counter::Blocked), 0);
+    assert_eq!(header.since, 2000);
+    assert!(rules.iter().all(|rule| rule.hits == 0));
+}
+
+#[test]
+fn reset_needs_a_privileged_caller() {
+    let engine = engine_blocking_cmd();
+    let platform = FakePlatform::new().process(Requestor::Current, 4, CMD);
+    pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    );
+
+    assert_eq!(
+        get_stats(&engine, &platform, STATS_FLAG_RESET, &Caller::default()),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    assert_eq!(platform.stats().get(Counter::Blocked), 1);
+
+    assert!(ioctl_on(
+        &engine,
+        &platform,
+        IOCTL_DELPROTECT_GET_STATS,
+        &[],
+        STATS_HEADER_SIZE - 1,
+        &Caller::default(),
+    )
+    .is_err());
+}
@@ -0,0 +1,282 @@
+use common::{
+    canary::CanaryRecord,
+    event::{EventKind, EventRecord},
+    options::OPTION_GUARD_TRUNCATION,
+    rule::RuleAction,
+    stats::Counter,
+    status::{STATUS_ACCESS_DENIED, STATUS_MEDIA_WRITE_PROTECTED, STATUS_SHARING_VIOLATION},
+    truncation::SizeInformation,
+};
+use delprotect_core::{
+    filter::{pre_set_size, EngineLock},
+    Decision, Host, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{engine, utf16, CMD, EXPLORER, TARGET, THREAD},
+    Call, FakeEngine, FakePlatform,
+};
+
+const CONTENT: &[u8] = b"quarterly figures";
+
+fn set_by(image_name: &str) -> FakePlatform {
+    FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, image_name)
+        .file_name(TARGET)
+        .file_content(CONTENT)
+}
+
+fn last_event(engine: &FakeEngine) -> Vec<u8> {
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    buffer.truncate(len);
+    buffer
+}
+
+#[test]
+fn truncation_is_only_decided_with_the_option() {
+    let engine = engine(0, RuleAction::Deny, STATUS_MEDIA_WRITE_PROTECTED);
+    let platform = set_by(CMD);
+
+    let verdict = pre_set_size(
+        &platform.platform(),
+        &engine,
+        false,
+        THREAD,
+        SizeInformation::EndOfFile,
+        0,
+    );
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+}
+
+#[test]
+fn truncation_by_blocked_process_is_denied() {
+    let engine = engine(
+        OPTION_GUARD_TRUNCATION,
+        RuleAction::Deny,
+        STATUS_MEDIA_WRITE_PROTECTED,
+    );
+    let platform = set_by(CMD);
+
+    let verdict = pre_set_size(
+        &platform.platform(),
+        &engine,
+        false,
+        THREAD,
+        SizeInformation::EndOfFile,
+        0,
+    );
+
+    assert_eq!(
+        verdict.pre_op,
+        PreOp::Complete(STATUS_MEDIA_WRITE_PROTECTED)
+    );
+    assert_eq!(verdict.decision, Some(Decision::Deny { rule_id: 1 }));
+    assert_eq!(platform.stats().get(Counter::Truncations), 1);
+    assert_eq!(platform.stats().get(Counter::Blocked), 1);
+
+    let buffer = last_event(&engine);
+    let (event, _) = EventRecord::decode(&buffer).unwrap();
+    assert_eq!(event.kind, EventKind::DeleteDenied as u16);
+    assert_eq!(event.status, STATUS_MEDIA_WRITE_PROTECTED);
+    assert_eq!(event.target, utf16(TARGET).as_slice());
+    assert_eq!(event.detail, utf16("end-of-file set to 0 bytes").as_slice());
+}
+
+#[test]
+fn every_size_class_is_guarded() {
+    for information in [
+        SizeInformation::Allocation,
+        SizeInformation::ValidDataLength,
+    ] {
+        let engine = engine(
+            OPTION_GUARD_TRUNCATION,
+            RuleAction::Deny,
+            STATUS_MEDIA_WRITE_PROTECTED,
+        );
+        let platform = set_by(CMD);
+
+        let verdict = pre_set_size(&platform.platform(), &engine, false, THREAD, information, 4);
+
+        assert_eq!(
+            verdict.pre_op,
+            PreOp::Complete(STATUS_MEDIA_WRITE_PROTECTED)
+        );
+        let buffer = last_event(&engine);
+        let (event, _) = EventRecord::decode(&buffer).unwrap();
+        let detail = format!("{} set to 4 bytes", information.as_str());
+        assert_eq!(event.detail, utf16(&detail).as_slice());
+    }
+}
+
+#[test]
+fn growing_a_file_is_not_decided() {
+    let engine = engine(
+        OPTION_GUARD_TRUNCATION,
+        RuleAction::Deny,
+        STATUS_MEDIA_WRITE_PROTECTED,
+    );
+    let platform = set_by(CMD);
+
+    let verdict = pre_set_size(
+        &platform.platform(),
+        &engine,
+        false,
+        THREAD,
+        SizeInformation::EndOfFile,
+        CONTENT.len() as i64 + 1,
+    );
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(platform.calls(), vec![Call::EndOfFile]);
+    assert_eq!(platform.stats().get(Counter::Truncations), 0);
+}
+
+#[test]
+fn kernel_mode_and_unknown_sizes_go_on() {
+    let engine = engine(
+        OPTION_GUARD_TRUNCATION,
+        RuleAction::Deny,
+        STATUS_MEDIA_WRITE_PROTECTED,
+    );
+    let platform = set_by(CMD);
+    let verdict = pre_set_size(
+        &platform.platform(),
+        &engine,
+        true,
+        THREAD,
+        SizeInformation::EndOfFile,
+        0,
+    );
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert!(platform.calls().is_empty());
+
+    let platform = set_by(CMD).failing_file_info(STATUS_SHARING_VIOLATION);
+    let verdict = pre_set_size(
+        &platform.platform(),
+        &engine,
+        false,
+        THREAD,
+        SizeInformation::EndOfFile,
+        0,
+    );
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(platform.calls(), vec![Call::EndOfFile]);
+}
+
+#[test]
+fn kernel_mode_size_change_does_not_take_the_engine_lock() {
+    let engine = engine(
+        OPTION_GUARD_TRUNCATION,
+        RuleAction::Deny,
+        STATUS_MEDIA_WRITE_PROTECTED,
+    );
+    let platform = set_by(CMD);
+
+    // the engine is borrowed already, locking it again would panic
+    let verdict = engine
+        .with_engine(|_| {
+            pre_set_size(
+                &platform.platform(),
+                &engine,
+                true,
+                THREAD,
+                SizeInformation::EndOfFile,
+                0,
+            )
+        })
+        .unwrap();
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+}
+
+#[test]
+fn truncation_by_other_process_goes_on_uncounted() {
+    let engine = engine(
+        OPTION_GUARD_TRUNCATION,
+        RuleAction::Deny,
+        STATUS_MEDIA_WRITE_PROTECTED,
+    );
+    let platform = set_by(EXPLORER);
+
+    let verdict = pre_set_size(
+        &platform.platform(),
+        &engine,
+        false,
+        THREAD,
+        SizeInformation::EndOfFile,
+        0,
+    );
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(verdict.decision, Some(Decision::Allow));
+    assert_eq!(platform.stats().get(Counter::Truncations), 1);
+    assert_eq!(platform.stats().get(Counter::Allowed), 0);
+}
+
+#[test]
+fn preserving_rule_copies_the_file_before_it_shrinks() {
+    let engine = engine(
+        OPTION_GUARD_TRUNCATION,
+        RuleAction::Preserve,
+        STATUS_MEDIA_WRITE_PROTECTED,
+    );
+    let platform = set_by(CMD);
+
+    let verdict = pre_set_size(
+        &platform.platform(),
+        &engine,
+        false,
+        THREAD,
+        SizeInformation::EndOfFile,
+        0,
+    );
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(verdict.decision, Some(Decision::Preserve { rule_id: 1 }));
+    let copies = platform.vault_copies();
+    assert_eq!(copies.values().next().unwrap(), CONTENT);
+    assert_eq!(platform.stats().get(Counter::Preserved), 1);
+}
+
+#[test]
+fn truncating_a_canary_locks_the_process_down() {
+    let engine = engine(
+        OPTION_GUARD_TRUNCATION,
+        RuleAction::Deny,
+        STATUS_MEDIA_WRITE_PROTECTED,
+    );
+    let path = utf16(TARGET);
+    let record = CanaryRecord {
+        id: 0,
+        flags: 0,
+        trips: 0,
+        last_trip: 0,
+        path: &path,
+    };
+    engine
+        .with_engine(|engine| engine.canaries_mut().add_record(&record))
+        .unwrap()
+        .unwrap();
+    let platform = set_by(EXPLORER);
+
+    let verdict = pre_set_size(
+        &platform.platform(),
+        &engine,
+        false,
+        THREAD,
+        SizeInformation::EndOfFile,
+        0,
+    );
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert_eq!(verdict.decision, Some(Decision::LockedDown));
+    let buffer = last_event(&engine);
+    let (event, _) = EventRecord::decode(&buffer).unwrap();
+    assert_eq!(event.kind, EventKind::CanaryTripped as u16);
+    assert_eq!(event.detail, utf16("overwrite of canary 1").as_slice());
+}
@@ -0,0 +1,339 @@
+use common::{
+    auth::Sha256,
+    event::{EventKind, EventRecord},
+    ioctl_codes::IOCTL_DELPROTECT_OPEN_VAULT,
+    rule::RuleAction,
+    schedule::{filetime_from_utc, TimeWindow},
+    stats::Counter,
+    status::{STATUS_ACCESS_DENIED, STATUS_OBJECT_NAME_NOT_FOUND, STATUS_SHARING_VIOLATION},
+    vault::{copy_name, decode_index, VaultHeader},
+};
+use delprotect_core::{
+    filter::{
+        pre_create, pre_set_disposition, EngineLock, FILE_DELETE_ON_CLOSE, FILE_WRITE_DATA,
+        MAXIMUM_ALLOWED,
+    },
+    ioctl::Caller,
+    Decision, Host, PreOp, Requestor,
+};
+use delprotect_fake::{
+    fixtures::{ioctl, utf16, CMD, THREAD},
+    Call, FakeEngine, FakePlatform,
+};
+
+const TARGET: &str = r"\Device\HarddiskVolume3\Data\report.docx";
+const CONTENT: &[u8] = b"quarterly numbers";
+const INDEX: &str = r"\Device\HarddiskVolume3\$DelProtectVault\index.dat";
+/// CreateDisposition FILE_OPEN in the high byte of the create options.
+const OPEN_EXISTING: u32 = 1 << 24;
+
+fn engine_preserving_cmd() -> FakeEngine {
+    let engine = FakeEngine::default();
+    engine
+        .with_engine(|engine| {
+            engine.rules_mut().push_action(
+                "cmd.exe",
+                TimeWindow::default(),
+                RuleAction::Preserve,
+                STATUS_ACCESS_DENIED,
+            )
+        })
+        .unwrap()
+        .unwrap();
+    engine
+}
+
+fn platform(now: u64) -> FakePlatform {
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .process(Requestor::Current, 42, CMD)
+        .file_name(TARGET)
+        .file_content(CONTENT);
+    platform.set_now(now);
+    platform
+}
+
+fn last_event(engine: &FakeEngine) -> (u16, i32, Vec<u8>) {
+    let mut buffer = vec![0u8; 4096];
+    let len = engine
+        .with_engine(|engine| engine.events().iter().last().unwrap().encode(&mut buffer))
+        .unwrap()
+        .unwrap();
+    let (event, _) = EventRecord::decode(&buffer[..len]).unwrap();
+    (event.kind, event.status, event.detail.to_vec())
+}
+
+#[test]
+fn preserved_delete_goes_on_with_a_copy_and_an_entry() {
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = engine_preserving_cmd();
+    let platform = platform(now);
+
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(verdict.decision, Some(Decision::Preserve { rule_id: 1 }));
+    assert_eq!(platform.stats().get(Counter::Preserved), 1);
+    assert_eq!(platform.stats().get(Counter::Blocked), 0);
+
+    let name = copy_name(now);
+    assert_eq!(platform.vault_copies().get(&name).unwrap(), CONTENT);
+
+    let index = platform.vault_index();
+    let (header, entries) = decode_index(&index).unwrap();
+    assert_eq!(header.entry_count, 1);
+    assert_eq!(header.used_bytes, CONTENT.len() as u64);
+    let entry = entries[0];
+    assert_eq!(entry.id, now);
+    assert_eq!(entry.time, now);
+    assert_eq!(entry.size, CONTENT.len() as u64);
+    assert_eq!(entry.process_id, 42);
+    assert_eq!(entry.rule_id, 1);
+    assert_eq!(entry.path, utf16(TARGET));
+    assert_eq!(entry.process, utf16(CMD));
+    let mut hasher = Sha256::new();
+    hasher.update(CONTENT);
+    assert_eq!(entry.sha256, hasher.finish());
+
+    let (kind, _, detail) = last_event(&engine);
+    assert_eq!(kind, EventKind::DeletePreserved as u16);
+    assert_eq!(detail, utf16(&name));
+}
+
+#[test]
+fn deletes_in_the_same_tick_get_their_own_copy() {
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = engine_preserving_cmd();
+    let platform = platform(now);
+
+    pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    );
+    pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+    // the clock going back does not reuse an id either
+    platform.set_now(now - 1000);
+    pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    let copies: Vec<String> = platform.vault_copies().into_keys().collect();
+    assert_eq!(
+        copies,
+        [copy_name(now), copy_name(now + 1), copy_name(now + 2)]
+    );
+    let (header, _) = decode_index(&platform.vault_index()).unwrap();
+    assert_eq!(header.entry_count, 3);
+}
+
+#[test]
+fn failed_copy_denies_the_delete() {
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = engine_preserving_cmd();
+    let platform = platform(now).failing_copy(STATUS_SHARING_VIOLATION);
+
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert_eq!(platform.stats().get(Counter::Blocked), 1);
+    assert!(platform.vault_index().is_empty());
+    let (kind, status, _) = last_event(&engine);
+    assert_eq!(kind, EventKind::DeleteDenied as u16);
+    assert_eq!(status, STATUS_ACCESS_DENIED);
+}
+
+#[test]
+fn delete_on_close_open_of_a_new_file_has_nothing_to_preserve() {
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = engine_preserving_cmd();
+    let platform = platform(now).failing_copy(STATUS_OBJECT_NAME_NOT_FOUND);
+
+    let verdict = pre_create(
+        &platform.platform(),
+        &engine,
+        false,
+        FILE_DELETE_ON_CLOSE,
+        0,
+    );
+
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert_eq!(verdict.decision, Some(Decision::Preserve { rule_id: 1 }));
+    assert_eq!(platform.stats().get(Counter::Preserved), 0);
+    assert_eq!(platform.stats().get(Counter::Blocked), 0);
+    assert!(platform.vault_index().is_empty());
+    assert!(engine
+        .with_engine(|engine| engine.events().iter().next().is_none())
+        .unwrap());
+}
+
+#[test]
+fn full_vault_denies_without_copying() {
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = engine_preserving_cmd();
+    let platform = platform(now).vault_header(VaultHeader {
+        max_bytes: 100,
+        used_bytes: 100,
+        ..VaultHeader::default()
+    });
+
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert!(!platform.called(&Call::CopyFile(copy_name(now))));
+}
+
+#[test]
+fn file_above_the_cap_denies_without_copying() {
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = engine_preserving_cmd();
+    let header = VaultHeader {
+        max_bytes: 20,
+        used_bytes: 10,
+        ..VaultHeader::default()
+    };
+    let platform = platform(now).vault_header(header);
+
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert!(platform.called(&Call::FileSize));
+    assert!(!platform.called(&Call::CopyFile(copy_name(now))));
+    assert!(platform.vault_copies().is_empty());
+    let index = platform.vault_index();
+    let (unchanged, entries) = decode_index(&index).unwrap();
+    assert_eq!(unchanged, header);
+    assert!(entries.is_empty());
+}
+
+#[test]
+fn files_of_the_vault_are_never_preserved() {
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = engine_preserving_cmd();
+    let platform = FakePlatform::new()
+        .process(Requestor::Thread(THREAD), 42, CMD)
+        .file_name(r"\Device\HarddiskVolume3\$DelProtectVault\index.dat");
+    platform.set_now(now);
+
+    let verdict = pre_set_disposition(&platform.platform(), &engine, THREAD, true);
+
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    assert!(platform.vault_copies().is_empty());
+}
+
+fn open_vault(engine: &FakeEngine, caller: &Caller) -> Result<(), i32> {
+    ioctl(engine, IOCTL_DELPROTECT_OPEN_VAULT, &[], 0, caller).map(|_| ())
+}
+
+/// An engine which found a vault on a volume.
+fn engine_with_vault() -> FakeEngine {
+    let engine = FakeEngine::default();
+    engine.with_engine(|engine| engine.note_vault()).unwrap();
+    engine
+}
+
+#[test]
+fn creates_skip_the_name_query_until_a_vault_exists() {
+    let engine = FakeEngine::default();
+    let writer = FakePlatform::new()
+        .process(Requestor::Current, 42, CMD)
+        .file_name(INDEX);
+
+    let verdict = pre_create(
+        &writer.platform(),
+        &engine,
+        false,
+        OPEN_EXISTING,
+        FILE_WRITE_DATA,
+    );
+    assert_eq!(verdict.pre_op, PreOp::PassThrough);
+    assert!(!writer.called(&Call::FileName));
+
+    // a preserved delete creates the vault
+    let now = filetime_from_utc(2026, 10, 19, 12, 0).unwrap();
+    let engine = engine_preserving_cmd();
+    pre_set_disposition(&platform(now).platform(), &engine, THREAD, true);
+    assert!(engine.published().has_vaults());
+}
+
+#[test]
+fn user_mode_changes_to_the_vault_are_denied() {
+    let engine = engine_with_vault();
+    let platform = FakePlatform::new()
+        .process(Requestor::Current, 42, CMD)
+        .file_name(INDEX);
+
+    for (options, access) in [
+        (OPEN_EXISTING, FILE_WRITE_DATA),
+        (OPEN_EXISTING, MAXIMUM_ALLOWED),
+        (OPEN_EXISTING | FILE_DELETE_ON_CLOSE, 0),
+        (0, 0),
+    ] {
+        let verdict = pre_create(&platform.platform(), &engine, false, options, access);
+        assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+    }
+    assert_eq!(platform.stats().get(Counter::Blocked), 4);
+
+    // reading it and the driver writing it are fine
+    let read = pre_create(&platform.platform(), &engine, false, OPEN_EXISTING, 1);
+    assert_eq!(read.pre_op, PreOp::PassThrough);
+    let kernel = pre_create(
+        &platform.platform(),
+        &engine,
+        true,
+        OPEN_EXISTING,
+        FILE_WRITE_DATA,
+    );
+    assert_eq!(kernel.pre_op, PreOp::PassThrough);
+}
+
+#[test]
+fn process_which_opened_the_vaults_changes_them_until_its_handle_closes() {
+    let engine = engine_with_vault();
+    let platform = FakePlatform::new()
+        .process(Requestor::Current, 42, CMD)
+        .file_name(INDEX);
+    let client = Caller {
+        file_object: 7,
+        privileged: true,
+        process_id: 42,
+        ..Caller::default()
+    };
+
+    let unprivileged = Caller {
+        privileged: false,
+        ..client.clone()
+    };
+    assert_eq!(
+        open_vault(&engine, &unprivileged),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    open_vault(&engine, &client).unwrap();
+
+    let write = |engine: &FakeEngine| {
+        pre_create(
+            &platform.platform(),
+            engine,
+            false,
+            OPEN_EXISTING,
+            FILE_WRITE_DATA,
+        )
+        .pre_op
+    };
+    assert_eq!(write(&engine), PreOp::PassThrough);
+
+    let other = FakePlatform::new()
+        .process(Requestor::Current, 43, CMD)
+        .file_name(INDEX);
+    let verdict = pre_create(
+        &other.platform(),
+        &engine,
+        false,
+        OPEN_EXISTING,
+        FILE_WRITE_DATA,
+    );
+    assert_eq!(verdict.pre_op, PreOp::Complete(STATUS_ACCESS_DENIED));
+
+    engine.with_engine(|engine| engine.close(7)).unwrap();
+    assert_eq!(write(&engine), PreOp::Complete(STATUS_ACCESS_DENIED));
+}
@@ -2,10 +2,14 @@ use kernel_string::{PCUNICODE_STRING, UNICODE_STRING};
 use km_api_sys::flt_kernel::{FltUnregisterFilter, PFLT_FILTER};
 use winapi::km::wdm::{IoDeleteDevice, IoDeleteSymbolicLink, PDEVICE_OBJECT};
 
+use crate::{process, registry_guard};
+
 pub struct Cleaner {
     device_object: Option<PDEVICE_OBJECT>,
     sym_link: Option<PCUNICODE_STRING>,
     filter_handle: Option<PFLT_FILTER>,
+    process_notify: bool,
+    registry_callback: bool,
 }
 
 impl Cleaner {
@@ -14,6 +18,8 @@ impl Cleaner {
             device_object: None,
             sym_link: None,
             filter_handle: None,
+            process_notify: false,
+            registry_callback: false,
         }
     }
 
@@ -29,6 +35,14 @@ impl Cleaner {
         self.filter_handle = Some(callback);
     }
 
+    pub fn init_process_notify(&mut self) {
+        self.process_notify = true;
+    }
+
+    pub fn init_registry_callback(&mut self) {
+        self.registry_callback = true;
+    }
+
     pub fn clean(&mut self) {
         unsafe {
             if let Some(device) = self.device_object {
@@ -39,6 +53,14 @@ impl Cleaner {
                 IoDeleteSymbolicLink(&(*sym_link).as_ntdef_unicode());
             }
 
+            if self.registry_callback {
+                registry_guard::unregister();
+            }
+
+            if self.process_notify {
+                process::unregister();
+            }
+
             if let Some(filter_handle) = self.filter_handle {
                 FltUnregisterFilter(filter_handle);
             }
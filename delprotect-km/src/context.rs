@@ -0,0 +1,213 @@
+//! The contexts DelProtect attaches to each of its instances and to the handles opened for
+//! write access while a rule is immutable.
+
+use alloc::string::String;
+use core::{mem::size_of, ptr::null_mut, slice};
+use kernel_macros::NT_SUCCESS;
+use winapi::shared::{
+    ntdef::{NTSTATUS, PVOID, USHORT},
+    ntstatus::STATUS_NAME_TOO_LONG,
+};
+
+use crate::ffi::{
+    FltAcquirePushLockExclusive, FltAllocateContext, FltDeletePushLock, FltGetInstanceContext,
+    FltGetStreamHandleContext, FltInitializePushLock, FltReleaseContext, FltReleasePushLock,
+    FltSetInstanceContext, FltSetStreamHandleContext, EX_PUSH_LOCK, FLT_CONTEXT_END,
+    FLT_CONTEXT_REGISTRATION, FLT_INSTANCE_CONTEXT, FLT_SET_CONTEXT_KEEP_IF_EXISTS,
+    FLT_STREAMHANDLE_CONTEXT, FLT_VARIABLE_SIZED_CONTEXTS, NON_PAGED_POOL_NX,
+};
+
+const POOL_TAG: u32 = u32::from_ne_bytes(*b"DPic");
+const HANDLE_POOL_TAG: u32 = u32::from_ne_bytes(*b"DPhc");
+
+pub const CONTEXTS: &[FLT_CONTEXT_REGISTRATION] = &[
+    FLT_CONTEXT_REGISTRATION {
+        ContextType: FLT_INSTANCE_CONTEXT,
+        Flags: 0,
+        ContextCleanupCallback: Some(InstanceContextCleanup),
+        Size: size_of::<InstanceContext>(),
+        PoolTag: POOL_TAG,
+        ContextAllocateCallback: null_mut(),
+        ContextFreeCallback: null_mut(),
+        Reserved1: null_mut(),
+    },
+    FLT_CONTEXT_REGISTRATION {
+        ContextType: FLT_STREAMHANDLE_CONTEXT,
+        Flags: 0,
+        ContextCleanupCallback: None,
+        Size: FLT_VARIABLE_SIZED_CONTEXTS,
+        PoolTag: HANDLE_POOL_TAG,
+        ContextAllocateCallback: null_mut(),
+        ContextFreeCallback: null_mut(),
+        Reserved1: null_mut(),
+    },
+    FLT_CONTEXT_REGISTRATION {
+        ContextType: FLT_CONTEXT_END,
+        Flags: 0,
+        ContextCleanupCallback: None,
+        Size: 0,
+        PoolTag: 0,
+        ContextAllocateCallback: null_mut(),
+        ContextFreeCallback: null_mut(),
+        Reserved1: null_mut(),
+    },
+];
+
+/// Allocated by the filter manager, in non-paged pool.
+#[repr(C)]
+struct InstanceContext {
+    /// Held while the vault index of the volume is rewritten. File I/O needs PASSIVE_LEVEL,
+    /// which the push lock leaves, unlike a fast mutex.
+    index_lock: EX_PUSH_LOCK,
+}
+
+/// Attaches the context to a new instance, from the instance setup callback.
+pub unsafe fn attach(filter: PVOID, instance: PVOID) -> NTSTATUS {
+    let mut context: PVOID = null_mut();
+    let status = FltAllocateContext(
+        filter,
+        FLT_INSTANCE_CONTEXT,
+        size_of::<InstanceContext>(),
+        NON_PAGED_POOL_NX,
+        &mut context,
+    );
+    if !NT_SUCCESS!(status) {
+        return status;
+    }
+
+    FltInitializePushLock(&mut (*(context as *mut InstanceContext)).index_lock);
+    let status = FltSetInstanceContext(
+        instance,
+        FLT_SET_CONTEXT_KEEP_IF_EXISTS,
+        context,
+        null_mut(),
+    );
+    // the instance holds its own reference once set, a context which was not set is freed
+    FltReleaseContext(context);
+    status
+}
+
+extern "system" fn InstanceContextCleanup(context: PVOID, _context_type: USHORT) {
+    unsafe {
+        FltDeletePushLock(&mut (*(context as *mut InstanceContext)).index_lock);
+    }
+}
+
+/// The index lock of an instance, held until dropped.
+pub struct IndexLock {
+    context: *mut InstanceContext,
+}
+
+impl IndexLock {
+    /// Fails if the instance has no context, its setup could not attach one.
+    pub fn acquire(instance: PVOID) -> Result<Self, NTSTATUS> {
+        let mut context: PVOID = null_mut();
+        unsafe {
+            let status = FltGetInstanceContext(instance, &mut context);
+            if !NT_SUCCESS!(status) {
+                return Err(status);
+            }
+            let context = context as *mut InstanceContext;
+            FltAcquirePushLockExclusive(&mut (*context).index_lock);
+            Ok(Self { context })
+        }
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        unsafe {
+            FltReleasePushLock(&mut (*self.context).index_lock);
+            FltReleaseContext(self.context as PVOID);
+        }
+    }
+}
+
+/// The name an open for write access was decided with, see
+/// `FileNameProvider::keep_opened_name`. `len` UTF-16 units follow the header.
+#[repr(C)]
+struct HandleContext {
+    len: u16,
+}
+
+/// A handle context holding `name`, allocated before the create goes down so that the
+/// post-create callback only has to attach it. Released if it is never attached.
+pub struct OpenedName {
+    context: PVOID,
+}
+
+impl OpenedName {
+    pub unsafe fn new(filter: PVOID, name: &str) -> Result<Self, NTSTATUS> {
+        let len = name.encode_utf16().count();
+        if len > u16::MAX as usize {
+            return Err(STATUS_NAME_TOO_LONG);
+        }
+
+        let mut context: PVOID = null_mut();
+        let status = FltAllocateContext(
+            filter,
+            FLT_STREAMHANDLE_CONTEXT,
+            size_of::<HandleContext>() + len * size_of::<u16>(),
+            NON_PAGED_POOL_NX,
+            &mut context,
+        );
+        if !NT_SUCCESS!(status) {
+            return Err(status);
+        }
+
+        let header = context as *mut HandleContext;
+        (*header).len = len as u16;
+        let units = slice::from_raw_parts_mut(header.add(1) as *mut u16, len);
+        for (dst, src) in units.iter_mut().zip(name.encode_utf16()) {
+            *dst = src;
+        }
+        Ok(Self { context })
+    }
+
+    /// For the completion context of the create.
+    pub fn into_raw(self) -> PVOID {
+        let context = self.context;
+        core::mem::forget(self);
+        context
+    }
+
+    pub unsafe fn from_raw(context: PVOID) -> Self {
+        Self { context }
+    }
+
+    /// Attaches the name to the handle the create opened.
+    pub unsafe fn attach(self, instance: PVOID, file_object: PVOID) -> NTSTATUS {
+        FltSetStreamHandleContext(
+            instance,
+            file_object,
+            FLT_SET_CONTEXT_KEEP_IF_EXISTS,
+            self.context,
+            null_mut(),
+        )
+    }
+
+    /// The name attached to the handle, `None` if the handle has none.
+    pub unsafe fn get(instance: PVOID, file_object: PVOID) -> Option<String> {
+        let mut context: PVOID = null_mut();
+        if !NT_SUCCESS!(FltGetStreamHandleContext(
+            instance,
+            file_object,
+            &mut context
+        )) {
+            return None;
+        }
+
+        let header = context as *const HandleContext;
+        let units = slice::from_raw_parts(header.add(1) as *const u16, (*header).len as usize);
+        let name = String::from_utf16_lossy(units);
+        FltReleaseContext(context);
+        Some(name)
+    }
+}
+
+impl Drop for OpenedName {
+    fn drop(&mut self) {
+        // the handle holds its own reference once attached
+        unsafe { FltReleaseContext(self.context) };
+    }
+}
@@ -0,0 +1,687 @@
+//! Kernel routines and structures used by DelProtect which km-api-sys does not export.
+
+use km_api_sys::flt_kernel::{FILE_INFORMATION_CLASS, FLT_CALLBACK_DATA, PFLT_VOLUME};
+use winapi::{
+    km::wdm::{KPROCESSOR_MODE, PDEVICE_OBJECT, PDRIVER_OBJECT},
+    shared::{
+        guiddef::GUID,
+        ntdef::{
+            BOOLEAN, HANDLE, LUID, NTSTATUS, PHANDLE, POBJECT_ATTRIBUTES, PULONG, PUNICODE_STRING,
+            PVOID, UCHAR, ULONG, UNICODE_STRING, USHORT,
+        },
+    },
+};
+
+pub type ACCESS_MASK = ULONG;
+
+pub const KEY_READ: ACCESS_MASK = 0x20019;
+pub const KEY_WRITE: ACCESS_MASK = 0x20006;
+pub const REG_OPTION_NON_VOLATILE: ULONG = 0;
+pub const REG_BINARY: ULONG = 3;
+pub const REG_DWORD: ULONG = 4;
+
+pub const FILE_REMOVABLE_MEDIA: ULONG = 0x0000_0001;
+pub const FILE_DEVICE_SECURE_OPEN: ULONG = 0x0000_0100;
+
+/// Major function of the callback data the filter manager uses for section creation, the
+/// `(UCHAR)-1` of fltKernel.h.
+pub const IRP_MJ_ACQUIRE_FOR_SECTION_SYNCHRONIZATION: UCHAR = 0xff;
+/// FS_FILTER_SECTION_SYNC_TYPE of a section being created, SyncTypeCreateSection.
+pub const SYNC_TYPE_CREATE_SECTION: ULONG = 1;
+/// IrpFlags of paging I/O.
+pub const IRP_PAGING_IO: ULONG = 0x0000_0002;
+
+/// FilterUnloadCallback flag: the unload cannot be refused (e.g. system shutdown).
+pub const FLTFL_FILTER_UNLOAD_MANDATORY: ULONG = 0x0000_0001;
+
+pub const BCRYPT_USE_SYSTEM_PREFERRED_RNG: ULONG = 0x0000_0002;
+
+pub const SE_LOAD_DRIVER_PRIVILEGE: ULONG = 10;
+
+pub const FLT_FILE_NAME_NORMALIZED: ULONG = 0x0000_0001;
+pub const FLT_FILE_NAME_QUERY_DEFAULT: ULONG = 0x0000_0100;
+
+pub const FILE_READ_DATA: ACCESS_MASK = 0x0001;
+pub const FILE_READ_ATTRIBUTES: ACCESS_MASK = 0x0080;
+pub const FILE_WRITE_DATA: ACCESS_MASK = 0x0002;
+pub const FILE_LIST_DIRECTORY: ACCESS_MASK = 0x0001;
+pub const DELETE: ACCESS_MASK = 0x0001_0000;
+pub const SYNCHRONIZE: ACCESS_MASK = 0x0010_0000;
+
+pub const FILE_SHARE_READ: ULONG = 0x0000_0001;
+pub const FILE_SHARE_WRITE: ULONG = 0x0000_0002;
+pub const FILE_SHARE_DELETE: ULONG = 0x0000_0004;
+
+pub const FILE_ATTRIBUTE_HIDDEN: ULONG = 0x0000_0002;
+pub const FILE_ATTRIBUTE_SYSTEM: ULONG = 0x0000_0004;
+pub const FILE_ATTRIBUTE_NORMAL: ULONG = 0x0000_0080;
+
+pub const FILE_OPEN: ULONG = 0x0000_0001;
+pub const FILE_CREATE: ULONG = 0x0000_0002;
+pub const FILE_OPEN_IF: ULONG = 0x0000_0003;
+
+pub const FILE_DIRECTORY_FILE: ULONG = 0x0000_0001;
+pub const FILE_SYNCHRONOUS_IO_NONALERT: ULONG = 0x0000_0020;
+pub const FILE_NON_DIRECTORY_FILE: ULONG = 0x0000_0040;
+pub const FILE_DELETE_ON_CLOSE: ULONG = 0x0000_1000;
+
+pub const FILE_ALL_ACCESS: ACCESS_MASK = 0x001F_01FF;
+
+pub const SECURITY_DESCRIPTOR_REVISION: ULONG = 1;
+pub const ACL_REVISION: ULONG = 2;
+/// AceFlags of an ACE inherited by files and directories created below.
+pub const OBJECT_INHERIT_ACE: ULONG = 0x01;
+pub const CONTAINER_INHERIT_ACE: ULONG = 0x02;
+/// SECURITY_DESCRIPTOR_CONTROL bit keeping inheritable ACEs of the parent out of the DACL.
+pub const SE_DACL_PROTECTED: USHORT = 0x1000;
+
+/// PROCESSINFOCLASS ProcessBreakOnTermination: a ULONG, not 0 for a critical process whose end
+/// bugchecks the system.
+pub const PROCESS_BREAK_ON_TERMINATION: ULONG = 29;
+
+/// FLT_CONTEXT_TYPE of a context attached to an instance, to a handle, and the one ending the
+/// registration.
+pub const FLT_INSTANCE_CONTEXT: USHORT = 0x0002;
+pub const FLT_STREAMHANDLE_CONTEXT: USHORT = 0x0008;
+pub const FLT_CONTEXT_END: USHORT = 0xffff;
+/// Size of a context registration whose contexts are allocated with their own size.
+pub const FLT_VARIABLE_SIZED_CONTEXTS: usize = usize::MAX;
+/// Post-operation flag of an instance being torn down, nothing may be attached.
+pub const FLTFL_POST_OPERATION_DRAINING: ULONG = 0x0000_0001;
+/// FLT_SET_CONTEXT_OPERATION keeping a context already set.
+pub const FLT_SET_CONTEXT_KEEP_IF_EXISTS: ULONG = 1;
+/// POOL_TYPE NonPagedPoolNx, push locks must not be paged out.
+pub const NON_PAGED_POOL_NX: ULONG = 512;
+
+/// EX_PUSH_LOCK, a pointer sized lock word initialized by FltInitializePushLock.
+pub type EX_PUSH_LOCK = usize;
+
+/// Called before the filter manager frees a context.
+pub type PFLT_CONTEXT_CLEANUP_CALLBACK = extern "system" fn(Context: PVOID, ContextType: USHORT);
+
+#[repr(C)]
+pub struct FLT_CONTEXT_REGISTRATION {
+    pub ContextType: USHORT,
+    pub Flags: USHORT,
+    pub ContextCleanupCallback: Option<PFLT_CONTEXT_CLEANUP_CALLBACK>,
+    pub Size: usize,
+    pub PoolTag: ULONG,
+    pub ContextAllocateCallback: PVOID,
+    pub ContextFreeCallback: PVOID,
+    pub Reserved1: PVOID,
+}
+
+/// ByteOffset of FltWriteFile appending at the end of the file, FILE_WRITE_TO_END_OF_FILE with
+/// a high part of -1.
+pub const FILE_WRITE_TO_END_OF_FILE: i64 = -1;
+
+#[repr(C)]
+pub struct SECURITY_SUBJECT_CONTEXT {
+    pub ClientToken: PVOID,
+    pub ImpersonationLevel: ULONG,
+    pub PrimaryToken: PVOID,
+    pub ProcessAuditId: PVOID,
+}
+
+/// Absolute security descriptor of ntifs.h.
+#[repr(C)]
+pub struct SECURITY_DESCRIPTOR {
+    pub Revision: UCHAR,
+    pub Sbz1: UCHAR,
+    pub Control: USHORT,
+    pub Owner: PVOID,
+    pub Group: PVOID,
+    pub Sacl: PVOID,
+    pub Dacl: PVOID,
+}
+
+#[repr(C)]
+pub struct FILE_BASIC_INFORMATION {
+    pub CreationTime: i64,
+    pub LastAccessTime: i64,
+    pub LastWriteTime: i64,
+    pub ChangeTime: i64,
+    pub FileAttributes: ULONG,
+}
+
+#[repr(C)]
+pub struct FILE_STANDARD_INFORMATION {
+    pub AllocationSize: i64,
+    pub EndOfFile: i64,
+    pub NumberOfLinks: ULONG,
+    pub DeletePending: BOOLEAN,
+    pub Directory: BOOLEAN,
+}
+
+#[repr(C)]
+pub struct FLT_FILE_NAME_INFORMATION {
+    pub Size: USHORT,
+    pub NamesParsed: USHORT,
+    pub Format: ULONG,
+    pub Name: UNICODE_STRING,
+    pub Volume: UNICODE_STRING,
+    pub Share: UNICODE_STRING,
+    pub Extension: UNICODE_STRING,
+    pub Stream: UNICODE_STRING,
+    pub FinalComponent: UNICODE_STRING,
+    pub ParentDir: UNICODE_STRING,
+}
+
+#[repr(C)]
+pub struct IO_STATUS_BLOCK {
+    /// NTSTATUS in the low 32 bits, the union is pointer sized.
+    pub Status: usize,
+    pub Information: usize,
+}
+
+#[repr(C)]
+pub struct CLIENT_ID {
+    pub UniqueProcess: HANDLE,
+    pub UniqueThread: HANDLE,
+}
+
+#[repr(C)]
+pub struct PS_CREATE_NOTIFY_INFO {
+    pub Size: usize,
+    pub Flags: ULONG,
+    pub ParentProcessId: HANDLE,
+    pub CreatingThreadId: CLIENT_ID,
+    pub FileObject: PVOID,
+    pub ImageFileName: *const UNICODE_STRING,
+    pub CommandLine: *const UNICODE_STRING,
+    pub CreationStatus: NTSTATUS,
+}
+
+/// REG_NOTIFY_CLASS values of wdm.h the registry callback looks at.
+pub const REG_NT_PRE_DELETE_KEY: ULONG = 0;
+pub const REG_NT_PRE_SET_VALUE_KEY: ULONG = 1;
+pub const REG_NT_PRE_DELETE_VALUE_KEY: ULONG = 2;
+pub const REG_NT_PRE_SET_INFORMATION_KEY: ULONG = 3;
+pub const REG_NT_PRE_RENAME_KEY: ULONG = 4;
+pub const REG_NT_PRE_ENUMERATE_VALUE_KEY: ULONG = 6;
+pub const REG_NT_PRE_QUERY_VALUE_KEY: ULONG = 8;
+pub const REG_NT_PRE_QUERY_MULTIPLE_VALUE_KEY: ULONG = 9;
+pub const REG_NT_PRE_SET_KEY_SECURITY: ULONG = 38;
+pub const REG_NT_PRE_RESTORE_KEY: ULONG = 41;
+pub const REG_NT_PRE_SAVE_KEY: ULONG = 43;
+pub const REG_NT_PRE_REPLACE_KEY: ULONG = 45;
+
+/// Argument of REG_NT_PRE_DELETE_KEY.
+#[repr(C)]
+pub struct REG_DELETE_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_SET_VALUE_KEY.
+#[repr(C)]
+pub struct REG_SET_VALUE_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub ValueName: PUNICODE_STRING,
+    pub TitleIndex: ULONG,
+    pub Type: ULONG,
+    pub Data: PVOID,
+    pub DataSize: ULONG,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_DELETE_VALUE_KEY.
+#[repr(C)]
+pub struct REG_DELETE_VALUE_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub ValueName: PUNICODE_STRING,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_SET_INFORMATION_KEY.
+#[repr(C)]
+pub struct REG_SET_INFORMATION_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub KeySetInformationClass: ULONG,
+    pub KeySetInformation: PVOID,
+    pub KeySetInformationLength: ULONG,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_RENAME_KEY.
+#[repr(C)]
+pub struct REG_RENAME_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub NewName: PUNICODE_STRING,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_ENUMERATE_VALUE_KEY.
+#[repr(C)]
+pub struct REG_ENUMERATE_VALUE_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub Index: ULONG,
+    pub KeyValueInformationClass: KEY_VALUE_INFORMATION_CLASS,
+    pub KeyValueInformation: PVOID,
+    pub Length: ULONG,
+    pub ResultLength: PULONG,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_QUERY_VALUE_KEY.
+#[repr(C)]
+pub struct REG_QUERY_VALUE_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub ValueName: PUNICODE_STRING,
+    pub KeyValueInformationClass: KEY_VALUE_INFORMATION_CLASS,
+    pub KeyValueInformation: PVOID,
+    pub Length: ULONG,
+    pub ResultLength: PULONG,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_QUERY_MULTIPLE_VALUE_KEY.
+#[repr(C)]
+pub struct REG_QUERY_MULTIPLE_VALUE_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub ValueEntries: PVOID,
+    pub EntryCount: ULONG,
+    pub ValueBuffer: PVOID,
+    pub BufferLength: PULONG,
+    pub RequiredBufferLength: PULONG,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_SET_KEY_SECURITY.
+#[repr(C)]
+pub struct REG_SET_KEY_SECURITY_INFORMATION {
+    pub Object: PVOID,
+    pub SecurityInformation: PULONG,
+    pub SecurityDescriptor: PVOID,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_RESTORE_KEY.
+#[repr(C)]
+pub struct REG_RESTORE_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub FileHandle: HANDLE,
+    pub Flags: ULONG,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_SAVE_KEY.
+#[repr(C)]
+pub struct REG_SAVE_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub FileHandle: HANDLE,
+    pub Format: ULONG,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+/// Argument of REG_NT_PRE_REPLACE_KEY.
+#[repr(C)]
+pub struct REG_REPLACE_KEY_INFORMATION {
+    pub Object: PVOID,
+    pub OldFileName: PUNICODE_STRING,
+    pub NewFileName: PUNICODE_STRING,
+    pub CallContext: PVOID,
+    pub ObjectContext: PVOID,
+    pub Reserved: PVOID,
+}
+
+pub type PEX_CALLBACK_FUNCTION =
+    extern "system" fn(CallbackContext: PVOID, Argument1: PVOID, Argument2: PVOID) -> NTSTATUS;
+
+/// WORK_QUEUE_TYPE of wdm.h for work which may take a while.
+pub const DELAYED_WORK_QUEUE: ULONG = 1;
+
+/// Runs at PASSIVE_LEVEL in a system thread, `FltObject` is the filter it was queued with.
+pub type PFLT_GENERIC_WORKITEM_ROUTINE =
+    extern "system" fn(FltWorkItem: PVOID, FltObject: PVOID, Context: PVOID);
+
+/// Called with `CreateInfo` when a process is created and with a null one when it exits.
+pub type PCREATE_PROCESS_NOTIFY_ROUTINE_EX =
+    extern "system" fn(Process: PVOID, ProcessId: HANDLE, CreateInfo: *mut PS_CREATE_NOTIFY_INFO);
+
+#[repr(C)]
+pub enum KEY_VALUE_INFORMATION_CLASS {
+    KeyValueBasicInformation = 0,
+    KeyValueFullInformation = 1,
+    KeyValuePartialInformation = 2,
+}
+
+#[repr(C)]
+pub struct KEY_VALUE_PARTIAL_INFORMATION {
+    pub TitleIndex: ULONG,
+    pub Type: ULONG,
+    pub DataLength: ULONG,
+    pub Data: [UCHAR; 1],
+}
+
+#[repr(C)]
+pub struct FLT_VOLUME_PROPERTIES {
+    pub DeviceType: ULONG,
+    pub DeviceCharacteristics: ULONG,
+    pub DeviceObjectFlags: ULONG,
+    pub AlignmentRequirement: ULONG,
+    pub SectorSize: USHORT,
+    pub Flags: USHORT,
+    pub FileSystemDriverName: UNICODE_STRING,
+    pub FileSystemDeviceName: UNICODE_STRING,
+    pub RealDeviceName: UNICODE_STRING,
+}
+
+extern "system" {
+    pub fn CmRegisterCallbackEx(
+        Function: PEX_CALLBACK_FUNCTION,
+        Altitude: *const UNICODE_STRING,
+        Driver: PVOID,
+        Context: PVOID,
+        Cookie: *mut i64,
+        Reserved: PVOID,
+    ) -> NTSTATUS;
+
+    pub fn CmUnRegisterCallback(Cookie: i64) -> NTSTATUS;
+
+    /// The full name of the key object, e.g. `\REGISTRY\MACHINE\SYSTEM\ControlSet001\...`.
+    pub fn CmCallbackGetKeyObjectIDEx(
+        Cookie: *const i64,
+        Object: PVOID,
+        ObjectID: *mut usize,
+        ObjectName: *mut PUNICODE_STRING,
+        Flags: ULONG,
+    ) -> NTSTATUS;
+
+    pub fn CmCallbackReleaseKeyObjectIDEx(ObjectName: PUNICODE_STRING);
+
+    pub fn ExGetPreviousMode() -> KPROCESSOR_MODE;
+
+    pub fn ZwCreateKey(
+        KeyHandle: PHANDLE,
+        DesiredAccess: ACCESS_MASK,
+        ObjectAttributes: POBJECT_ATTRIBUTES,
+        TitleIndex: ULONG,
+        Class: PUNICODE_STRING,
+        CreateOptions: ULONG,
+        Disposition: PULONG,
+    ) -> NTSTATUS;
+
+    pub fn ZwQueryValueKey(
+        KeyHandle: HANDLE,
+        ValueName: PUNICODE_STRING,
+        KeyValueInformationClass: KEY_VALUE_INFORMATION_CLASS,
+        KeyValueInformation: PVOID,
+        Length: ULONG,
+        ResultLength: PULONG,
+    ) -> NTSTATUS;
+
+    pub fn ZwSetValueKey(
+        KeyHandle: HANDLE,
+        ValueName: PUNICODE_STRING,
+        TitleIndex: ULONG,
+        Type: ULONG,
+        Data: PVOID,
+        DataSize: ULONG,
+    ) -> NTSTATUS;
+
+    /// `Filter` and `Instance` are a PFLT_FILTER and a PFLT_INSTANCE, the create is only seen by
+    /// the filters below the instance.
+    pub fn FltCreateFileEx2(
+        Filter: PVOID,
+        Instance: PVOID,
+        FileHandle: PHANDLE,
+        FileObject: *mut PVOID,
+        DesiredAccess: ACCESS_MASK,
+        ObjectAttributes: POBJECT_ATTRIBUTES,
+        IoStatusBlock: *mut IO_STATUS_BLOCK,
+        AllocationSize: *const i64,
+        FileAttributes: ULONG,
+        ShareAccess: ULONG,
+        CreateDisposition: ULONG,
+        CreateOptions: ULONG,
+        EaBuffer: PVOID,
+        EaLength: ULONG,
+        Flags: ULONG,
+        DriverContext: PVOID,
+    ) -> NTSTATUS;
+
+    /// Synchronous without `CallbackRoutine`.
+    pub fn FltReadFile(
+        InitiatingInstance: PVOID,
+        FileObject: PVOID,
+        ByteOffset: *const i64,
+        Length: ULONG,
+        Buffer: PVOID,
+        Flags: ULONG,
+        BytesRead: PULONG,
+        CallbackRoutine: PVOID,
+        CallbackContext: PVOID,
+    ) -> NTSTATUS;
+
+    pub fn FltWriteFile(
+        InitiatingInstance: PVOID,
+        FileObject: PVOID,
+        ByteOffset: *const i64,
+        Length: ULONG,
+        Buffer: PVOID,
+        Flags: ULONG,
+        BytesWritten: PULONG,
+        CallbackRoutine: PVOID,
+        CallbackContext: PVOID,
+    ) -> NTSTATUS;
+
+    pub fn FltClose(FileHandle: HANDLE) -> NTSTATUS;
+
+    pub fn RtlCreateSecurityDescriptor(
+        SecurityDescriptor: *mut SECURITY_DESCRIPTOR,
+        Revision: ULONG,
+    ) -> NTSTATUS;
+
+    pub fn RtlCreateAcl(Acl: PVOID, AclLength: ULONG, AclRevision: ULONG) -> NTSTATUS;
+
+    pub fn RtlAddAccessAllowedAceEx(
+        Acl: PVOID,
+        AceRevision: ULONG,
+        AceFlags: ULONG,
+        AccessMask: ACCESS_MASK,
+        Sid: PVOID,
+    ) -> NTSTATUS;
+
+    pub fn RtlSetDaclSecurityDescriptor(
+        SecurityDescriptor: *mut SECURITY_DESCRIPTOR,
+        DaclPresent: BOOLEAN,
+        Dacl: PVOID,
+        DaclDefaulted: BOOLEAN,
+    ) -> NTSTATUS;
+
+    pub fn FltAllocateContext(
+        Filter: PVOID,
+        ContextType: USHORT,
+        ContextSize: usize,
+        PoolType: ULONG,
+        ReturnedContext: *mut PVOID,
+    ) -> NTSTATUS;
+
+    pub fn FltSetInstanceContext(
+        Instance: PVOID,
+        Operation: ULONG,
+        NewContext: PVOID,
+        OldContext: *mut PVOID,
+    ) -> NTSTATUS;
+
+    pub fn FltGetInstanceContext(Instance: PVOID, Context: *mut PVOID) -> NTSTATUS;
+
+    pub fn FltSetStreamHandleContext(
+        Instance: PVOID,
+        FileObject: PVOID,
+        Operation: ULONG,
+        NewContext: PVOID,
+        OldContext: *mut PVOID,
+    ) -> NTSTATUS;
+
+    pub fn FltGetStreamHandleContext(
+        Instance: PVOID,
+        FileObject: PVOID,
+        Context: *mut PVOID,
+    ) -> NTSTATUS;
+
+    pub fn FltReleaseContext(Context: PVOID);
+
+    pub fn FltInitializePushLock(PushLock: *mut EX_PUSH_LOCK);
+
+    /// Disables normal kernel APCs until the lock is released.
+    pub fn FltAcquirePushLockExclusive(PushLock: *mut EX_PUSH_LOCK);
+
+    pub fn FltReleasePushLock(PushLock: *mut EX_PUSH_LOCK);
+
+    pub fn FltDeletePushLock(PushLock: *mut EX_PUSH_LOCK);
+
+    pub fn FltGetVolumeName(
+        Volume: PFLT_VOLUME,
+        VolumeName: PUNICODE_STRING,
+        BufferSizeNeeded: PULONG,
+    ) -> NTSTATUS;
+
+    pub fn FltGetVolumeGuidName(
+        Volume: PFLT_VOLUME,
+        VolumeGuidName: PUNICODE_STRING,
+        BufferSizeNeeded: PULONG,
+    ) -> NTSTATUS;
+
+    pub fn FltGetVolumeProperties(
+        Volume: PFLT_VOLUME,
+        VolumeProperties: *mut FLT_VOLUME_PROPERTIES,
+        VolumePropertiesLength: ULONG,
+        LengthReturned: PULONG,
+    ) -> NTSTATUS;
+
+    pub fn FltGetDiskDeviceObject(
+        Volume: PFLT_VOLUME,
+        DiskDeviceObject: *mut PDEVICE_OBJECT,
+    ) -> NTSTATUS;
+
+    pub fn IoVolumeDeviceToDosName(VolumeDeviceObject: PVOID, DosName: PUNICODE_STRING)
+        -> NTSTATUS;
+
+    pub fn ObfDereferenceObject(Object: PVOID) -> isize;
+
+    pub fn ObfReferenceObject(Object: PVOID) -> isize;
+
+    pub fn IoGetCurrentProcess() -> PVOID;
+
+    pub fn ZwTerminateProcess(ProcessHandle: HANDLE, ExitStatus: NTSTATUS) -> NTSTATUS;
+
+    /// Exported by ntoskrnl but not documented, what NtSuspendProcess calls.
+    pub fn PsSuspendProcess(Process: PVOID) -> NTSTATUS;
+
+    /// Exported by ntoskrnl but not documented, true for the System process.
+    pub fn PsIsSystemProcess(Process: PVOID) -> BOOLEAN;
+
+    /// Exported by ntoskrnl but not documented, true for protected processes, light ones
+    /// included.
+    pub fn PsIsProtectedProcess(Process: PVOID) -> BOOLEAN;
+
+    pub fn ZwQueryInformationProcess(
+        ProcessHandle: HANDLE,
+        ProcessInformationClass: ULONG,
+        ProcessInformation: PVOID,
+        ProcessInformationLength: ULONG,
+        ReturnLength: PULONG,
+    ) -> NTSTATUS;
+
+    pub fn FltAllocateGenericWorkItem() -> PVOID;
+
+    pub fn FltQueueGenericWorkItem(
+        FltWorkItem: PVOID,
+        FltObject: PVOID,
+        WorkerRoutine: PFLT_GENERIC_WORKITEM_ROUTINE,
+        QueueType: ULONG,
+        Context: PVOID,
+    ) -> NTSTATUS;
+
+    pub fn FltFreeGenericWorkItem(FltWorkItem: PVOID);
+
+    pub fn ExFreePool(P: PVOID);
+
+    pub fn PsGetCurrentProcessId() -> HANDLE;
+
+    pub fn PsGetProcessId(Process: PVOID) -> HANDLE;
+
+    /// Needs the driver linked with /INTEGRITYCHECK.
+    pub fn PsSetCreateProcessNotifyRoutineEx(
+        NotifyRoutine: PCREATE_PROCESS_NOTIFY_ROUTINE_EX,
+        Remove: BOOLEAN,
+    ) -> NTSTATUS;
+
+    pub fn FltGetFileNameInformation(
+        CallbackData: *mut FLT_CALLBACK_DATA,
+        NameOptions: ULONG,
+        FileNameInformation: *mut *mut FLT_FILE_NAME_INFORMATION,
+    ) -> NTSTATUS;
+
+    pub fn FltReleaseFileNameInformation(FileNameInformation: *mut FLT_FILE_NAME_INFORMATION);
+
+    pub fn FltQueryInformationFile(
+        Instance: PVOID,
+        FileObject: PVOID,
+        FileInformation: PVOID,
+        Length: ULONG,
+        FileInformationClass: FILE_INFORMATION_CLASS,
+        LengthReturned: PULONG,
+    ) -> NTSTATUS;
+
+    pub fn SeSinglePrivilegeCheck(PrivilegeValue: LUID, PreviousMode: KPROCESSOR_MODE) -> BOOLEAN;
+
+    pub fn SeCaptureSubjectContext(SubjectContext: *mut SECURITY_SUBJECT_CONTEXT);
+
+    pub fn SeLockSubjectContext(SubjectContext: *mut SECURITY_SUBJECT_CONTEXT);
+
+    pub fn SeUnlockSubjectContext(SubjectContext: *mut SECURITY_SUBJECT_CONTEXT);
+
+    pub fn SeReleaseSubjectContext(SubjectContext: *mut SECURITY_SUBJECT_CONTEXT);
+
+    pub fn SeTokenIsAdmin(Token: PVOID) -> BOOLEAN;
+}
+
+#[link(name = "wdmsec")]
+extern "system" {
+    pub fn IoCreateDeviceSecure(
+        DriverObject: PDRIVER_OBJECT,
+        DeviceExtensionSize: ULONG,
+        DeviceName: *const UNICODE_STRING,
+        DeviceType: ULONG,
+        DeviceCharacteristics: ULONG,
+        Exclusive: BOOLEAN,
+        DefaultSDDLString: *const UNICODE_STRING,
+        DeviceClassGuid: *const GUID,
+        DeviceObject: *mut PDEVICE_OBJECT,
+    ) -> NTSTATUS;
+}
+
+#[link(name = "cng")]
+extern "system" {
+    pub fn BCryptGenRandom(
+        Algorithm: PVOID,
+        Buffer: *mut UCHAR,
+        BufferSize: ULONG,
+        Flags: ULONG,
+    ) -> NTSTATUS;
+}
@@ -0,0 +1,272 @@
+//! Kernel implementations of the `delprotect_core::host` traits.
+
+use alloc::{string::String, vec::Vec};
+use common::{logging::TARGET_POLICY, status::NtStatus};
+use core::{cell::RefCell, ptr::null_mut};
+use delprotect_core::{
+    Allocator, Clock, FileInfoProvider, FileNameProvider, Host, ProcessIdentity, Requestor, Stats,
+};
+use kernel_macros::NT_SUCCESS;
+use kernel_string::PUNICODE_STRING;
+use km_api_sys::{
+    flt_kernel::{FILE_INFORMATION_CLASS, FLT_CALLBACK_DATA},
+    ntddk::PROCESSINFOCLASS,
+    ntifs::{ObOpenObjectByPointer, PsGetThreadProcess},
+    ntoskrnl::{ExAllocatePool2, ExFreePoolWithTag, POOL_FLAG_PAGED},
+    wmd::{NtCurrentProcess, ZwClose, ZwQueryInformationProcess},
+};
+use winapi::{
+    km::wdm::KPROCESSOR_MODE,
+    shared::{
+        ntdef::{HANDLE, OBJ_KERNEL_HANDLE, PVOID, ULONG},
+        ntstatus::{STATUS_INSUFFICIENT_RESOURCES, STATUS_NOT_FOUND},
+    },
+};
+
+use crate::{
+    context::OpenedName,
+    ffi::{
+        BCryptGenRandom, FltGetFileNameInformation, FltQueryInformationFile,
+        FltReleaseFileNameInformation, PsGetCurrentProcessId, PsGetProcessId,
+        BCRYPT_USE_SYSTEM_PREFERRED_RNG, FILE_BASIC_INFORMATION, FILE_STANDARD_INFORMATION,
+        FLT_FILE_NAME_INFORMATION, FLT_FILE_NAME_NORMALIZED, FLT_FILE_NAME_QUERY_DEFAULT,
+    },
+    time::KeQuerySystemTime,
+    G_STATS,
+};
+
+const POOL_TAG: u32 = u32::from_ne_bytes(*b"RDER");
+
+/// Clock, random numbers and memory of the kernel. BCryptGenRandom with the system preferred
+/// RNG may be called below DISPATCH_LEVEL, so it is fine under the engine fast mutex.
+pub struct KernelHost;
+
+impl Clock for KernelHost {
+    fn now(&self) -> u64 {
+        KeQuerySystemTime()
+    }
+}
+
+impl Host for KernelHost {
+    fn fill_random(&self, buffer: &mut [u8]) -> Result<(), NtStatus> {
+        let status = unsafe {
+            BCryptGenRandom(
+                null_mut(),
+                buffer.as_mut_ptr(),
+                buffer.len() as ULONG,
+                BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+            )
+        };
+        if NT_SUCCESS!(status) {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+
+    fn stats(&self) -> &Stats {
+        &G_STATS
+    }
+}
+
+impl Allocator for KernelHost {
+    fn name_buffer(&self, units: usize) -> Option<Vec<u16>> {
+        let mut buffer = Vec::new();
+        buffer.try_reserve_exact(units).ok()?;
+        buffer.resize(units, 0);
+        Some(buffer)
+    }
+}
+
+/// Processes as seen by the kernel.
+pub struct KernelIdentity;
+
+impl ProcessIdentity for KernelIdentity {
+    fn process_id(&self, requestor: Requestor) -> u32 {
+        unsafe {
+            match requestor {
+                Requestor::Current => PsGetCurrentProcessId() as usize as u32,
+                Requestor::Thread(thread) => {
+                    let process = PsGetThreadProcess(thread as _);
+                    if process.is_null() {
+                        return 0;
+                    }
+                    PsGetProcessId(process as PVOID) as usize as u32
+                },
+            }
+        }
+    }
+
+    fn query_image_name(
+        &self,
+        requestor: Requestor,
+        buffer: &mut [u16],
+    ) -> Result<usize, NtStatus> {
+        unsafe {
+            match requestor {
+                Requestor::Current => query_process_image_name(NtCurrentProcess(), buffer),
+                Requestor::Thread(thread) => {
+                    let process = PsGetThreadProcess(thread as _);
+                    if process.is_null() {
+                        //something is wrong
+                        return Err(STATUS_NOT_FOUND);
+                    }
+
+                    let mut h_process: HANDLE = usize::MAX as HANDLE;
+                    let status = ObOpenObjectByPointer(
+                        process,
+                        OBJ_KERNEL_HANDLE,
+                        null_mut(),
+                        0,
+                        null_mut(),
+                        KPROCESSOR_MODE::KernelMode,
+                        &mut h_process,
+                    );
+                    if !NT_SUCCESS!(status) {
+                        return Err(status);
+                    }
+
+                    let result = query_process_image_name(h_process, buffer);
+                    ZwClose(h_process);
+                    result
+                },
+            }
+        }
+    }
+}
+
+/// Copies the NT image path of the process, e.g.
+/// `\Device\HarddiskVolume3\Windows\System32\cmd.exe`, into `buffer`.
+unsafe fn query_process_image_name(
+    h_process: HANDLE,
+    buffer: &mut [u16],
+) -> Result<usize, NtStatus> {
+    let process_name_size = 300;
+    let process_name =
+        ExAllocatePool2(POOL_FLAG_PAGED, process_name_size, POOL_TAG) as PUNICODE_STRING;
+
+    if process_name.is_null() {
+        log::info!(
+            target: TARGET_POLICY,
+            "fail to reserve a {} bytes of memory",
+            process_name_size
+        );
+        return Err(STATUS_INSUFFICIENT_RESOURCES);
+    }
+
+    let mut return_length: ULONG = 0;
+    let status = ZwQueryInformationProcess(
+        h_process,
+        PROCESSINFOCLASS::ProcessImageFileName,
+        process_name as PVOID,
+        (process_name_size - 2) as u32,
+        &mut return_length,
+    );
+
+    let result = if NT_SUCCESS!(status) {
+        let name = &*process_name;
+        Ok(copy_name(name.Buffer, name.Length, buffer))
+    } else {
+        Err(status)
+    };
+
+    ExFreePoolWithTag(process_name as PVOID, POOL_TAG);
+
+    result
+}
+
+/// The file of a pre-operation callback.
+pub struct CallbackFile {
+    data: *mut FLT_CALLBACK_DATA,
+    /// Set by `keep_opened_name`, taken by the pre-create callback into the handle context.
+    kept: RefCell<Option<String>>,
+}
+
+impl CallbackFile {
+    pub fn new(data: &mut FLT_CALLBACK_DATA) -> Self {
+        Self {
+            data,
+            kept: RefCell::new(None),
+        }
+    }
+
+    pub fn take_kept_name(&self) -> Option<String> {
+        self.kept.borrow_mut().take()
+    }
+}
+
+impl FileNameProvider for CallbackFile {
+    fn query_file_name(&self, buffer: &mut [u16]) -> Result<usize, NtStatus> {
+        unsafe {
+            let mut info: *mut FLT_FILE_NAME_INFORMATION = null_mut();
+            let status = FltGetFileNameInformation(
+                self.data,
+                FLT_FILE_NAME_NORMALIZED | FLT_FILE_NAME_QUERY_DEFAULT,
+                &mut info,
+            );
+            if !NT_SUCCESS!(status) {
+                return Err(status);
+            }
+
+            let name = &(*info).Name;
+            let len = copy_name(name.Buffer, name.Length, buffer);
+            FltReleaseFileNameInformation(info);
+            Ok(len)
+        }
+    }
+
+    fn opened_name(&self) -> Option<String> {
+        unsafe {
+            let iopb = &*(*self.data).Iopb;
+            OpenedName::get(iopb.TargetInstance as PVOID, iopb.TargetFileObject as PVOID)
+        }
+    }
+
+    fn keep_opened_name(&self, name: &str) {
+        *self.kept.borrow_mut() = Some(String::from(name));
+    }
+}
+
+impl CallbackFile {
+    /// Queries the file of the callback data below this filter. `T` is the structure of
+    /// `class`.
+    unsafe fn query_information<T>(&self, class: FILE_INFORMATION_CLASS) -> Result<T, NtStatus> {
+        let iopb = &*(*self.data).Iopb;
+        let mut info: T = core::mem::zeroed();
+        let status = FltQueryInformationFile(
+            iopb.TargetInstance as PVOID,
+            iopb.TargetFileObject as PVOID,
+            &mut info as *mut T as PVOID,
+            core::mem::size_of::<T>() as ULONG,
+            class,
+            null_mut(),
+        );
+        if !NT_SUCCESS!(status) {
+            return Err(status);
+        }
+        Ok(info)
+    }
+}
+
+impl FileInfoProvider for CallbackFile {
+    fn query_end_of_file(&self) -> Result<u64, NtStatus> {
+        let info: FILE_STANDARD_INFORMATION =
+            unsafe { self.query_information(FILE_INFORMATION_CLASS::FileStandardInformation)? };
+        Ok(info.EndOfFile.max(0) as u64)
+    }
+
+    fn query_attributes(&self) -> Result<u32, NtStatus> {
+        let info: FILE_BASIC_INFORMATION =
+            unsafe { self.query_information(FILE_INFORMATION_CLASS::FileBasicInformation)? };
+        Ok(info.FileAttributes)
+    }
+}
+
+/// Copies a UNICODE_STRING (`length` in bytes) into `buffer`, truncating it if needed.
+unsafe fn copy_name(source: *const u16, length: u16, buffer: &mut [u16]) -> usize {
+    let len = (length as usize / 2).min(buffer.len());
+    if len != 0 {
+        buffer[..len].copy_from_slice(core::slice::from_raw_parts(source, len));
+    }
+    len
+}
@@ -0,0 +1,112 @@
+use alloc::string::String;
+use core::{mem::size_of, ptr::null_mut};
+use kernel_macros::NT_SUCCESS;
+use km_api_sys::flt_kernel::PFLT_VOLUME;
+use winapi::{
+    km::wdm::PDEVICE_OBJECT,
+    shared::ntdef::{PVOID, ULONG, UNICODE_STRING},
+};
+
+use common::volume::{VolumeInfo, GUID_STRING_LEN, MAX_VOLUME_NAME_BYTES};
+
+use crate::ffi::{
+    ExFreePool, FltGetDiskDeviceObject, FltGetVolumeGuidName, FltGetVolumeName,
+    FltGetVolumeProperties, IoVolumeDeviceToDosName, ObfDereferenceObject, FILE_REMOVABLE_MEDIA,
+    FLT_VOLUME_PROPERTIES,
+};
+
+/// Volume properties are followed by three variable length names.
+const VOLUME_PROPERTIES_SIZE: usize = size_of::<FLT_VOLUME_PROPERTIES>() + 512;
+
+/// Collects what the attach policy needs to know about `volume`. Every query is best effort,
+/// a volume without a GUID name or a drive letter (e.g. a network redirector) simply leaves
+/// these fields empty.
+pub unsafe fn query_volume(
+    volume: PFLT_VOLUME,
+    device_type: u32,
+    filesystem_type: u32,
+) -> (VolumeInfo, String) {
+    let mut info = VolumeInfo {
+        filesystem_type,
+        device_type,
+        removable: false,
+        drive_letter: 0,
+        guid: [0; GUID_STRING_LEN],
+    };
+
+    let mut properties = [0u64; VOLUME_PROPERTIES_SIZE / size_of::<u64>()];
+    let mut returned: ULONG = 0;
+    let status = FltGetVolumeProperties(
+        volume,
+        properties.as_mut_ptr() as *mut FLT_VOLUME_PROPERTIES,
+        VOLUME_PROPERTIES_SIZE as ULONG,
+        &mut returned,
+    );
+    if NT_SUCCESS!(status) {
+        let properties = &*(properties.as_ptr() as *const FLT_VOLUME_PROPERTIES);
+        info.removable = properties.DeviceCharacteristics & FILE_REMOVABLE_MEDIA != 0;
+    }
+
+    // "\??\Volume{xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}"
+    let mut guid_name = [0u16; 64];
+    if let Some(name) = query_name(&mut guid_name, |s, needed| {
+        FltGetVolumeGuidName(volume, s, needed)
+    }) {
+        if let Some(start) = name.iter().position(|c| *c == u16::from(b'{')) {
+            let guid = &name[start..];
+            if guid.len() >= GUID_STRING_LEN + 2 {
+                for (dst, src) in info.guid.iter_mut().zip(&guid[1..=GUID_STRING_LEN]) {
+                    *dst = (*src as u8).to_ascii_uppercase();
+                }
+            }
+        }
+    }
+
+    let mut disk: PDEVICE_OBJECT = null_mut();
+    if NT_SUCCESS!(FltGetDiskDeviceObject(volume, &mut disk)) {
+        let mut dos_name = UNICODE_STRING {
+            Length: 0,
+            MaximumLength: 0,
+            Buffer: null_mut(),
+        };
+        if NT_SUCCESS!(IoVolumeDeviceToDosName(disk as PVOID, &mut dos_name)) {
+            // "C:" for volumes with a drive letter, a volume GUID path otherwise
+            if dos_name.Length >= 4 && *dos_name.Buffer.offset(1) == u16::from(b':') {
+                let letter = *dos_name.Buffer as u8;
+                if letter.is_ascii_alphabetic() {
+                    info.drive_letter = letter.to_ascii_uppercase();
+                }
+            }
+            ExFreePool(dos_name.Buffer as PVOID);
+        }
+        ObfDereferenceObject(disk as PVOID);
+    }
+
+    let mut volume_name = [0u16; MAX_VOLUME_NAME_BYTES / 2];
+    let name = query_name(&mut volume_name, |s, needed| {
+        FltGetVolumeName(volume, s, needed)
+    })
+    .map(String::from_utf16_lossy)
+    .unwrap_or_default();
+
+    (info, name)
+}
+
+/// Calls one of the FltGetVolume*Name routines with `buffer` as the output string and returns
+/// the part of it that was filled.
+unsafe fn query_name(
+    buffer: &mut [u16],
+    query: impl FnOnce(&mut UNICODE_STRING, &mut ULONG) -> i32,
+) -> Option<&[u16]> {
+    let mut name = UNICODE_STRING {
+        Length: 0,
+        MaximumLength: (buffer.len() * size_of::<u16>()) as u16,
+        Buffer: buffer.as_mut_ptr(),
+    };
+    let mut needed: ULONG = 0;
+    if !NT_SUCCESS!(query(&mut name, &mut needed)) {
+        return None;
+    }
+
+    Some(&buffer[..name.Length as usize / size_of::<u16>()])
+}
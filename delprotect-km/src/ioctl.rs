@@ -0,0 +1,40 @@
+//! The only place turning the METHOD_BUFFERED system buffer into slices. The engine decodes
+//! the slices with `common::input` and the wire formats, never with raw pointers.
+
+use common::input::{check_input, check_output, DecodeError};
+use delprotect_core::ioctl::IoctlBuffer;
+use winapi::km::wdm::IRP;
+
+/// The system buffer of a METHOD_BUFFERED request, see `IoctlBuffer`.
+pub struct SystemBuffer {
+    buffer: *mut u8,
+    input_len: usize,
+    output_len: usize,
+}
+
+impl SystemBuffer {
+    pub unsafe fn new(irp: &mut IRP, input_len: u32, output_len: u32) -> Self {
+        Self {
+            buffer: *irp.AssociatedIrp.SystemBuffer() as *mut u8,
+            input_len: input_len as usize,
+            output_len: output_len as usize,
+        }
+    }
+}
+
+impl IoctlBuffer for SystemBuffer {
+    fn input(&self) -> Result<&[u8], DecodeError> {
+        check_input(self.buffer.is_null(), self.input_len)?;
+        if self.input_len == 0 {
+            return Ok(&[]);
+        }
+
+        // the I/O manager allocated at least max(input_len, output_len) bytes
+        Ok(unsafe { core::slice::from_raw_parts(self.buffer, self.input_len) })
+    }
+
+    fn output(&mut self, min_len: usize) -> Result<&mut [u8], DecodeError> {
+        check_output(self.buffer.is_null(), self.output_len, min_len)?;
+        Ok(unsafe { core::slice::from_raw_parts_mut(self.buffer, self.output_len) })
+    }
+}
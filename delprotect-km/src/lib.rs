@@ -4,65 +4,130 @@
 extern crate alloc;
 
 mod cleaner;
+mod context;
+mod ffi;
+mod host;
+mod instance;
+mod ioctl;
+mod process;
+mod registry;
+mod registry_guard;
+mod response;
+mod security;
+mod time;
+mod vault;
 
 /// kernel-init deliver a few elements (eg. panic implementation) necessary to run code in kernel
 #[allow(unused_imports)]
 use kernel_init;
 use kernel_macros::{NT_SUCCESS, PAGED_CODE};
 
-use common::ioctl_codes;
-
-use kernel_string::{PUNICODE_STRING, UNICODE_STRING};
-use km_api_sys::{
-    flt_kernel::*,
-    ntddk::{PFILE_DISPOSITION_INFORMATION, PROCESSINFOCLASS},
-    ntifs::{ObOpenObjectByPointer, PsGetThreadProcess},
-    ntoskrnl::{ExAllocatePool2, ExFreePoolWithTag, POOL_FLAG_PAGED},
-    wmd::{NtCurrentProcess, ZwClose, ZwQueryInformationProcess, FILE_DELETE_ON_CLOSE},
+use common::{
+    auth::MAX_SECRET_SIZE,
+    detector::{DetectorSettings, DETECTOR_SETTINGS_SIZE},
+    logging::{LogLevel, TARGET_IOCTL, TARGET_LIFECYCLE, TARGET_POLICY},
+    truncation::SizeInformation,
+    volume::{VolumePolicy, MAX_VOLUME_GUIDS, VOLUME_POLICY_HEADER_SIZE},
+};
+use delprotect_core::{
+    filter::{self, EngineLock, Platform, PreOp, Published},
+    ioctl::{Caller, Persist},
+    logging, Config, Engine, ProcessIdentity, Requestor, Stats,
 };
 
+use kernel_string::UNICODE_STRING;
+use km_api_sys::{flt_kernel::*, ntddk::PFILE_DISPOSITION_INFORMATION};
+
 use kernel_log::KernelLogger;
 use log::LevelFilter;
 use winapi::{
     km::wdm::{DEVICE_TYPE, DRIVER_OBJECT, KPROCESSOR_MODE},
     shared::{
-        ntdef::{FALSE, HANDLE, NTSTATUS, OBJ_KERNEL_HANDLE, PVOID, ULONG, USHORT},
-        ntstatus::{STATUS_ACCESS_DENIED, STATUS_INSUFFICIENT_RESOURCES, STATUS_SUCCESS},
+        ntdef::{FALSE, NTSTATUS, PVOID, ULONG, USHORT},
+        ntstatus::{
+            STATUS_FLT_DO_NOT_ATTACH, STATUS_INSUFFICIENT_RESOURCES, STATUS_SUCCESS,
+            STATUS_UNSUCCESSFUL,
+        },
     },
 };
 
-use crate::cleaner::Cleaner;
+use crate::{
+    cleaner::Cleaner,
+    context::OpenedName,
+    ffi::{
+        IoCreateDeviceSecure, FILE_BASIC_INFORMATION, FILE_DEVICE_SECURE_OPEN,
+        FLTFL_FILTER_UNLOAD_MANDATORY, FLTFL_POST_OPERATION_DRAINING,
+        IRP_MJ_ACQUIRE_FOR_SECTION_SYNCHRONIZATION, IRP_PAGING_IO, KEY_READ, KEY_WRITE,
+        SYNC_TYPE_CREATE_SECTION,
+    },
+    host::{CallbackFile, KernelHost, KernelIdentity},
+    instance::query_volume,
+    ioctl::SystemBuffer,
+    registry::ParametersKey,
+    response::KernelResponder,
+    time::KeQuerySystemTime,
+    vault::KernelVault,
+};
 use winapi::{
     km::wdm::{
-        IoCompleteRequest, IoCreateDevice, IoCreateSymbolicLink, IoDeleteDevice,
-        IoDeleteSymbolicLink, IoGetCurrentIrpStackLocation, DEVICE_OBJECT, IRP, IRP_MJ,
-        PDEVICE_OBJECT,
+        IoCompleteRequest, IoCreateSymbolicLink, IoDeleteDevice, IoDeleteSymbolicLink,
+        IoGetCurrentIrpStackLocation, DEVICE_OBJECT, IRP, IRP_MJ, PDEVICE_OBJECT,
     },
-    shared::ntstatus::STATUS_INVALID_DEVICE_REQUEST,
+    shared::{guiddef::GUID, ntstatus::STATUS_INVALID_DEVICE_REQUEST},
 };
 
-use alloc::{collections::VecDeque, string::String};
+use alloc::{string::String, vec::Vec};
 use core::ptr::null_mut;
 use kernel_fast_mutex::{auto_lock::AutoLock, fast_mutex::FastMutex, locker::Locker};
 
-const POOL_TAG: u32 = u32::from_ne_bytes(*b"RDER");
-const MAX_ITEM_COUNT: usize = 32;
-
 const DEVICE_NAME: &str = "\\Device\\DelProtect";
 const SYM_LINK_NAME: &str = "\\??\\DelProtect";
-
-static mut G_PROCESS_NAMES: Option<VecDeque<String>> = None;
+/// Only SYSTEM and administrators may open the control device. Opening it is not enough to
+/// change anything, see `security::is_caller_privileged`.
+const DEVICE_SDDL: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)";
+/// {8e6d3f2a-5b1c-4c7e-9a40-2f1d7b6c9e15}, the class of the control device. Keeps the SDDL
+/// from being overridden by a class-wide security setting.
+const DEVICE_CLASS_GUID: GUID = GUID {
+    Data1: 0x8e6d3f2a,
+    Data2: 0x5b1c,
+    Data3: 0x4c7e,
+    Data4: [0x9a, 0x40, 0x2f, 0x1d, 0x7b, 0x6c, 0x9e, 0x15],
+};
+const VOLUME_POLICY_VALUE: &str = "VolumePolicy";
+const OPTIONS_VALUE: &str = "Options";
+pub(crate) const SECRET_VALUE: &str = "Secret";
+const LOG_LEVEL_VALUE: &str = "LogLevel";
+const DETECTOR_VALUE: &str = "Detector";
+const VOLUME_POLICY_MAX_SIZE: usize =
+    VOLUME_POLICY_HEADER_SIZE + MAX_VOLUME_GUIDS * common::volume::GUID_STRING_LEN;
+
+/// Rules, events, instances and tamper protection, guarded by `G_MUTEX`. See `with_engine`.
+static mut G_ENGINE: Option<Engine> = None;
+/// What the callbacks read of `G_ENGINE` without `G_MUTEX`.
+static G_PUBLISHED: Published = Published::new();
 static mut G_MUTEX: FastMutex = FastMutex::new();
-static mut G_FILTER_HANDLE: PFLT_FILTER = null_mut();
+pub(crate) static mut G_FILTER_HANDLE: PFLT_FILTER = null_mut();
+/// Counters of the callbacks, atomic so they are updated without `G_MUTEX`.
+pub(crate) static G_STATS: Stats = Stats::new();
 
 const CALLBACKS: &'static [FLT_OPERATION_REGISTRATION] = {
     &[
         FLT_OPERATION_REGISTRATION::new()
             .set_major_function(FLT_OPERATION_REGISTRATION::IRP_MJ_CREATE)
-            .set_preop(DelProtectPreCreate),
+            .set_preop(DelProtectPreCreate)
+            .set_postop(DelProtectPostCreate),
         FLT_OPERATION_REGISTRATION::new()
             .set_major_function(FLT_OPERATION_REGISTRATION::IRP_MJ_SET_INFORMATION)
             .set_preop(DelProtectPreSetInformation),
+        FLT_OPERATION_REGISTRATION::new()
+            .set_major_function(FLT_OPERATION_REGISTRATION::IRP_MJ_SET_SECURITY)
+            .set_preop(DelProtectPreSetSecurity),
+        FLT_OPERATION_REGISTRATION::new()
+            .set_major_function(FLT_OPERATION_REGISTRATION::IRP_MJ_WRITE)
+            .set_preop(DelProtectPreWrite),
+        FLT_OPERATION_REGISTRATION::new()
+            .set_major_function(IRP_MJ_ACQUIRE_FOR_SECTION_SYNCHRONIZATION)
+            .set_preop(DelProtectPreAcquireForSectionSynchronization),
         FLT_OPERATION_REGISTRATION::new()
             .set_major_function(FLT_OPERATION_REGISTRATION::IRP_MJ_OPERATION_END),
     ]
@@ -72,7 +137,7 @@ const FILTER_REGISTRATION: FLT_REGISTRATION = FLT_REGISTRATION {
     Size: ::core::mem::size_of::<FLT_REGISTRATION>() as USHORT, /*sizeof*/
     Version: FLT_REGISTRATION_VERSION,
     Flags: 0,
-    ContextRegistration: null_mut(),
+    ContextRegistration: context::CONTEXTS.as_ptr() as _,
     OperationRegistration: CALLBACKS.as_ptr(),
     FilterUnloadCallback: DelProtectUnload,
     InstanceSetupCallback: DelProtectInstanceSetup,
@@ -94,29 +159,32 @@ const FILTER_REGISTRATION: FLT_REGISTRATION = FLT_REGISTRATION {
 #[no_mangle]
 pub unsafe extern "system" fn DriverEntry(
     driver: &mut DRIVER_OBJECT,
-    _path: *const UNICODE_STRING,
+    path: *const UNICODE_STRING,
 ) -> NTSTATUS {
-    KernelLogger::init(LevelFilter::Info).expect("Failed to initialize logger");
+    // the logger passes everything, the persisted level is applied as the log max level
+    KernelLogger::init(LevelFilter::Trace).expect("Failed to initialize logger");
+    logging::set_level(LogLevel::default());
 
-    log::info!("START DelProtect");
+    log::info!(target: TARGET_LIFECYCLE, "START DelProtect");
 
     let hello_world = UNICODE_STRING::create("Hello World!");
-    log::info!("{}", hello_world.as_rust_string().unwrap_or_default());
+    log::info!(
+        target: TARGET_LIFECYCLE,
+        "{}",
+        hello_world.as_rust_string().unwrap_or_default()
+    );
 
     //--------------------GLOBALS-----------------------
     G_MUTEX.Init();
 
-    //init processes vector
-    let mut events = VecDeque::new();
-    if let Err(e) = events.try_reserve_exact(MAX_ITEM_COUNT) {
-        log::info!(
-            "fail to reserve a {} bytes of memory. Err: {:?}",
-            ::core::mem::size_of::<String>() * MAX_ITEM_COUNT,
-            e
-        );
+    registry::init((*path).as_rust_string().unwrap_or_default());
+    let config = load_config();
+    logging::set_level(config.log_level);
+    let Some(engine) = Engine::new(config) else {
         return STATUS_INSUFFICIENT_RESOURCES;
-    }
-    G_PROCESS_NAMES = Some(events);
+    };
+    G_ENGINE = Some(engine);
+    G_STATS.reset(KeQuerySystemTime());
 
     //--------------------INIT VARIABLES-----------------------
     #[allow(unused_assignments)]
@@ -124,26 +192,29 @@ pub unsafe extern "system" fn DriverEntry(
 
     let dev_name = UNICODE_STRING::from(DEVICE_NAME);
     let sym_link = UNICODE_STRING::from(SYM_LINK_NAME);
+    let sddl = UNICODE_STRING::from(DEVICE_SDDL);
 
     let mut cleaner = Cleaner::new();
     let mut device_object: PDEVICE_OBJECT = null_mut();
 
     loop {
         //--------------------DEVICE-----------------------
-        status = IoCreateDevice(
+        status = IoCreateDeviceSecure(
             driver,
             0,
-            dev_name.as_ptr(),
-            DEVICE_TYPE::FILE_DEVICE_UNKNOWN,
-            0,
+            &dev_name.as_ntdef_unicode(),
+            DEVICE_TYPE::FILE_DEVICE_UNKNOWN as ULONG,
+            FILE_DEVICE_SECURE_OPEN,
             FALSE,
+            &sddl.as_ntdef_unicode(),
+            &DEVICE_CLASS_GUID,
             &mut device_object,
         );
 
         if NT_SUCCESS!(status) {
             cleaner.init_device(device_object);
         } else {
-            log::info!("failed to create device 0x{:08x}", status);
+            log::info!(target: TARGET_LIFECYCLE, "failed to create device 0x{:08x}", status);
             break;
         }
 
@@ -153,7 +224,7 @@ pub unsafe extern "system" fn DriverEntry(
         if NT_SUCCESS!(status) {
             cleaner.init_symlink(&sym_link);
         } else {
-            log::info!("failed to create sym_link 0x{:08x}", status);
+            log::info!(target: TARGET_LIFECYCLE, "failed to create sym_link 0x{:08x}", status);
             break;
         }
 
@@ -163,14 +234,42 @@ pub unsafe extern "system" fn DriverEntry(
         if NT_SUCCESS!(status) {
             cleaner.init_filter_handle(G_FILTER_HANDLE);
         } else {
-            log::info!("failed to create sym_link 0x{:08x}", status);
+            log::info!(target: TARGET_LIFECYCLE, "failed to create sym_link 0x{:08x}", status);
+            break;
+        }
+
+        //--------------------PROCESS_NOTIFY-----------------------
+        status = process::register();
+
+        if NT_SUCCESS!(status) {
+            cleaner.init_process_notify();
+        } else {
+            log::info!(
+                target: TARGET_LIFECYCLE,
+                "failed to register the process notification 0x{:08x}",
+                status
+            );
+            break;
+        }
+
+        //--------------------REGISTRY_CALLBACK-----------------------
+        status = registry_guard::register(driver);
+
+        if NT_SUCCESS!(status) {
+            cleaner.init_registry_callback();
+        } else {
+            log::info!(
+                target: TARGET_LIFECYCLE,
+                "failed to register the registry callback 0x{:08x}",
+                status
+            );
             break;
         }
 
         //--------------------DISPATCH_ROUTINES-----------------------
         driver.DriverUnload = Some(DelProtectUnloadDriver);
-        driver.MajorFunction[IRP_MJ::CREATE as usize] = Some(DispatchCreateClose);
-        driver.MajorFunction[IRP_MJ::CLOSE as usize] = Some(DispatchCreateClose);
+        driver.MajorFunction[IRP_MJ::CREATE as usize] = Some(DispatchCreate);
+        driver.MajorFunction[IRP_MJ::CLOSE as usize] = Some(DispatchClose);
         driver.MajorFunction[IRP_MJ::DEVICE_CONTROL as usize] = Some(DispatchDeviceControl);
 
         status = FltStartFiltering(G_FILTER_HANDLE);
@@ -178,20 +277,33 @@ pub unsafe extern "system" fn DriverEntry(
     }
 
     if NT_SUCCESS!(status) {
-        log::info!("SUCCESS");
+        log::info!(target: TARGET_LIFECYCLE, "SUCCESS");
     } else {
         cleaner.clean();
     }
 
-    log::info!("SUCCESS: {}", status);
+    log::info!(target: TARGET_LIFECYCLE, "SUCCESS: {}", status);
     status
 }
 
-extern "system" fn DelProtectUnload(_flags: FLT_REGISTRATION_FLAGS) -> NTSTATUS {
-    log::info!("delprotect_unload");
+extern "system" fn DelProtectUnload(flags: FLT_REGISTRATION_FLAGS) -> NTSTATUS {
+    log::info!(target: TARGET_LIFECYCLE, "delprotect_unload");
 
     PAGED_CODE!();
     unsafe {
+        let mandatory = flags & FLTFL_FILTER_UNLOAD_MANDATORY != 0;
+        let process_id = KernelIdentity.process_id(Requestor::Current);
+        let image_name = current_image_name();
+        let now = KeQuerySystemTime();
+        let status =
+            with_engine(|engine| engine.query_unload(mandatory, process_id, &image_name, now))
+                .unwrap_or(STATUS_SUCCESS);
+        if !NT_SUCCESS!(status) {
+            return status;
+        }
+
+        registry_guard::unregister();
+        process::unregister();
         FltUnregisterFilter(G_FILTER_HANDLE);
     }
 
@@ -200,29 +312,64 @@ extern "system" fn DelProtectUnload(_flags: FLT_REGISTRATION_FLAGS) -> NTSTATUS
 
 #[link_section = "PAGE"]
 extern "system" fn DelProtectInstanceSetup(
-    _flt_objects: PFLT_RELATED_OBJECTS,
+    flt_objects: PFLT_RELATED_OBJECTS,
     _flags: FLT_INSTANCE_SETUP_FLAGS,
-    _volume_device_type: DEVICE_TYPE,
-    _volume_filesystem_type: FLT_FILESYSTEM_TYPE,
+    volume_device_type: DEVICE_TYPE,
+    volume_filesystem_type: FLT_FILESYSTEM_TYPE,
 ) -> NTSTATUS {
     //log::info!("DelProtectInstanceSetup");
     PAGED_CODE!();
+
+    unsafe {
+        let (info, volume_name) = query_volume(
+            (*flt_objects).Volume,
+            volume_device_type as u32,
+            volume_filesystem_type as u32,
+        );
+
+        let instance = (*flt_objects).Instance as usize;
+        let attach = with_engine(|engine| engine.attach_instance(instance, volume_name, info))
+            .unwrap_or(true);
+        if !attach {
+            return STATUS_FLT_DO_NOT_ATTACH;
+        }
+
+        // without its context every preserve on the volume is denied
+        let status = context::attach((*flt_objects).Filter as PVOID, instance as PVOID);
+        if !NT_SUCCESS!(status) {
+            log::info!(
+                target: TARGET_LIFECYCLE,
+                "failed to set the instance context 0x{:08x}",
+                status
+            );
+        }
+        if KernelVault::new(&*flt_objects).exists() {
+            with_engine(|engine| engine.note_vault());
+        }
+    }
+
     STATUS_SUCCESS
 }
 
+/// Called only for manual detach (`fltmc detach`, FilterDetach). Tearing down one instance
+/// must not touch the filter itself, unregistering is left to `DelProtectUnload`.
 #[link_section = "PAGE"]
 extern "system" fn DelProtectInstanceQueryTeardown(
-    _flt_objects: PFLT_RELATED_OBJECTS,
+    flt_objects: PFLT_RELATED_OBJECTS,
     _flags: FLT_INSTANCE_QUERY_TEARDOWN_FLAGS,
 ) -> NTSTATUS {
     //log::info!("DelProtectInstanceQueryTeardown");
 
     PAGED_CODE!();
     unsafe {
-        FltUnregisterFilter(G_FILTER_HANDLE);
+        let instance = (*flt_objects).Instance as usize;
+        let process_id = KernelIdentity.process_id(Requestor::Current);
+        let process_name = current_image_name();
+        let now = KeQuerySystemTime();
+
+        with_engine(|engine| engine.query_detach(instance, process_id, &process_name, now))
+            .unwrap_or(STATUS_SUCCESS)
     }
-    //log::info!("DelProtectInstanceQueryTeardown SUCCESS");
-    STATUS_SUCCESS
 }
 
 #[link_section = "PAGE"]
@@ -239,12 +386,16 @@ extern "system" fn DelProtectInstanceTeardownStart(
 
 #[link_section = "PAGE"]
 extern "system" fn DelProtectInstanceTeardownComplete(
-    _flt_objects: PFLT_RELATED_OBJECTS,
+    flt_objects: PFLT_RELATED_OBJECTS,
     _flags: FLT_INSTANCE_TEARDOWN_FLAGS,
 ) -> NTSTATUS {
     //log::info!("DelProtectInstanceTeardownComplete");
 
     PAGED_CODE!();
+    unsafe {
+        let instance = (*flt_objects).Instance as usize;
+        with_engine(|engine| engine.detach_instance(instance));
+    }
     //log::info!("DelProtectInstanceTeardownComplete SUCCESS");
     STATUS_SUCCESS
 }
@@ -254,147 +405,239 @@ extern "system" fn DelProtectInstanceTeardownComplete(
 *************************************************************************/
 extern "system" fn DelProtectPreCreate(
     data: &mut FLT_CALLBACK_DATA,
-    _flt_objects: &mut FLT_RELATED_OBJECTS,
-    _reserved: *mut PVOID,
+    flt_objects: &mut FLT_RELATED_OBJECTS,
+    completion_context: *mut PVOID,
 ) -> FLT_PREOP_CALLBACK_STATUS {
-    let mut status = FLT_PREOP_CALLBACK_STATUS::FLT_PREOP_SUCCESS_NO_CALLBACK;
+    let kernel_mode = matches!(data.RequestorMode, KPROCESSOR_MODE::KernelMode);
+    let (options, desired_access) = unsafe {
+        let create = &(*data.Iopb).Parameters.Create;
+        (create.Options, (*create.SecurityContext).DesiredAccess)
+    };
 
-    //let mut data = data as &mut FLT_CALLBACK_DATA;
-    if let KPROCESSOR_MODE::KernelMode = data.RequestorMode {
-        return status;
-    }
-    //log::info!("DelProtectPreCreate not in kernel");
+    let file = CallbackFile::new(data);
+    let vault = KernelVault::new(flt_objects);
+    let verdict = filter::pre_create(
+        &platform(&file, &vault),
+        &GlobalEngine,
+        kernel_mode,
+        options,
+        desired_access,
+    );
 
-    unsafe {
-        let params = &(*data.Iopb).Parameters.Create;
-
-        if (params.Options & FILE_DELETE_ON_CLOSE) > 0 {
-            log::info!("Delete on close");
-            if !IsDeleteAllowed(NtCurrentProcess()) {
-                *data.IoStatus.__bindgen_anon_1.Status_mut() = STATUS_ACCESS_DENIED;
-                status = FLT_PREOP_CALLBACK_STATUS::FLT_PREOP_COMPLETE;
-                log::info!("Prevent delete by cmd.exe");
-            }
+    // an open for write access decided under an immutable rule keeps its name for the handle,
+    // allocated here so that the post-create only attaches it
+    if let (PreOp::PassThrough, Some(name)) = (verdict.pre_op, file.take_kept_name()) {
+        match unsafe { OpenedName::new(flt_objects.Filter as PVOID, &name) } {
+            Ok(opened) => {
+                unsafe { *completion_context = opened.into_raw() };
+                return FLT_PREOP_CALLBACK_STATUS::FLT_PREOP_SUCCESS_WITH_CALLBACK;
+            },
+            Err(status) => {
+                log::info!(target: TARGET_POLICY, "cannot keep the name of {}: {:#x}", name, status);
+            },
         }
     }
+    complete_pre_op(data, verdict.pre_op)
+}
 
-    status
+extern "system" fn DelProtectPostCreate(
+    data: &mut FLT_CALLBACK_DATA,
+    flt_objects: &mut FLT_RELATED_OBJECTS,
+    completion_context: PVOID,
+    flags: FLT_POST_OPERATION_FLAGS,
+) -> FLT_POSTOP_CALLBACK_STATUS {
+    let opened = unsafe { OpenedName::from_raw(completion_context) };
+    let status = unsafe { *data.IoStatus.__bindgen_anon_1.Status() };
+    if status == STATUS_SUCCESS && flags & FLTFL_POST_OPERATION_DRAINING == 0 {
+        unsafe {
+            opened.attach(
+                flt_objects.Instance as PVOID,
+                flt_objects.FileObject as PVOID,
+            )
+        };
+    }
+    FLT_POSTOP_CALLBACK_STATUS::FLT_POSTOP_FINISHED_PROCESSING
 }
 
 extern "system" fn DelProtectPreSetInformation(
     data: &mut FLT_CALLBACK_DATA,
-    _flt_objects: &mut FLT_RELATED_OBJECTS,
+    flt_objects: &mut FLT_RELATED_OBJECTS,
     _reserved: *mut PVOID,
 ) -> FLT_PREOP_CALLBACK_STATUS {
     //log::info!("DelProtectPreSetInformation");
-    let mut status = FLT_PREOP_CALLBACK_STATUS::FLT_PREOP_SUCCESS_NO_CALLBACK;
-
     let params = unsafe { &(*data.Iopb).Parameters.SetFileInformation };
 
+    let thread = data.Thread as usize;
     match params.FileInformationClass {
         FILE_INFORMATION_CLASS::FileDispositionInformation
-        | FILE_INFORMATION_CLASS::FileDispositionInformationEx => {},
-        _ => return status,
+        | FILE_INFORMATION_CLASS::FileDispositionInformationEx => {
+            let info = params.InfoBuffer as PFILE_DISPOSITION_INFORMATION;
+            let delete = unsafe { (*info).DeleteFile != 0 };
+
+            let file = CallbackFile::new(data);
+            let vault = KernelVault::new(flt_objects);
+            let verdict = filter::pre_set_disposition(
+                &platform(&file, &vault),
+                &GlobalEngine,
+                thread,
+                delete,
+            );
+            complete_pre_op(data, verdict.pre_op)
+        },
+        FILE_INFORMATION_CLASS::FileRenameInformation
+        | FILE_INFORMATION_CLASS::FileRenameInformationEx => {
+            let file = CallbackFile::new(data);
+            let vault = KernelVault::new(flt_objects);
+            let verdict = filter::pre_rename(&platform(&file, &vault), &GlobalEngine, thread);
+            complete_pre_op(data, verdict.pre_op)
+        },
+        // FILE_END_OF_FILE_INFORMATION, FILE_ALLOCATION_INFORMATION and
+        // FILE_VALID_DATA_LENGTH_INFORMATION all start with the new size
+        FILE_INFORMATION_CLASS::FileEndOfFileInformation
+        | FILE_INFORMATION_CLASS::FileAllocationInformation
+        | FILE_INFORMATION_CLASS::FileValidDataLengthInformation => {
+            let Some(information) = SizeInformation::from_class(params.FileInformationClass as u32)
+            else {
+                return FLT_PREOP_CALLBACK_STATUS::FLT_PREOP_SUCCESS_NO_CALLBACK;
+            };
+            let kernel_mode = matches!(data.RequestorMode, KPROCESSOR_MODE::KernelMode);
+            let new_size = unsafe { *(params.InfoBuffer as *const i64) };
+
+            let file = CallbackFile::new(data);
+            let vault = KernelVault::new(flt_objects);
+            let verdict = filter::pre_set_size(
+                &platform(&file, &vault),
+                &GlobalEngine,
+                kernel_mode,
+                thread,
+                information,
+                new_size,
+            );
+            complete_pre_op(data, verdict.pre_op)
+        },
+        FILE_INFORMATION_CLASS::FileBasicInformation => {
+            let kernel_mode = matches!(data.RequestorMode, KPROCESSOR_MODE::KernelMode);
+            let info = params.InfoBuffer as *const FILE_BASIC_INFORMATION;
+            let file_attributes = unsafe { (*info).FileAttributes };
+
+            let file = CallbackFile::new(data);
+            let vault = KernelVault::new(flt_objects);
+            let verdict = filter::pre_set_basic(
+                &platform(&file, &vault),
+                &GlobalEngine,
+                kernel_mode,
+                thread,
+                file_attributes,
+            );
+            complete_pre_op(data, verdict.pre_op)
+        },
+        _ => FLT_PREOP_CALLBACK_STATUS::FLT_PREOP_SUCCESS_NO_CALLBACK,
     }
+}
 
-    let info = params.InfoBuffer as PFILE_DISPOSITION_INFORMATION;
-    unsafe {
-        if (*info).DeleteFile == 0 {
-            return status;
-        }
-
-        let process = PsGetThreadProcess(data.Thread);
-        if process.is_null() {
-            //something is wrong
-            return status;
-        }
-
-        let mut h_process: HANDLE = usize::MAX as HANDLE;
-        let ret = ObOpenObjectByPointer(
-            process,
-            OBJ_KERNEL_HANDLE,
-            null_mut(),
-            0,
-            null_mut(),
-            KPROCESSOR_MODE::KernelMode,
-            &mut h_process,
-        );
-        if !NT_SUCCESS!(ret) {
-            return status;
-        }
-
-        if !IsDeleteAllowed(h_process) {
-            *data.IoStatus.__bindgen_anon_1.Status_mut() = STATUS_ACCESS_DENIED;
-            status = FLT_PREOP_CALLBACK_STATUS::FLT_PREOP_COMPLETE;
-            log::info!("Prevent delete by cmd.exe");
-        }
-        ZwClose(h_process);
-    }
-    status
+extern "system" fn DelProtectPreSetSecurity(
+    data: &mut FLT_CALLBACK_DATA,
+    flt_objects: &mut FLT_RELATED_OBJECTS,
+    _reserved: *mut PVOID,
+) -> FLT_PREOP_CALLBACK_STATUS {
+    let kernel_mode = matches!(data.RequestorMode, KPROCESSOR_MODE::KernelMode);
+    let security_information = unsafe { (*data.Iopb).Parameters.SetSecurity.SecurityInformation };
+    let thread = data.Thread as usize;
+
+    let file = CallbackFile::new(data);
+    let vault = KernelVault::new(flt_objects);
+    let verdict = filter::pre_set_security(
+        &platform(&file, &vault),
+        &GlobalEngine,
+        kernel_mode,
+        thread,
+        security_information,
+    );
+    complete_pre_op(data, verdict.pre_op)
 }
 
-unsafe fn IsDeleteAllowed(h_process: HANDLE) -> bool {
-    let process_name_size = 300;
-    let process_name =
-        ExAllocatePool2(POOL_FLAG_PAGED, process_name_size, POOL_TAG) as PUNICODE_STRING;
+extern "system" fn DelProtectPreWrite(
+    data: &mut FLT_CALLBACK_DATA,
+    flt_objects: &mut FLT_RELATED_OBJECTS,
+    _reserved: *mut PVOID,
+) -> FLT_PREOP_CALLBACK_STATUS {
+    let kernel_mode = matches!(data.RequestorMode, KPROCESSOR_MODE::KernelMode);
+    let paging_io = unsafe { (*data.Iopb).IrpFlags & IRP_PAGING_IO != 0 };
+    let thread = data.Thread as usize;
+
+    let file = CallbackFile::new(data);
+    let vault = KernelVault::new(flt_objects);
+    let verdict = filter::pre_write(
+        &platform(&file, &vault),
+        &GlobalEngine,
+        kernel_mode,
+        paging_io,
+        thread,
+    );
+    complete_pre_op(data, verdict.pre_op)
+}
 
-    if process_name.is_null() {
-        log::info!("fail to reserve a {} bytes of memory", process_name_size);
-        return true;
-    }
+extern "system" fn DelProtectPreAcquireForSectionSynchronization(
+    data: &mut FLT_CALLBACK_DATA,
+    flt_objects: &mut FLT_RELATED_OBJECTS,
+    _reserved: *mut PVOID,
+) -> FLT_PREOP_CALLBACK_STATUS {
+    let kernel_mode = matches!(data.RequestorMode, KPROCESSOR_MODE::KernelMode);
+    let (create_section, page_protection) = unsafe {
+        let sync = &(*data.Iopb).Parameters.AcquireForSectionSynchronization;
+        (
+            sync.SyncType as ULONG == SYNC_TYPE_CREATE_SECTION,
+            sync.PageProtection,
+        )
+    };
 
-    let mut delete_allowed = true;
-    let mut return_length: ULONG = 0;
-    let status = ZwQueryInformationProcess(
-        h_process,
-        PROCESSINFOCLASS::ProcessImageFileName,
-        process_name as PVOID,
-        (process_name_size - 2) as u32,
-        &mut return_length,
+    let file = CallbackFile::new(data);
+    let vault = KernelVault::new(flt_objects);
+    let verdict = filter::pre_acquire_section(
+        &platform(&file, &vault),
+        &GlobalEngine,
+        kernel_mode,
+        create_section,
+        page_protection,
     );
+    complete_pre_op(data, verdict.pre_op)
+}
 
-    // log::info!(
-    //     "ZwQueryInformationProcess - status: {}, returnLength: {}",
-    //     status,
-    //     return_length
-    // );
+fn platform<'a>(file: &'a CallbackFile, vault: &'a KernelVault) -> Platform<'a> {
+    Platform {
+        identity: &KernelIdentity,
+        files: file,
+        info: file,
+        clock: &KernelHost,
+        allocator: &KernelHost,
+        stats: &G_STATS,
+        vault,
+        responder: &KernelResponder,
+    }
+}
 
-    if NT_SUCCESS!(status) {
-        let process_name = &*process_name;
-
-        if process_name.Length != 0 {
-            let rust_process_name = process_name.as_rust_string().unwrap_or_default();
-            log::info!("Delete operation from {}", rust_process_name);
-            let _locker = AutoLock::new(&mut G_MUTEX);
-            if let Some(process_names) = &G_PROCESS_NAMES {
-                for name in process_names {
-                    log::info!("name (from list) in bytes: {:?}", name.as_bytes());
-                    log::info!("name (from list): \"{}\"", name);
-                    log::info!("name to delete: \"{}\"", rust_process_name);
-                    log::info!(
-                        "name to delete in bytes: {:?}",
-                        rust_process_name.as_bytes()
-                    );
-                    if rust_process_name.contains(name) {
-                        delete_allowed = false;
-                        log::info!("DELETE BLOCK ");
-                        break;
-                    }
-                }
+fn complete_pre_op(data: &mut FLT_CALLBACK_DATA, pre_op: PreOp) -> FLT_PREOP_CALLBACK_STATUS {
+    match pre_op {
+        PreOp::PassThrough => FLT_PREOP_CALLBACK_STATUS::FLT_PREOP_SUCCESS_NO_CALLBACK,
+        PreOp::Complete(status) => {
+            unsafe {
+                *data.IoStatus.__bindgen_anon_1.Status_mut() = status;
             }
-        }
+            FLT_PREOP_CALLBACK_STATUS::FLT_PREOP_COMPLETE
+        },
     }
+}
 
-    ExFreePoolWithTag(process_name as PVOID, POOL_TAG);
-
-    delete_allowed
+/// NT image path of the current process, empty if it cannot be queried.
+pub(crate) fn current_image_name() -> String {
+    filter::image_name(&KernelIdentity, &KernelHost, Requestor::Current).unwrap_or_default()
 }
 
 /*************************************************************************
                     Dispatch  routines.
 *************************************************************************/
 extern "system" fn DelProtectUnloadDriver(driver: &mut DRIVER_OBJECT) {
-    log::info!("rust_unload");
+    log::info!(target: TARGET_LIFECYCLE, "rust_unload");
     unsafe {
         IoDeleteDevice(driver.DeviceObject);
 
@@ -403,91 +646,55 @@ extern "system" fn DelProtectUnloadDriver(driver: &mut DRIVER_OBJECT) {
     }
 }
 
-extern "system" fn DispatchCreateClose(_driver: &mut DEVICE_OBJECT, irp: &mut IRP) -> NTSTATUS {
+extern "system" fn DispatchCreate(_driver: &mut DEVICE_OBJECT, irp: &mut IRP) -> NTSTATUS {
     complete_irp_success(irp)
 }
 
-extern "system" fn DispatchDeviceControl(_driver: &mut DEVICE_OBJECT, irp: &mut IRP) -> NTSTATUS {
+extern "system" fn DispatchClose(_driver: &mut DEVICE_OBJECT, irp: &mut IRP) -> NTSTATUS {
     unsafe {
         let stack = IoGetCurrentIrpStackLocation(irp);
-        let device_io = (*stack).Parameters.DeviceIoControl();
-
-        log::info!("device_io.IoControlCode: {} ", device_io.IoControlCode);
-        match device_io.IoControlCode {
-            ioctl_codes::IOCTL_DELPROTECT_ADD_EXE_UTF8 => {
-                log::info!("IOCTL_DELPROTECT_ADD_EXE_UTF8 ");
-                let proc_name = get_rust_name_from_system_buffer_UTF8(
-                    *irp.AssociatedIrp.SystemBuffer() as PVOID as *mut u8,
-                    device_io.InputBufferLength as usize,
-                );
-
-                log::info!("proc_name: {}", proc_name);
-
-                push_item_thread_safe(&proc_name);
-            },
-            ioctl_codes::IOCTL_DELPROTECT_ADD_EXE_UTF16 => {
-                log::info!("IOCTL_DELPROTECT_ADD_EXE_UTF16 ");
-                let proc_name = get_rust_name_from_system_buffer_UTF16(
-                    *irp.AssociatedIrp.SystemBuffer() as PVOID as *mut u16,
-                    device_io.InputBufferLength as usize,
-                );
-
-                log::info!("proc_name: {}", proc_name);
-
-                push_item_thread_safe(&proc_name);
-            },
-            ioctl_codes::IOCTL_DELPROTECT_CLEAR => {
-                log::info!("before lock ");
-                let _locker = AutoLock::new(&mut G_MUTEX);
-                log::info!("after lock");
-                if let Some(events) = &mut G_PROCESS_NAMES {
-                    log::info!("before clear ");
-                    events.clear();
-                    log::info!("after clear ");
-                }
-            },
-            _ => {
-                log::info!("IOCTL_ other ");
-                return complete_irp_with_status(irp, STATUS_INVALID_DEVICE_REQUEST);
-            },
-        }
+        let file_object = (*stack).FileObject as usize;
+        with_engine(|engine| engine.close(file_object));
     }
-
     complete_irp_success(irp)
 }
 
-unsafe fn get_rust_name_from_system_buffer_UTF8(name: *mut u8, name_len_in_bytes: usize) -> String {
-    let name_len = {
-        let name_len = name_len_in_bytes / ::core::mem::size_of::<u8>();
+extern "system" fn DispatchDeviceControl(_driver: &mut DEVICE_OBJECT, irp: &mut IRP) -> NTSTATUS {
+    unsafe {
+        let stack = IoGetCurrentIrpStackLocation(irp);
+        let device_io = (*stack).Parameters.DeviceIoControl();
+        let code = device_io.IoControlCode;
+
+        // everything needing PASSIVE_LEVEL is collected before the engine is locked
+        let caller = Caller {
+            file_object: (*stack).FileObject as usize,
+            privileged: security::is_caller_privileged(irp.RequestorMode),
+            process_id: KernelIdentity.process_id(Requestor::Current),
+            image_name: current_image_name(),
+        };
+        let mut buffer = SystemBuffer::new(
+            irp,
+            device_io.InputBufferLength,
+            device_io.OutputBufferLength,
+        );
 
-        if *name.offset(name_len as isize - 1) == 0 {
-            name_len - 1
-        } else {
-            name_len
+        let result =
+            with_engine(|engine| engine.handle_ioctl(code, &mut buffer, &caller, &KernelHost))
+                .unwrap_or(Err(STATUS_INVALID_DEVICE_REQUEST));
+
+        match result {
+            Ok(reply) => {
+                let status = match reply.persist {
+                    Some(persist) => persist_state(persist, caller.file_object),
+                    None => STATUS_SUCCESS,
+                };
+                complete_irp(irp, status, reply.written)
+            },
+            Err(status) => complete_irp_with_status(irp, status),
         }
-    };
-
-    let buffer = core::slice::from_raw_parts::<u8>(name, name_len);
-    String::from_utf8_lossy(buffer).into()
+    }
 }
 
-unsafe fn get_rust_name_from_system_buffer_UTF16(
-    name: *mut u16,
-    name_len_in_bytes: usize,
-) -> String {
-    let name_len = {
-        let name_len = name_len_in_bytes / ::core::mem::size_of::<u16>();
-
-        if *name.offset(name_len as isize - 1) == 0 {
-            name_len - 1
-        } else {
-            name_len
-        }
-    };
-
-    let buffer = core::slice::from_raw_parts::<u16>(name, name_len);
-    String::from_utf16_lossy(buffer)
-}
 /*************************************************************************
                     IRP functions
 *************************************************************************/
@@ -511,24 +718,114 @@ fn complete_irp(irp: &mut IRP, status: NTSTATUS, info: usize) -> NTSTATUS {
 }
 
 /*************************************************************************
-                    Thread safe operations.
+                    Engine and persisted state.
 *************************************************************************/
-unsafe fn push_item_thread_safe(process_name: &str) {
-    let mut p_name = String::new();
-    if let Err(e) = p_name.try_reserve_exact(process_name.len()) {
-        log::info!(
-            "fail to reserve a {} bytes of memory. Err: {:?}",
-            process_name.len(),
-            e
-        );
-        return;
-    }
-    p_name.push_str(process_name);
+/// Runs `f` with `G_MUTEX` held and publishes the engine in `G_PUBLISHED`. Returns `None` if
+/// the engine was not created.
+unsafe fn with_engine<R>(f: impl FnOnce(&mut Engine) -> R) -> Option<R> {
     let _locker = AutoLock::new(&mut G_MUTEX);
-    if let Some(process_names) = &mut G_PROCESS_NAMES {
-        if process_names.len() >= MAX_ITEM_COUNT {
-            process_names.pop_front();
+    let engine = G_ENGINE.as_mut()?;
+    let result = f(engine);
+    G_PUBLISHED.publish(engine);
+    Some(result)
+}
+
+/// `G_ENGINE` for the pre-operation flow of `delprotect_core::filter`.
+struct GlobalEngine;
+
+impl EngineLock for GlobalEngine {
+    fn with_engine<R>(&self, f: impl FnOnce(&mut Engine) -> R) -> Option<R> {
+        unsafe { with_engine(f) }
+    }
+
+    fn published(&self) -> &Published {
+        &G_PUBLISHED
+    }
+}
+
+/// Reads the options, attach policy, secret, log level and detector settings persisted in the
+/// service key. Missing values leave the defaults: no options, attach everywhere, unlocked,
+/// info, no detector.
+unsafe fn load_config() -> Config {
+    let mut config = Config::default();
+    let Some(key) = ParametersKey::open(KEY_READ) else {
+        return config;
+    };
+
+    if let Some(options) = key.read_dword(OPTIONS_VALUE) {
+        config.options = options;
+    }
+    if let Some(level) = key.read_dword(LOG_LEVEL_VALUE).and_then(LogLevel::from_u32) {
+        config.log_level = level;
+    }
+
+    let mut detector = [0u8; DETECTOR_SETTINGS_SIZE];
+    if let Some(settings) = key
+        .read_binary(DETECTOR_VALUE, &mut detector)
+        .and_then(|len| DetectorSettings::decode(&detector[..len]).ok())
+    {
+        config.detector = settings;
+    }
+
+    let mut buffer = [0u8; VOLUME_POLICY_MAX_SIZE];
+    if let Some(policy) = key
+        .read_binary(VOLUME_POLICY_VALUE, &mut buffer)
+        .and_then(|len| VolumePolicy::decode(&buffer[..len]).ok())
+    {
+        config.volume_policy = policy;
+    }
+
+    let mut secret = [0u8; MAX_SECRET_SIZE];
+    if let Some(len) = key.read_binary(SECRET_VALUE, &mut secret) {
+        let mut copy = Vec::new();
+        if copy.try_reserve_exact(len).is_ok() {
+            copy.extend_from_slice(&secret[..len]);
+            config.secret = Some(copy);
         }
-        process_names.push_back(p_name);
+    }
+
+    config
+}
+
+/// Writes what an IOCTL changed to the service key. Registry routines need PASSIVE_LEVEL, so
+/// this runs after the engine is unlocked.
+unsafe fn persist_state(persist: Persist, file_object: usize) -> NTSTATUS {
+    let Some(key) = ParametersKey::open(KEY_WRITE) else {
+        // without the key the change only lasts until the next boot, except for the secret
+        return match persist {
+            Persist::Secret(_) => STATUS_UNSUCCESSFUL,
+            _ => STATUS_SUCCESS,
+        };
+    };
+
+    match persist {
+        Persist::Options(options) => key.write_dword(OPTIONS_VALUE, options),
+        Persist::VolumePolicy(policy) => key.write_binary(VOLUME_POLICY_VALUE, &policy),
+        Persist::LogLevel(level) => key.write_dword(LOG_LEVEL_VALUE, level as u32),
+        Persist::Detector(settings) => {
+            let mut value = [0u8; DETECTOR_SETTINGS_SIZE];
+            settings.encode(&mut value);
+            key.write_binary(DETECTOR_VALUE, &value)
+        },
+        Persist::Secret(secret) => {
+            // committed under the lock first, the handle may have lost its authorization
+            let previous = match with_engine(|engine| engine.commit_secret(file_object, &secret)) {
+                Some(Ok(previous)) => previous,
+                Some(Err(status)) => return status,
+                None => return STATUS_UNSUCCESSFUL,
+            };
+
+            // a secret which is not persisted would unlock the driver after the next boot
+            let status = key.write_binary(SECRET_VALUE, &secret);
+            if !NT_SUCCESS!(status) {
+                log::info!(
+                    target: TARGET_IOCTL,
+                    "failed to persist the secret 0x{:08x}",
+                    status
+                );
+                with_engine(|engine| engine.restore_secret(&secret, previous));
+            }
+            status
+        },
     }
 }
@@ -0,0 +1,61 @@
+//! Process creation and exit notifications. A locked down process may pass its lockdown on to
+//! the processes it creates, the lineage of `inherit` rules follows them, and both last as
+//! long as the process.
+
+use alloc::string::String;
+use winapi::shared::ntdef::{FALSE, HANDLE, NTSTATUS, PVOID, TRUE, UNICODE_STRING};
+
+use crate::{
+    current_image_name,
+    ffi::{PsSetCreateProcessNotifyRoutineEx, PS_CREATE_NOTIFY_INFO},
+    time::KeQuerySystemTime,
+    with_engine,
+};
+
+pub fn register() -> NTSTATUS {
+    unsafe { PsSetCreateProcessNotifyRoutineEx(DelProtectProcessNotify, FALSE) }
+}
+
+pub fn unregister() {
+    unsafe {
+        PsSetCreateProcessNotifyRoutineEx(DelProtectProcessNotify, TRUE);
+    }
+}
+
+/// Runs at PASSIVE_LEVEL in the context of the creating or the exiting process. The creator
+/// is the parent unless it was given another one, lineages follow the creator so that a
+/// borrowed parent does not take the process out of them.
+extern "system" fn DelProtectProcessNotify(
+    _process: PVOID,
+    process_id: HANDLE,
+    create_info: *mut PS_CREATE_NOTIFY_INFO,
+) {
+    let process_id = process_id as usize as u32;
+    unsafe {
+        if create_info.is_null() {
+            with_engine(|engine| engine.process_exited(process_id));
+            return;
+        }
+
+        let creator_id = (*create_info).CreatingThreadId.UniqueProcess as usize as u32;
+        let image_name = unicode_to_string((*create_info).ImageFileName);
+        // the image of the creator is only needed to start a lineage
+        let creator_image = match with_engine(|engine| engine.tracks_lineage()) {
+            Some(true) => current_image_name(),
+            _ => String::new(),
+        };
+        let now = KeQuerySystemTime();
+        with_engine(|engine| {
+            engine.process_created(creator_id, &creator_image, process_id, &image_name, now)
+        });
+    }
+}
+
+/// Empty if the name is missing.
+unsafe fn unicode_to_string(name: *const UNICODE_STRING) -> String {
+    if name.is_null() || (*name).Buffer.is_null() {
+        return String::new();
+    }
+    let units = core::slice::from_raw_parts((*name).Buffer, (*name).Length as usize / 2);
+    String::from_utf16_lossy(units)
+}
@@ -0,0 +1,233 @@
+use alloc::{string::String, vec::Vec};
+use common::logging::TARGET_LIFECYCLE;
+use core::{mem::size_of, ptr::null_mut};
+use kernel_macros::NT_SUCCESS;
+use km_api_sys::wmd::ZwClose;
+use winapi::shared::ntdef::{
+    HANDLE, NTSTATUS, OBJECT_ATTRIBUTES, OBJ_CASE_INSENSITIVE, OBJ_KERNEL_HANDLE, PVOID, ULONG,
+    UNICODE_STRING,
+};
+
+use crate::ffi::{
+    ZwCreateKey, ZwQueryValueKey, ZwSetValueKey, ACCESS_MASK, KEY_VALUE_INFORMATION_CLASS,
+    KEY_VALUE_PARTIAL_INFORMATION, REG_BINARY, REG_DWORD, REG_OPTION_NON_VOLATILE,
+};
+
+const PARAMETERS_KEY: &str = "Parameters";
+/// Offset of `Data` in KEY_VALUE_PARTIAL_INFORMATION.
+const PARTIAL_INFORMATION_HEADER: usize = 3 * size_of::<ULONG>();
+
+/// Service key path passed to DriverEntry, e.g.
+/// `\REGISTRY\MACHINE\SYSTEM\ControlSet001\Services\DelProtect`.
+static mut G_SERVICE_KEY_PATH: Option<String> = None;
+
+pub unsafe fn init(service_key_path: String) {
+    G_SERVICE_KEY_PATH = Some(service_key_path);
+}
+
+/// The keys of the service the registry callback guards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServiceKey {
+    /// The service key, whose `Start` and `ImagePath` values load the driver.
+    Service,
+    /// Its `Parameters` subkey.
+    Parameters,
+}
+
+/// Which key of the service `name`, the full name of a key object, is.
+pub unsafe fn service_key(name: &[u16]) -> Option<ServiceKey> {
+    let service_path = G_SERVICE_KEY_PATH.as_deref()?;
+    let rest = strip_prefix(name, service_path)?;
+    if rest.is_empty() {
+        return Some(ServiceKey::Service);
+    }
+    let rest = strip_prefix(strip_prefix(rest, "\\")?, PARAMETERS_KEY)?;
+    rest.is_empty().then_some(ServiceKey::Parameters)
+}
+
+/// True if `name` is `expected`, ignoring the case of ASCII letters.
+pub fn is_named(name: &[u16], expected: &str) -> bool {
+    strip_prefix(name, expected).is_some_and(|rest| rest.is_empty())
+}
+
+/// `name` without `prefix`, ignoring the case of ASCII letters.
+fn strip_prefix<'a>(name: &'a [u16], prefix: &str) -> Option<&'a [u16]> {
+    let mut rest = name;
+    for unit in prefix.encode_utf16() {
+        match rest.split_first() {
+            Some((&actual, tail)) if eq_ignore_ascii_case(actual, unit) => rest = tail,
+            _ => return None,
+        }
+    }
+    Some(rest)
+}
+
+fn eq_ignore_ascii_case(a: u16, b: u16) -> bool {
+    let lower = |unit: u16| match unit {
+        0x41..=0x5A => unit + 0x20,
+        _ => unit,
+    };
+    lower(a) == lower(b)
+}
+
+/// UTF-16 copy of a Rust string which stays alive as long as the UNICODE_STRING built from it.
+struct WideString {
+    buffer: Vec<u16>,
+}
+
+impl WideString {
+    fn new(s: &str) -> Option<Self> {
+        let mut buffer = Vec::new();
+        buffer.try_reserve_exact(s.len()).ok()?;
+        buffer.extend(s.encode_utf16());
+        Some(Self { buffer })
+    }
+
+    fn as_unicode(&mut self) -> UNICODE_STRING {
+        let len = (self.buffer.len() * size_of::<u16>()) as u16;
+        UNICODE_STRING {
+            Length: len,
+            MaximumLength: len,
+            Buffer: self.buffer.as_mut_ptr(),
+        }
+    }
+}
+
+/// Handle to the `Parameters` subkey of the service key, where DelProtect persists its
+/// configuration. Values are written by the driver and by the INF at install time.
+pub struct ParametersKey {
+    handle: HANDLE,
+}
+
+impl ParametersKey {
+    pub unsafe fn open(access: ACCESS_MASK) -> Option<Self> {
+        let service_path = G_SERVICE_KEY_PATH.as_deref()?;
+        let service = Self::create(null_mut(), service_path, access)?;
+        Self::create(service.handle, PARAMETERS_KEY, access)
+    }
+
+    unsafe fn create(root: HANDLE, path: &str, access: ACCESS_MASK) -> Option<Self> {
+        let mut wide_path = WideString::new(path)?;
+        let mut name = wide_path.as_unicode();
+        let mut attributes = OBJECT_ATTRIBUTES {
+            Length: size_of::<OBJECT_ATTRIBUTES>() as ULONG,
+            RootDirectory: root,
+            ObjectName: &mut name,
+            Attributes: OBJ_CASE_INSENSITIVE | OBJ_KERNEL_HANDLE,
+            SecurityDescriptor: null_mut(),
+            SecurityQualityOfService: null_mut(),
+        };
+
+        let mut handle: HANDLE = null_mut();
+        let status = ZwCreateKey(
+            &mut handle,
+            access,
+            &mut attributes,
+            0,
+            null_mut(),
+            REG_OPTION_NON_VOLATILE,
+            null_mut(),
+        );
+        if !NT_SUCCESS!(status) {
+            log::info!(
+                target: TARGET_LIFECYCLE,
+                "failed to open registry key {} 0x{:08x}",
+                path,
+                status
+            );
+            return None;
+        }
+
+        Some(Self { handle })
+    }
+
+    /// Reads a REG_BINARY value into `buffer` and returns its length. Values longer than
+    /// `buffer` are treated as missing.
+    pub unsafe fn read_binary(&self, value_name: &str, buffer: &mut [u8]) -> Option<usize> {
+        let mut info = Vec::new();
+        info.try_reserve_exact(PARTIAL_INFORMATION_HEADER + buffer.len())
+            .ok()?;
+        info.resize(PARTIAL_INFORMATION_HEADER + buffer.len(), 0u8);
+
+        let mut name = WideString::new(value_name)?;
+        let mut result_length: ULONG = 0;
+        let status = ZwQueryValueKey(
+            self.handle,
+            &mut name.as_unicode(),
+            KEY_VALUE_INFORMATION_CLASS::KeyValuePartialInformation,
+            info.as_mut_ptr() as PVOID,
+            info.len() as ULONG,
+            &mut result_length,
+        );
+        if !NT_SUCCESS!(status) {
+            return None;
+        }
+
+        let header = &*(info.as_ptr() as *const KEY_VALUE_PARTIAL_INFORMATION);
+        let len = header.DataLength as usize;
+        if header.Type != REG_BINARY || len > buffer.len() {
+            return None;
+        }
+
+        buffer[..len]
+            .copy_from_slice(&info[PARTIAL_INFORMATION_HEADER..PARTIAL_INFORMATION_HEADER + len]);
+        Some(len)
+    }
+
+    pub unsafe fn read_dword(&self, value_name: &str) -> Option<u32> {
+        let mut info = [0u8; PARTIAL_INFORMATION_HEADER + size_of::<u32>()];
+        let mut name = WideString::new(value_name)?;
+        let mut result_length: ULONG = 0;
+        let status = ZwQueryValueKey(
+            self.handle,
+            &mut name.as_unicode(),
+            KEY_VALUE_INFORMATION_CLASS::KeyValuePartialInformation,
+            info.as_mut_ptr() as PVOID,
+            info.len() as ULONG,
+            &mut result_length,
+        );
+        if !NT_SUCCESS!(status) {
+            return None;
+        }
+
+        let header = &*(info.as_ptr() as *const KEY_VALUE_PARTIAL_INFORMATION);
+        if header.Type != REG_DWORD || header.DataLength as usize != size_of::<u32>() {
+            return None;
+        }
+
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&info[PARTIAL_INFORMATION_HEADER..]);
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    pub unsafe fn write_binary(&self, value_name: &str, data: &[u8]) -> NTSTATUS {
+        self.write(value_name, REG_BINARY, data)
+    }
+
+    pub unsafe fn write_dword(&self, value_name: &str, value: u32) -> NTSTATUS {
+        self.write(value_name, REG_DWORD, &value.to_le_bytes())
+    }
+
+    unsafe fn write(&self, value_name: &str, value_type: ULONG, data: &[u8]) -> NTSTATUS {
+        let Some(mut name) = WideString::new(value_name) else {
+            return winapi::shared::ntstatus::STATUS_INSUFFICIENT_RESOURCES;
+        };
+
+        ZwSetValueKey(
+            self.handle,
+            &mut name.as_unicode(),
+            0,
+            value_type,
+            data.as_ptr() as PVOID,
+            data.len() as ULONG,
+        )
+    }
+}
+
+impl Drop for ParametersKey {
+    fn drop(&mut self) {
+        unsafe {
+            ZwClose(self.handle);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Registry callback guarding the service while tamper protection is locked. The `Parameters`
+//! key holds the secret the challenges are answered with: user mode may neither read that value
+//! nor change, delete, rename, restore, save or re-secure anything in the key, administrators
+//! included. In the service key itself, the `Start` and `ImagePath` values which load the driver
+//! may not be changed or deleted. The driver itself writes the keys with Zw routines, which
+//! come from kernel mode.
+
+use common::logging::TARGET_IOCTL;
+use core::ptr::null_mut;
+use kernel_string::UNICODE_STRING;
+use winapi::{
+    km::wdm::{DRIVER_OBJECT, KPROCESSOR_MODE},
+    shared::{
+        ntdef::{NTSTATUS, PUNICODE_STRING, PVOID},
+        ntstatus::{STATUS_ACCESS_DENIED, STATUS_SUCCESS},
+    },
+};
+
+use crate::{
+    ffi::{
+        CmCallbackGetKeyObjectIDEx, CmCallbackReleaseKeyObjectIDEx, CmRegisterCallbackEx,
+        CmUnRegisterCallback, ExGetPreviousMode, KEY_VALUE_INFORMATION_CLASS,
+        REG_DELETE_KEY_INFORMATION, REG_DELETE_VALUE_KEY_INFORMATION,
+        REG_ENUMERATE_VALUE_KEY_INFORMATION, REG_NT_PRE_DELETE_KEY, REG_NT_PRE_DELETE_VALUE_KEY,
+        REG_NT_PRE_ENUMERATE_VALUE_KEY, REG_NT_PRE_QUERY_MULTIPLE_VALUE_KEY,
+        REG_NT_PRE_QUERY_VALUE_KEY, REG_NT_PRE_RENAME_KEY, REG_NT_PRE_REPLACE_KEY,
+        REG_NT_PRE_RESTORE_KEY, REG_NT_PRE_SAVE_KEY, REG_NT_PRE_SET_INFORMATION_KEY,
+        REG_NT_PRE_SET_KEY_SECURITY, REG_NT_PRE_SET_VALUE_KEY,
+        REG_QUERY_MULTIPLE_VALUE_KEY_INFORMATION, REG_QUERY_VALUE_KEY_INFORMATION,
+        REG_RENAME_KEY_INFORMATION, REG_REPLACE_KEY_INFORMATION, REG_RESTORE_KEY_INFORMATION,
+        REG_SAVE_KEY_INFORMATION, REG_SET_INFORMATION_KEY_INFORMATION,
+        REG_SET_KEY_SECURITY_INFORMATION, REG_SET_VALUE_KEY_INFORMATION,
+    },
+    registry::{is_named, service_key, ServiceKey},
+    with_engine, SECRET_VALUE,
+};
+
+/// Values of the service key which decide whether and what the system loads.
+const LOAD_VALUES: [&str; 2] = ["Start", "ImagePath"];
+
+/// Altitude of the registry callback, next to the one of the minifilter.
+const ALTITUDE: &str = "345101";
+
+/// Identifies the callback, set by `register`.
+static mut G_COOKIE: i64 = 0;
+
+pub fn register(driver: &mut DRIVER_OBJECT) -> NTSTATUS {
+    let altitude = UNICODE_STRING::from(ALTITUDE);
+    unsafe {
+        CmRegisterCallbackEx(
+            DelProtectRegistryCallback,
+            &altitude.as_ntdef_unicode(),
+            driver as *mut DRIVER_OBJECT as PVOID,
+            null_mut(),
+            &mut G_COOKIE,
+            null_mut(),
+        )
+    }
+}
+
+pub fn unregister() {
+    unsafe {
+        CmUnRegisterCallback(G_COOKIE);
+    }
+}
+
+/// What an operation does to the key it targets, from the REG_*_INFORMATION of its class.
+enum Access {
+    /// Reads the value named `ValueName`.
+    ReadValue(PUNICODE_STRING),
+    /// Changes or deletes the value named `ValueName`.
+    WriteValue(PUNICODE_STRING),
+    /// Reads the data of any value.
+    ReadValues,
+    /// Changes the key as a whole.
+    WriteKey,
+}
+
+/// The key object and the access of the operation of `class`, `None` for the classes which
+/// are not guarded.
+unsafe fn access(class: u32, argument: PVOID) -> Option<(PVOID, Access)> {
+    Some(match class {
+        REG_NT_PRE_SET_VALUE_KEY => {
+            let info = &*(argument as *const REG_SET_VALUE_KEY_INFORMATION);
+            (info.Object, Access::WriteValue(info.ValueName))
+        },
+        REG_NT_PRE_DELETE_VALUE_KEY => {
+            let info = &*(argument as *const REG_DELETE_VALUE_KEY_INFORMATION);
+            (info.Object, Access::WriteValue(info.ValueName))
+        },
+        REG_NT_PRE_QUERY_VALUE_KEY => {
+            let info = &*(argument as *const REG_QUERY_VALUE_KEY_INFORMATION);
+            (info.Object, Access::ReadValue(info.ValueName))
+        },
+        REG_NT_PRE_ENUMERATE_VALUE_KEY => {
+            let info = &*(argument as *const REG_ENUMERATE_VALUE_KEY_INFORMATION);
+            // the names alone do not give the secret away
+            if matches!(
+                info.KeyValueInformationClass,
+                KEY_VALUE_INFORMATION_CLASS::KeyValueBasicInformation
+            ) {
+                return None;
+            }
+            (info.Object, Access::ReadValues)
+        },
+        REG_NT_PRE_QUERY_MULTIPLE_VALUE_KEY => {
+            let info = &*(argument as *const REG_QUERY_MULTIPLE_VALUE_KEY_INFORMATION);
+            (info.Object, Access::ReadValues)
+        },
+        REG_NT_PRE_DELETE_KEY => {
+            let info = &*(argument as *const REG_DELETE_KEY_INFORMATION);
+            (info.Object, Access::WriteKey)
+        },
+        REG_NT_PRE_SET_INFORMATION_KEY => {
+            let info = &*(argument as *const REG_SET_INFORMATION_KEY_INFORMATION);
+            (info.Object, Access::WriteKey)
+        },
+        REG_NT_PRE_RENAME_KEY => {
+            let info = &*(argument as *const REG_RENAME_KEY_INFORMATION);
+            (info.Object, Access::WriteKey)
+        },
+        REG_NT_PRE_SET_KEY_SECURITY => {
+            let info = &*(argument as *const REG_SET_KEY_SECURITY_INFORMATION);
+            (info.Object, Access::WriteKey)
+        },
+        REG_NT_PRE_RESTORE_KEY => {
+            let info = &*(argument as *const REG_RESTORE_KEY_INFORMATION);
+            (info.Object, Access::WriteKey)
+        },
+        REG_NT_PRE_REPLACE_KEY => {
+            let info = &*(argument as *const REG_REPLACE_KEY_INFORMATION);
+            (info.Object, Access::WriteKey)
+        },
+        REG_NT_PRE_SAVE_KEY => {
+            let info = &*(argument as *const REG_SAVE_KEY_INFORMATION);
+            (info.Object, Access::WriteKey)
+        },
+        _ => return None,
+    })
+}
+
+/// True if tamper protection keeps user mode from doing `access` to `key`.
+unsafe fn is_guarded(key: ServiceKey, access: &Access) -> bool {
+    match (key, access) {
+        (ServiceKey::Parameters, Access::ReadValue(name)) => is_value(*name, SECRET_VALUE),
+        (ServiceKey::Parameters, _) => true,
+        (ServiceKey::Service, Access::WriteValue(name)) => {
+            LOAD_VALUES.iter().any(|value| is_value(*name, value))
+        },
+        (ServiceKey::Service, _) => false,
+    }
+}
+
+/// Runs at PASSIVE_LEVEL in the context of the thread doing the registry operation.
+extern "system" fn DelProtectRegistryCallback(
+    _context: PVOID,
+    argument1: PVOID,
+    argument2: PVOID,
+) -> NTSTATUS {
+    let class = argument1 as usize as u32;
+    unsafe {
+        if argument2.is_null() || matches!(ExGetPreviousMode(), KPROCESSOR_MODE::KernelMode) {
+            return STATUS_SUCCESS;
+        }
+
+        let Some((object, access)) = access(class, argument2) else {
+            return STATUS_SUCCESS;
+        };
+        if !key_of(object).is_some_and(|key| is_guarded(key, &access)) {
+            return STATUS_SUCCESS;
+        }
+
+        if with_engine(|engine| engine.auth().is_locked()).unwrap_or(false) {
+            log::info!(
+                target: TARGET_IOCTL,
+                "registry operation {} on the service keys refused",
+                class
+            );
+            return STATUS_ACCESS_DENIED;
+        }
+    }
+    STATUS_SUCCESS
+}
+
+unsafe fn key_of(object: PVOID) -> Option<ServiceKey> {
+    let mut name: PUNICODE_STRING = null_mut();
+    let status = CmCallbackGetKeyObjectIDEx(&G_COOKIE, object, null_mut(), &mut name, 0);
+    if status != STATUS_SUCCESS || name.is_null() {
+        return None;
+    }
+
+    let units = core::slice::from_raw_parts((*name).Buffer, (*name).Length as usize / 2);
+    let key = service_key(units);
+    CmCallbackReleaseKeyObjectIDEx(name);
+    key
+}
+
+/// True if `name` is the value `expected`, the default value is none of them.
+unsafe fn is_value(name: PUNICODE_STRING, expected: &str) -> bool {
+    if name.is_null() || (*name).Buffer.is_null() {
+        return false;
+    }
+    let units = core::slice::from_raw_parts((*name).Buffer, (*name).Length as usize / 2);
+    is_named(units, expected)
+}
@@ -0,0 +1,160 @@
+//! Terminates or suspends the process whose delete a rule denied, see
+//! `common::rule::Response`. Neither may run in the pre-operation callback: it holds the
+//! file system locks of the delete, and terminating a process waits for its threads, the
+//! requesting one included. The process is referenced and handed to a generic work item of
+//! the filter, so the unload waits for it. The System process, protected processes and
+//! critical ones, whose end bugchecks the system, are never touched: the response is skipped
+//! and counted as failed.
+
+use common::{logging::TARGET_POLICY, rule::Response, stats::Counter, status::NtStatus};
+use core::{mem::size_of, ptr::null_mut};
+use delprotect_core::{Requestor, Responder};
+use kernel_macros::NT_SUCCESS;
+use km_api_sys::{
+    ntifs::{ObOpenObjectByPointer, PsGetThreadProcess},
+    wmd::ZwClose,
+};
+use winapi::{
+    km::wdm::KPROCESSOR_MODE,
+    shared::{
+        ntdef::{HANDLE, NTSTATUS, OBJ_KERNEL_HANDLE, PVOID, ULONG},
+        ntstatus::{
+            STATUS_ACCESS_DENIED, STATUS_INSUFFICIENT_RESOURCES, STATUS_INVALID_PARAMETER,
+            STATUS_NOT_FOUND, STATUS_SUCCESS,
+        },
+    },
+};
+
+use crate::{
+    ffi::{
+        FltAllocateGenericWorkItem, FltFreeGenericWorkItem, FltQueueGenericWorkItem,
+        IoGetCurrentProcess, ObfDereferenceObject, ObfReferenceObject, PsIsProtectedProcess,
+        PsIsSystemProcess, PsSuspendProcess, ZwQueryInformationProcess, ZwTerminateProcess,
+        DELAYED_WORK_QUEUE, PFLT_GENERIC_WORKITEM_ROUTINE, PROCESS_BREAK_ON_TERMINATION,
+    },
+    G_FILTER_HANDLE, G_STATS,
+};
+
+/// Queues the responses as work items of the filter, the callbacks only run once it is
+/// registered.
+pub struct KernelResponder;
+
+impl Responder for KernelResponder {
+    fn queue_response(&self, requestor: Requestor, response: Response) -> Result<(), NtStatus> {
+        let routine: PFLT_GENERIC_WORKITEM_ROUTINE = match response {
+            Response::Terminate => terminate,
+            Response::Suspend => suspend,
+            Response::None | Response::Demote => return Err(STATUS_INVALID_PARAMETER),
+        };
+
+        unsafe {
+            let process = match requestor {
+                Requestor::Current => IoGetCurrentProcess(),
+                Requestor::Thread(thread) => PsGetThreadProcess(thread as _) as PVOID,
+            };
+            if process.is_null() {
+                return Err(STATUS_NOT_FOUND);
+            }
+
+            let work_item = FltAllocateGenericWorkItem();
+            if work_item.is_null() {
+                return Err(STATUS_INSUFFICIENT_RESOURCES);
+            }
+            // released by the routine
+            ObfReferenceObject(process);
+            let status = FltQueueGenericWorkItem(
+                work_item,
+                G_FILTER_HANDLE as PVOID,
+                routine,
+                DELAYED_WORK_QUEUE,
+                process,
+            );
+            if !NT_SUCCESS!(status) {
+                ObfDereferenceObject(process);
+                FltFreeGenericWorkItem(work_item);
+                return Err(status);
+            }
+        }
+        Ok(())
+    }
+}
+
+extern "system" fn terminate(work_item: PVOID, _filter: PVOID, process: PVOID) {
+    let status = unsafe {
+        with_handle(process, |h_process| match may_respond(process, h_process) {
+            STATUS_SUCCESS => ZwTerminateProcess(h_process, STATUS_ACCESS_DENIED),
+            status => status,
+        })
+    };
+    finish(work_item, process, "terminate", status);
+}
+
+extern "system" fn suspend(work_item: PVOID, _filter: PVOID, process: PVOID) {
+    let status = unsafe {
+        with_handle(process, |h_process| match may_respond(process, h_process) {
+            STATUS_SUCCESS => PsSuspendProcess(process),
+            status => status,
+        })
+    };
+    finish(work_item, process, "suspend", status);
+}
+
+/// Runs `f` with a kernel handle of the process.
+unsafe fn with_handle(process: PVOID, f: impl FnOnce(HANDLE) -> NTSTATUS) -> NTSTATUS {
+    let mut h_process: HANDLE = usize::MAX as HANDLE;
+    let status = ObOpenObjectByPointer(
+        process as _,
+        OBJ_KERNEL_HANDLE,
+        null_mut(),
+        0,
+        null_mut(),
+        KPROCESSOR_MODE::KernelMode,
+        &mut h_process,
+    );
+    if !NT_SUCCESS!(status) {
+        return status;
+    }
+    let status = f(h_process);
+    ZwClose(h_process);
+    status
+}
+
+/// STATUS_ACCESS_DENIED for the System process, a protected or a critical process, the
+/// status of the query if it cannot be told whether the process is critical.
+unsafe fn may_respond(process: PVOID, h_process: HANDLE) -> NTSTATUS {
+    if PsIsSystemProcess(process) != 0 || PsIsProtectedProcess(process) != 0 {
+        return STATUS_ACCESS_DENIED;
+    }
+    let mut critical: ULONG = 0;
+    let status = ZwQueryInformationProcess(
+        h_process,
+        PROCESS_BREAK_ON_TERMINATION,
+        &mut critical as *mut ULONG as PVOID,
+        size_of::<ULONG>() as ULONG,
+        null_mut(),
+    );
+    if !NT_SUCCESS!(status) {
+        status
+    } else if critical != 0 {
+        STATUS_ACCESS_DENIED
+    } else {
+        STATUS_SUCCESS
+    }
+}
+
+/// Counts a failed response and releases what `queue_response` took.
+fn finish(work_item: PVOID, process: PVOID, action: &str, status: NTSTATUS) {
+    if !NT_SUCCESS!(status) {
+        G_STATS.count(Counter::ResponseFailures);
+        log::warn!(
+            target: TARGET_POLICY,
+            "cannot {} process 0x{:08x}",
+            action,
+            status
+        );
+    }
+    unsafe {
+        ObfDereferenceObject(process);
+        FltFreeGenericWorkItem(work_item);
+    }
+}
@@ -0,0 +1,51 @@
+//! Checks of the caller of a control device request. The device SDDL already limits who can
+//! open it; these checks keep mutating requests for elevated callers even if the SDDL is
+//! overridden, e.g. by a class-wide security setting.
+
+use core::ptr::null_mut;
+use winapi::{km::wdm::KPROCESSOR_MODE, shared::ntdef::LUID};
+
+use crate::ffi::{
+    SeCaptureSubjectContext, SeLockSubjectContext, SeReleaseSubjectContext, SeSinglePrivilegeCheck,
+    SeTokenIsAdmin, SeUnlockSubjectContext, SECURITY_SUBJECT_CONTEXT, SE_LOAD_DRIVER_PRIVILEGE,
+};
+
+/// True for kernel callers, callers holding an enabled SeLoadDriverPrivilege (anyone able to
+/// load drivers can replace DelProtect anyway) and members of the Administrators group.
+///
+/// Must run in the context of the requesting thread, which holds for IRP_MJ_DEVICE_CONTROL
+/// sent to our own device.
+pub unsafe fn is_caller_privileged(requestor_mode: KPROCESSOR_MODE) -> bool {
+    if let KPROCESSOR_MODE::KernelMode = requestor_mode {
+        return true;
+    }
+
+    let load_driver = LUID {
+        LowPart: SE_LOAD_DRIVER_PRIVILEGE,
+        HighPart: 0,
+    };
+    if SeSinglePrivilegeCheck(load_driver, requestor_mode) != 0 {
+        return true;
+    }
+
+    let mut context = SECURITY_SUBJECT_CONTEXT {
+        ClientToken: null_mut(),
+        ImpersonationLevel: 0,
+        PrimaryToken: null_mut(),
+        ProcessAuditId: null_mut(),
+    };
+    SeCaptureSubjectContext(&mut context);
+    SeLockSubjectContext(&mut context);
+
+    // SeQuerySubjectContextToken: the impersonation token wins over the primary one
+    let token = if context.ClientToken.is_null() {
+        context.PrimaryToken
+    } else {
+        context.ClientToken
+    };
+    let is_admin = SeTokenIsAdmin(token) != 0;
+
+    SeUnlockSubjectContext(&mut context);
+    SeReleaseSubjectContext(&mut context);
+    is_admin
+}
@@ -0,0 +1,25 @@
+/// Address of KUSER_SHARED_DATA as seen from kernel mode.
+const KI_USER_SHARED_DATA: usize = 0xFFFF_F780_0000_0000;
+/// Offset of the `SystemTime` field in KUSER_SHARED_DATA, a KSYSTEM_TIME: `LowPart`,
+/// `High1Time` and `High2Time`, each 32 bits.
+const SYSTEM_TIME_OFFSET: usize = 0x14;
+
+/// Rust version of the x64 `KeQuerySystemTime` macro from wdm.h. On x64 the routine is not
+/// exported by ntoskrnl, the macro reads the current system time (FILETIME, UTC) straight from
+/// the shared user data page. The field is only 4 byte aligned and the clock interrupt writes
+/// `High2Time`, `LowPart`, then `High1Time`, so the parts are read one by one, `High1Time`
+/// first, until both high parts agree.
+pub fn KeQuerySystemTime() -> u64 {
+    let system_time = (KI_USER_SHARED_DATA + SYSTEM_TIME_OFFSET) as *const u32;
+    loop {
+        unsafe {
+            let high1 = core::ptr::read_volatile(system_time.add(1));
+            let low = core::ptr::read_volatile(system_time);
+            let high2 = core::ptr::read_volatile(system_time.add(2));
+            if high1 == high2 {
+                return (high1 as u64) << 32 | low as u64;
+            }
+        }
+        core::hint::spin_loop();
+    }
+}
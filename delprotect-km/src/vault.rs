@@ -0,0 +1,511 @@
+//! The vault of a volume, `delprotect_core::Vault` over the Flt file routines. Files are opened
+//! through the instance of the callback, so the I/O only goes to the filters below it and never
+//! comes back to DelProtect. Everything here runs at PASSIVE_LEVEL in the pre-operation
+//! and instance setup callbacks, outside of the engine lock.
+
+use alloc::{string::String, vec::Vec};
+use common::{
+    auth::Sha256,
+    logging::TARGET_POLICY,
+    status::NtStatus,
+    vault::{VaultHeader, VAULT_DIRECTORY, VAULT_HEADER_SIZE, VAULT_INDEX_FILE},
+    volume::MAX_VOLUME_NAME_BYTES,
+};
+use core::{mem::size_of, ptr::null_mut};
+use delprotect_core::Vault;
+use kernel_macros::NT_SUCCESS;
+use km_api_sys::flt_kernel::{FILE_INFORMATION_CLASS, FLT_RELATED_OBJECTS, PFLT_VOLUME};
+use winapi::shared::{
+    ntdef::{
+        FALSE, HANDLE, OBJECT_ATTRIBUTES, OBJ_CASE_INSENSITIVE, OBJ_KERNEL_HANDLE, PVOID, TRUE,
+        ULONG, UNICODE_STRING,
+    },
+    ntstatus::{
+        STATUS_END_OF_FILE, STATUS_FILE_CORRUPT_ERROR, STATUS_INSUFFICIENT_RESOURCES,
+        STATUS_OBJECT_NAME_NOT_FOUND,
+    },
+};
+
+use crate::{
+    context::IndexLock,
+    ffi::{
+        FltClose, FltCreateFileEx2, FltGetVolumeName, FltQueryInformationFile, FltReadFile,
+        FltWriteFile, ObfDereferenceObject, RtlAddAccessAllowedAceEx, RtlCreateAcl,
+        RtlCreateSecurityDescriptor, RtlSetDaclSecurityDescriptor, ACCESS_MASK, ACL_REVISION,
+        CONTAINER_INHERIT_ACE, DELETE, FILE_ALL_ACCESS, FILE_ATTRIBUTE_HIDDEN,
+        FILE_ATTRIBUTE_NORMAL, FILE_ATTRIBUTE_SYSTEM, FILE_CREATE, FILE_DELETE_ON_CLOSE,
+        FILE_DIRECTORY_FILE, FILE_LIST_DIRECTORY, FILE_NON_DIRECTORY_FILE, FILE_OPEN, FILE_OPEN_IF,
+        FILE_READ_ATTRIBUTES, FILE_READ_DATA, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE,
+        FILE_STANDARD_INFORMATION, FILE_SYNCHRONOUS_IO_NONALERT, FILE_WRITE_DATA,
+        FILE_WRITE_TO_END_OF_FILE, IO_STATUS_BLOCK, OBJECT_INHERIT_ACE, SECURITY_DESCRIPTOR,
+        SECURITY_DESCRIPTOR_REVISION, SE_DACL_PROTECTED, SYNCHRONIZE,
+    },
+};
+
+/// S-1-5-18, LocalSystem.
+const SYSTEM_SID: [u8; 12] = [1, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+/// S-1-5-32-544, BUILTIN\Administrators.
+const ADMINISTRATORS_SID: [u8; 16] = [1, 2, 0, 0, 0, 0, 0, 5, 32, 0, 0, 0, 0x20, 0x02, 0, 0];
+/// ACL header and two ACCESS_ALLOWED_ACEs, each a header and mask before its SID.
+const VAULT_ACL_SIZE: usize = 8 + (8 + SYSTEM_SID.len()) + (8 + ADMINISTRATORS_SID.len());
+
+/// Size of the chunks a file is copied in.
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// The vault of the volume of a callback. The volume name is only queried when a `preserve`
+/// rule decides a delete.
+pub struct KernelVault {
+    filter: PVOID,
+    instance: PVOID,
+    volume: PFLT_VOLUME,
+}
+
+impl KernelVault {
+    pub fn new(objects: &FLT_RELATED_OBJECTS) -> Self {
+        Self {
+            filter: objects.Filter as PVOID,
+            instance: objects.Instance as PVOID,
+            volume: objects.Volume,
+        }
+    }
+
+    fn open(
+        &self,
+        path: &str,
+        access: ACCESS_MASK,
+        attributes: ULONG,
+        disposition: ULONG,
+        options: ULONG,
+        security: *mut SECURITY_DESCRIPTOR,
+    ) -> Result<File, NtStatus> {
+        File::open(
+            self.filter,
+            self.instance,
+            path,
+            access,
+            attributes,
+            disposition,
+            options,
+            security,
+        )
+    }
+
+    /// `\Device\HarddiskVolume3\$DelProtectVault`, followed by `\name` if not empty.
+    fn path(&self, name: &str) -> Result<String, NtStatus> {
+        let mut buffer = [0u16; MAX_VOLUME_NAME_BYTES / 2];
+        let mut volume_name = UNICODE_STRING {
+            Length: 0,
+            MaximumLength: (buffer.len() * size_of::<u16>()) as u16,
+            Buffer: buffer.as_mut_ptr(),
+        };
+        let mut needed: ULONG = 0;
+        let status = unsafe { FltGetVolumeName(self.volume, &mut volume_name, &mut needed) };
+        if !NT_SUCCESS!(status) {
+            return Err(status);
+        }
+
+        let volume = String::from_utf16_lossy(&buffer[..volume_name.Length as usize / 2]);
+        let mut path = String::new();
+        path.try_reserve_exact(volume.len() + VAULT_DIRECTORY.len() + name.len() + 2)
+            .map_err(|_| STATUS_INSUFFICIENT_RESOURCES)?;
+        path.push_str(&volume);
+        path.push('\\');
+        path.push_str(VAULT_DIRECTORY);
+        if !name.is_empty() {
+            path.push('\\');
+            path.push_str(name);
+        }
+        Ok(path)
+    }
+
+    /// True if the volume has a vault directory, from the instance setup callback.
+    pub fn exists(&self) -> bool {
+        self.path("")
+            .and_then(|path| {
+                self.open(
+                    &path,
+                    FILE_LIST_DIRECTORY,
+                    FILE_ATTRIBUTE_NORMAL,
+                    FILE_OPEN,
+                    FILE_DIRECTORY_FILE,
+                    null_mut(),
+                )
+            })
+            .is_ok()
+    }
+
+    /// Creates the hidden vault directory on first use. Only SYSTEM and Administrators get
+    /// access to it and, by inheritance, to the index and the copies; the ACL of the volume
+    /// root is not inherited.
+    fn create_directory(&self) -> Result<(), NtStatus> {
+        let mut security = VaultSecurity::new();
+        let directory = self.open(
+            &self.path("")?,
+            FILE_LIST_DIRECTORY,
+            FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM,
+            FILE_OPEN_IF,
+            FILE_DIRECTORY_FILE,
+            security.descriptor()?,
+        )?;
+        drop(directory);
+        Ok(())
+    }
+
+    fn copy(&self, source: &File, target: &File) -> Result<(u64, [u8; 32]), NtStatus> {
+        let mut buffer = Vec::new();
+        buffer
+            .try_reserve_exact(COPY_CHUNK)
+            .map_err(|_| STATUS_INSUFFICIENT_RESOURCES)?;
+        buffer.resize(COPY_CHUNK, 0);
+
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        loop {
+            let read = source.read(&mut buffer, size as i64)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            target.write(&buffer[..read], size as i64)?;
+            size += read as u64;
+        }
+        Ok((size, hasher.finish()))
+    }
+}
+
+impl Vault for KernelVault {
+    fn read_header(&self) -> Result<VaultHeader, NtStatus> {
+        let index = match self.open(
+            &self.path(VAULT_INDEX_FILE)?,
+            FILE_READ_DATA,
+            FILE_ATTRIBUTE_NORMAL,
+            FILE_OPEN,
+            FILE_NON_DIRECTORY_FILE,
+            null_mut(),
+        ) {
+            Ok(index) => index,
+            Err(STATUS_OBJECT_NAME_NOT_FOUND) => return Ok(VaultHeader::default()),
+            Err(status) => return Err(status),
+        };
+        read_header(&index)
+    }
+
+    fn file_size(&self, path: &str) -> Result<u64, NtStatus> {
+        let file = self.open(
+            path,
+            FILE_READ_ATTRIBUTES,
+            FILE_ATTRIBUTE_NORMAL,
+            FILE_OPEN,
+            FILE_NON_DIRECTORY_FILE,
+            null_mut(),
+        )?;
+        file.end_of_file()
+    }
+
+    fn copy_file(&self, path: &str, copy_name: &str) -> Result<(u64, [u8; 32]), NtStatus> {
+        let source = self.open(
+            path,
+            FILE_READ_DATA,
+            FILE_ATTRIBUTE_NORMAL,
+            FILE_OPEN,
+            FILE_NON_DIRECTORY_FILE,
+            null_mut(),
+        )?;
+        self.create_directory()?;
+        let target = self.open(
+            &self.path(copy_name)?,
+            FILE_WRITE_DATA,
+            FILE_ATTRIBUTE_NORMAL,
+            FILE_CREATE,
+            FILE_NON_DIRECTORY_FILE,
+            null_mut(),
+        )?;
+
+        let result = self.copy(&source, &target);
+        drop(target);
+        if result.is_err() {
+            self.remove_copy(copy_name);
+        }
+        result
+    }
+
+    fn remove_copy(&self, copy_name: &str) {
+        // opened below DelProtect, the FILE_DELETE_ON_CLOSE open is not decided
+        let removed = self.path(copy_name).and_then(|path| {
+            self.open(
+                &path,
+                DELETE,
+                FILE_ATTRIBUTE_NORMAL,
+                FILE_OPEN,
+                FILE_NON_DIRECTORY_FILE | FILE_DELETE_ON_CLOSE,
+                null_mut(),
+            )
+        });
+        if let Err(status) = removed {
+            log::info!(
+                target: TARGET_POLICY,
+                "failed to remove vault copy {} 0x{:08x}",
+                copy_name,
+                status
+            );
+        }
+    }
+
+    fn append_entry(
+        &self,
+        entry: &[u8],
+        update: &mut dyn FnMut(&mut VaultHeader) -> Result<(), NtStatus>,
+    ) -> Result<(), NtStatus> {
+        let path = self.path(VAULT_INDEX_FILE)?;
+        let _lock = IndexLock::acquire(self.instance)?;
+
+        // the client holds the index without sharing while rewriting it, the delete is then
+        // denied with STATUS_SHARING_VIOLATION
+        let index = self.open(
+            &path,
+            FILE_READ_DATA | FILE_WRITE_DATA,
+            FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM,
+            FILE_OPEN_IF,
+            FILE_NON_DIRECTORY_FILE,
+            null_mut(),
+        )?;
+        let mut header = read_header(&index)?;
+        update(&mut header)?;
+
+        let mut encoded = [0u8; VAULT_HEADER_SIZE];
+        header
+            .encode(&mut encoded)
+            .ok_or(STATUS_INSUFFICIENT_RESOURCES)?;
+        // written first so the entry of a new index goes after it
+        index.write(&encoded, 0)?;
+        index.write(entry, FILE_WRITE_TO_END_OF_FILE)
+    }
+}
+
+/// The header of an open index, the defaults for an index just created.
+fn read_header(index: &File) -> Result<VaultHeader, NtStatus> {
+    let mut buffer = [0u8; VAULT_HEADER_SIZE];
+    let read = index.read(&mut buffer, 0)?;
+    if read == 0 {
+        return Ok(VaultHeader::default());
+    }
+    VaultHeader::decode(&buffer[..read])
+        .map(|(header, _)| header)
+        .map_err(|_| STATUS_FILE_CORRUPT_ERROR)
+}
+
+/// A kernel handle to a file opened below an instance for synchronous I/O, closed on drop.
+struct File {
+    instance: PVOID,
+    handle: HANDLE,
+    file_object: PVOID,
+}
+
+impl File {
+    #[allow(clippy::too_many_arguments)]
+    fn open(
+        filter: PVOID,
+        instance: PVOID,
+        path: &str,
+        access: ACCESS_MASK,
+        attributes: ULONG,
+        disposition: ULONG,
+        options: ULONG,
+        security: *mut SECURITY_DESCRIPTOR,
+    ) -> Result<Self, NtStatus> {
+        let mut wide = Vec::new();
+        wide.try_reserve_exact(path.len())
+            .map_err(|_| STATUS_INSUFFICIENT_RESOURCES)?;
+        wide.extend(path.encode_utf16());
+        let len = (wide.len() * size_of::<u16>()) as u16;
+        let mut name = UNICODE_STRING {
+            Length: len,
+            MaximumLength: len,
+            Buffer: wide.as_mut_ptr(),
+        };
+        let mut object_attributes = OBJECT_ATTRIBUTES {
+            Length: size_of::<OBJECT_ATTRIBUTES>() as ULONG,
+            RootDirectory: null_mut(),
+            ObjectName: &mut name,
+            Attributes: OBJ_CASE_INSENSITIVE | OBJ_KERNEL_HANDLE,
+            SecurityDescriptor: security as PVOID,
+            SecurityQualityOfService: null_mut(),
+        };
+
+        let mut handle: HANDLE = null_mut();
+        let mut file_object: PVOID = null_mut();
+        let mut io = IO_STATUS_BLOCK {
+            Status: 0,
+            Information: 0,
+        };
+        let status = unsafe {
+            FltCreateFileEx2(
+                filter,
+                instance,
+                &mut handle,
+                &mut file_object,
+                access | SYNCHRONIZE,
+                &mut object_attributes,
+                &mut io,
+                null_mut(),
+                attributes,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                disposition,
+                options | FILE_SYNCHRONOUS_IO_NONALERT,
+                null_mut(),
+                0,
+                0,
+                null_mut(),
+            )
+        };
+        if !NT_SUCCESS!(status) {
+            return Err(status);
+        }
+        Ok(Self {
+            instance,
+            handle,
+            file_object,
+        })
+    }
+
+    /// Returns the number of bytes read, 0 at the end of the file.
+    fn read(&self, buffer: &mut [u8], offset: i64) -> Result<usize, NtStatus> {
+        let mut read: ULONG = 0;
+        let status = unsafe {
+            FltReadFile(
+                self.instance,
+                self.file_object,
+                &offset,
+                buffer.len() as ULONG,
+                buffer.as_mut_ptr() as PVOID,
+                0,
+                &mut read,
+                null_mut(),
+                null_mut(),
+            )
+        };
+        match status {
+            STATUS_END_OF_FILE => Ok(0),
+            status if NT_SUCCESS!(status) => Ok(read as usize),
+            status => Err(status),
+        }
+    }
+
+    fn end_of_file(&self) -> Result<u64, NtStatus> {
+        let mut info = FILE_STANDARD_INFORMATION {
+            AllocationSize: 0,
+            EndOfFile: 0,
+            NumberOfLinks: 0,
+            DeletePending: 0,
+            Directory: 0,
+        };
+        let status = unsafe {
+            FltQueryInformationFile(
+                self.instance,
+                self.file_object,
+                &mut info as *mut FILE_STANDARD_INFORMATION as PVOID,
+                size_of::<FILE_STANDARD_INFORMATION>() as ULONG,
+                FILE_INFORMATION_CLASS::FileStandardInformation,
+                null_mut(),
+            )
+        };
+        check(status)?;
+        Ok(info.EndOfFile.max(0) as u64)
+    }
+
+    fn write(&self, buffer: &[u8], offset: i64) -> Result<(), NtStatus> {
+        let mut written: ULONG = 0;
+        let status = unsafe {
+            FltWriteFile(
+                self.instance,
+                self.file_object,
+                &offset,
+                buffer.len() as ULONG,
+                buffer.as_ptr() as PVOID,
+                0,
+                &mut written,
+                null_mut(),
+                null_mut(),
+            )
+        };
+        if !NT_SUCCESS!(status) {
+            return Err(status);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe {
+            ObfDereferenceObject(self.file_object);
+            FltClose(self.handle);
+        }
+    }
+}
+
+/// Security descriptor of a new vault directory: full access for SYSTEM and Administrators,
+/// inherited by everything created in it.
+struct VaultSecurity {
+    descriptor: SECURITY_DESCRIPTOR,
+    /// u32 for the alignment RtlCreateAcl needs.
+    acl: [u32; VAULT_ACL_SIZE.div_ceil(4)],
+}
+
+impl VaultSecurity {
+    fn new() -> Self {
+        Self {
+            descriptor: SECURITY_DESCRIPTOR {
+                Revision: 0,
+                Sbz1: 0,
+                Control: 0,
+                Owner: null_mut(),
+                Group: null_mut(),
+                Sacl: null_mut(),
+                Dacl: null_mut(),
+            },
+            acl: [0; VAULT_ACL_SIZE.div_ceil(4)],
+        }
+    }
+
+    /// Builds the descriptor in place, it points at the ACL and must not move afterwards.
+    fn descriptor(&mut self) -> Result<*mut SECURITY_DESCRIPTOR, NtStatus> {
+        let acl = self.acl.as_mut_ptr() as PVOID;
+        let inherit = OBJECT_INHERIT_ACE | CONTAINER_INHERIT_ACE;
+        unsafe {
+            check(RtlCreateSecurityDescriptor(
+                &mut self.descriptor,
+                SECURITY_DESCRIPTOR_REVISION,
+            ))?;
+            check(RtlCreateAcl(
+                acl,
+                (self.acl.len() * size_of::<u32>()) as ULONG,
+                ACL_REVISION,
+            ))?;
+            for sid in [&SYSTEM_SID[..], &ADMINISTRATORS_SID[..]] {
+                check(RtlAddAccessAllowedAceEx(
+                    acl,
+                    ACL_REVISION,
+                    inherit,
+                    FILE_ALL_ACCESS,
+                    sid.as_ptr() as PVOID,
+                ))?;
+            }
+            check(RtlSetDaclSecurityDescriptor(
+                &mut self.descriptor,
+                TRUE,
+                acl,
+                FALSE,
+            ))?;
+        }
+        self.descriptor.Control |= SE_DACL_PROTECTED;
+        Ok(&mut self.descriptor)
+    }
+}
+
+fn check(status: NtStatus) -> Result<(), NtStatus> {
+    if !NT_SUCCESS!(status) {
+        return Err(status);
+    }
+    Ok(())
+}
@@ -0,0 +1,8 @@
+//! The readers and the replay platform of the simulator, a library so the flows under `tests/`
+//! can run them without the command line.
+
+pub mod platform;
+pub mod policy;
+pub mod procmon;
+pub mod trace;
+pub mod usn;
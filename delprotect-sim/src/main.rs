@@ -0,0 +1,217 @@
+//! Replays a trace of file operations against a policy with the engine and pre-operation flow
+//! of the driver, to see which deletes a new rule would block before deploying it.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::File,
+    io::{self, BufReader},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use common::{
+    rule_args::{format_time_window, parse_utc_time},
+    schedule::TICKS_PER_SECOND,
+};
+use delprotect_core::{filter::EngineLock, Decision, Stats};
+use delprotect_sim::{
+    platform::{replay, SimEngine},
+    policy, procmon,
+    trace::{self, Entry},
+    usn,
+};
+
+/// Seconds from 1601-01-01 to 1970-01-01.
+const UNIX_EPOCH_FILETIME_SECONDS: u64 = 11_644_473_600;
+
+#[derive(Default)]
+struct Totals {
+    operations: usize,
+    denied: usize,
+    preserved: usize,
+    locked: usize,
+    allowed: usize,
+    skipped: usize,
+    invalid: usize,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+fn print_usage() {
+    println!("Usage: delprotect-sim <policy.json> <trace | -> [--at YYYY-MM-DD[THH:MM]]");
+    println!("       delprotect-sim import <procmon.csv | procmon.xml>");
+    println!("       delprotect-sim usn <policy.json> <journal> --process <image path>");
+    println!(
+        "The trace is JSON Lines, or a Process Monitor export if it ends with .csv or .xml. \
+         Operations without a \"time\" are replayed at --at (UTC), the current time by default. \
+         import prints an export as JSON Lines. usn replays the deletes and renames of raw \
+         USN_RECORD_V2/V3 records at their time, as if --process made them."
+    );
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    match args {
+        [_, command, export] if command == "import" => import(export),
+        [_, command, policy, journal, option, process]
+            if command == "usn" && option == "--process" =>
+        {
+            simulate(policy, usn::import(journal, process)?, current_time())
+        },
+        [_, policy, trace] => simulate(policy, read_trace(trace)?, current_time()),
+        [_, policy, trace, option, at] if option == "--at" => {
+            simulate(policy, read_trace(trace)?, parse_utc_time(at)?)
+        },
+        _ => {
+            print_usage();
+            Err("invalid arguments".to_string())
+        },
+    }
+}
+
+/// Replays `entries` against the rules of the policy file and prints the decisions and the
+/// hits per rule.
+fn simulate(policy_path: &str, entries: Vec<Entry>, default_time: u64) -> Result<(), String> {
+    let engine = SimEngine::new(policy::load(policy_path)?);
+    let stats = Stats::new();
+
+    let mut totals = Totals::default();
+    let mut hits: BTreeMap<u32, usize> = BTreeMap::new();
+
+    for entry in entries {
+        let line_number = entry.line;
+        let record = match entry.record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("line {line_number}: {e}");
+                totals.invalid += 1;
+                continue;
+            },
+        };
+        let now = match &record.time {
+            Some(time) => match parse_utc_time(time) {
+                Ok(now) => now,
+                Err(e) => {
+                    eprintln!("line {line_number}: {e}");
+                    totals.invalid += 1;
+                    continue;
+                },
+            },
+            None => default_time,
+        };
+
+        let decision = replay(&record, now, &engine, &stats);
+        totals.operations += 1;
+        let outcome = match decision {
+            Some(Decision::Deny { rule_id }) => {
+                totals.denied += 1;
+                *hits.entry(rule_id).or_default() += 1;
+                format!("DENY  (rule {rule_id})")
+            },
+            Some(Decision::Preserve { rule_id }) => {
+                totals.preserved += 1;
+                *hits.entry(rule_id).or_default() += 1;
+                format!("KEEP  (rule {rule_id})")
+            },
+            Some(Decision::LockedDown) => {
+                totals.locked += 1;
+                "LOCK  (detector)".to_string()
+            },
+            Some(Decision::Allow) => {
+                totals.allowed += 1;
+                "ALLOW".to_string()
+            },
+            None => {
+                totals.skipped += 1;
+                "SKIP ".to_string()
+            },
+        };
+
+        let target = record
+            .target
+            .as_ref()
+            .map(|target| format!(" -> {target}"))
+            .unwrap_or_default();
+        println!(
+            "{line_number:>6} {outcome:<16} {:<18} pid {:<6} {}{target} by {}",
+            record.op.as_str(),
+            record.pid,
+            record.path,
+            record.process
+        );
+    }
+
+    println!();
+    println!(
+        "{} operations: {} denied, {} preserved, {} locked down, {} allowed, {} not filtered, {} \
+         invalid lines",
+        totals.operations,
+        totals.denied,
+        totals.preserved,
+        totals.locked,
+        totals.allowed,
+        totals.skipped,
+        totals.invalid
+    );
+
+    println!();
+    println!(
+        "{:<4} {:<8} {:<9} {:<32} time window",
+        "id", "hits", "action", "process"
+    );
+    engine.with_engine(|engine| {
+        for rule in engine.rules().iter() {
+            println!(
+                "{:<4} {:<8} {:<9} {:<32} {}",
+                rule.id,
+                hits.get(&rule.id).copied().unwrap_or(0),
+                rule.action.as_str(),
+                rule.process_name,
+                format_time_window(&rule.window)
+            );
+        }
+    });
+
+    Ok(())
+}
+
+fn read_trace(path: &str) -> Result<Vec<Entry>, String> {
+    if path == "-" {
+        trace::read_lines(io::stdin().lock()).map_err(|e| format!("cannot read the trace: {e}"))
+    } else if procmon::is_export(path) {
+        procmon::import(path)
+    } else {
+        let file = File::open(path).map_err(|e| format!("cannot open {path}: {e}"))?;
+        trace::read_lines(BufReader::new(file)).map_err(|e| format!("cannot read {path}: {e}"))
+    }
+}
+
+/// Prints the operations of a Procmon export as a JSON Lines trace, rows which cannot be
+/// converted go to stderr.
+fn import(path: &str) -> Result<(), String> {
+    for entry in procmon::import(path)? {
+        match entry.record {
+            Ok(record) => {
+                let line = serde_json::to_string(&record).map_err(|e| e.to_string())?;
+                println!("{line}");
+            },
+            Err(e) => eprintln!("line {}: {e}", entry.line),
+        }
+    }
+    Ok(())
+}
+
+/// Current time as FILETIME ticks.
+fn current_time() -> u64 {
+    let since_unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    (since_unix + UNIX_EPOCH_FILETIME_SECONDS) * TICKS_PER_SECOND
+}
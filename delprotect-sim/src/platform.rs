@@ -0,0 +1,251 @@
+//! The platform of one replayed operation: the process and file of the record at its time, and
+//! the callback of the driver the record goes through.
+
+use std::cell::RefCell;
+
+use common::{
+    rule::Response,
+    status::{NtStatus, STATUS_NOT_FOUND},
+    truncation::SizeInformation,
+    vault::VaultHeader,
+};
+use delprotect_core::{
+    filter::{
+        pre_create, pre_rename, pre_set_basic, pre_set_disposition, pre_set_security, pre_set_size,
+        pre_write, EngineLock, Platform, Published,
+    },
+    Allocator, Clock, Decision, Engine, FileInfoProvider, FileNameProvider, ProcessIdentity,
+    Requestor, Responder, Stats, Vault,
+};
+
+use crate::trace::{Operation, TraceRecord};
+
+/// Stands in for the PETHREAD of the callback data, every thread belongs to the record process.
+const REPLAY_THREAD: usize = 1;
+
+/// Runs the record through the callback the driver would call for it. `None` if the engine
+/// was not asked: not a delete, kernel mode or an operation the driver does not filter. Renames
+/// and overwrites only get a decision from a lockdown of the detector, writes from an immutable
+/// rule, size, security and attribute changes only with the option guarding them. A process
+/// creation is handed to the engine for the rules which inherit and gets no decision.
+pub fn replay(
+    record: &TraceRecord,
+    now: u64,
+    engine: &SimEngine,
+    stats: &Stats,
+) -> Option<Decision> {
+    let replay = Replay {
+        pid: record.pid,
+        image_name: &record.process,
+        file_name: &record.path,
+        now,
+        stats,
+        end_of_file: record.end_of_file,
+        attributes: record.attributes,
+    };
+    let platform = replay.platform();
+
+    let verdict = match record.op {
+        Operation::Create => pre_create(
+            &platform,
+            engine,
+            record.kernel_mode,
+            record.flags,
+            record.access.unwrap_or(0),
+        ),
+        Operation::SetDisposition | Operation::SetDispositionEx => {
+            pre_set_disposition(&platform, engine, REPLAY_THREAD, record.deletes())
+        },
+        Operation::Rename => pre_rename(&platform, engine, REPLAY_THREAD),
+        Operation::Write => pre_write(&platform, engine, record.kernel_mode, false, REPLAY_THREAD),
+        Operation::SetSize => pre_set_size(
+            &platform,
+            engine,
+            record.kernel_mode,
+            REPLAY_THREAD,
+            SizeInformation::from_class(record.flags)?,
+            record.size?,
+        ),
+        Operation::SetSecurity => pre_set_security(
+            &platform,
+            engine,
+            record.kernel_mode,
+            REPLAY_THREAD,
+            record.flags,
+        ),
+        Operation::SetBasic => pre_set_basic(
+            &platform,
+            engine,
+            record.kernel_mode,
+            REPLAY_THREAD,
+            record.flags,
+        ),
+        Operation::CreateProcess => {
+            if let (Some(child_pid), Some(image_name)) = (record.child_pid, &record.target) {
+                engine.with_engine(|engine| {
+                    engine.process_created(record.pid, &record.process, child_pid, image_name, now)
+                });
+            }
+            return None;
+        },
+        Operation::Other => return None,
+    };
+    verdict.decision
+}
+
+pub struct Replay<'a> {
+    pub pid: u32,
+    pub image_name: &'a str,
+    pub file_name: &'a str,
+    pub now: u64,
+    pub stats: &'a Stats,
+    /// The end of file a size change finds, `None` if the trace has none.
+    pub end_of_file: Option<u64>,
+    /// The attributes an attribute change finds, `None` if the trace has none.
+    pub attributes: Option<u32>,
+}
+
+impl Replay<'_> {
+    pub fn platform(&self) -> Platform<'_> {
+        Platform {
+            identity: self,
+            files: self,
+            info: self,
+            clock: self,
+            allocator: self,
+            stats: self.stats,
+            vault: self,
+            responder: self,
+        }
+    }
+}
+
+impl Clock for Replay<'_> {
+    fn now(&self) -> u64 {
+        self.now
+    }
+}
+
+impl Allocator for Replay<'_> {
+    fn name_buffer(&self, units: usize) -> Option<Vec<u16>> {
+        Some(vec![0; units])
+    }
+}
+
+/// Every requestor, the current process as well as any thread, is the process of the record.
+impl ProcessIdentity for Replay<'_> {
+    fn process_id(&self, _requestor: Requestor) -> u32 {
+        self.pid
+    }
+
+    fn query_image_name(
+        &self,
+        _requestor: Requestor,
+        buffer: &mut [u16],
+    ) -> Result<usize, NtStatus> {
+        match copy_name(self.image_name, buffer) {
+            0 => Err(STATUS_NOT_FOUND),
+            len => Ok(len),
+        }
+    }
+}
+
+/// Every record names its file, it stands for the name kept with the handle too.
+impl FileNameProvider for Replay<'_> {
+    fn query_file_name(&self, buffer: &mut [u16]) -> Result<usize, NtStatus> {
+        Ok(copy_name(self.file_name, buffer))
+    }
+
+    fn opened_name(&self) -> Option<String> {
+        Some(self.file_name.to_string())
+    }
+
+    fn keep_opened_name(&self, _name: &str) {}
+}
+
+/// A size or attribute change of a record without the end of file or attributes it found is
+/// never replayed as a truncation or a weakened protection, like a failed query in the driver.
+impl FileInfoProvider for Replay<'_> {
+    fn query_end_of_file(&self) -> Result<u64, NtStatus> {
+        self.end_of_file.ok_or(STATUS_NOT_FOUND)
+    }
+
+    fn query_attributes(&self) -> Result<u32, NtStatus> {
+        self.attributes.ok_or(STATUS_NOT_FOUND)
+    }
+}
+
+/// The simulator has no files, every copy succeeds into a vault without a size cap.
+impl Vault for Replay<'_> {
+    fn read_header(&self) -> Result<VaultHeader, NtStatus> {
+        Ok(VaultHeader {
+            max_bytes: 0,
+            ..VaultHeader::default()
+        })
+    }
+
+    fn file_size(&self, _path: &str) -> Result<u64, NtStatus> {
+        Ok(0)
+    }
+
+    fn copy_file(&self, _path: &str, _copy_name: &str) -> Result<(u64, [u8; 32]), NtStatus> {
+        Ok((0, [0; 32]))
+    }
+
+    fn remove_copy(&self, _copy_name: &str) {}
+
+    fn append_entry(
+        &self,
+        _entry: &[u8],
+        update: &mut dyn FnMut(&mut VaultHeader) -> Result<(), NtStatus>,
+    ) -> Result<(), NtStatus> {
+        update(&mut self.read_header()?)
+    }
+}
+
+/// The replayed processes are long gone, a response is only shown in the events.
+impl Responder for Replay<'_> {
+    fn queue_response(&self, _requestor: Requestor, _response: Response) -> Result<(), NtStatus> {
+        Ok(())
+    }
+}
+
+/// Truncated to the buffer like the kernel queries.
+fn copy_name(name: &str, buffer: &mut [u16]) -> usize {
+    let mut len = 0;
+    for (dst, src) in buffer.iter_mut().zip(name.encode_utf16()) {
+        *dst = src;
+        len += 1;
+    }
+    len
+}
+
+/// The simulator is single threaded, a `RefCell` stands in for the driver mutex.
+pub struct SimEngine {
+    engine: RefCell<Engine>,
+    published: Published,
+}
+
+impl SimEngine {
+    pub fn new(engine: Engine) -> Self {
+        let published = Published::new();
+        published.publish(&engine);
+        Self {
+            engine: RefCell::new(engine),
+            published,
+        }
+    }
+}
+
+impl EngineLock for SimEngine {
+    fn with_engine<R>(&self, f: impl FnOnce(&mut Engine) -> R) -> Option<R> {
+        let mut engine = self.engine.borrow_mut();
+        let result = f(&mut engine);
+        self.published.publish(&engine);
+        Some(result)
+    }
+
+    fn published(&self) -> &Published {
+        &self.published
+    }
+}
@@ -0,0 +1,224 @@
+//! Policy file of the simulator, the rules the client would add, e.g.
+//!
+//! ```json
+//! { "rules": [ { "process": "cmd.exe", "days": "mon,tue,wed,thu,fri", "hours": "08:00-18:00" } ] }
+//! ```
+//!
+//! The time fields take the same text as the `add` options of the client, in UTC. `action` is
+//! `deny` (the default) or another action of the client, `status` what a denied delete fails
+//! with like `--status` (`access-denied` by default). An optional `detector` object enables the mass-delete
+//! detector, e.g. `"detector": { "window": 10, "max_operations": 200, "max_directories": 10 }`,
+//! with the defaults of the client for the fields left out. `options` names the driver options
+//! set, like the `options` command of the client, e.g. `"options": ["deny-delete-access"]`.
+//! `flags` are those of `add`: `inherit` has the rule decide for the processes its process
+//! creates as well, `immutable` denies the writes of its processes under the NT `path` of the
+//! rule, e.g. `"flags": ["immutable"], "path": "\\Device\\HarddiskVolume3\\Data"`.
+
+use std::fs;
+
+use common::{
+    detector::{
+        DetectorSettings, DEFAULT_WINDOW_SECONDS, DETECTOR_SETTINGS_SIZE, MAX_TRACKED_DIRECTORIES,
+        MAX_WINDOW_SECONDS,
+    },
+    options::OPTION_NAMES,
+    rule::{
+        parse_failure_status, RuleAction, RuleRecord, MAX_RULE_PATH_BYTES, RULE_FLAG_IMMUTABLE,
+        RULE_FLAG_INHERIT,
+    },
+    rule_args::parse_time_window,
+    schedule::RuleState,
+    status::STATUS_ACCESS_DENIED,
+};
+use delprotect_core::{rules::MAX_RULE_COUNT, Config, Engine};
+use serde::Deserialize;
+
+const RULE_FLAG_NAMES: [(&str, u16); 2] = [
+    ("inherit", RULE_FLAG_INHERIT),
+    ("immutable", RULE_FLAG_IMMUTABLE),
+];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    rules: Vec<RuleEntry>,
+    detector: Option<DetectorEntry>,
+    #[serde(default)]
+    options: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DetectorEntry {
+    window: Option<u32>,
+    max_operations: Option<u32>,
+    max_directories: Option<u32>,
+}
+
+impl DetectorEntry {
+    /// Checked like the driver checks `IOCTL_DELPROTECT_SET_DETECTOR`.
+    fn settings(&self) -> Result<DetectorSettings, String> {
+        let defaults = DetectorSettings::default();
+        let settings = DetectorSettings {
+            window_seconds: self.window.unwrap_or(DEFAULT_WINDOW_SECONDS),
+            max_operations: self.max_operations.unwrap_or(defaults.max_operations),
+            max_directories: self.max_directories.unwrap_or(defaults.max_directories),
+        };
+
+        let mut encoded = [0u8; DETECTOR_SETTINGS_SIZE];
+        settings.encode(&mut encoded);
+        DetectorSettings::decode(&encoded).map_err(|_| {
+            format!(
+                "invalid detector: the window is at most {MAX_WINDOW_SECONDS} s, max_operations \
+                 at least 1 and max_directories below {MAX_TRACKED_DIRECTORIES}"
+            )
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    process: String,
+    action: Option<String>,
+    status: Option<String>,
+    not_before: Option<String>,
+    not_after: Option<String>,
+    days: Option<String>,
+    hours: Option<String>,
+    #[serde(default)]
+    flags: Vec<String>,
+    path: Option<String>,
+}
+
+impl RuleEntry {
+    /// The entry as `add` options of the client.
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (option, value) in [
+            ("--not-before", &self.not_before),
+            ("--not-after", &self.not_after),
+            ("--days", &self.days),
+            ("--hours", &self.hours),
+        ] {
+            if let Some(value) = value {
+                args.push(option.to_string());
+                args.push(value.clone());
+            }
+        }
+        args
+    }
+
+    /// The RULE_FLAG_* bits of `flags`, and the path as UTF-16LE bytes. Only immutable rules
+    /// have a path and they need one, like the driver checks `IOCTL_DELPROTECT_ADD_RULE`.
+    fn flags_and_path(&self) -> Result<(u16, Vec<u8>), String> {
+        let mut flags = 0;
+        for name in &self.flags {
+            let (_, flag) = RULE_FLAG_NAMES
+                .iter()
+                .find(|(flag, _)| flag == name)
+                .ok_or_else(|| format!("unknown flag \"{name}\""))?;
+            flags |= flag;
+        }
+
+        let path: Vec<u8> = self
+            .path
+            .iter()
+            .flat_map(|path| path.encode_utf16())
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        match (flags & RULE_FLAG_IMMUTABLE != 0, path.is_empty()) {
+            (true, true) => Err("an immutable rule needs a path".to_string()),
+            (false, false) => Err("only an immutable rule has a path".to_string()),
+            _ if path.len() > MAX_RULE_PATH_BYTES => Err(format!(
+                "the path is longer than {} characters",
+                MAX_RULE_PATH_BYTES / 2
+            )),
+            _ => Ok((flags, path)),
+        }
+    }
+}
+
+/// An engine holding the rules of the policy file, with ids in file order starting at 1.
+pub fn load(path: &str) -> Result<Engine, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    let policy: PolicyFile =
+        serde_json::from_str(&text).map_err(|e| format!("invalid policy {path}: {e}"))?;
+    if policy.rules.len() > MAX_RULE_COUNT {
+        return Err(format!(
+            "{path} has {} rules, the driver keeps at most {MAX_RULE_COUNT}",
+            policy.rules.len()
+        ));
+    }
+
+    let detector = match &policy.detector {
+        Some(entry) => entry.settings().map_err(|e| format!("{path}: {e}"))?,
+        None => DetectorSettings::default(),
+    };
+    let mut options = 0;
+    for name in &policy.options {
+        let (_, flag) = OPTION_NAMES
+            .iter()
+            .find(|(option, _)| option == name)
+            .ok_or_else(|| format!("unknown option \"{name}\""))?;
+        options |= flag;
+    }
+    let config = Config {
+        detector,
+        options,
+        ..Config::default()
+    };
+    let mut engine = Engine::new(config).ok_or("cannot create the engine")?;
+    for (index, entry) in policy.rules.iter().enumerate() {
+        let window = parse_time_window(&entry.args())
+            .map_err(|e| format!("rule {} ({}): {e}", index + 1, entry.process))?;
+        let action = match &entry.action {
+            Some(name) => RuleAction::parse(name).ok_or_else(|| {
+                format!(
+                    "rule {} ({}): unknown action \"{name}\"",
+                    index + 1,
+                    entry.process
+                )
+            })?,
+            None => RuleAction::Deny,
+        };
+        let failure_status = match &entry.status {
+            Some(text) => parse_failure_status(text).ok_or_else(|| {
+                format!(
+                    "rule {} ({}): status \"{text}\" is not allowed",
+                    index + 1,
+                    entry.process
+                )
+            })?,
+            None => STATUS_ACCESS_DENIED,
+        };
+        let (flags, path) = entry
+            .flags_and_path()
+            .map_err(|e| format!("rule {} ({}): {e}", index + 1, entry.process))?;
+        let process: Vec<u8> = entry
+            .process
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let record = RuleRecord {
+            id: 0,
+            state: RuleState::Active,
+            action,
+            flags,
+            window,
+            failure_status,
+            process: &process,
+            path: &path,
+        };
+        engine.rules_mut().add_record(&record).map_err(|status| {
+            format!(
+                "rule {} ({}) rejected: 0x{:08x}",
+                index + 1,
+                entry.process,
+                status
+            )
+        })?;
+    }
+
+    Ok(engine)
+}
@@ -0,0 +1,264 @@
+//! Process Monitor exports (File > Save, "All events" as CSV or XML) turned into trace records.
+//! Only the rows the driver would see are kept: `CreateFile` with Delete On Close,
+//! `SetDispositionInformationFile`, `SetDispositionInformationEx` and
+//! `SetRenameInformationFile`. Procmon has no date, so the records carry no time.
+//!
+//! Procmon names processes and files with Win32 paths, the rules still match since they look
+//! for the process name anywhere in the image path. The CSV export has the full image path
+//! only if the "Image Path" column was enabled, the process name is used otherwise.
+
+use std::{collections::HashMap, fs::File, io::BufReader};
+
+use delprotect_core::filter::FILE_DELETE_ON_CLOSE;
+use quick_xml::events::Event;
+
+use crate::trace::{Entry, Operation, TraceRecord};
+
+const DISPOSITION_FLAGS: [(&str, u32); 5] = [
+    ("FILE_DISPOSITION_DELETE", 0x01),
+    ("FILE_DISPOSITION_POSIX_SEMANTICS", 0x02),
+    ("FILE_DISPOSITION_FORCE_IMAGE_SECTION_CHECK", 0x04),
+    ("FILE_DISPOSITION_ON_CLOSE", 0x08),
+    ("FILE_DISPOSITION_IGNORE_READONLY_ATTRIBUTE", 0x10),
+];
+
+/// True if the file looks like a Procmon export rather than a JSON Lines trace.
+pub fn is_export(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".csv") || path.ends_with(".xml")
+}
+
+pub fn import(path: &str) -> Result<Vec<Entry>, String> {
+    if path.to_ascii_lowercase().ends_with(".xml") {
+        import_xml(path)
+    } else {
+        import_csv(path)
+    }
+}
+
+/// The columns of one event, as Procmon prints them.
+struct Row<'a> {
+    image: &'a str,
+    pid: &'a str,
+    operation: &'a str,
+    path: &'a str,
+    detail: &'a str,
+}
+
+impl Row<'_> {
+    /// `None` for the operations the driver does not see.
+    fn convert(&self) -> Option<Result<TraceRecord, String>> {
+        let (op, flags, target) = match self.operation {
+            "CreateFile" if self.detail.contains("Delete On Close") => {
+                (Operation::Create, FILE_DELETE_ON_CLOSE, None)
+            },
+            "SetDispositionInformationFile" => (
+                Operation::SetDisposition,
+                field(self.detail, "Delete").map_or(0, |v| v.eq_ignore_ascii_case("True") as u32),
+                None,
+            ),
+            "SetDispositionInformationEx" => (
+                Operation::SetDispositionEx,
+                disposition_flags(self.detail),
+                None,
+            ),
+            "SetRenameInformationFile" => (
+                Operation::Rename,
+                field(self.detail, "ReplaceIfExists")
+                    .map_or(0, |v| v.eq_ignore_ascii_case("True") as u32),
+                self.detail
+                    .split_once("FileName: ")
+                    .map(|(_, name)| name.trim().to_string()),
+            ),
+            _ => return None,
+        };
+
+        let Ok(pid) = self.pid.trim().parse() else {
+            return Some(Err(format!("invalid PID \"{}\"", self.pid)));
+        };
+
+        Some(Ok(TraceRecord {
+            process: self.image.to_string(),
+            pid,
+            path: self.path.to_string(),
+            op,
+            flags,
+            target,
+            access: None,
+            size: None,
+            end_of_file: None,
+            attributes: None,
+            child_pid: None,
+            time: None,
+            kernel_mode: false,
+        }))
+    }
+}
+
+/// Value of `name` in a detail like `Delete: True` or `ReplaceIfExists: False, FileName: ...`.
+fn field<'a>(detail: &'a str, name: &str) -> Option<&'a str> {
+    detail.split(", ").find_map(|part| {
+        let (key, value) = part.split_once(':')?;
+        (key.trim() == name).then(|| value.trim())
+    })
+}
+
+/// `Flags: FILE_DISPOSITION_DELETE, FILE_DISPOSITION_POSIX_SEMANTICS` as FILE_DISPOSITION_*
+/// bits. Unknown names are left out.
+fn disposition_flags(detail: &str) -> u32 {
+    let Some((_, flags)) = detail.split_once("Flags:") else {
+        return 0;
+    };
+    flags
+        .split(',')
+        .filter_map(|name| {
+            DISPOSITION_FLAGS
+                .iter()
+                .find(|(flag, _)| *flag == name.trim())
+                .map(|(_, bit)| bit)
+        })
+        .fold(0, |flags, bit| flags | bit)
+}
+
+fn import_csv(path: &str) -> Result<Vec<Entry>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| format!("cannot open {path}: {e}"))?;
+
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("invalid export {path}: {e}"))?
+        .clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.trim_start_matches('\u{feff}') == name)
+    };
+    let required =
+        |name: &str| column(name).ok_or_else(|| format!("{path} has no \"{name}\" column"));
+
+    let process = required("Process Name")?;
+    let image = column("Image Path").unwrap_or(process);
+    let pid = required("PID")?;
+    let operation = required("Operation")?;
+    let file = required("Path")?;
+    let detail = required("Detail")?;
+
+    let mut entries = Vec::new();
+    // Procmon writes one event per line, ended by CRLF. The reader counts the LF of a line as
+    // part of the next record, so the lines are counted here, after the header.
+    for (index, row) in reader.records().enumerate() {
+        let row = row.map_err(|e| format!("invalid export {path}: {e}"))?;
+        let line = index + 2;
+        let get = |index: usize| row.get(index).unwrap_or_default();
+        let columns = Row {
+            image: get(image),
+            pid: get(pid),
+            operation: get(operation),
+            path: get(file),
+            detail: get(detail),
+        };
+        if let Some(record) = columns.convert() {
+            entries.push(Entry { line, record });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Reads the process list for the image paths, then the events. An event has its columns as
+/// child elements, `Time_of_Day`, `Process_Name`, `PID`, `Operation`, `Path`, `Detail`, and
+/// the `ProcessIndex` of its process.
+fn import_xml(path: &str) -> Result<Vec<Entry>, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open {path}: {e}"))?;
+    let mut reader = quick_xml::Reader::from_reader(BufReader::new(file));
+
+    let mut images: HashMap<String, String> = HashMap::new();
+    let mut entries = Vec::new();
+    let mut event_count = 0;
+
+    let mut buffer = Vec::new();
+    // Children of the current <process> or <event>, `None` outside of them.
+    let mut fields: Option<HashMap<String, String>> = None;
+    let mut depth = 0;
+    let mut field_name: Option<String> = None;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .map_err(|e| format!("invalid export {path} at {}: {e}", reader.buffer_position()))?;
+        match event {
+            Event::Start(element) => match (&fields, element.name().as_ref()) {
+                (None, b"process" | b"event") => {
+                    fields = Some(HashMap::new());
+                    depth = 0;
+                },
+                (Some(_), name) => {
+                    depth += 1;
+                    field_name = (depth == 1).then(|| String::from_utf8_lossy(name).into_owned());
+                },
+                _ => {},
+            },
+            Event::Text(text) => {
+                if let (Some(fields), Some(name)) = (&mut fields, &field_name) {
+                    let text = text
+                        .unescape()
+                        .map_err(|e| format!("invalid export {path}: {e}"))?;
+                    fields.entry(name.clone()).or_default().push_str(&text);
+                }
+            },
+            Event::End(element) => match element.name().as_ref() {
+                b"process" if depth == 0 => {
+                    if let Some(mut process) = fields.take() {
+                        if let (Some(index), Some(image)) =
+                            (process.remove("ProcessIndex"), process.remove("ImagePath"))
+                        {
+                            images.insert(index, image);
+                        }
+                    }
+                },
+                b"event" if depth == 0 => {
+                    event_count += 1;
+                    if let Some(event) = fields.take() {
+                        if let Some(record) = event_row(&event, &images).convert() {
+                            entries.push(Entry {
+                                line: event_count,
+                                record,
+                            });
+                        }
+                    }
+                },
+                _ => {
+                    if fields.is_some() {
+                        depth -= 1;
+                        field_name = None;
+                    }
+                },
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+        buffer.clear();
+    }
+
+    Ok(entries)
+}
+
+fn event_row<'a>(
+    event: &'a HashMap<String, String>,
+    images: &'a HashMap<String, String>,
+) -> Row<'a> {
+    let get = |name: &str| event.get(name).map(String::as_str).unwrap_or_default();
+    let image = event
+        .get("ProcessIndex")
+        .and_then(|index| images.get(index))
+        .map_or(get("Process_Name"), String::as_str);
+    Row {
+        image,
+        pid: get("PID"),
+        operation: get("Operation"),
+        path: get("Path"),
+        detail: get("Detail"),
+    }
+}
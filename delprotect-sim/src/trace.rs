@@ -0,0 +1,130 @@
+//! Recorded file operations, one JSON object per line, e.g.
+//!
+//! ```json
+//! {"process": "\\Device\\HarddiskVolume3\\Windows\\System32\\cmd.exe", "pid": 4242, "path": "\\Device\\HarddiskVolume3\\work\\a.txt", "op": "set_disposition", "flags": 1}
+//! ```
+//!
+//! `flags` is what the driver reads from the operation: CreateOptions for `create`,
+//! DeleteFile for `set_disposition` and the FILE_DISPOSITION_* flags for `set_disposition_ex`.
+//! A `rename` names the new path in `target` and has ReplaceIfExists as `flags`. A `create` may
+//! carry its DesiredAccess in `access`.
+//!
+//! A `set_size` has the FileInformationClass in `flags`, 19 (allocation), 20 (end of file) or
+//! 39 (valid data length), the size it sets in `size` and the end of file before it in
+//! `end_of_file`. A `set_security` has the SecurityInformation in `flags`, a `set_basic` the
+//! FileAttributes it sets in `flags` and the ones before it in `attributes`. A
+//! `create_process` starts the process `child_pid` with the NT image path `target`, for the
+//! rules which inherit.
+
+use std::io::BufRead;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// IRP_MJ_CREATE.
+    Create,
+    /// IRP_MJ_SET_INFORMATION with FileDispositionInformation.
+    SetDisposition,
+    /// IRP_MJ_SET_INFORMATION with FileDispositionInformationEx.
+    SetDispositionEx,
+    /// IRP_MJ_SET_INFORMATION with FileRenameInformation, only shown to the detector.
+    Rename,
+    /// IRP_MJ_WRITE, not paging I/O.
+    Write,
+    /// IRP_MJ_SET_INFORMATION with FileAllocationInformation, FileEndOfFileInformation or
+    /// FileValidDataLengthInformation.
+    SetSize,
+    /// IRP_MJ_SET_SECURITY.
+    SetSecurity,
+    /// IRP_MJ_SET_INFORMATION with FileBasicInformation.
+    SetBasic,
+    /// The process creation notification.
+    CreateProcess,
+    /// Anything the driver does not filter, replayed as let through.
+    #[serde(other)]
+    Other,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::SetDisposition => "set_disposition",
+            Operation::SetDispositionEx => "set_disposition_ex",
+            Operation::Rename => "rename",
+            Operation::Write => "write",
+            Operation::SetSize => "set_size",
+            Operation::SetSecurity => "set_security",
+            Operation::SetBasic => "set_basic",
+            Operation::CreateProcess => "create_process",
+            Operation::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TraceRecord {
+    /// NT image path of the process, matched against the rules like the driver does.
+    pub process: String,
+    pub pid: u32,
+    pub path: String,
+    pub op: Operation,
+    #[serde(default)]
+    pub flags: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_of_file: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub child_pid: Option<u32>,
+    /// `YYYY-MM-DD[THH:MM]` in UTC, the time given on the command line if missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    #[serde(default)]
+    pub kernel_mode: bool,
+}
+
+impl TraceRecord {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        serde_json::from_str(line).map_err(|e| e.to_string())
+    }
+
+    /// The driver reads the first byte of both disposition structures, DeleteFile of the
+    /// legacy one and the low byte of the Flags of the Ex one.
+    pub fn deletes(&self) -> bool {
+        self.flags & 0xff != 0
+    }
+}
+
+/// A record of the trace or the reason it could not be read.
+pub struct Entry {
+    /// Line of a JSON Lines or CSV file, number of the event of an XML export or of the
+    /// record of a change journal.
+    pub line: usize,
+    pub record: Result<TraceRecord, String>,
+}
+
+/// Reads a JSON Lines trace, blank lines are skipped.
+pub fn read_lines(input: impl BufRead) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(Entry {
+            line: index + 1,
+            record: TraceRecord::parse(&line),
+        });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,127 @@
+//! Deletes and renames of a raw change journal, e.g. a `$Extend\$UsnJrnl:$J` extract, as
+//! trace records. The journal does not know which process made a change, every record is
+//! attributed to the image path given on the command line. Records are replayed at their own
+//! time.
+//!
+//! A delete is the record with FILE_DELETE and CLOSE, so every deleted file is counted once. A
+//! rename pairs the RENAME_OLD_NAME record with the next RENAME_NEW_NAME one of the file.
+//! Paths are rebuilt from the names the journal showed so far, a directory it never named
+//! stays as its file reference, e.g. `<0003000000000a1f>\report.docx`.
+
+use std::{collections::HashMap, fs};
+
+use common::{
+    rule_args::format_utc_time,
+    usn::{
+        UsnRecord, UsnRecords, USN_REASON_CLOSE, USN_REASON_FILE_DELETE,
+        USN_REASON_RENAME_NEW_NAME, USN_REASON_RENAME_OLD_NAME,
+    },
+};
+
+use crate::trace::{Entry, Operation, TraceRecord};
+
+/// MFT record number of the root directory of an NTFS volume.
+const NTFS_ROOT_RECORD: u128 = 5;
+
+/// Names seen in the journal, by file reference.
+#[derive(Default)]
+struct Names {
+    files: HashMap<u128, (u128, String)>,
+}
+
+impl Names {
+    fn update(&mut self, record: &UsnRecord) {
+        let name = String::from_utf16_lossy(&record.file_name_units().collect::<Vec<_>>());
+        self.files
+            .insert(record.file_reference, (record.parent_reference, name));
+    }
+
+    /// Path of `reference` from the outermost directory with a known name.
+    fn path(&self, reference: u128) -> String {
+        let mut components = Vec::new();
+        let mut current = reference;
+        // The depth bound stops on a journal whose references form a cycle.
+        while let Some((parent, name)) = self.files.get(&current) {
+            if components.len() > 256 {
+                break;
+            }
+            components.push(name.as_str());
+            current = *parent;
+        }
+
+        let root = if current & 0xffff_ffff_ffff == NTFS_ROOT_RECORD {
+            String::new()
+        } else {
+            format!("<{current:016x}>")
+        };
+        components.push(&root);
+        components.reverse();
+        components.join("\\")
+    }
+}
+
+pub fn import(path: &str, process: &str) -> Result<Vec<Entry>, String> {
+    let journal = fs::read(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+
+    let mut names = Names::default();
+    let mut old_names: HashMap<u128, String> = HashMap::new();
+    let mut entries = Vec::new();
+
+    let mut records = UsnRecords::new(&journal);
+    let mut index = 0;
+    loop {
+        let offset = records.offset();
+        let Some(record) = records.next() else {
+            break;
+        };
+        index += 1;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                entries.push(Entry {
+                    line: index,
+                    record: Err(format!("{} at offset {offset}", e.as_str())),
+                });
+                break;
+            },
+        };
+
+        names.update(&record);
+        let file = names.path(record.file_reference);
+
+        let (op, flags, file, target) = if record.has_reason(USN_REASON_RENAME_OLD_NAME) {
+            old_names.insert(record.file_reference, file);
+            continue;
+        } else if record.has_reason(USN_REASON_RENAME_NEW_NAME) {
+            match old_names.remove(&record.file_reference) {
+                Some(old_name) => (Operation::Rename, 0, old_name, Some(file)),
+                None => continue,
+            }
+        } else if record.has_reason(USN_REASON_FILE_DELETE) && record.has_reason(USN_REASON_CLOSE) {
+            (Operation::SetDisposition, 1, file, None)
+        } else {
+            continue;
+        };
+
+        entries.push(Entry {
+            line: index,
+            record: Ok(TraceRecord {
+                process: process.to_string(),
+                pid: 0,
+                path: file,
+                op,
+                flags,
+                target,
+                access: None,
+                size: None,
+                end_of_file: None,
+                attributes: None,
+                child_pid: None,
+                time: Some(format_utc_time(record.time_stamp)),
+                kernel_mode: false,
+            }),
+        });
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,181 @@
+use std::{env, fs};
+
+use common::{
+    options::{OPTION_DENY_DELETE_ACCESS, OPTION_GUARD_TRUNCATION},
+    rule::{RuleAction, RULE_FLAG_IMMUTABLE, RULE_FLAG_INHERIT},
+    status::{STATUS_ACCESS_DENIED, STATUS_CANNOT_DELETE},
+};
+use delprotect_core::{rules::MAX_RULE_COUNT, Engine};
+use delprotect_sim::policy::load;
+
+/// Loads `text` as the policy file `name`.
+fn policy(name: &str, text: &str) -> Result<Engine, String> {
+    let path = env::temp_dir().join(format!("delprotect-sim-{}-{name}.json", std::process::id()));
+    fs::write(&path, text).unwrap();
+    let engine = load(path.to_str().unwrap());
+    fs::remove_file(&path).unwrap();
+    engine
+}
+
+/// What loading `text` as the policy file `name` fails with.
+fn error(name: &str, text: &str) -> String {
+    policy(name, text).err().unwrap()
+}
+
+#[test]
+fn sample_policy_loads() {
+    let engine = load(&format!(
+        "{}/samples/policy.json",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap();
+
+    let rules: Vec<(u32, &str, RuleAction)> = engine
+        .rules()
+        .iter()
+        .map(|rule| (rule.id, rule.process_name.as_str(), rule.action))
+        .collect();
+    assert_eq!(
+        rules,
+        [
+            (1, "cmd.exe", RuleAction::Deny),
+            (2, "powershell.exe", RuleAction::Deny),
+            (3, "robocopy.exe", RuleAction::Deny),
+            (4, "explorer.exe", RuleAction::Preserve),
+        ]
+    );
+    assert_eq!(engine.options(), 0);
+}
+
+#[test]
+fn rule_fields_reach_the_engine() {
+    let engine = policy(
+        "fields",
+        r#"{
+            "rules": [
+                { "process": "cmd.exe", "status": "cannot-delete", "not_after": "2026-10-27T18:00" },
+                { "process": "wscript.exe", "flags": ["inherit"] },
+                { "process": "backup.exe", "flags": ["immutable", "inherit"],
+                  "path": "\\Device\\HarddiskVolume3\\Data" }
+            ],
+            "options": ["deny-delete-access", "guard-truncation"]
+        }"#,
+    )
+    .unwrap();
+
+    let cmd = engine.rules().get(1).unwrap();
+    assert_eq!(cmd.failure_status, STATUS_CANNOT_DELETE);
+    assert_ne!(cmd.window.not_after, 0);
+    assert_eq!(cmd.flags, 0);
+    let wscript = engine.rules().get(2).unwrap();
+    assert_eq!(wscript.flags, RULE_FLAG_INHERIT);
+    assert_eq!(wscript.failure_status, STATUS_ACCESS_DENIED);
+    let backup = engine.rules().get(3).unwrap();
+    assert_eq!(backup.flags, RULE_FLAG_IMMUTABLE | RULE_FLAG_INHERIT);
+    assert_eq!(backup.path, r"\Device\HarddiskVolume3\Data");
+    assert!(backup.covers(r"\Device\HarddiskVolume3\data\x.txt"));
+    assert_eq!(
+        engine.options(),
+        OPTION_DENY_DELETE_ACCESS | OPTION_GUARD_TRUNCATION
+    );
+}
+
+#[test]
+fn immutable_rules_and_only_they_have_a_path() {
+    assert_eq!(
+        error(
+            "no-path",
+            r#"{ "rules": [ { "process": "cmd.exe", "flags": ["immutable"] } ] }"#
+        ),
+        "rule 1 (cmd.exe): an immutable rule needs a path"
+    );
+    assert_eq!(
+        error(
+            "stray-path",
+            r#"{ "rules": [ { "process": "cmd.exe", "path": "\\Device\\HarddiskVolume3" } ] }"#
+        ),
+        "rule 1 (cmd.exe): only an immutable rule has a path"
+    );
+    assert_eq!(
+        error(
+            "unknown-flag",
+            r#"{ "rules": [ { "process": "cmd.exe", "flags": ["recursive"] } ] }"#
+        ),
+        "rule 1 (cmd.exe): unknown flag \"recursive\""
+    );
+}
+
+#[test]
+fn invalid_rules_name_their_position() {
+    assert_eq!(
+        error(
+            "action",
+            r#"{ "rules": [ { "process": "cmd.exe" }, { "process": "x.exe", "action": "ignore" } ] }"#
+        ),
+        "rule 2 (x.exe): unknown action \"ignore\""
+    );
+    assert_eq!(
+        error(
+            "status",
+            r#"{ "rules": [ { "process": "cmd.exe", "status": "success" } ] }"#
+        ),
+        "rule 1 (cmd.exe): status \"success\" is not allowed"
+    );
+    assert!(error(
+        "hours",
+        r#"{ "rules": [ { "process": "cmd.exe", "hours": "25:00-26:00" } ] }"#
+    )
+    .starts_with("rule 1 (cmd.exe): "));
+    assert_eq!(
+        error(
+            "option",
+            r#"{ "rules": [], "options": ["deny-everything"] }"#
+        ),
+        "unknown option \"deny-everything\""
+    );
+}
+
+#[test]
+fn malformed_policies_are_rejected() {
+    for (name, text) in [
+        (
+            "field",
+            r#"{ "rules": [ { "process": "cmd.exe", "user": "x" } ] }"#,
+        ),
+        ("rules", r#"{ "detector": { "window": 10 } }"#),
+        ("json", "{ rules: [] }"),
+    ] {
+        assert!(error(name, text).starts_with("invalid policy "), "{name}");
+    }
+    assert!(load("/nonexistent/policy.json")
+        .err()
+        .is_some_and(|e| e.starts_with("cannot read /nonexistent/policy.json")));
+}
+
+#[test]
+fn policy_holds_at_most_the_rules_of_the_driver() {
+    let rules: Vec<String> = (0..=MAX_RULE_COUNT)
+        .map(|index| format!(r#"{{ "process": "tool{index}.exe" }}"#))
+        .collect();
+
+    let e = error("count", &format!(r#"{{ "rules": [{}] }}"#, rules.join(",")));
+
+    assert!(e.ends_with(&format!(
+        "has {} rules, the driver keeps at most {MAX_RULE_COUNT}",
+        MAX_RULE_COUNT + 1
+    )));
+}
+
+#[test]
+fn detector_takes_the_defaults_for_the_fields_left_out() {
+    assert!(policy(
+        "detector",
+        r#"{ "rules": [], "detector": { "max_operations": 50 } }"#
+    )
+    .is_ok());
+    assert!(error(
+        "no-operations",
+        r#"{ "rules": [], "detector": { "max_operations": 0 } }"#
+    )
+    .contains("invalid detector"));
+}
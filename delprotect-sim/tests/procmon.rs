@@ -0,0 +1,124 @@
+use delprotect_core::filter::FILE_DELETE_ON_CLOSE;
+use delprotect_sim::{
+    procmon::{import, is_export},
+    trace::{Entry, Operation, TraceRecord},
+};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn record(entry: &Entry) -> &TraceRecord {
+    entry.record.as_ref().unwrap()
+}
+
+#[test]
+fn exports_are_told_apart_by_their_extension() {
+    assert!(is_export("Logfile.CSV"));
+    assert!(is_export(r"C:\traces\logfile.xml"));
+    assert!(!is_export("trace.jsonl"));
+    assert!(!is_export("-"));
+}
+
+#[test]
+fn only_the_operations_of_the_driver_are_kept() {
+    let entries = import(&fixture("operations.csv")).unwrap();
+
+    let kept: Vec<(usize, Operation, &str)> = entries
+        .iter()
+        .map(|entry| (entry.line, record(entry).op, record(entry).path.as_str()))
+        .collect();
+    assert_eq!(
+        kept,
+        [
+            (2, Operation::Create, r"C:\work\a.txt"),
+            (5, Operation::SetDisposition, r"C:\work\c.txt"),
+            (6, Operation::SetDisposition, r"C:\work\c.txt"),
+            (7, Operation::SetDispositionEx, r"C:\work\d.txt"),
+            (8, Operation::Rename, r"C:\work\e.txt"),
+        ]
+    );
+}
+
+#[test]
+fn details_become_the_flags_the_driver_reads() {
+    let entries = import(&fixture("operations.csv")).unwrap();
+    let flags: Vec<u32> = entries.iter().map(|entry| record(entry).flags).collect();
+
+    // FILE_DISPOSITION_DELETE | FILE_DISPOSITION_POSIX_SEMANTICS, the unknown flag left out
+    assert_eq!(flags, [FILE_DELETE_ON_CLOSE, 1, 0, 0x03, 1]);
+    assert!(record(&entries[1]).deletes());
+    assert!(!record(&entries[2]).deletes());
+
+    let rename = record(&entries[4]);
+    assert_eq!(rename.target.as_deref(), Some(r"C:\work\archive\e.txt"));
+    assert_eq!(rename.process, r"C:\Windows\explorer.exe");
+    assert_eq!(rename.pid, 3300);
+    assert!(entries[..4]
+        .iter()
+        .all(|entry| record(entry).target.is_none()));
+    assert!(entries.iter().all(|entry| {
+        let record = record(entry);
+        record.time.is_none() && record.access.is_none() && !record.kernel_mode
+    }));
+}
+
+#[test]
+fn header_may_start_with_a_byte_order_mark() {
+    let entries = import(&fixture("bom.csv")).unwrap();
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(record(&entries[0]).pid, 4242);
+    // without an Image Path column the process name stands in for it
+    assert_eq!(record(&entries[0]).process, "cmd.exe");
+}
+
+#[test]
+fn non_numeric_pid_is_reported_on_its_line() {
+    let entries = import(&fixture("bom.csv")).unwrap();
+
+    assert_eq!(entries[1].line, 3);
+    assert_eq!(
+        entries[1].record.as_ref().unwrap_err(),
+        "invalid PID \"n/a\""
+    );
+}
+
+#[test]
+fn export_without_a_required_column_is_rejected() {
+    let path = fixture("no_pid.csv");
+
+    assert_eq!(
+        import(&path).err(),
+        Some(format!("{path} has no \"PID\" column"))
+    );
+    assert!(import(&fixture("missing.csv"))
+        .err()
+        .is_some_and(|e| e.starts_with("cannot open")));
+}
+
+#[test]
+fn xml_events_take_the_image_path_of_their_process() {
+    let entries = import(&fixture("processes.xml")).unwrap();
+
+    assert_eq!(entries.len(), 3);
+    let delete = record(&entries[0]);
+    assert_eq!(entries[0].line, 1);
+    assert_eq!(delete.process, r"C:\Windows\System32\cmd.exe");
+    assert_eq!(delete.path, r"C:\work\a&b.txt");
+    assert_eq!((delete.op, delete.flags), (Operation::SetDisposition, 1));
+
+    // process 18 is not in the process list
+    let rename = record(&entries[1]);
+    assert_eq!(entries[1].line, 3);
+    assert_eq!(rename.process, "explorer.exe");
+    assert_eq!(rename.op, Operation::Rename);
+    assert_eq!(rename.flags, 0);
+    assert_eq!(rename.target.as_deref(), Some(r"C:\work\d.txt"));
+
+    assert_eq!(entries[2].line, 4);
+    assert_eq!(
+        entries[2].record.as_ref().unwrap_err(),
+        "invalid PID \"unknown\""
+    );
+}
@@ -0,0 +1,221 @@
+use common::{
+    options::{OPTION_GUARD_SECURITY, OPTION_GUARD_TRUNCATION},
+    rule::{RuleAction, RuleRecord, RULE_FLAG_IMMUTABLE, RULE_FLAG_INHERIT},
+    schedule::{filetime_from_utc, RuleState, TimeWindow},
+    status::STATUS_ACCESS_DENIED,
+};
+use delprotect_core::{
+    filter::{FILE_DELETE_ON_CLOSE, FILE_WRITE_DATA},
+    Config, Decision, Engine, Stats,
+};
+use delprotect_sim::{
+    platform::{replay, SimEngine},
+    trace::TraceRecord,
+};
+
+const CMD: &str = r"\Device\HarddiskVolume3\Windows\System32\cmd.exe";
+const EXPLORER: &str = r"\Device\HarddiskVolume3\Windows\explorer.exe";
+const DATA: &str = r"\Device\HarddiskVolume3\Data";
+
+fn noon() -> u64 {
+    filetime_from_utc(2026, 10, 19, 12, 0).unwrap()
+}
+
+fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// An engine with `options` and a rule for `cmd.exe` with `flags`, immutable under `DATA`.
+fn engine(options: u32, flags: u16) -> SimEngine {
+    let mut engine = Engine::new(Config {
+        options,
+        ..Config::default()
+    })
+    .unwrap();
+    let process = utf16("cmd.exe");
+    let path = if flags & RULE_FLAG_IMMUTABLE != 0 {
+        utf16(DATA)
+    } else {
+        Vec::new()
+    };
+    engine
+        .rules_mut()
+        .add_record(&RuleRecord {
+            id: 0,
+            state: RuleState::Active,
+            action: RuleAction::Deny,
+            flags,
+            window: TimeWindow::default(),
+            failure_status: STATUS_ACCESS_DENIED,
+            process: &process,
+            path: &path,
+        })
+        .unwrap();
+    SimEngine::new(engine)
+}
+
+/// A record of `process` (pid 4242) on a file under `DATA`, the other fields from `fields`.
+fn record(process: &str, fields: &str) -> TraceRecord {
+    let line = format!(
+        r#"{{"process": {process:?}, "pid": 4242, "path": {:?}, {fields}}}"#,
+        format!(r"{DATA}\x.txt")
+    );
+    TraceRecord::parse(&line).unwrap()
+}
+
+fn decide(engine: &SimEngine, record: &TraceRecord) -> Option<Decision> {
+    replay(record, noon(), engine, &Stats::new())
+}
+
+const DENIED: Option<Decision> = Some(Decision::Deny { rule_id: 1 });
+
+#[test]
+fn deletes_of_the_ruled_process_are_denied() {
+    let engine = engine(0, 0);
+
+    for fields in [
+        r#""op": "set_disposition", "flags": 1"#,
+        r#""op": "set_disposition_ex", "flags": 3"#,
+        &format!(r#""op": "create", "flags": {FILE_DELETE_ON_CLOSE}"#),
+    ] {
+        assert_eq!(decide(&engine, &record(CMD, fields)), DENIED, "{fields}");
+        assert_eq!(
+            decide(&engine, &record(EXPLORER, fields)),
+            Some(Decision::Allow),
+            "{fields}"
+        );
+    }
+}
+
+#[test]
+fn operations_the_driver_lets_through_get_no_decision() {
+    let engine = engine(0, 0);
+
+    for fields in [
+        r#""op": "set_disposition", "flags": 0"#,
+        r#""op": "create", "flags": 0"#,
+        &format!(r#""op": "create", "flags": {FILE_DELETE_ON_CLOSE}, "kernel_mode": true"#),
+        r#""op": "read""#,
+        // no rule is immutable and no option guards sizes, security or attributes
+        r#""op": "write""#,
+        r#""op": "set_size", "flags": 20, "size": 0, "end_of_file": 100"#,
+        r#""op": "set_security", "flags": 4"#,
+        r#""op": "set_basic", "flags": 128, "attributes": 1"#,
+    ] {
+        assert_eq!(decide(&engine, &record(CMD, fields)), None, "{fields}");
+    }
+}
+
+#[test]
+fn renames_are_not_decided_by_the_rules() {
+    let engine = engine(0, 0);
+
+    let decision = decide(
+        &engine,
+        &record(CMD, r#""op": "rename", "target": "b.txt""#),
+    );
+
+    assert_ne!(decision, DENIED);
+}
+
+#[test]
+fn immutable_rule_denies_writes_under_its_path() {
+    let engine = engine(0, RULE_FLAG_IMMUTABLE);
+
+    assert_eq!(decide(&engine, &record(CMD, r#""op": "write""#)), DENIED);
+    assert_eq!(
+        decide(
+            &engine,
+            &record(
+                CMD,
+                &format!(r#""op": "create", "access": {FILE_WRITE_DATA}"#)
+            )
+        ),
+        DENIED
+    );
+    let mut outside = record(CMD, r#""op": "write""#);
+    outside.path = r"\Device\HarddiskVolume3\Temp\x.txt".to_string();
+    assert_ne!(decide(&engine, &outside), DENIED);
+    assert_ne!(
+        decide(&engine, &record(EXPLORER, r#""op": "write""#)),
+        DENIED
+    );
+}
+
+#[test]
+fn truncations_are_decided_with_the_option() {
+    let engine = engine(OPTION_GUARD_TRUNCATION, 0);
+
+    for (fields, decision) in [
+        (r#""flags": 20, "size": 10, "end_of_file": 100"#, DENIED),
+        (r#""flags": 19, "size": 0, "end_of_file": 100"#, DENIED),
+        // growing the file loses nothing
+        (r#""flags": 20, "size": 200, "end_of_file": 100"#, None),
+        // the size of the file is unknown
+        (r#""flags": 20, "size": 10"#, None),
+        // not a size information class
+        (r#""flags": 4, "size": 10, "end_of_file": 100"#, None),
+    ] {
+        let fields = format!(r#""op": "set_size", {fields}"#);
+        assert_eq!(decide(&engine, &record(CMD, &fields)), decision, "{fields}");
+    }
+}
+
+#[test]
+fn security_and_attribute_changes_are_decided_with_the_option() {
+    let engine = engine(OPTION_GUARD_SECURITY, 0);
+
+    for (fields, decision) in [
+        // DACL_SECURITY_INFORMATION
+        (r#""op": "set_security", "flags": 4"#, DENIED),
+        // GROUP_SECURITY_INFORMATION does not weaken the protection
+        (r#""op": "set_security", "flags": 2"#, None),
+        // FILE_ATTRIBUTE_NORMAL over FILE_ATTRIBUTE_READONLY
+        (
+            r#""op": "set_basic", "flags": 128, "attributes": 1"#,
+            DENIED,
+        ),
+        (r#""op": "set_basic", "flags": 129, "attributes": 1"#, None),
+        (r#""op": "set_basic", "flags": 128"#, None),
+    ] {
+        assert_eq!(decide(&engine, &record(CMD, fields)), decision, "{fields}");
+    }
+}
+
+#[test]
+fn inheriting_rule_follows_the_processes_created() {
+    let engine = engine(0, RULE_FLAG_INHERIT);
+    let create_process = record(
+        CMD,
+        &format!(r#""op": "create_process", "target": {EXPLORER:?}, "child_pid": 5000"#),
+    );
+    let mut delete = record(EXPLORER, r#""op": "set_disposition", "flags": 1"#);
+    delete.pid = 5000;
+
+    assert_eq!(decide(&engine, &delete), Some(Decision::Allow));
+    assert_eq!(decide(&engine, &create_process), None);
+
+    assert_eq!(decide(&engine, &delete), DENIED);
+    delete.pid = 5001;
+    assert_eq!(decide(&engine, &delete), Some(Decision::Allow));
+}
+
+#[test]
+fn records_are_replayed_at_their_time() {
+    let mut engine = Engine::new(Config::default()).unwrap();
+    engine
+        .rules_mut()
+        .push(
+            "cmd.exe",
+            TimeWindow {
+                not_before: noon() + 1,
+                ..TimeWindow::default()
+            },
+        )
+        .unwrap();
+    let engine = SimEngine::new(engine);
+    let delete = record(CMD, r#""op": "set_disposition", "flags": 1"#);
+
+    assert_eq!(decide(&engine, &delete), Some(Decision::Allow));
+    assert_eq!(replay(&delete, noon() + 1, &engine, &Stats::new()), DENIED);
+}
@@ -0,0 +1,95 @@
+use delprotect_sim::trace::{read_lines, Operation, TraceRecord};
+
+#[test]
+fn record_fields_default_when_left_out() {
+    let record = TraceRecord::parse(
+        r#"{"process": "\\Device\\HarddiskVolume3\\Windows\\System32\\cmd.exe", "pid": 4242, "path": "\\Device\\HarddiskVolume3\\work\\a.txt", "op": "rename"}"#,
+    )
+    .unwrap();
+
+    assert_eq!(record.pid, 4242);
+    assert_eq!(record.op, Operation::Rename);
+    assert_eq!(record.flags, 0);
+    assert!(record.target.is_none() && record.access.is_none() && record.time.is_none());
+    assert!(record.size.is_none() && record.end_of_file.is_none());
+    assert!(record.attributes.is_none() && record.child_pid.is_none());
+    assert!(!record.kernel_mode);
+    assert!(!record.deletes());
+}
+
+#[test]
+fn every_operation_has_its_name() {
+    for op in [
+        Operation::Create,
+        Operation::SetDisposition,
+        Operation::SetDispositionEx,
+        Operation::Rename,
+        Operation::Write,
+        Operation::SetSize,
+        Operation::SetSecurity,
+        Operation::SetBasic,
+        Operation::CreateProcess,
+    ] {
+        let line = format!(
+            r#"{{"process": "cmd.exe", "pid": 1, "path": "a", "op": "{}"}}"#,
+            op.as_str()
+        );
+        assert_eq!(TraceRecord::parse(&line).unwrap().op, op);
+    }
+}
+
+#[test]
+fn unknown_operations_are_replayed_as_other() {
+    let record =
+        TraceRecord::parse(r#"{"process": "cmd.exe", "pid": 1, "path": "a", "op": "read"}"#)
+            .unwrap();
+
+    assert_eq!(record.op, Operation::Other);
+}
+
+#[test]
+fn unknown_fields_and_missing_ones_are_rejected() {
+    for line in [
+        r#"{"process": "cmd.exe", "pid": 1, "path": "a", "op": "create", "options": 1}"#,
+        r#"{"process": "cmd.exe", "path": "a", "op": "create"}"#,
+        r#"{"process": "cmd.exe", "pid": -1, "path": "a", "op": "create"}"#,
+        "not json",
+    ] {
+        assert!(TraceRecord::parse(line).is_err(), "{line}");
+    }
+}
+
+#[test]
+fn only_the_low_byte_of_the_flags_deletes() {
+    let record = |flags: u32| {
+        TraceRecord::parse(&format!(
+            r#"{{"process": "cmd.exe", "pid": 1, "path": "a", "op": "set_disposition_ex", "flags": {flags}}}"#
+        ))
+        .unwrap()
+    };
+
+    assert!(record(0x01).deletes());
+    assert!(record(0x03).deletes());
+    assert!(!record(0x100).deletes());
+}
+
+#[test]
+fn lines_are_numbered_past_blank_ones() {
+    let trace = concat!(
+        r#"{"process": "cmd.exe", "pid": 1, "path": "a", "op": "set_disposition", "flags": 1}"#,
+        "\n\n   \n",
+        "{\"process\": \"cmd.exe\"}\n",
+        r#"{"process": "cmd.exe", "pid": 1, "path": "b", "op": "write", "time": "2026-10-19T12:00"}"#,
+        "\n",
+    );
+
+    let entries = read_lines(trace.as_bytes()).unwrap();
+
+    let lines: Vec<usize> = entries.iter().map(|entry| entry.line).collect();
+    assert_eq!(lines, [1, 4, 5]);
+    assert!(entries[0].record.is_ok());
+    assert!(entries[1].record.is_err());
+    let write = entries[2].record.as_ref().unwrap();
+    assert_eq!(write.op, Operation::Write);
+    assert_eq!(write.time.as_deref(), Some("2026-10-19T12:00"));
+}
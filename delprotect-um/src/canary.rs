@@ -0,0 +1,185 @@
+use common::{
+    canary::{CanaryRecord, CANARY_FLAG_DESCENDANTS},
+    ioctl_codes,
+    rule_args::format_utc_time,
+    wire::{ListHeader, LIST_HEADER_SIZE},
+};
+use std::{ffi::c_void, fs, ptr::null_mut};
+
+use windows_sys::Win32::{
+    Foundation::HANDLE, Storage::FileSystem::QueryDosDeviceW, System::IO::DeviceIoControl,
+};
+
+use crate::{utf16_to_string, LIST_BUFFER_SIZE};
+
+/// `canary add <path> [--descendants]`, `canary list` or `canary remove <id>`.
+pub(crate) fn canary(h_device: HANDLE, args: &[String]) -> i32 {
+    let result = match args {
+        [add, path, rest @ ..] if add == "add" => match rest {
+            [] => nt_path(path).map(|path| add_canary(h_device, &path, 0)),
+            [flag] if flag == "--descendants" => {
+                nt_path(path).map(|path| add_canary(h_device, &path, CANARY_FLAG_DESCENDANTS))
+            },
+            _ => Err(format!("unknown option \"{}\"", rest.join(" "))),
+        },
+        [list] if list == "list" => Ok(list_canaries(h_device)),
+        [remove, id] if remove == "remove" => id
+            .parse::<u32>()
+            .map(|id| remove_canary(h_device, id))
+            .map_err(|_| format!("invalid canary id \"{id}\"")),
+        _ => Err("expected canary add <path> [--descendants], list or remove <id>".to_string()),
+    };
+
+    result.unwrap_or_else(|e| {
+        println!("{e}");
+        1
+    })
+}
+
+/// `C:\Users\Public\budget.xlsx` becomes `\Device\HarddiskVolume3\Users\Public\budget.xlsx`,
+/// the name the driver sees. The file has to exist. NT paths are taken as they are.
+pub(crate) fn nt_path(path: &str) -> Result<String, String> {
+    if path.starts_with("\\Device\\") {
+        return Ok(path.to_string());
+    }
+
+    let full = fs::canonicalize(path).map_err(|e| format!("cannot find \"{path}\": {e}"))?;
+    let full = full.to_string_lossy();
+    let full = full.strip_prefix("\\\\?\\").unwrap_or(&full);
+    let (drive, rest) = match full.as_bytes() {
+        [letter, b':', b'\\', ..] if letter.is_ascii_alphabetic() => full.split_at(2),
+        _ => return Err(format!("\"{full}\" is not on a drive letter")),
+    };
+
+    let drive_name: Vec<u16> = drive.encode_utf16().chain([0]).collect();
+    let mut device = [0u16; 1024];
+    let len = unsafe {
+        QueryDosDeviceW(
+            drive_name.as_ptr(),
+            device.as_mut_ptr(),
+            device.len() as u32,
+        )
+    };
+    if len == 0 {
+        return Err(format!("cannot find the device of {drive}"));
+    }
+
+    let end = device.iter().position(|&c| c == 0).unwrap_or(len as usize);
+    Ok(format!(
+        "{}{rest}",
+        String::from_utf16_lossy(&device[..end])
+    ))
+}
+
+fn add_canary(h_device: HANDLE, path: &str, flags: u32) -> i32 {
+    let path_bytes: Vec<u8> = path.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let record = CanaryRecord {
+        id: 0,
+        flags,
+        trips: 0,
+        last_trip: 0,
+        path: &path_bytes,
+    };
+    let mut input = vec![0u8; record.encoded_len()];
+    if record.encode(&mut input).is_none() {
+        println!("path too long");
+        return 1;
+    }
+    let mut output = [0u8; 4];
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_ADD_CANARY,
+            input.as_ptr() as *const c_void,
+            input.len() as u32,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status != 0 {
+        println!("Canary {} added: {path}", u32::from_le_bytes(output));
+    }
+    status
+}
+
+fn list_canaries(h_device: HANDLE) -> i32 {
+    let mut output = vec![0u8; LIST_BUFFER_SIZE];
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_LIST_CANARIES,
+            null_mut(),
+            0,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    let output = &output[..returned as usize];
+    let Ok(header) = ListHeader::decode(output) else {
+        println!("Invalid response from driver");
+        return status;
+    };
+
+    println!(
+        "{:<4} {:<11} {:<6} {:<17} path",
+        "id", "descendants", "trips", "last trip"
+    );
+    let mut offset = LIST_HEADER_SIZE;
+    for _ in 0..header.returned {
+        let Ok((record, len)) = CanaryRecord::decode(&output[offset..]) else {
+            println!("Invalid canary record at offset {offset}");
+            break;
+        };
+        offset += len;
+
+        let descendants = if record.flags & CANARY_FLAG_DESCENDANTS != 0 {
+            "yes"
+        } else {
+            "no"
+        };
+        let last_trip = match record.last_trip {
+            0 => "never".to_string(),
+            time => format_utc_time(time),
+        };
+        println!(
+            "{:<4} {:<11} {:<6} {:<17} {}",
+            record.id,
+            descendants,
+            record.trips,
+            last_trip,
+            utf16_to_string(record.path)
+        );
+    }
+
+    status
+}
+
+fn remove_canary(h_device: HANDLE, id: u32) -> i32 {
+    let input = id.to_le_bytes();
+
+    let mut returned: u32 = 0;
+    unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_REMOVE_CANARY,
+            input.as_ptr() as *const c_void,
+            input.len() as u32,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    }
+}
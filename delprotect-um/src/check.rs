@@ -0,0 +1,239 @@
+use common::{
+    evaluate::{
+        EvaluateDecision, EvaluateOperation, EvaluateReply, EvaluateRequest, EVALUATE_REPLY_SIZE,
+        MAX_EVALUATE_ANCESTORS,
+    },
+    explain::{RuleTraces, PREDICATES},
+    ioctl_codes,
+    rule_args::{format_utc_time, parse_utc_time},
+};
+use std::{ffi::c_void, ptr::null_mut};
+
+use windows_sys::Win32::{
+    Foundation::HANDLE, Storage::FileSystem::QueryDosDeviceW, System::IO::DeviceIoControl,
+};
+
+use crate::{utf16_to_string, LIST_BUFFER_SIZE};
+
+/// Arguments of `check --as <image> --op <operation> [--parent <image>]... [--at <time>]
+/// [--explain] <path>`.
+pub(crate) struct CheckArgs {
+    image: String,
+    /// Creators of the process, nearest first.
+    parents: Vec<String>,
+    operation: EvaluateOperation,
+    /// FILETIME ticks, 0 for the current time of the driver.
+    time: u64,
+    /// Print how every rule was evaluated.
+    explain: bool,
+    target: String,
+}
+
+pub(crate) fn parse_check_args(args: &[String]) -> Result<CheckArgs, String> {
+    let mut image = None;
+    let mut parents = Vec::new();
+    let mut operation = None;
+    let mut time = 0;
+    let mut explain = false;
+    let mut target = None;
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if !arg.starts_with("--") {
+            if target.replace(arg.clone()).is_some() {
+                return Err(format!("unexpected argument \"{arg}\""));
+            }
+            continue;
+        }
+        if arg == "--explain" {
+            explain = true;
+            continue;
+        }
+
+        let value = it
+            .next()
+            .ok_or_else(|| format!("missing value for \"{arg}\""))?;
+        match arg.as_str() {
+            "--as" => image = Some(value.clone()),
+            "--parent" if parents.len() < MAX_EVALUATE_ANCESTORS => parents.push(value.clone()),
+            "--parent" => {
+                return Err(format!(
+                    "at most {MAX_EVALUATE_ANCESTORS} \"--parent\" options"
+                ))
+            },
+            "--op" => {
+                operation = Some(EvaluateOperation::parse(value).ok_or_else(|| {
+                    let names: Vec<_> = EvaluateOperation::ALL
+                        .iter()
+                        .map(EvaluateOperation::as_str)
+                        .collect();
+                    format!(
+                        "unknown operation \"{value}\", expected one of {}",
+                        names.join(", ")
+                    )
+                })?)
+            },
+            "--at" => time = parse_utc_time(value)?,
+            _ => return Err(format!("unknown option \"{arg}\"")),
+        }
+    }
+
+    Ok(CheckArgs {
+        image: image.ok_or("missing --as <image path>")?,
+        parents,
+        operation: operation.ok_or("missing --op <operation>")?,
+        time,
+        explain,
+        target: target.ok_or("missing the path to check")?,
+    })
+}
+
+/// Asks the driver how it would decide the operation. Paths are translated to the NT paths
+/// the driver sees, so the rules match them exactly like a real operation.
+pub(crate) fn check(h_device: HANDLE, args: &CheckArgs) -> i32 {
+    let image = nt_path(&args.image);
+    let target = nt_path(&args.target);
+
+    let process = utf16_bytes(&image);
+    let target_bytes = utf16_bytes(&target);
+    let parents: Vec<String> = args.parents.iter().map(|parent| nt_path(parent)).collect();
+    let ancestors: Vec<u8> = parents
+        .iter()
+        .flat_map(|parent| parent.encode_utf16().chain([0]))
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let request = EvaluateRequest {
+        operation: args.operation,
+        time: args.time,
+        process: &process,
+        sid: &[],
+        target: &target_bytes,
+        ancestors: &ancestors,
+    };
+    let mut input = vec![0u8; request.encoded_len()];
+    if request.encode(&mut input).is_none() {
+        println!("Path too long");
+        return 1;
+    }
+
+    // the rule traces follow the reply if there is room for them
+    let output_len = if args.explain {
+        LIST_BUFFER_SIZE
+    } else {
+        EVALUATE_REPLY_SIZE
+    };
+    let mut output = vec![0u8; output_len];
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_EVALUATE,
+            input.as_ptr() as *const c_void,
+            input.len() as u32,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    let output = &output[..returned as usize];
+    let Ok(reply) = EvaluateReply::decode(output) else {
+        println!("Invalid response from driver");
+        return status;
+    };
+
+    println!("{} of {}", args.operation.as_str(), target);
+    println!("by {}", image);
+    for parent in &parents {
+        println!("created by {parent}");
+    }
+    match reply.decision {
+        EvaluateDecision::Deny => println!(
+            "would be DENIED by rule {} at {}",
+            reply.rule_id,
+            format_utc_time(reply.time)
+        ),
+        EvaluateDecision::Preserve => println!(
+            "would be PRESERVED in the vault by rule {} at {}",
+            reply.rule_id,
+            format_utc_time(reply.time)
+        ),
+        EvaluateDecision::Canary => println!(
+            "would be DENIED, the file is canary {} and the process would be locked down",
+            reply.rule_id
+        ),
+        EvaluateDecision::Allow => println!("would be allowed at {}", format_utc_time(reply.time)),
+    }
+
+    if args.explain {
+        println!(
+            "rules are evaluated in the order they were added, the first one whose predicates all \
+             hold decides"
+        );
+        print_traces(&output[EVALUATE_REPLY_SIZE..]);
+    }
+
+    status
+}
+
+/// One line per traced rule: step, id, outcome, predicates and the process name of the rule,
+/// with the generation of the ancestor an inherited rule named.
+pub(crate) fn print_traces(traces: &[u8]) {
+    for trace in RuleTraces::new(traces) {
+        let Ok(trace) = trace else {
+            println!("\tinvalid rule trace");
+            break;
+        };
+
+        let predicates: Vec<String> = PREDICATES
+            .iter()
+            .map(|(bit, name)| {
+                let held = trace.predicates & bit != 0;
+                format!("{name}={}", if held { "yes" } else { "no" })
+            })
+            .collect();
+        let inherited = match trace.generation {
+            0 => String::new(),
+            1 => " (inherited from the creator)".to_string(),
+            generation => format!(" (inherited from ancestor {generation})"),
+        };
+        println!(
+            "\tstep {:<3} rule {:<5} {:<9} {} {}{inherited}",
+            trace.step,
+            trace.rule_id,
+            trace.outcome.as_str(),
+            predicates.join(" "),
+            utf16_to_string(trace.name)
+        );
+    }
+}
+
+/// `C:\Data\x.txt` becomes `\Device\HarddiskVolume3\Data\x.txt`. Paths without a drive letter
+/// or on a drive which cannot be resolved are kept as they are.
+fn nt_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    if bytes.len() < 2 || bytes[1] != b':' || !bytes[0].is_ascii_alphabetic() {
+        return path.to_string();
+    }
+
+    let drive: Vec<u16> = path[..2].encode_utf16().chain([0]).collect();
+    let mut device = [0u16; 1024];
+    let len = unsafe { QueryDosDeviceW(drive.as_ptr(), device.as_mut_ptr(), device.len() as u32) };
+    if len == 0 {
+        return path.to_string();
+    }
+
+    // the result is a list of null terminated strings, the first one is the current mapping
+    let end = device.iter().position(|&c| c == 0).unwrap_or(len as usize);
+    String::from_utf16_lossy(&device[..end]) + &path[2..]
+}
+
+fn utf16_bytes(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect()
+}
@@ -0,0 +1,231 @@
+use common::{
+    detector::{
+        DetectorSettings, LockdownCause, LockdownRecord, DEFAULT_WINDOW_SECONDS,
+        DETECTOR_SETTINGS_SIZE,
+    },
+    ioctl_codes,
+    rule_args::format_utc_time,
+    wire::{ListHeader, LIST_HEADER_SIZE},
+};
+use std::{ffi::c_void, ptr::null_mut};
+
+use windows_sys::Win32::{Foundation::HANDLE, System::IO::DeviceIoControl};
+
+use crate::{utf16_to_string, LIST_BUFFER_SIZE};
+
+/// `detector [on|off] [--window S] [--max-ops N] [--max-dirs N]`, parsed against the current
+/// settings so that options left out keep their value. `None` if nothing is to be changed.
+fn parse_detector_args(
+    args: &[String],
+    current: DetectorSettings,
+) -> Result<Option<DetectorSettings>, String> {
+    if args.is_empty() {
+        return Ok(None);
+    }
+
+    let mut settings = current;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "on" if !settings.is_enabled() => settings.window_seconds = DEFAULT_WINDOW_SECONDS,
+            "on" => {},
+            "off" => settings.window_seconds = 0,
+            "--window" | "--max-ops" | "--max-dirs" => {
+                let value = it
+                    .next()
+                    .ok_or_else(|| format!("missing value for \"{arg}\""))?;
+                let number: u32 = value
+                    .parse()
+                    .map_err(|_| format!("invalid number \"{value}\""))?;
+                match arg.as_str() {
+                    "--window" => settings.window_seconds = number,
+                    "--max-ops" => settings.max_operations = number,
+                    _ => settings.max_directories = number,
+                }
+            },
+            _ => return Err(format!("unknown option \"{arg}\"")),
+        }
+    }
+    Ok(Some(settings))
+}
+
+pub(crate) fn detector(h_device: HANDLE, args: &[String]) -> i32 {
+    let Some(current) = get_detector(h_device) else {
+        return 0;
+    };
+    let settings = match parse_detector_args(args, current) {
+        Ok(Some(settings)) => settings,
+        Ok(None) => {
+            print_detector(&current);
+            return 1;
+        },
+        Err(e) => {
+            println!("{e}");
+            return 1;
+        },
+    };
+
+    let mut input = [0u8; DETECTOR_SETTINGS_SIZE];
+    settings.encode(&mut input);
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_SET_DETECTOR,
+            input.as_ptr() as *const c_void,
+            input.len() as u32,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status != 0 {
+        print_detector(&settings);
+    }
+    status
+}
+
+fn get_detector(h_device: HANDLE) -> Option<DetectorSettings> {
+    let mut output = [0u8; DETECTOR_SETTINGS_SIZE];
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_GET_DETECTOR,
+            null_mut(),
+            0,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return None;
+    }
+
+    match DetectorSettings::decode(&output[..returned as usize]) {
+        Ok(settings) => Some(settings),
+        Err(_) => {
+            println!("Invalid response from driver");
+            None
+        },
+    }
+}
+
+fn print_detector(settings: &DetectorSettings) {
+    let state = if settings.is_enabled() { "on" } else { "off" };
+    let directories = match settings.max_directories {
+        0 => "any number of directories".to_string(),
+        count => format!("more than {count} directories"),
+    };
+    println!(
+        "detector={state} window={}s: lock down above {} operations in {directories}",
+        settings.window_seconds, settings.max_operations
+    );
+}
+
+/// `lockdown list` or `lockdown release <pid|all>`.
+pub(crate) fn lockdown(h_device: HANDLE, args: &[String]) -> i32 {
+    match args {
+        [list] if list == "list" => list_lockdowns(h_device),
+        [release, pid] if release == "release" => {
+            let process_id = if pid == "all" {
+                0
+            } else {
+                match pid.parse::<u32>() {
+                    Ok(process_id) if process_id != 0 => process_id,
+                    _ => {
+                        println!("invalid process id \"{pid}\"");
+                        return 1;
+                    },
+                }
+            };
+            release_lockdown(h_device, process_id)
+        },
+        _ => {
+            println!("expected lockdown list or lockdown release <pid|all>");
+            1
+        },
+    }
+}
+
+fn list_lockdowns(h_device: HANDLE) -> i32 {
+    let mut output = vec![0u8; LIST_BUFFER_SIZE];
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_LIST_LOCKDOWNS,
+            null_mut(),
+            0,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    let output = &output[..returned as usize];
+    let Ok(header) = ListHeader::decode(output) else {
+        println!("Invalid response from driver");
+        return status;
+    };
+
+    println!("{} process(es) locked down", header.total);
+    let mut offset = LIST_HEADER_SIZE;
+    for _ in 0..header.returned {
+        let Ok((record, len)) = LockdownRecord::decode(&output[offset..]) else {
+            println!("Invalid lockdown record at offset {offset}");
+            break;
+        };
+        offset += len;
+
+        let cause = match record.cause {
+            _ if record.parent_id != 0 => format!("created by locked down {}", record.parent_id),
+            LockdownCause::MassDelete => format!(
+                "{} operations in {} directories",
+                record.operations, record.directories
+            ),
+            LockdownCause::Canary => "touched a canary".to_string(),
+            LockdownCause::Demoted => "demoted by a rule".to_string(),
+        };
+        println!(
+            "{} pid {:<6} {} {cause}",
+            format_utc_time(record.time),
+            record.process_id,
+            utf16_to_string(record.process)
+        );
+    }
+
+    status
+}
+
+fn release_lockdown(h_device: HANDLE, process_id: u32) -> i32 {
+    let input = process_id.to_le_bytes();
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_RELEASE_LOCKDOWN,
+            input.as_ptr() as *const c_void,
+            input.len() as u32,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status != 0 {
+        println!("Released");
+    }
+    status
+}
@@ -3,7 +3,11 @@ use windows::core::imp::{
     FormatMessageW, GetLastError, FORMAT_MESSAGE_ALLOCATE_BUFFER, FORMAT_MESSAGE_FROM_SYSTEM,
     FORMAT_MESSAGE_IGNORE_INSERTS,
 };
-use windows_sys::Win32::Foundation::LocalFree;
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, LocalFree, ERROR_ACCESS_DENIED, HANDLE},
+    Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY},
+    System::Threading::{GetCurrentProcess, OpenProcessToken},
+};
 
 pub(crate) fn print_last_error(msg: &str) {
     let error_code = unsafe { GetLastError() };
@@ -14,6 +18,36 @@ pub(crate) fn print_last_error(msg: &str) {
         error_code,
         error_msg.trim_end()
     );
+
+    if error_code == ERROR_ACCESS_DENIED {
+        if is_elevated() {
+            println!("The driver is locked, pass the secret with --secret-file");
+        } else {
+            println!("This command requires elevation, run it from an administrator prompt");
+        }
+    }
+}
+
+fn is_elevated() -> bool {
+    unsafe {
+        let mut token: HANDLE = 0;
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
+
+        let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
+        let mut returned: u32 = 0;
+        let status = GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut TOKEN_ELEVATION as *mut c_void,
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut returned,
+        );
+        CloseHandle(token);
+
+        status != 0 && elevation.TokenIsElevated != 0
+    }
 }
 
 pub(crate) fn get_error_as_string(error_msg_id: u32) -> Option<String> {
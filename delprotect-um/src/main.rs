@@ -1,28 +1,105 @@
+mod canary;
+mod check;
+mod detector;
 mod error_msg;
+mod secret;
+mod vault;
+mod volume_args;
 
-use crate::error_msg::print_last_error;
+use crate::{
+    canary::{canary, nt_path},
+    check::{check, parse_check_args, print_traces},
+    detector::{detector, lockdown},
+    error_msg::print_last_error,
+    secret::{
+        allow_unload, authenticate, generate_secret_file, read_secret_file, set_secret,
+        show_lock_status,
+    },
+    vault::{open_vault, vault_command},
+    volume_args::{device_type_name, filesystem_name, media_name, parse_volume_policy},
+};
 
-use common::ioctl_codes;
+use common::{
+    detector::{DEFAULT_WINDOW_SECONDS, MAX_WINDOW_SECONDS},
+    event::{EventKind, EventRecord},
+    ioctl_codes,
+    logging::LogLevel,
+    options::{OptionsUpdate, OPTION_NAMES},
+    rule::{
+        failure_status_name, parse_failure_status, Response, RuleAction, RuleRecord,
+        FAILURE_STATUSES, LIST_FLAG_PURGE_EXPIRED, RULE_FLAG_IMMUTABLE, RULE_FLAG_INHERIT,
+    },
+    rule_args::{format_time_window, format_utc_time, parse_time_window},
+    schedule::{RuleState, TimeWindow},
+    stats::{Counter, RuleStats, StatsHeader, RULE_STATS_SIZE, STATS_FLAG_RESET},
+    status::{NtStatus, STATUS_ACCESS_DENIED},
+    vault::VAULT_DIRECTORY,
+    volume::{InstanceRecord, VolumePolicy},
+    wire::{ListHeader, LIST_HEADER_SIZE},
+};
 use std::{env, ffi::c_void, ptr::null_mut};
 
 use windows_sys::Win32::{
-    Foundation::{CloseHandle, GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE},
+    Foundation::{CloseHandle, GENERIC_READ, GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE},
     Storage::FileSystem::{CreateFileA, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING},
     System::IO::DeviceIoControl,
 };
 
+const LIST_BUFFER_SIZE: usize = 64 * 1024;
+/// Secret used to authorize the handle when `--secret-file` is not given.
+const SECRET_FILE_ENV: &str = "DELPROTECT_SECRET_FILE";
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
     //println!("{args:?}");
+    let mut secret_file = env::var(SECRET_FILE_ENV).ok();
+    if args.len() >= 3 && args[1] == "--secret-file" {
+        secret_file = Some(args[2].clone());
+        args.drain(1..3);
+    }
     if args.len() < 2 {
         print_usage();
         return;
     }
 
+    // does not need the driver, used at install time before `secret set`
+    if args[1] == "secret" && args.len() == 4 && args[2] == "generate" {
+        match generate_secret_file(&args[3]) {
+            Ok(()) => println!("Secret written to {}", args[3]),
+            Err(e) => println!("{e}"),
+        }
+        return;
+    }
+
+    // does not need the driver either, the vault is read from the volume. Changes to it need a
+    // handle which opened the vaults, the driver denies them otherwise.
+    if args[1] == "vault" && args.get(2).is_some_and(|command| command == "list") {
+        if let Err(e) = vault_command(&args[2..]) {
+            println!("{e}");
+            print_usage();
+        }
+        return;
+    }
+
+    let secret = match secret_file.as_deref().map(read_secret_file).transpose() {
+        Ok(secret) => secret,
+        Err(e) => {
+            println!("{e}");
+            return;
+        },
+    };
+
+    // read-only commands work with a read-only handle, the driver refuses changes on it
+    let access = if secret.is_some() || is_mutating_command(&args[1..]) {
+        GENERIC_READ | GENERIC_WRITE
+    } else {
+        GENERIC_READ
+    };
+
     let h_device = unsafe {
         CreateFileA(
             "\\\\.\\DelProtect\0".as_ptr(),
-            GENERIC_WRITE,
+            access,
             FILE_SHARE_READ | FILE_SHARE_WRITE,
             null_mut(),
             OPEN_EXISTING,
@@ -37,22 +114,28 @@ fn main() {
     }
     println!("CreateFile success!");
 
+    if let Some(secret) = &secret {
+        if authenticate(h_device, secret) == 0 {
+            print_last_error("Authorization failed");
+            unsafe {
+                CloseHandle(h_device);
+            }
+            return;
+        }
+    }
+
     let status = match args[1].as_str() {
         "add" => {
-            if args.len() == 3 {
-                let name = args[2].clone() + "\0";
-                let mut returned: u32 = 0;
-                unsafe {
-                    DeviceIoControl(
-                        h_device,
-                        ioctl_codes::IOCTL_DELPROTECT_ADD_EXE_UTF8,
-                        name.as_ptr() as *const c_void,
-                        name.len() as u32,
-                        null_mut(),
-                        0,
-                        &mut returned as *mut u32,
-                        null_mut(),
-                    )
+            if args.len() >= 3 {
+                match parse_rule_options(&args[3..]).and_then(|(options, rest)| {
+                    parse_time_window(&rest).map(|window| (options, window))
+                }) {
+                    Ok((options, window)) => add_rule(h_device, &args[2], &options, window),
+                    Err(e) => {
+                        println!("{e}");
+                        print_usage();
+                        1
+                    },
                 }
             } else {
                 print_usage();
@@ -80,6 +163,79 @@ fn main() {
                 0
             }
         },
+        "list" => {
+            let purge = args.iter().skip(2).any(|a| a == "--purge-expired");
+            list_rules(h_device, purge)
+        },
+        "stats" => {
+            let reset = args.iter().skip(2).any(|a| a == "--reset");
+            show_stats(h_device, reset)
+        },
+        "volumes" => list_instances(h_device),
+        "volume-policy" => match parse_volume_policy(&args[2..]) {
+            Ok(policy) => set_volume_policy(h_device, &policy),
+            Err(e) => {
+                println!("{e}");
+                print_usage();
+                1
+            },
+        },
+        "events" => read_events(h_device),
+        "detector" => detector(h_device, &args[2..]),
+        "lockdown" => lockdown(h_device, &args[2..]),
+        "canary" => canary(h_device, &args[2..]),
+        "log-level" => match args.get(2).map(|name| LogLevel::parse(name)) {
+            None => show_log_level(h_device),
+            Some(Some(level)) => set_log_level(h_device, level),
+            Some(None) => {
+                println!("Unknown log level {}", args[2]);
+                print_usage();
+                1
+            },
+        },
+        "check" => match parse_check_args(&args[2..]) {
+            Ok(check_args) => check(h_device, &check_args),
+            Err(e) => {
+                println!("{e}");
+                print_usage();
+                1
+            },
+        },
+        "options" => match parse_options_update(&args[2..]) {
+            Ok(update) if update.mask != 0 => set_options(h_device, &update),
+            Ok(_) => show_options(h_device),
+            Err(e) => {
+                println!("{e}");
+                print_usage();
+                1
+            },
+        },
+        "secret" => match (args.get(2).map(String::as_str), args.get(3)) {
+            (Some("set"), Some(path)) => match read_secret_file(path) {
+                Ok(new_secret) => set_secret(h_device, &new_secret),
+                Err(e) => {
+                    println!("{e}");
+                    1
+                },
+            },
+            (Some("status"), None) => show_lock_status(h_device),
+            _ => {
+                print_usage();
+                1
+            },
+        },
+        "unlock" => allow_unload(h_device),
+        "vault" => {
+            if open_vault(h_device) == 0 {
+                0
+            } else {
+                if let Err(e) = vault_command(&args[2..]) {
+                    println!("{e}");
+                    print_usage();
+                }
+                1
+            }
+        },
         "clear" => {
             let mut returned: u32 = 0;
             unsafe {
@@ -110,7 +266,626 @@ fn main() {
     }
 }
 
+fn is_mutating_command(args: &[String]) -> bool {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    !matches!(
+        args.as_slice(),
+        ["list"]
+            | ["stats"]
+            | ["volumes"]
+            | ["log-level"]
+            | ["detector"]
+            | ["lockdown", "list"]
+            | ["canary", "list"]
+            | ["options"]
+            | ["secret", "status"]
+            | ["check", ..]
+    )
+}
+
+/// The options of `add` besides the time ones.
+struct RuleOptions {
+    action: RuleAction,
+    flags: u16,
+    failure_status: NtStatus,
+    /// NT path of `--immutable`.
+    path: String,
+}
+
+/// Takes `--action <action>`, `--inherit`, `--immutable <path>` and `--status <status>` out of
+/// the options of `add`, the rest are time options.
+fn parse_rule_options(args: &[String]) -> Result<(RuleOptions, Vec<String>), String> {
+    let mut options = RuleOptions {
+        action: RuleAction::default(),
+        flags: 0,
+        failure_status: STATUS_ACCESS_DENIED,
+        path: String::new(),
+    };
+    let mut rest = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--inherit" => options.flags |= RULE_FLAG_INHERIT,
+            "--immutable" => {
+                let value = it.next().ok_or("missing value for \"--immutable\"")?;
+                options.flags |= RULE_FLAG_IMMUTABLE;
+                options.path = nt_path(value)?;
+            },
+            "--status" => {
+                let value = it.next().ok_or("missing value for \"--status\"")?;
+                options.failure_status = parse_failure_status(value).ok_or_else(|| {
+                    let names: Vec<_> = FAILURE_STATUSES.iter().map(|(_, name)| *name).collect();
+                    format!(
+                        "status \"{value}\" is not allowed, expected one of {}",
+                        names.join(", ")
+                    )
+                })?;
+            },
+            "--action" => {
+                let value = it.next().ok_or("missing value for \"--action\"")?;
+                options.action = RuleAction::parse(value).ok_or_else(|| {
+                    format!(
+                        "unknown action \"{value}\", expected deny, preserve, deny-and-terminate, \
+                         deny-and-suspend or deny-and-demote"
+                    )
+                })?;
+            },
+            _ => rest.push(arg.clone()),
+        }
+    }
+    Ok((options, rest))
+}
+
+fn add_rule(h_device: HANDLE, exe_name: &str, options: &RuleOptions, window: TimeWindow) -> i32 {
+    let process: Vec<u8> = exe_name
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    let path: Vec<u8> = options
+        .path
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+
+    let record = RuleRecord {
+        id: 0,
+        state: RuleState::Active,
+        action: options.action,
+        flags: options.flags,
+        window,
+        failure_status: options.failure_status,
+        process: &process,
+        path: &path,
+    };
+    let mut input = vec![0u8; record.encoded_len()];
+    record.encode(&mut input);
+
+    let mut returned: u32 = 0;
+    unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_ADD_RULE,
+            input.as_ptr() as *const c_void,
+            input.len() as u32,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    }
+}
+
+fn list_rules(h_device: HANDLE, purge_expired: bool) -> i32 {
+    let flags: u32 = if purge_expired {
+        LIST_FLAG_PURGE_EXPIRED
+    } else {
+        0
+    };
+    let mut output = vec![0u8; LIST_BUFFER_SIZE];
+    output[..4].copy_from_slice(&flags.to_le_bytes());
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_LIST_RULES,
+            output.as_ptr() as *const c_void,
+            4,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    let output = &output[..returned as usize];
+    let Ok(header) = ListHeader::decode(output) else {
+        println!("Invalid response from driver");
+        return status;
+    };
+
+    let mut offset = LIST_HEADER_SIZE;
+    for _ in 0..header.returned {
+        let Ok((record, len)) = RuleRecord::decode(&output[offset..]) else {
+            println!("Invalid rule record at offset {offset}");
+            break;
+        };
+        offset += len;
+
+        let inherit = if record.flags & RULE_FLAG_INHERIT != 0 {
+            ", inherit"
+        } else {
+            ""
+        };
+        let immutable = if record.flags & RULE_FLAG_IMMUTABLE != 0 {
+            format!(", immutable under {}", utf16_to_string(record.path))
+        } else {
+            String::new()
+        };
+        let failure = match record.failure_status {
+            STATUS_ACCESS_DENIED => String::new(),
+            status => format!(
+                ", fails with {}",
+                failure_status_name(status).unwrap_or_default()
+            ),
+        };
+        println!(
+            "{:>4}  {:<8} {:<8} {:<24} {}{inherit}{immutable}{failure}",
+            record.id,
+            record.state.as_str(),
+            record.action.as_str(),
+            utf16_to_string(record.process),
+            format_time_window(&record.window)
+        );
+    }
+
+    if header.returned < header.total {
+        println!(
+            "... {} more rule(s) not shown",
+            header.total - header.returned
+        );
+    }
+    if header.removed > 0 {
+        println!("Purged {} expired rule(s)", header.removed);
+    }
+
+    status
+}
+
+fn show_stats(h_device: HANDLE, reset: bool) -> i32 {
+    let flags: u32 = if reset { STATS_FLAG_RESET } else { 0 };
+    let mut output = vec![0u8; LIST_BUFFER_SIZE];
+    output[..4].copy_from_slice(&flags.to_le_bytes());
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_GET_STATS,
+            output.as_ptr() as *const c_void,
+            4,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    let output = &output[..returned as usize];
+    let Ok((header, mut offset)) = StatsHeader::decode(output) else {
+        println!("Invalid response from driver");
+        return status;
+    };
+
+    println!("since {}", format_utc_time(header.since));
+    for counter in Counter::ALL {
+        println!("{:<24} {}", counter.as_str(), header.counter(counter));
+    }
+
+    if header.rule_count > 0 {
+        println!("\nrule  hits        last hit");
+    }
+    for _ in 0..header.rule_count {
+        let Ok(record) = RuleStats::decode(&output[offset..]) else {
+            println!("Invalid rule statistics at offset {offset}");
+            break;
+        };
+        offset += RULE_STATS_SIZE;
+
+        let last_hit = match record.last_hit {
+            0 => "-".to_string(),
+            time => format_utc_time(time),
+        };
+        println!("{:>4}  {:<10}  {}", record.rule_id, record.hits, last_hit);
+    }
+
+    if reset {
+        println!("Counters reset");
+    }
+
+    status
+}
+
+fn set_volume_policy(h_device: HANDLE, policy: &VolumePolicy) -> i32 {
+    let mut input = vec![0u8; policy.encoded_len()];
+    policy.encode(&mut input);
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_SET_VOLUME_POLICY,
+            input.as_ptr() as *const c_void,
+            input.len() as u32,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status != 0 {
+        println!("Volume policy saved, it applies to volumes attached from now on");
+    }
+
+    status
+}
+
+fn list_instances(h_device: HANDLE) -> i32 {
+    let mut output = vec![0u8; LIST_BUFFER_SIZE];
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_LIST_INSTANCES,
+            null_mut(),
+            0,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    let output = &output[..returned as usize];
+    let Ok(header) = ListHeader::decode(output) else {
+        println!("Invalid response from driver");
+        return status;
+    };
+
+    let mut offset = LIST_HEADER_SIZE;
+    for _ in 0..header.returned {
+        let Ok((record, len)) = InstanceRecord::decode(&output[offset..]) else {
+            println!("Invalid instance record at offset {offset}");
+            break;
+        };
+        offset += len;
+
+        let letter = if record.drive_letter != 0 {
+            format!("{}:", record.drive_letter as char)
+        } else {
+            "-".to_string()
+        };
+        println!(
+            "{:<3} {:<36} {:<8} {:<8} {}",
+            letter,
+            utf16_to_string(record.volume_name),
+            filesystem_name(record.filesystem_type),
+            device_type_name(record.device_type),
+            media_name(record.media)
+        );
+    }
+
+    if header.returned < header.total {
+        println!(
+            "... {} more instance(s) not shown",
+            header.total - header.returned
+        );
+    }
+
+    status
+}
+
+fn read_events(h_device: HANDLE) -> i32 {
+    let mut output = vec![0u8; LIST_BUFFER_SIZE];
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_GET_EVENTS,
+            null_mut(),
+            0,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    let output = &output[..returned as usize];
+    let Ok(header) = ListHeader::decode(output) else {
+        println!("Invalid response from driver");
+        return status;
+    };
+
+    if header.removed > 0 {
+        println!(
+            "{} event(s) were dropped, the queue was full",
+            header.removed
+        );
+    }
+
+    let mut offset = LIST_HEADER_SIZE;
+    for _ in 0..header.returned {
+        let Ok((record, len)) = EventRecord::decode(&output[offset..]) else {
+            println!("Invalid event record at offset {offset}");
+            break;
+        };
+        offset += len;
+
+        let kind = EventKind::from_u16(record.kind)
+            .map(|kind| kind.as_str().to_string())
+            .unwrap_or_else(|| format!("kind-{}", record.kind));
+        println!(
+            "{} {:<7} {:<16} pid {:<6} status 0x{:08x} {}",
+            format_utc_time(record.time),
+            record.severity.as_str(),
+            kind,
+            record.process_id,
+            record.status,
+            utf16_to_string(record.process)
+        );
+        if !record.target.is_empty() {
+            println!("\ttarget: {}", utf16_to_string(record.target));
+        }
+        if record.rule_id != 0 {
+            println!("\trule: {}", record.rule_id);
+        }
+        if record.response != Response::None {
+            println!("\tresponse: {}", record.response.as_str());
+        }
+        if !record.detail.is_empty() {
+            println!("\t{}", utf16_to_string(record.detail));
+        }
+        print_traces(record.trace);
+    }
+
+    if header.returned < header.total {
+        println!(
+            "... {} more event(s), run again to read them",
+            header.total - header.returned
+        );
+    }
+
+    status
+}
+
+/// Accepts `name=on|off` pairs, e.g. `deny-detach=on`.
+fn parse_options_update(args: &[String]) -> Result<OptionsUpdate, String> {
+    let mut update = OptionsUpdate::default();
+    for arg in args {
+        let (name, value) = arg
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=on|off, got \"{arg}\""))?;
+        let (_, flag) = OPTION_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .ok_or_else(|| format!("unknown option \"{name}\""))?;
+
+        update.mask |= flag;
+        match value {
+            "on" => update.values |= flag,
+            "off" => update.values &= !flag,
+            _ => return Err(format!("invalid value \"{value}\", expected on or off")),
+        }
+    }
+    Ok(update)
+}
+
+fn set_options(h_device: HANDLE, update: &OptionsUpdate) -> i32 {
+    let mut input = [0u8; 8];
+    update.encode(&mut input);
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_SET_OPTIONS,
+            input.as_ptr() as *const c_void,
+            input.len() as u32,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    show_options(h_device)
+}
+
+fn show_options(h_device: HANDLE) -> i32 {
+    let mut output = [0u8; 4];
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_GET_OPTIONS,
+            null_mut(),
+            0,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    let options = u32::from_le_bytes(output);
+    for (name, flag) in OPTION_NAMES {
+        let value = if options & flag != 0 { "on" } else { "off" };
+        println!("{name}={value}");
+    }
+
+    status
+}
+
+fn set_log_level(h_device: HANDLE, level: LogLevel) -> i32 {
+    let input = (level as u32).to_le_bytes();
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_SET_LOG_LEVEL,
+            input.as_ptr() as *const c_void,
+            input.len() as u32,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    show_log_level(h_device)
+}
+
+fn show_log_level(h_device: HANDLE) -> i32 {
+    let mut output = [0u8; 4];
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_GET_LOG_LEVEL,
+            null_mut(),
+            0,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return status;
+    }
+
+    match LogLevel::decode(&output) {
+        Ok(level) => println!("log-level={}", level.as_str()),
+        Err(_) => println!("Invalid response from driver"),
+    }
+
+    status
+}
+
+fn utf16_to_string(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
 fn print_usage() {
-    println!("Usage: DelProtectConfig <option> [exename]\n");
-    println!("\tOption: add, remove or clear\n");
+    println!("Usage: DelProtectConfig [--secret-file <path>] <option> [exename] [time options]\n");
+    println!(
+        "\tOption: add, remove, list, clear, volumes, volume-policy, events, options, secret, \
+         unlock, check, stats, log-level, detector, lockdown, canary or vault\n"
+    );
+    println!(
+        "\t--secret-file (or {SECRET_FILE_ENV}) authorizes changes when the driver is locked\n"
+    );
+    println!("\tRule options for add:");
+    println!("\t\t--action deny|preserve  preserve copies the file into the vault, then lets");
+    println!("\t\t                        the delete go on (deny by default)");
+    println!("\t\t--action deny-and-terminate|deny-and-suspend");
+    println!("\t\t                        deny, then terminate or suspend the process");
+    println!("\t\t--action deny-and-demote");
+    println!("\t\t                        deny, then lock the process down like the detector");
+    println!("\t\t--status <status>       what a denied delete fails with: access-denied (the");
+    println!("\t\t                        default), cannot-delete, sharing-violation or");
+    println!("\t\t                        media-write-protected, by name or value");
+    println!("\t\t--inherit               the processes it creates, and theirs, are subject to");
+    println!("\t\t                        the rule as well");
+    println!("\t\t--immutable <path>      its writes, writable mappings and opens for write");
+    println!("\t\t                        access of the file, or of the files below the");
+    println!("\t\t                        directory, fail as well, reads go on\n");
+    println!("\tTime options for add (UTC):");
+    println!("\t\t--not-before YYYY-MM-DD[THH:MM]");
+    println!("\t\t--not-after YYYY-MM-DD[THH:MM]");
+    println!("\t\t--days mon,tue,wed,thu,fri,sat,sun");
+    println!("\t\t--hours HH:MM-HH:MM\n");
+    println!("\tOptions for list:");
+    println!("\t\t--purge-expired\n");
+    println!("\tOptions for stats:");
+    println!("\t\t--reset            zero the counters after showing them\n");
+    println!("\tOptions for log-level (no argument = show the current level):");
+    println!("\t\toff|error|warn|info|debug|trace  persisted across reboots\n");
+    println!("\tOptions for detector (no options = show the settings), persisted across reboots:");
+    println!("\t\ton|off             on starts with a {DEFAULT_WINDOW_SECONDS} s window");
+    println!("\t\t--window S         window in seconds, at most {MAX_WINDOW_SECONDS}");
+    println!("\t\t--max-ops N        lock a process down above N deletes, renames and overwrites");
+    println!("\t\t--max-dirs N       ... spread over more than N directories, 0 to ignore\n");
+    println!("\tOptions for lockdown (locked down processes cannot delete, rename or overwrite):");
+    println!("\t\tlist");
+    println!("\t\trelease <pid|all>\n");
+    println!("\tOptions for canary (touching a canary locks the process down):");
+    println!("\t\tadd <path> [--descendants]  the processes it creates are locked down too");
+    println!("\t\tlist");
+    println!("\t\tremove <id>\n");
+    println!("\tOptions for volume-policy (no options = attach to every volume):");
+    println!("\t\t--fs ntfs,refs,fat,exfat,...");
+    println!("\t\t--device disk,cdrom,network,...");
+    println!("\t\t--media fixed,removable,network");
+    println!("\t\t--letters CD");
+    println!("\t\t--guid xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx\n");
+    println!("\tOptions for options (no arguments = show current values):");
+    println!("\t\tdeny-detach=on|off");
+    println!("\t\tdeny-delete-access=on|off  fail opens for DELETE by blocked processes");
+    println!("\t\tguard-truncation=on|off    decide shrinking a file like deleting it");
+    println!("\t\tguard-security=on|off      decide owner, DACL and READONLY/SYSTEM changes like");
+    println!("\t\t                           deleting the file\n");
+    println!("\tOptions for secret:");
+    println!("\t\tgenerate <path>  write a new random secret");
+    println!("\t\tset <path>       lock the driver with the secret, or replace it");
+    println!("\t\tstatus\n");
+    println!("\tunlock allows the next \"fltmc unload\" of a locked driver\n");
+    println!("\tOptions for check (asks the driver how it would decide, nothing is deleted):");
+    println!(
+        "\t\t--as <image path>  process doing the operation, e.g. C:\\Windows\\System32\\cmd.exe"
+    );
+    println!("\t\t--op delete|open-for-delete|truncate|write|set-security|rename|overwrite");
+    println!("\t\t                   decided with the options and canaries of the driver");
+    println!("\t\t--parent <image path>  creator of the process, repeat for its ancestors");
+    println!("\t\t--at YYYY-MM-DD[THH:MM]  UTC, now by default");
+    println!("\t\t--explain          show how every rule was evaluated");
+    println!("\t\t<path>             file to check\n");
+    println!("\tvault commands (read X:\\{VAULT_DIRECTORY} directly, all but list open the");
+    println!("\t\tvaults through the driver first, which denies changes to them otherwise):");
+    println!("\t\tvault list <X:>");
+    println!("\t\tvault restore <X:> <id> [--to <path>]  never overwrites an existing file");
+    println!("\t\tvault purge <X:> [--all | --id <id>]   past the retention by default");
+    println!("\t\t\tthe only way the retention is applied, the driver keeps every copy");
+    println!("\t\tvault retention <X:> [--days N] [--max-mb M]  0 disables the limit\n");
 }
@@ -0,0 +1,145 @@
+use common::{
+    auth::{
+        is_valid_secret, response, ChallengeMessage, AUTH_FLAG_AUTHORIZED, AUTH_FLAG_LOCKED,
+        AUTH_FLAG_UNLOAD_ALLOWED, CHALLENGE_MESSAGE_SIZE, MAX_SECRET_SIZE, MIN_SECRET_SIZE,
+    },
+    ioctl_codes,
+};
+use std::{ffi::c_void, fs, ptr::null_mut};
+
+use windows_sys::Win32::{
+    Foundation::HANDLE,
+    Security::Cryptography::{BCryptGenRandom, BCRYPT_USE_SYSTEM_PREFERRED_RNG},
+    System::IO::DeviceIoControl,
+};
+
+/// Size of secrets written by `secret generate`.
+const GENERATED_SECRET_SIZE: usize = 32;
+
+pub(crate) fn read_secret_file(path: &str) -> Result<Vec<u8>, String> {
+    let secret = fs::read(path).map_err(|e| format!("failed to read \"{path}\": {e}"))?;
+    if !is_valid_secret(&secret) {
+        return Err(format!(
+            "secret in \"{path}\" must be {MIN_SECRET_SIZE} to {MAX_SECRET_SIZE} bytes long"
+        ));
+    }
+    Ok(secret)
+}
+
+pub(crate) fn generate_secret_file(path: &str) -> Result<(), String> {
+    let mut secret = [0u8; GENERATED_SECRET_SIZE];
+    let status = unsafe {
+        BCryptGenRandom(
+            null_mut(),
+            secret.as_mut_ptr(),
+            secret.len() as u32,
+            BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+        )
+    };
+    if status != 0 {
+        return Err(format!(
+            "failed to generate a secret, status 0x{status:08x}"
+        ));
+    }
+
+    fs::write(path, secret).map_err(|e| format!("failed to write \"{path}\": {e}"))
+}
+
+fn get_challenge(h_device: HANDLE) -> Option<ChallengeMessage> {
+    let mut output = [0u8; CHALLENGE_MESSAGE_SIZE];
+
+    let mut returned: u32 = 0;
+    let status = unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_GET_CHALLENGE,
+            null_mut(),
+            0,
+            output.as_mut_ptr() as *mut c_void,
+            output.len() as u32,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    };
+    if status == 0 {
+        return None;
+    }
+
+    ChallengeMessage::decode(&output[..returned as usize]).ok()
+}
+
+/// Answers a challenge so the handle may change the configuration of a locked driver.
+/// Unlocked drivers need no answer.
+pub(crate) fn authenticate(h_device: HANDLE, secret: &[u8]) -> i32 {
+    let Some(message) = get_challenge(h_device) else {
+        return 0;
+    };
+    if message.flags & AUTH_FLAG_LOCKED == 0 {
+        return 1;
+    }
+
+    let response = response(secret, &message.challenge);
+    let mut returned: u32 = 0;
+    unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_AUTHENTICATE,
+            response.as_ptr() as *const c_void,
+            response.len() as u32,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    }
+}
+
+pub(crate) fn set_secret(h_device: HANDLE, secret: &[u8]) -> i32 {
+    let mut returned: u32 = 0;
+    unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_SET_SECRET,
+            secret.as_ptr() as *const c_void,
+            secret.len() as u32,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    }
+}
+
+pub(crate) fn allow_unload(h_device: HANDLE) -> i32 {
+    let mut returned: u32 = 0;
+    unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_ALLOW_UNLOAD,
+            null_mut(),
+            0,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    }
+}
+
+pub(crate) fn show_lock_status(h_device: HANDLE) -> i32 {
+    let Some(message) = get_challenge(h_device) else {
+        return 0;
+    };
+
+    let yes_no = |flag| {
+        if message.flags & flag != 0 {
+            "yes"
+        } else {
+            "no"
+        }
+    };
+    println!("locked: {}", yes_no(AUTH_FLAG_LOCKED));
+    println!("authorized: {}", yes_no(AUTH_FLAG_AUTHORIZED));
+    println!("unload allowed: {}", yes_no(AUTH_FLAG_UNLOAD_ALLOWED));
+    1
+}
@@ -0,0 +1,382 @@
+use common::{
+    auth::Sha256,
+    ioctl_codes,
+    rule_args::format_utc_time,
+    schedule::TICKS_PER_SECOND,
+    vault::{
+        copy_name, decode_index, encode_index, expired, VaultEntry, VaultHeader, VAULT_DIRECTORY,
+        VAULT_INDEX_FILE,
+    },
+};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    ptr::null_mut,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, GENERIC_READ, GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE},
+    Storage::FileSystem::{
+        CreateFileW, QueryDosDeviceW, ReadFile, SetEndOfFile, SetFilePointerEx, WriteFile,
+        FILE_BEGIN, OPEN_EXISTING,
+    },
+    System::IO::DeviceIoControl,
+};
+
+use crate::utf16_to_string;
+
+/// Lets this process change the vaults while `h_device` stays open.
+pub(crate) fn open_vault(h_device: HANDLE) -> i32 {
+    let mut returned: u32 = 0;
+    unsafe {
+        DeviceIoControl(
+            h_device,
+            ioctl_codes::IOCTL_DELPROTECT_OPEN_VAULT,
+            null_mut(),
+            0,
+            null_mut(),
+            0,
+            &mut returned as *mut u32,
+            null_mut(),
+        )
+    }
+}
+
+/// FILETIME of 1970-01-01.
+const UNIX_EPOCH_TICKS: u64 = 116_444_736_000_000_000;
+
+/// `vault list|restore|purge|retention <X:> ...`. The vault is read from the volume directly,
+/// the driver only lets the process change it after `open_vault`.
+pub(crate) fn vault_command(args: &[String]) -> Result<(), String> {
+    let (command, drive, rest) = match args {
+        [command, drive, rest @ ..] => (command.as_str(), drive.as_str(), rest),
+        _ => return Err("expected vault list|restore|purge|retention <X:>".to_string()),
+    };
+    let vault = VaultDir::new(drive)?;
+
+    match (command, rest) {
+        ("list", []) => vault.list(),
+        ("restore", [id]) => vault.restore(parse_id(id)?, None),
+        ("restore", [id, to, path]) if to == "--to" => vault.restore(parse_id(id)?, Some(path)),
+        ("purge", []) => vault.purge(Purge::Expired),
+        ("purge", [all]) if all == "--all" => vault.purge(Purge::All),
+        ("purge", [flag, id]) if flag == "--id" => vault.purge(Purge::Id(parse_id(id)?)),
+        ("retention", rest) => vault.retention(rest),
+        _ => Err(format!("invalid vault command \"{}\"", args.join(" "))),
+    }
+}
+
+enum Purge {
+    /// Entries past the retention or above the size cap, the driver never drops them itself.
+    Expired,
+    All,
+    Id(u64),
+}
+
+/// `X:\$DelProtectVault`.
+struct VaultDir {
+    drive: String,
+    directory: String,
+}
+
+impl VaultDir {
+    fn new(drive: &str) -> Result<Self, String> {
+        let bytes = drive.as_bytes();
+        if bytes.len() != 2 || bytes[1] != b':' || !bytes[0].is_ascii_alphabetic() {
+            return Err(format!("expected a drive letter like C:, got \"{drive}\""));
+        }
+        Ok(Self {
+            drive: drive.to_ascii_uppercase(),
+            directory: format!("{drive}\\{VAULT_DIRECTORY}"),
+        })
+    }
+
+    fn open_index(&self, write: bool) -> Result<(IndexFile, Vec<u8>), String> {
+        let path = format!("{}\\{VAULT_INDEX_FILE}", self.directory);
+        let file =
+            IndexFile::open(&path, write).map_err(|e| format!("failed to open \"{path}\": {e}"))?;
+        let index = file
+            .read_all()
+            .map_err(|e| format!("failed to read \"{path}\": {e}"))?;
+        Ok((file, index))
+    }
+
+    fn rewrite_index(
+        &self,
+        file: &mut IndexFile,
+        header: &VaultHeader,
+        entries: &[VaultEntry],
+    ) -> Result<(), String> {
+        file.rewrite(&encode_index(header, entries))
+            .map_err(|e| format!("failed to write the index: {e}"))
+    }
+
+    fn list(&self) -> Result<(), String> {
+        let (_, index) = self.open_index(false)?;
+        let (header, entries) =
+            decode_index(&index).map_err(|e| format!("invalid index: {e:?}"))?;
+
+        print_header(&header);
+        for entry in &entries {
+            println!(
+                "{}  {}  {:>12}  pid {:<6} rule {:<4} {}",
+                copy_name(entry.id),
+                format_utc_time(entry.time),
+                entry.size,
+                entry.process_id,
+                entry.rule_id,
+                self.dos_path(&utf16_to_string(entry.path))
+            );
+            println!("\tby {}", utf16_to_string(entry.process));
+        }
+        Ok(())
+    }
+
+    /// Copies the file back, to its original path unless `to` is given, and drops its entry.
+    /// An existing file is never overwritten.
+    fn restore(&self, id: u64, to: Option<&str>) -> Result<(), String> {
+        let (mut file, index) = self.open_index(true)?;
+        let (header, mut entries) =
+            decode_index(&index).map_err(|e| format!("invalid index: {e:?}"))?;
+        let position = entries
+            .iter()
+            .position(|entry| entry.id == id)
+            .ok_or_else(|| format!("no entry {}", copy_name(id)))?;
+        let entry = entries[position];
+
+        let copy_path = format!("{}\\{}", self.directory, copy_name(id));
+        let content =
+            fs::read(&copy_path).map_err(|e| format!("failed to read \"{copy_path}\": {e}"))?;
+        let mut hasher = Sha256::new();
+        hasher.update(&content);
+        if hasher.finish() != entry.sha256 {
+            return Err(format!("\"{copy_path}\" does not match its entry"));
+        }
+
+        let target = match to {
+            Some(path) => path.to_string(),
+            None => self.dos_path(&utf16_to_string(entry.path)),
+        };
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&target)
+            .and_then(|mut restored| restored.write_all(&content))
+            .map_err(|e| format!("failed to restore \"{target}\": {e}"))?;
+
+        entries.remove(position);
+        self.rewrite_index(&mut file, &header, &entries)?;
+        self.remove_copy(id);
+        println!("Restored {target}");
+        Ok(())
+    }
+
+    fn purge(&self, purge: Purge) -> Result<(), String> {
+        let (mut file, index) = self.open_index(true)?;
+        let (header, entries) =
+            decode_index(&index).map_err(|e| format!("invalid index: {e:?}"))?;
+
+        let dropped = match purge {
+            Purge::Expired => expired(&header, &entries, now()),
+            Purge::All => vec![true; entries.len()],
+            Purge::Id(id) => {
+                if !entries.iter().any(|entry| entry.id == id) {
+                    return Err(format!("no entry {}", copy_name(id)));
+                }
+                entries.iter().map(|entry| entry.id == id).collect()
+            },
+        };
+        let kept: Vec<VaultEntry> = entries
+            .iter()
+            .zip(&dropped)
+            .filter(|(_, dropped)| !**dropped)
+            .map(|(entry, _)| *entry)
+            .collect();
+
+        // the index first, a copy without an entry is only wasted space
+        self.rewrite_index(&mut file, &header, &kept)?;
+        for (entry, _) in entries
+            .iter()
+            .zip(&dropped)
+            .filter(|(_, dropped)| **dropped)
+        {
+            self.remove_copy(entry.id);
+        }
+        println!("Purged {} entry(ies)", entries.len() - kept.len());
+        Ok(())
+    }
+
+    /// `[--days N] [--max-mb M]`, 0 disables either limit. Without options shows them.
+    fn retention(&self, args: &[String]) -> Result<(), String> {
+        let (mut file, index) = self.open_index(!args.is_empty())?;
+        let (mut header, entries) =
+            decode_index(&index).map_err(|e| format!("invalid index: {e:?}"))?;
+
+        let mut it = args.iter();
+        while let Some(arg) = it.next() {
+            let value = it
+                .next()
+                .ok_or_else(|| format!("missing value for \"{arg}\""))?;
+            let number: u64 = value
+                .parse()
+                .map_err(|_| format!("invalid number \"{value}\""))?;
+            match arg.as_str() {
+                "--days" => {
+                    header.retention_days =
+                        u32::try_from(number).map_err(|_| format!("too many days {number}"))?
+                },
+                "--max-mb" => header.max_bytes = number.saturating_mul(1024 * 1024),
+                _ => return Err(format!("unknown option \"{arg}\"")),
+            }
+        }
+
+        if !args.is_empty() {
+            self.rewrite_index(&mut file, &header, &entries)?;
+        }
+        print_header(&header);
+        Ok(())
+    }
+
+    fn remove_copy(&self, id: u64) {
+        let path = format!("{}\\{}", self.directory, copy_name(id));
+        if let Err(e) = fs::remove_file(&path) {
+            println!("failed to remove \"{path}\": {e}");
+        }
+    }
+
+    /// `\Device\HarddiskVolume3\Data\x.txt` becomes `C:\Data\x.txt`. The copies of a vault come
+    /// from its own volume, a path on another device is kept as it is.
+    fn dos_path(&self, nt_path: &str) -> String {
+        let drive: Vec<u16> = self.drive.encode_utf16().chain([0]).collect();
+        let mut device = [0u16; 1024];
+        let len =
+            unsafe { QueryDosDeviceW(drive.as_ptr(), device.as_mut_ptr(), device.len() as u32) };
+        if len == 0 {
+            return nt_path.to_string();
+        }
+
+        let end = device.iter().position(|&c| c == 0).unwrap_or(len as usize);
+        let device = String::from_utf16_lossy(&device[..end]);
+        match nt_path.strip_prefix(&device) {
+            Some(rest) if rest.starts_with('\\') => format!("{}{rest}", self.drive),
+            _ => nt_path.to_string(),
+        }
+    }
+}
+
+/// The index, opened without sharing: a delete preserved meanwhile is denied rather than lost
+/// from the index.
+struct IndexFile {
+    handle: HANDLE,
+}
+
+impl IndexFile {
+    fn open(path: &str, write: bool) -> io::Result<Self> {
+        let access = if write {
+            GENERIC_READ | GENERIC_WRITE
+        } else {
+            GENERIC_READ
+        };
+        let path: Vec<u16> = path.encode_utf16().chain([0]).collect();
+        let handle = unsafe {
+            CreateFileW(
+                path.as_ptr(),
+                access,
+                0,
+                null_mut(),
+                OPEN_EXISTING,
+                0,
+                0isize,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { handle })
+    }
+
+    fn read_all(&self) -> io::Result<Vec<u8>> {
+        let mut index = Vec::new();
+        let mut chunk = [0u8; 64 * 1024];
+        loop {
+            let mut read: u32 = 0;
+            let ok = unsafe {
+                ReadFile(
+                    self.handle,
+                    chunk.as_mut_ptr(),
+                    chunk.len() as u32,
+                    &mut read,
+                    null_mut(),
+                )
+            };
+            if ok == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if read == 0 {
+                return Ok(index);
+            }
+            index.extend_from_slice(&chunk[..read as usize]);
+        }
+    }
+
+    /// Replaces the whole content with `index`.
+    fn rewrite(&mut self, index: &[u8]) -> io::Result<()> {
+        let mut written: u32 = 0;
+        let ok = unsafe {
+            SetFilePointerEx(self.handle, 0, null_mut(), FILE_BEGIN) != 0
+                && WriteFile(
+                    self.handle,
+                    index.as_ptr(),
+                    index.len() as u32,
+                    &mut written,
+                    null_mut(),
+                ) != 0
+                && SetEndOfFile(self.handle) != 0
+        };
+        if !ok {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for IndexFile {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle);
+        }
+    }
+}
+
+fn print_header(header: &VaultHeader) {
+    let retention = match header.retention_days {
+        0 => "kept until purged".to_string(),
+        days => format!("{days} day(s)"),
+    };
+    let cap = match header.max_bytes {
+        0 => "no size cap".to_string(),
+        bytes => format!("cap {} MB", bytes / (1024 * 1024)),
+    };
+    println!(
+        "{} entry(ies), {} bytes, retention {retention}, {cap}",
+        header.entry_count, header.used_bytes
+    );
+    if header.is_full() {
+        println!("The vault is full, preserved deletes are denied until `vault purge`");
+    }
+}
+
+fn parse_id(id: &str) -> Result<u64, String> {
+    u64::from_str_radix(id, 16).map_err(|_| format!("invalid vault id \"{id}\""))
+}
+
+/// Current FILETIME, UTC.
+fn now() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    UNIX_EPOCH_TICKS
+        + since_epoch.as_secs() * TICKS_PER_SECOND
+        + since_epoch.subsec_nanos() as u64 / 100
+}
@@ -0,0 +1,81 @@
+use common::volume::{
+    VolumePolicy, DEVICE_TYPE_NAMES, FILESYSTEM_NAMES, MAX_VOLUME_GUIDS, MEDIA_NAMES,
+};
+
+/// Parses the options of `volume-policy` into a policy. Without options the policy attaches
+/// to every volume.
+pub(crate) fn parse_volume_policy(args: &[String]) -> Result<VolumePolicy, String> {
+    let mut policy = VolumePolicy::default();
+
+    let mut it = args.iter();
+    while let Some(option) = it.next() {
+        let value = it
+            .next()
+            .ok_or_else(|| format!("missing value for \"{option}\""))?;
+
+        match option.as_str() {
+            "--fs" => {
+                for name in value.split(',') {
+                    policy.filesystems |= 1 << lookup(&FILESYSTEM_NAMES, name, "filesystem")?;
+                }
+            },
+            "--device" => {
+                for name in value.split(',') {
+                    policy.device_types |= 1u64 << lookup(&DEVICE_TYPE_NAMES, name, "device type")?;
+                }
+            },
+            "--media" => {
+                for name in value.split(',') {
+                    policy.media |= lookup(&MEDIA_NAMES, name, "media")?;
+                }
+            },
+            "--letters" => {
+                for letter in value.chars().filter(|c| *c != ',' && *c != ':') {
+                    if !letter.is_ascii_alphabetic() {
+                        return Err(format!("invalid drive letter \"{letter}\""));
+                    }
+                    policy.drive_letters |= 1 << (letter.to_ascii_uppercase() as u8 - b'A');
+                }
+            },
+            "--guid" => {
+                if !policy.add_guid(value) {
+                    return Err(format!(
+                        "invalid volume GUID \"{value}\" or more than {MAX_VOLUME_GUIDS} GUIDs"
+                    ));
+                }
+            },
+            _ => return Err(format!("unknown option \"{option}\"")),
+        }
+    }
+
+    Ok(policy)
+}
+
+fn lookup(names: &[(&str, u32)], name: &str, what: &str) -> Result<u32, String> {
+    let name = name.trim().to_ascii_lowercase();
+    names
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| *value)
+        .ok_or_else(|| format!("unknown {what} \"{name}\""))
+}
+
+pub(crate) fn filesystem_name(filesystem_type: u32) -> String {
+    name_of(&FILESYSTEM_NAMES, filesystem_type)
+}
+
+pub(crate) fn device_type_name(device_type: u32) -> String {
+    name_of(&DEVICE_TYPE_NAMES, device_type)
+}
+
+pub(crate) fn media_name(media: u32) -> String {
+    name_of(&MEDIA_NAMES, media)
+}
+
+fn name_of(names: &[(&str, u32)], value: u32) -> String {
+    names
+        .iter()
+        .find(|(_, v)| *v == value)
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_else(|| format!("0x{value:x}"))
+}